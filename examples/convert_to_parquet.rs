@@ -0,0 +1,51 @@
+//! Convert logs to a Parquet file, for querying with DuckDB, Spark, or Polars.
+//!
+//! ```text
+//! cargo run --example convert_to_parquet --features app,parquet -- \
+//!     --output access.parquet access.log access.log.1.gz
+//! ```
+//!
+//! Entries are written in batches as they are read, so memory use stays bounded however long
+//! the input is.
+
+use std::{error::Error, fs::File, io::BufWriter};
+
+use common_log_format::{
+    app::{Args, ParserConfig, UsageError},
+    parquet::ParquetSink,
+    sink::Sink,
+};
+
+const USAGE: &str = "usage: convert_to_parquet --output FILE [OPTIONS] [FILE...]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::from_env();
+    if args.flag("--help") {
+        println!("{}\n{}", USAGE, ParserConfig::USAGE);
+        return Ok(());
+    }
+    let config = ParserConfig::from_args(&mut args)?;
+    let output: String = args
+        .value("--output")?
+        .ok_or_else(|| UsageError(USAGE.to_owned()))?;
+    let paths = args.finish()?;
+    if config.follow {
+        return Err(UsageError("--follow would never finish the file".to_owned()).into());
+    }
+
+    let mut sink = ParquetSink::new(BufWriter::new(File::create(&output)?))?;
+    let mut entries = config.open(&paths)?;
+    for entry in entries.by_ref() {
+        sink.send(&entry?.entry)?;
+    }
+    let rows = sink.rows();
+    sink.finish()?;
+
+    eprintln!(
+        "wrote {} entries to {} ({} lines skipped)",
+        rows,
+        output,
+        entries.skipped()
+    );
+    Ok(())
+}
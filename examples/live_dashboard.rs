@@ -0,0 +1,128 @@
+//! A live terminal dashboard of the traffic in a log.
+//!
+//! ```text
+//! cargo run --example live_dashboard --features app -- --follow /var/log/nginx/access.log
+//! ```
+//!
+//! The screen is redrawn at most once a second, as entries arrive: request and error rates over
+//! the last `--window` seconds, the busiest paths, and the number of distinct clients.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use common_log_format::{
+    app::{Args, ParserConfig},
+    stats::{HyperLogLog, TopK},
+    LogEntry,
+};
+
+const USAGE: &str = "usage: live_dashboard [--window SECS] [--top N] [OPTIONS] [FILE...]";
+
+/// When each recent request arrived, and whether it failed.
+struct Recent {
+    window: Duration,
+    arrivals: VecDeque<(Instant, bool)>,
+}
+
+impl Recent {
+    fn push(&mut self, now: Instant, error: bool) {
+        self.arrivals.push_back((now, error));
+        while let Some(&(t, _)) = self.arrivals.front() {
+            if now.duration_since(t) <= self.window {
+                break;
+            }
+            self.arrivals.pop_front();
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.arrivals.len() as f64 / self.window.as_secs_f64()
+    }
+
+    fn error_rate(&self) -> f64 {
+        let errors = self.arrivals.iter().filter(|(_, e)| *e).count();
+        errors as f64 / self.arrivals.len().max(1) as f64
+    }
+}
+
+struct Dashboard {
+    recent: Recent,
+    paths: TopK<String>,
+    clients: HyperLogLog,
+    total: u64,
+    top_n: usize,
+}
+
+impl Dashboard {
+    fn observe(&mut self, entry: &LogEntry, now: Instant) {
+        self.total += 1;
+        let error = entry.status_code.is_some_and(|s| s.is_server_error());
+        self.recent.push(now, error);
+        if let Some(path) = entry.path() {
+            self.paths.observe(path.to_owned());
+        }
+        if let Some(host) = entry.host {
+            self.clients.insert(&host);
+        }
+    }
+
+    fn draw(&self, skipped: u64) -> std::io::Result<()> {
+        let mut out = std::io::stdout().lock();
+        // Clear the screen and move to the top left.
+        write!(out, "\x1b[2J\x1b[H")?;
+        writeln!(out, "requests        {}", self.total)?;
+        writeln!(out, "skipped lines   {}", skipped)?;
+        writeln!(out, "distinct hosts  ~{}", self.clients.estimate())?;
+        writeln!(
+            out,
+            "last {}s        {:.1} req/s, {:.1}% 5xx",
+            self.recent.window.as_secs(),
+            self.recent.rate(),
+            self.recent.error_rate() * 100.
+        )?;
+        writeln!(out, "\ntop paths")?;
+        for (path, n) in self.paths.top(self.top_n) {
+            writeln!(out, "  {:>8}  {}", n, path)?;
+        }
+        out.flush()
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::from_env();
+    if args.flag("--help") {
+        println!("{}\n{}", USAGE, ParserConfig::USAGE);
+        return Ok(());
+    }
+    let config = ParserConfig::from_args(&mut args)?;
+    let window = Duration::from_secs(args.value("--window")?.unwrap_or(60));
+    let top_n: usize = args.value("--top")?.unwrap_or(10);
+    let paths = args.finish()?;
+
+    let mut dashboard = Dashboard {
+        recent: Recent {
+            window,
+            arrivals: VecDeque::new(),
+        },
+        paths: TopK::new(top_n.max(1) * 10),
+        clients: HyperLogLog::new(14),
+        total: 0,
+        top_n,
+    };
+    let mut drawn = Instant::now();
+    let mut entries = config.open(&paths)?;
+    while let Some(entry) = entries.next() {
+        let now = Instant::now();
+        dashboard.observe(&entry?.entry, now);
+        if now.duration_since(drawn) >= Duration::from_secs(1) {
+            dashboard.draw(entries.skipped())?;
+            drawn = now;
+        }
+    }
+    dashboard.draw(entries.skipped())?;
+    Ok(())
+}
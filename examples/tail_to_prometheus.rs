@@ -0,0 +1,91 @@
+//! Count requests into a Prometheus text file, for node_exporter's textfile collector.
+//!
+//! ```text
+//! cargo run --example tail_to_prometheus --features app -- \
+//!     --follow /var/log/nginx/access.log --output /var/lib/node_exporter/access_log.prom
+//! ```
+//!
+//! The file is rewritten (atomically, by renaming) at most every `--interval` seconds, as entries
+//! arrive, and once more at the end of the input.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Write as _,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common_log_format::app::{Args, ParserConfig, UsageError};
+
+const USAGE: &str = "usage: tail_to_prometheus --output FILE [--interval SECS] [OPTIONS] [FILE...]";
+
+#[derive(Default)]
+struct Metrics {
+    /// Requests by method and status code.
+    requests: BTreeMap<(String, u16), u64>,
+    response_bytes: u64,
+    skipped: u64,
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP http_requests_total Requests logged, by method and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, status), n) in &self.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, n
+            );
+        }
+        out.push_str("# HELP http_response_bytes_total Response bytes logged.\n");
+        out.push_str("# TYPE http_response_bytes_total counter\n");
+        let _ = writeln!(out, "http_response_bytes_total {}", self.response_bytes);
+        out.push_str("# HELP access_log_skipped_lines_total Lines which failed to parse.\n");
+        out.push_str("# TYPE access_log_skipped_lines_total counter\n");
+        let _ = writeln!(out, "access_log_skipped_lines_total {}", self.skipped);
+        out
+    }
+
+    fn write(&self, path: &PathBuf) -> std::io::Result<()> {
+        let tmp = path.with_extension("prom.tmp");
+        std::fs::write(&tmp, self.render())?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::from_env();
+    if args.flag("--help") {
+        println!("{}\n{}", USAGE, ParserConfig::USAGE);
+        return Ok(());
+    }
+    let config = ParserConfig::from_args(&mut args)?;
+    let output: PathBuf = args
+        .value("--output")?
+        .ok_or_else(|| UsageError(USAGE.to_owned()))?;
+    let interval = Duration::from_secs(args.value("--interval")?.unwrap_or(15));
+    let paths = args.finish()?;
+
+    let mut metrics = Metrics::default();
+    let mut entries = config.open(&paths)?;
+    let mut written = Instant::now();
+    while let Some(entry) = entries.next() {
+        let entry = entry?.entry;
+        let method = entry.method().unwrap_or("-").to_owned();
+        let status = entry.status_code.map_or(0, |s| s.as_u16());
+        *metrics.requests.entry((method, status)).or_default() += 1;
+        metrics.response_bytes += entry.object_size.unwrap_or(0) as u64;
+        metrics.skipped = entries.skipped();
+
+        if written.elapsed() >= interval {
+            metrics.write(&output)?;
+            written = Instant::now();
+        }
+    }
+    metrics.skipped = entries.skipped();
+    metrics.write(&output)?;
+    Ok(())
+}
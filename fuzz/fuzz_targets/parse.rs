@@ -0,0 +1,9 @@
+#![no_main]
+
+use common_log_format::LogEntry;
+use libfuzzer_sys::fuzz_target;
+
+// Parsing must never panic, whatever the input.
+fuzz_target!(|line: &str| {
+    let _ = line.parse::<LogEntry>();
+});
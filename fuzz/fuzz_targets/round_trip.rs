@@ -0,0 +1,11 @@
+#![no_main]
+
+use common_log_format::LogEntry;
+use libfuzzer_sys::fuzz_target;
+
+// Every entry written out parses back into itself.
+fuzz_target!(|entry: LogEntry| {
+    let line = entry.to_string();
+    let parsed: LogEntry = line.parse().expect("written entry should parse");
+    assert_eq!(parsed, entry, "{}", line);
+});
@@ -0,0 +1,256 @@
+//! Access logging middleware for actix-web applications.
+//!
+//! actix-web's own `Logger` writes Apache-style lines, but with the time in its own format, the
+//! `Content-Length` the handler set rather than the bytes sent, and no user. [`AccessLog`] writes
+//! Combined Log Format lines the rest of this crate reads back, as the `tower` middleware does:
+//!
+//! | field         | from                                                                 |
+//! |---------------|----------------------------------------------------------------------|
+//! | host          | the address of the peer the request came from                        |
+//! | authuser      | the user of HTTP Basic authentication                                |
+//! | time          | when the request was received                                        |
+//! | request line  | the method, path and query, and version                              |
+//! | status        | the response's, including error responses                            |
+//! | size          | the bytes of response body sent                                      |
+//! | referer, user agent | the request's `Referer` and `User-Agent` headers               |
+//!
+//! The peer address is the connection's, not one from a `Forwarded` or `X-Forwarded-For`
+//! header, which a client could set to anything; behind a proxy, resolve the real client
+//! address with [`forwarded`](crate::forwarded) when reading the log.
+
+use std::{
+    future::{ready, Future, Ready},
+    io::Write,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web::Bytes,
+    Error,
+};
+use pin_project_lite::pin_project;
+
+use crate::{
+    combined::CombinedLogEntry,
+    duration::DurationUnit,
+    request_log::{basic_auth_user, Record, Writer},
+    LogEntry,
+};
+
+/// Middleware which logs the requests an app answers, as described in the [module docs](self).
+///
+/// Lines are written to one writer, shared by every worker, each with a single `write_all`
+/// while holding its lock. Errors writing are ignored.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use actix_web::{test, web, App, HttpResponse};
+/// use common_log_format::actix::AccessLog;
+///
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+/// impl std::io::Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let buffer = Buffer::default();
+/// let app = test::init_service(
+///     App::new()
+///         .wrap(AccessLog::new(buffer.clone()))
+///         .route("/", web::get().to(|| async { HttpResponse::Ok().body("hello") })),
+/// )
+/// .await;
+///
+/// let request = test::TestRequest::get()
+///     .uri("/?lang=en")
+///     .peer_addr("10.0.0.1:51234".parse().unwrap())
+///     .insert_header(("Authorization", "Basic ZnJhbms6c2VjcmV0")) // frank:secret
+///     .insert_header(("User-Agent", "curl/8.5.0"))
+///     .to_request();
+/// let response = test::call_service(&app, request).await;
+/// test::read_body(response).await;
+///
+/// let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+/// assert!(line.starts_with("10.0.0.1 - frank ["));
+/// assert!(line.ends_with("] \"GET /?lang=en HTTP/1.1\" 200 5 \"-\" \"curl/8.5.0\"\n"));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct AccessLog {
+    writer: Writer,
+    duration: Option<DurationUnit>,
+}
+
+impl AccessLog {
+    /// Write lines to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        AccessLog {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            duration: None,
+        }
+    }
+
+    /// Append how long each request took, from receiving it to the end of the response body, to
+    /// its line, in `unit`.
+    pub fn with_duration(mut self, unit: DurationUnit) -> Self {
+        self.duration = Some(unit);
+        self
+    }
+
+    fn record(&self, request: &ServiceRequest) -> Record {
+        let header = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let target = request
+            .uri()
+            .path_and_query()
+            .map_or("/".to_owned(), |p| p.to_string());
+        let entry = CombinedLogEntry {
+            entry: LogEntry {
+                host: request.peer_addr().map(|a| a.ip().to_canonical()),
+                ident: None,
+                authuser: header(header::AUTHORIZATION).and_then(|a| basic_auth_user(&a)),
+                time: Some(chrono::Utc::now()),
+                request_line: Some(format!(
+                    "{} {} {:?}",
+                    request.method(),
+                    target,
+                    request.version()
+                )),
+                status_code: None,
+                object_size: Some(0),
+            },
+            referer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+        };
+        Record::new(&self.writer, self.duration, entry)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLogMiddleware {
+            service,
+            log: self.clone(),
+        }))
+    }
+}
+
+/// The service [`AccessLog`] wraps an app's in.
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    log: AccessLog,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let record = self.log.record(&request);
+        ResponseFuture {
+            inner: self.service.call(request),
+            record: Some(record),
+        }
+    }
+}
+
+pin_project! {
+    /// The future of an [`AccessLogMiddleware`]'s response.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        record: Option<Record>,
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    type Output = Result<ServiceResponse<LoggedBody<B>>, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let mut record = this.record.take();
+        match result {
+            Ok(response) => {
+                if let Some(record) = &mut record {
+                    record.entry.entry.status_code = Some(response.status());
+                }
+                Poll::Ready(Ok(
+                    response.map_body(|_, inner| LoggedBody { inner, record })
+                ))
+            }
+            // The error is turned into a response, whose body isn't seen here.
+            Err(e) => {
+                if let Some(record) = &mut record {
+                    record.entry.entry.status_code = Some(e.as_response_error().status_code());
+                }
+                Poll::Ready(Err(e))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// A response body which counts the bytes sent, and logs its request once it ends or is
+    /// dropped.
+    pub struct LoggedBody<B> {
+        #[pin]
+        inner: B,
+        record: Option<Record>,
+    }
+}
+
+impl<B: MessageBody> MessageBody for LoggedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.inner.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.project();
+        let chunk = ready!(this.inner.poll_next(cx));
+        match (&chunk, this.record.as_mut()) {
+            (Some(Ok(bytes)), Some(record)) => record.add_size(bytes.len()),
+            (None, _) => drop(this.record.take()),
+            _ => (),
+        }
+        Poll::Ready(chunk)
+    }
+}
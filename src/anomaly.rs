@@ -0,0 +1,242 @@
+//! Flagging unusual intervals in a traffic time series.
+//!
+//! An [`AnomalyDetector`] follows the [`Bucket`]s of a [`rollup`](crate::rollup) as they're
+//! completed, keeping an exponentially weighted moving average and variance of each metric, and
+//! reports an [`Anomaly`] when a bucket strays too many standard deviations from it. Traffic
+//! usually follows the time of day, so the baseline can be kept separately for each slot of a
+//! season, such as each hour of the day, so that the morning rush isn't flagged every morning.
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::rollup::Bucket;
+
+/// A quantity of a [`Bucket`] which is checked for anomalies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Requests,
+    /// [`Bucket::error_rate`].
+    ErrorRate,
+    Bytes,
+}
+
+const METRICS: [Metric; 3] = [Metric::Requests, Metric::ErrorRate, Metric::Bytes];
+
+impl Metric {
+    fn value(self, bucket: &Bucket) -> f64 {
+        match self {
+            Metric::Requests => bucket.requests as f64,
+            Metric::ErrorRate => bucket.error_rate(),
+            Metric::Bytes => bucket.bytes as f64,
+        }
+    }
+}
+
+/// Whether an anomalous value was above or below what was expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+/// A bucket whose value for a metric was far from the baseline.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Anomaly {
+    pub metric: Metric,
+    /// The start of the bucket.
+    pub start: DateTime<FixedOffset>,
+    pub value: f64,
+    /// The baseline's moving average before this bucket.
+    pub expected: f64,
+    /// How many standard deviations `value` is from `expected`.
+    pub score: f64,
+    pub direction: Direction,
+}
+
+/// An exponentially weighted moving average and variance.
+#[derive(Debug, Clone, Copy, Default)]
+struct Baseline {
+    mean: f64,
+    variance: f64,
+    observed: u64,
+}
+
+impl Baseline {
+    fn update(&mut self, x: f64, alpha: f64) {
+        if self.observed == 0 {
+            self.mean = x;
+        } else {
+            let diff = x - self.mean;
+            let step = alpha * diff;
+            self.mean += step;
+            self.variance = (1. - alpha) * (self.variance + diff * step);
+        }
+        self.observed += 1;
+    }
+}
+
+/// Reports buckets whose request count, error rate, or bytes stray from a moving baseline.
+///
+/// A bucket is anomalous for a metric if its value is at least the threshold (3 by default)
+/// standard deviations from the baseline, and differs from it by at least the metric's minimum
+/// change: 20% of the baseline for requests and bytes, and 1 percentage point for the error
+/// rate. The minimum keeps a flat baseline, with almost no variance, from flagging every wobble.
+///
+/// Nothing is reported until a baseline has seen 5 buckets (in its slot, if seasonal), and the
+/// error rate of buckets with fewer than 10 requests is ignored. Anomalous buckets still update
+/// the baseline, so a lasting change of level stops being reported once the baseline has caught
+/// up with it.
+///
+/// # Example
+/// ```
+/// use chrono::Duration;
+/// use common_log_format::{anomaly::{AnomalyDetector, Direction, Metric}, rollup::Rollup, LogEntry};
+///
+/// // 100 requests a minute for an hour, but 400 in minute 40, half of them failing.
+/// let mut entries: Vec<LogEntry> = Vec::new();
+/// for minute in 0..60 {
+///     let (requests, failing) = if minute == 40 { (400, 200) } else { (100 + minute % 7, 0) };
+///     for i in 0..requests {
+///         let status = if i < failing { 503 } else { 200 };
+///         let line = format!("10.0.0.1 - - [2024-05-01T13:{:02}:00Z] \"GET / HTTP/1.1\" {} 10", minute, status);
+///         entries.push(line.parse().unwrap());
+///     }
+/// }
+/// let buckets = Rollup::new(Duration::minutes(1)).run(&entries);
+///
+/// let anomalies = AnomalyDetector::new().detect(&buckets);
+/// let found: Vec<_> = anomalies.iter().map(|a| (a.start.format("%M").to_string(), a.metric, a.direction)).collect();
+/// assert_eq!(found, [
+///     ("40".to_owned(), Metric::Requests, Direction::Above),
+///     ("40".to_owned(), Metric::ErrorRate, Direction::Above),
+///     ("40".to_owned(), Metric::Bytes, Direction::Above),
+/// ]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AnomalyDetector {
+    alpha: f64,
+    threshold: f64,
+    warmup: u64,
+    min_requests: u64,
+    /// The minimum change for each metric, indexed like [`METRICS`].
+    min_change: [f64; 3],
+    /// The width of a bucket and the number of slots in a season, in seconds.
+    season: Option<(i64, usize)>,
+    /// The baselines for each metric, indexed like [`METRICS`], for each slot.
+    baselines: Vec<[Baseline; 3]>,
+}
+
+impl Default for AnomalyDetector {
+    fn default() -> Self {
+        AnomalyDetector {
+            alpha: 0.1,
+            threshold: 3.,
+            warmup: 5,
+            min_requests: 10,
+            min_change: [0.2, 0.01, 0.2],
+            season: None,
+            baselines: vec![Default::default()],
+        }
+    }
+}
+
+impl AnomalyDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Weight each new bucket by `alpha`, between 0 and 1, in the moving averages: the higher,
+    /// the faster the baseline follows changes. 0.1 by default.
+    pub fn with_alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha.clamp(f64::EPSILON, 1.);
+        self
+    }
+
+    /// Flag values `threshold` standard deviations or more from the baseline.
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Don't report anything until a baseline has seen `warmup` buckets.
+    pub fn with_warmup(mut self, warmup: u64) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Ignore the error rate of buckets with fewer than `min_requests` requests.
+    pub fn with_min_requests(mut self, min_requests: u64) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    /// Only flag values of `metric` which differ from the baseline by at least `change`: a
+    /// fraction of the baseline for requests and bytes, and an absolute difference for the
+    /// error rate.
+    pub fn with_min_change(mut self, metric: Metric, change: f64) -> Self {
+        self.min_change[metric as usize] = change;
+        self
+    }
+
+    /// Keep a separate baseline for each bucket of `width` within a repeating `period`, such as
+    /// for each hour (the rollup's width) of a day. Buckets are assigned to slots by their local
+    /// time, so a period of a day starts at midnight in the rollup's time zone.
+    pub fn with_seasonality(mut self, width: chrono::Duration, period: chrono::Duration) -> Self {
+        let width = width.num_seconds().max(1);
+        let slots = (period.num_seconds() / width).max(1) as usize;
+        self.season = Some((width, slots));
+        self.baselines = vec![Default::default(); slots];
+        self
+    }
+
+    /// Check `bucket` against the baselines, and add it to them. Buckets should be observed in
+    /// time order.
+    pub fn observe(&mut self, bucket: &Bucket) -> Vec<Anomaly> {
+        let slot = match self.season {
+            Some((width, slots)) => {
+                let local = bucket.start.naive_local().and_utc().timestamp();
+                local.div_euclid(width).rem_euclid(slots as i64) as usize
+            }
+            None => 0,
+        };
+
+        let mut anomalies = Vec::new();
+        for (i, metric) in METRICS.into_iter().enumerate() {
+            if metric == Metric::ErrorRate && bucket.requests < self.min_requests {
+                continue;
+            }
+            let value = metric.value(bucket);
+            let baseline = &mut self.baselines[slot][i];
+            if baseline.observed >= self.warmup {
+                let diff = value - baseline.mean;
+                let min_change = match metric {
+                    Metric::ErrorRate => self.min_change[i],
+                    _ => self.min_change[i] * baseline.mean.abs(),
+                };
+                let score = diff / baseline.variance.sqrt().max(f64::MIN_POSITIVE);
+                if score.abs() >= self.threshold && diff.abs() >= min_change {
+                    anomalies.push(Anomaly {
+                        metric,
+                        start: bucket.start,
+                        value,
+                        expected: baseline.mean,
+                        score: score.abs(),
+                        direction: if diff > 0. {
+                            Direction::Above
+                        } else {
+                            Direction::Below
+                        },
+                    });
+                }
+            }
+            baseline.update(value, self.alpha);
+        }
+        anomalies
+    }
+
+    /// Observe each of `buckets` in turn, returning every anomaly found.
+    pub fn detect<'a>(&mut self, buckets: impl IntoIterator<Item = &'a Bucket>) -> Vec<Anomaly> {
+        buckets.into_iter().flat_map(|b| self.observe(b)).collect()
+    }
+}
@@ -0,0 +1,185 @@
+//! Anonymizing and pseudonymizing client addresses for sharing logs.
+//!
+//! Truncating addresses, as [`crate::privacy`] does, keeps the network but loses the client: a
+//! study of sessions or per-client behaviour needs to tell clients apart without knowing who they
+//! are. A [`Pseudonymizer`] replaces each address with one derived from it by a keyed hash
+//! (HMAC-SHA256), so that the same client always gets the same pseudonym while the key is kept
+//! secret, and nobody without the key can reverse it by hashing every address. With a rotation
+//! period, the key is mixed with the period each entry falls in, so that pseudonyms cannot be
+//! linked across periods either.
+//!
+//! An [`Anonymizer`] applies a policy to the `host` of each entry and, optionally, to addresses
+//! which appear in the request line, as in `/lookup?ip=203.0.113.7`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    privacy::{replace_ips, truncate_ip},
+    LogEntry,
+};
+
+/// Maps addresses to pseudonyms with a keyed hash.
+///
+/// A pseudonym is an address of the same family made of the leading bits of the hash, so
+/// pseudonymized entries still parse and serialize as usual. IPv4 pseudonyms have only 32 bits,
+/// so with tens of thousands of clients a few of them will share one.
+///
+/// # Example
+/// ```
+/// use common_log_format::anonymize::Pseudonymizer;
+/// let p = Pseudonymizer::new(b"a secret key").with_rotation(chrono::Duration::days(1));
+/// let ip = "203.0.113.7".parse().unwrap();
+/// let monday = "2024-05-06T09:00:00Z".parse().ok();
+///
+/// let pseudonym = p.pseudonymize(ip, monday);
+/// assert_ne!(pseudonym, ip);
+/// assert!(pseudonym.is_ipv4());
+/// assert_eq!(p.pseudonymize(ip, "2024-05-06T17:00:00Z".parse().ok()), pseudonym);
+/// assert_ne!(p.pseudonymize(ip, "2024-05-07T09:00:00Z".parse().ok()), pseudonym);
+/// ```
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+    rotation: Option<chrono::Duration>,
+}
+
+impl std::fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pseudonymizer")
+            .field("rotation", &self.rotation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Pseudonymizer {
+    /// Pseudonymize with `key`, which should be random and kept secret. The same key always
+    /// gives the same pseudonyms.
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Pseudonymizer {
+            key: key.as_ref().to_vec(),
+            rotation: None,
+        }
+    }
+
+    /// Give each client a new pseudonym every `period`, counting periods from the Unix epoch.
+    /// Periods of less than a second turn rotation off.
+    pub fn with_rotation(mut self, period: chrono::Duration) -> Self {
+        self.rotation = Some(period).filter(|p| p.num_seconds() > 0);
+        self
+    }
+
+    /// The pseudonym for `ip` at `time`.
+    ///
+    /// With rotation, addresses without a time are all hashed as if in one extra period of their
+    /// own. IPv4-mapped IPv6 addresses are treated as IPv4.
+    pub fn pseudonymize(&self, ip: IpAddr, time: Option<DateTime<Utc>>) -> IpAddr {
+        let period = match (self.rotation, time) {
+            (Some(r), Some(t)) => Some(t.timestamp().div_euclid(r.num_seconds())),
+            _ => None,
+        };
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        match (self.rotation, period) {
+            (None, _) => mac.update(&[0]),
+            (Some(_), Some(p)) => {
+                mac.update(&[1]);
+                mac.update(&p.to_be_bytes());
+            }
+            (Some(_), None) => mac.update(&[2]),
+        }
+
+        let ip = ip.to_canonical();
+        match ip {
+            IpAddr::V4(v4) => mac.update(&v4.octets()),
+            IpAddr::V6(v6) => mac.update(&v6.octets()),
+        }
+        let hash = mac.finalize().into_bytes();
+
+        match ip {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(hash[0], hash[1], hash[2], hash[3])),
+            IpAddr::V6(_) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&hash[..16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        }
+    }
+}
+
+/// What to do with each address.
+#[derive(Debug, Clone)]
+pub enum IpPolicy {
+    Keep,
+    /// Zero the last octet of IPv4 addresses and the last 80 bits of IPv6 addresses.
+    Truncate,
+    Pseudonymize(Pseudonymizer),
+}
+
+/// Applies an [`IpPolicy`] to the addresses in log entries.
+///
+/// # Example
+/// ```
+/// use common_log_format::{anonymize::{Anonymizer, IpPolicy}, LogEntry};
+/// let line = "192.0.2.77 - - [1996-12-19T16:39:57-08:00] \"GET /whois?ip=198.51.100.23 HTTP/1.0\" 200 2326";
+/// let mut entry: LogEntry = line.parse().unwrap();
+///
+/// Anonymizer::new(IpPolicy::Truncate)
+///     .with_request_line(true)
+///     .apply(&mut entry);
+/// assert_eq!(entry.host, Some("192.0.2.0".parse().unwrap()));
+/// assert_eq!(entry.request_line.as_deref(), Some("GET /whois?ip=198.51.100.0 HTTP/1.0"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    policy: IpPolicy,
+    request_line: bool,
+}
+
+impl Anonymizer {
+    /// Apply `policy` to the `host` of each entry.
+    pub fn new(policy: IpPolicy) -> Self {
+        Anonymizer {
+            policy,
+            request_line: false,
+        }
+    }
+
+    /// Whether to also apply the policy to addresses written out in the request line.
+    ///
+    /// Only literal addresses are found; percent-encoded ones, and addresses which run into
+    /// neighbouring hex digits or dots, are left as they are. Other dotted quads, such as a
+    /// four-part version number in a path, are rewritten as if they were addresses.
+    pub fn with_request_line(mut self, request_line: bool) -> Self {
+        self.request_line = request_line;
+        self
+    }
+
+    /// The policy applied to `ip`, seen at `time`.
+    pub fn anonymize_ip(&self, ip: IpAddr, time: Option<DateTime<Utc>>) -> IpAddr {
+        match &self.policy {
+            IpPolicy::Keep => ip,
+            IpPolicy::Truncate => truncate_ip(ip),
+            IpPolicy::Pseudonymize(p) => p.pseudonymize(ip, time),
+        }
+    }
+
+    /// Anonymize `entry` in place.
+    pub fn apply(&self, entry: &mut LogEntry) {
+        if matches!(self.policy, IpPolicy::Keep) {
+            return;
+        }
+
+        let time = entry.time;
+        entry.host = entry.host.map(|ip| self.anonymize_ip(ip, time));
+        if self.request_line {
+            if let Some(rl) = entry.request_line.as_deref() {
+                entry.request_line = Some(replace_ips(rl, |ip| self.anonymize_ip(ip, time)));
+            }
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! Building blocks for command-line tools over logs.
+//!
+//! Most tools built on this crate start the same way: take some options and some paths from the
+//! command line, then read entries from those files (or standard input), in a chosen format,
+//! possibly following the file as it grows, possibly keeping only the entries matching a filter.
+//! [`Args`] is a small argument parser for that, with no dependencies, and [`ParserConfig`]
+//! turns the common options into an iterator of entries. The examples in the repository are built
+//! from these.
+//!
+//! # Example
+//! ```no_run
+//! use common_log_format::app::{Args, ParserConfig};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let mut args = Args::from_env();
+//!     let config = ParserConfig::from_args(&mut args)?;
+//!     let top: usize = args.value("--top")?.unwrap_or(10);
+//!     let paths = args.finish()?;
+//!
+//!     let mut entries = config.open(&paths)?;
+//!     for entry in entries.by_ref().take(top) {
+//!         println!("{}", entry?.entry);
+//!     }
+//!     eprintln!("{} lines skipped", entries.skipped());
+//!     Ok(())
+//! }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    io::{self, BufRead, BufReader},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    canonical::CanonicalEntry, combined::CombinedLogEntry, filter::Filter, follow::Follow,
+    format::Format, index::LogIndex, reader, reader::trim_line_end, LogEntry, ParseOptions,
+};
+
+/// The command line was not understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for UsageError {}
+
+/// Command-line arguments, taken option by option.
+///
+/// Options are `--name` flags and `--name value` or `--name=value` pairs, and may come in any
+/// order among the positional arguments. Everything after `--` is positional.
+///
+/// # Example
+/// ```
+/// use common_log_format::app::Args;
+/// let mut args = Args::new(["--top=5", "access.log", "--follow", "--", "--odd-name.log"]);
+/// assert!(args.flag("--follow"));
+/// assert_eq!(args.value::<usize>("--top").unwrap(), Some(5));
+/// assert_eq!(args.value::<String>("--format").unwrap(), None);
+/// assert_eq!(args.finish().unwrap(), ["access.log", "--odd-name.log"]);
+///
+/// assert!(Args::new(["--tpo", "5"]).finish().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Args {
+    options: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    pub fn new<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let mut args = args.into_iter().map(Into::into);
+        let options = args.by_ref().take_while(|a| a != "--").collect();
+        Args {
+            options,
+            positional: args.collect(),
+        }
+    }
+
+    /// The arguments this process was run with, except the program name.
+    pub fn from_env() -> Self {
+        Self::new(std::env::args().skip(1))
+    }
+
+    /// Take `name` if it was given, returning whether it was.
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.options.iter().position(|a| a == name) {
+            Some(i) => {
+                self.options.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take the value of `name`, if it was given, parsed as a `T`. If it was given more than
+    /// once, the last value is taken and the others are left, to be reported by [`Args::finish`].
+    pub fn value<T>(&mut self, name: &str) -> Result<Option<T>, UsageError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let prefix = format!("{}=", name);
+        let found = self
+            .options
+            .iter()
+            .rposition(|a| a == name || a.starts_with(&prefix));
+        let i = match found {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let arg = self.options.remove(i);
+        let value = match arg.strip_prefix(&prefix) {
+            Some(v) => v.to_owned(),
+            None if i < self.options.len() => self.options.remove(i),
+            None => return Err(UsageError(format!("{} needs a value", name))),
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|e| UsageError(format!("invalid value {:?} for {}: {}", value, name, e)))
+    }
+
+    /// The positional arguments, or an error naming the first option which wasn't taken.
+    pub fn finish(self) -> Result<Vec<String>, UsageError> {
+        let mut positional = vec![];
+        for arg in self.options {
+            if arg.starts_with("--") || (arg.starts_with('-') && arg != "-") {
+                return Err(UsageError(format!("unknown option {}", arg)));
+            }
+            positional.push(arg);
+        }
+        positional.extend(self.positional);
+        Ok(positional)
+    }
+}
+
+/// How to read and parse input, from the options every tool shares.
+///
+/// | option              | meaning                                                  |
+/// |---------------------|----------------------------------------------------------|
+/// | `--format NAME`     | the [`Format`] of the input (default `clf`)              |
+/// | `--size-separators` | accept thousands separators in sizes                     |
+/// | `--filter EXPR`     | keep only the entries matching a [`Filter`] expression   |
+/// | `--follow`          | keep reading the (single) file as it grows, like `tail -F` |
+/// | `--from-start`      | with `--follow`, read what is already in the file first  |
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    pub format: Format,
+    pub options: ParseOptions,
+    pub filter: Option<Filter>,
+    pub follow: bool,
+    pub from_start: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            format: Format::Clf,
+            options: ParseOptions::default(),
+            filter: None,
+            follow: false,
+            from_start: false,
+        }
+    }
+}
+
+impl ParserConfig {
+    /// A summary of the options, for a tool's usage message.
+    pub const USAGE: &'static str =
+        "  --format NAME        input format: clf, proxied-clf, forwarded-clf, or combined
+  --size-separators    accept thousands separators in sizes
+  --filter EXPR        keep only the entries matching EXPR
+  --follow             keep reading the file as it grows
+  --from-start         with --follow, read what is already in the file first
+";
+
+    /// Take the shared options from `args`.
+    pub fn from_args(args: &mut Args) -> Result<Self, UsageError> {
+        let config = ParserConfig {
+            format: args.value("--format")?.unwrap_or(Format::Clf),
+            options: ParseOptions {
+                size_thousands_separators: args.flag("--size-separators"),
+            },
+            filter: args.value("--filter")?,
+            follow: args.flag("--follow"),
+            from_start: args.flag("--from-start"),
+        };
+        if config.from_start && !config.follow {
+            return Err(UsageError("--from-start needs --follow".to_owned()));
+        }
+        Ok(config)
+    }
+
+    /// Read entries from the files at `paths` in turn, or from standard input if there are none.
+    /// A path of `-` is standard input too. Compressed files are decompressed.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if following anything but a single file.
+    /// Following `access.log`, say, an index of it saved as `access.log.idx` (see [`LogIndex`])
+    /// is used to find times in [`Entries::seek_to_time`].
+    pub fn open(&self, paths: &[String]) -> io::Result<Entries> {
+        let source = if self.follow {
+            let path = match paths {
+                [path] if path != "-" => path,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--follow needs exactly one file",
+                    ))
+                }
+            };
+            let mut follow = match self.from_start {
+                true => Follow::from_start(path)?,
+                false => Follow::new(path)?,
+            };
+            if let Ok(index) = LogIndex::load(format!("{}.idx", path)) {
+                follow = follow.with_index(index);
+            }
+            Source::Follow(Box::new(follow))
+        } else {
+            let mut paths: VecDeque<String> = paths.iter().cloned().collect();
+            if paths.is_empty() {
+                paths.push_back("-".to_owned());
+            }
+            Source::Files {
+                paths,
+                current: None,
+            }
+        };
+        Ok(Entries {
+            config: self.clone(),
+            source,
+            buf: vec![],
+            skipped: 0,
+        })
+    }
+
+    /// Parse `line`, returning `None` if it fails to parse.
+    pub fn parse(&self, line: &str) -> Option<CanonicalEntry> {
+        match self.format {
+            // Only the Common and Combined Log Format parsers take options.
+            Format::Clf => LogEntry::parse_with(line, &self.options)
+                .ok()
+                .map(Into::into),
+            Format::Combined => CombinedLogEntry::parse_with(line, &self.options)
+                .ok()
+                .map(Into::into),
+            format => format.parse(line).ok(),
+        }
+    }
+
+    /// Parse `line`, which needn't be valid UTF-8, returning `None` if it fails to parse.
+    ///
+    /// Invalid sequences are replaced with `U+FFFD`, as [`LogEntry::from_bytes`] does.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::app::{Args, ParserConfig};
+    /// let config = ParserConfig::from_args(&mut Args::new(Vec::<String>::new())).unwrap();
+    /// let line = b"10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /caf\xe9 HTTP/1.1\" 200 10";
+    /// let entry = config.parse_bytes(line).unwrap();
+    /// assert_eq!(entry.entry.path(), Some("/caf\u{fffd}"));
+    /// ```
+    pub fn parse_bytes(&self, line: &[u8]) -> Option<CanonicalEntry> {
+        match self.format {
+            Format::Clf => LogEntry::from_bytes_with(line, &self.options)
+                .ok()
+                .map(Into::into),
+            _ => self.parse(&String::from_utf8_lossy(line)),
+        }
+    }
+
+    /// Whether `entry` matches the filter, if there is one.
+    pub fn matches(&self, entry: &CanonicalEntry) -> bool {
+        self.filter.as_ref().is_none_or(|f| f.matches(&entry.entry))
+    }
+}
+
+enum Source {
+    Files {
+        paths: VecDeque<String>,
+        current: Option<Box<dyn BufRead + Send>>,
+    },
+    Follow(Box<Follow>),
+}
+
+/// The entries read by [`ParserConfig::open`].
+///
+/// Lines which fail to parse are skipped and counted. Lines needn't be UTF-8; see
+/// [`ParserConfig::parse_bytes`]. Following a file, the iterator never ends.
+pub struct Entries {
+    config: ParserConfig,
+    source: Source,
+    buf: Vec<u8>,
+    skipped: u64,
+}
+
+impl Entries {
+    /// The number of lines skipped so far because they failed to parse.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Following a file, move back (or forward) to its first entry at or after `target`, as
+    /// [`Follow::seek_to_time`] does, and return its offset. The entries from there are
+    /// replayed through the filter, then following carries on.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if not following a file.
+    pub fn seek_to_time(&mut self, target: DateTime<Utc>) -> io::Result<u64> {
+        match &mut self.source {
+            Source::Follow(follow) => follow.seek_to_time(target),
+            Source::Files { .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only a followed file can be rewound",
+            )),
+        }
+    }
+
+    /// Replay the entries from the last `by` of wall-clock time, as with
+    /// [`Entries::seek_to_time`].
+    pub fn rewind(&mut self, by: chrono::Duration) -> io::Result<u64> {
+        self.seek_to_time(Utc::now() - by)
+    }
+
+    /// The next entry, if one is ready. Following a file, `None` means nothing more has been
+    /// written yet, rather than the end; otherwise this is the same as [`Iterator::next`].
+    pub fn try_next(&mut self) -> Option<io::Result<CanonicalEntry>> {
+        self.next_entry(false)
+    }
+
+    fn next_entry(&mut self, wait: bool) -> Option<io::Result<CanonicalEntry>> {
+        loop {
+            match self.next_line(wait) {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+            let parsed = self.config.parse_bytes(&self.buf);
+            match parsed {
+                Some(entry) if self.config.matches(&entry) => return Some(Ok(entry)),
+                Some(_) => (),
+                None => self.skipped += 1,
+            }
+        }
+    }
+
+    /// Read the next non-blank line into `buf`, returning `false` at the end of the input, or
+    /// if following and `wait` is false, when no line is ready.
+    fn next_line(&mut self, wait: bool) -> io::Result<bool> {
+        match &mut self.source {
+            Source::Follow(follow) => {
+                let line = match wait {
+                    true => Some(follow.next_line()?),
+                    false => follow.try_next_line()?,
+                };
+                match line {
+                    Some(l) => {
+                        self.buf = l;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            Source::Files { paths, current } => loop {
+                let reader = match current {
+                    Some(r) => r,
+                    None => match paths.pop_front() {
+                        Some(p) if p == "-" => {
+                            current.insert(Box::new(BufReader::new(io::stdin())))
+                        }
+                        Some(p) => current.insert(reader::open(p)?),
+                        None => return Ok(false),
+                    },
+                };
+                self.buf.clear();
+                if reader.read_until(b'\n', &mut self.buf)? == 0 {
+                    *current = None;
+                    continue;
+                }
+                let len = trim_line_end(&self.buf).len();
+                if len > 0 {
+                    self.buf.truncate(len);
+                    return Ok(true);
+                }
+            },
+        }
+    }
+}
+
+impl Iterator for Entries {
+    type Item = io::Result<CanonicalEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(true)
+    }
+}
@@ -0,0 +1,208 @@
+//! Modelling the times between requests.
+//!
+//! Load tests and simulations need a model of when requests arrive, and the gaps between them in
+//! a real log are the place to get one. [`interarrivals`] extracts the gaps, and [`fit`] fits
+//! the usual candidate distributions to them by maximum likelihood, ranking them by how closely
+//! each follows the data (the Kolmogorov–Smirnov statistic):
+//!
+//! - [`Model::Exponential`], the gaps of a Poisson process: requests arriving independently at a
+//!   constant rate.
+//! - [`Model::LogNormal`] and [`Model::Pareto`], heavier-tailed alternatives for bursty traffic,
+//!   which real traffic often is.
+//!
+//! Common Log Format times are usually to the second, which makes many gaps zero. The exponential
+//! fit counts them; the others can't, and are fitted to the positive gaps only.
+
+use std::fmt::Display;
+
+use crate::LogEntry;
+
+/// The seconds between consecutive requests, in time order. Entries without a time are ignored,
+/// and the rest are sorted, so a log which is slightly out of order doesn't give negative gaps.
+///
+/// # Example
+/// ```
+/// use common_log_format::{arrivals::interarrivals, LogEntry};
+/// let entries: Vec<LogEntry> = ["13:00:00", "13:00:02.5", "13:00:01"]
+///     .iter()
+///     .map(|t| format!("10.0.0.1 - - [2024-05-01T{}Z] \"GET / HTTP/1.1\" 200 1", t))
+///     .map(|l| l.parse().unwrap())
+///     .collect();
+/// assert_eq!(interarrivals(&entries), [1., 1.5]);
+/// ```
+pub fn interarrivals<'a>(entries: impl IntoIterator<Item = &'a LogEntry>) -> Vec<f64> {
+    let mut times: Vec<_> = entries.into_iter().filter_map(|e| e.time).collect();
+    times.sort_unstable();
+    times
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6)
+        .collect()
+}
+
+/// A distribution of the gaps between requests, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "model", rename_all = "kebab-case")]
+pub enum Model {
+    /// Gaps with mean `1 / rate`.
+    Exponential { rate: f64 },
+    /// Gaps whose logarithm is normally distributed, with mean `mu` and standard deviation
+    /// `sigma`.
+    LogNormal { mu: f64, sigma: f64 },
+    /// Gaps of at least `scale`, whose tail falls off as `x^-shape`.
+    Pareto { scale: f64, shape: f64 },
+}
+
+impl Model {
+    /// The maximum likelihood exponential distribution of `samples`, or `None` if there are none
+    /// or they are all zero.
+    pub fn fit_exponential(samples: &[f64]) -> Option<Model> {
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        (mean > 0.).then(|| Model::Exponential { rate: 1. / mean })
+    }
+
+    /// The maximum likelihood log-normal distribution of the positive `samples`, or `None` if
+    /// there are fewer than two, or they are all the same.
+    pub fn fit_lognormal(samples: &[f64]) -> Option<Model> {
+        let logs: Vec<f64> = positive(samples).map(f64::ln).collect();
+        if logs.len() < 2 {
+            return None;
+        }
+        let n = logs.len() as f64;
+        let mu = logs.iter().sum::<f64>() / n;
+        let sigma = (logs.iter().map(|l| (l - mu).powi(2)).sum::<f64>() / n).sqrt();
+        (sigma > 0.).then_some(Model::LogNormal { mu, sigma })
+    }
+
+    /// The maximum likelihood Pareto distribution of the positive `samples`, or `None` if there
+    /// are fewer than two, or they are all the same.
+    pub fn fit_pareto(samples: &[f64]) -> Option<Model> {
+        let scale = positive(samples).fold(f64::INFINITY, f64::min);
+        let (n, sum) =
+            positive(samples).fold((0, 0.), |(n, sum), x| (n + 1, sum + (x / scale).ln()));
+        (n >= 2 && sum > 0.).then(|| Model::Pareto {
+            scale,
+            shape: n as f64 / sum,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Exponential { .. } => "exponential",
+            Model::LogNormal { .. } => "lognormal",
+            Model::Pareto { .. } => "pareto",
+        }
+    }
+
+    /// The mean gap, which is infinite for a Pareto distribution with a shape of 1 or less.
+    pub fn mean(&self) -> f64 {
+        match *self {
+            Model::Exponential { rate } => 1. / rate,
+            Model::LogNormal { mu, sigma } => (mu + sigma * sigma / 2.).exp(),
+            Model::Pareto { scale, shape } if shape > 1. => shape * scale / (shape - 1.),
+            Model::Pareto { .. } => f64::INFINITY,
+        }
+    }
+
+    /// The probability that a gap is at most `x` seconds.
+    pub fn cdf(&self, x: f64) -> f64 {
+        match *self {
+            Model::Exponential { rate } => match x > 0. {
+                true => 1. - (-rate * x).exp(),
+                false => 0.,
+            },
+            Model::LogNormal { mu, sigma } => match x > 0. {
+                true => 0.5 * (1. + erf((x.ln() - mu) / (sigma * std::f64::consts::SQRT_2))),
+                false => 0.,
+            },
+            Model::Pareto { scale, shape } => match x >= scale {
+                true => 1. - (scale / x).powf(shape),
+                false => 0.,
+            },
+        }
+    }
+
+    /// The Kolmogorov–Smirnov statistic of `samples` against this distribution: the largest
+    /// difference between their empirical CDF and this one. 0 is a perfect fit, 1 the worst.
+    pub fn ks_statistic(&self, samples: &[f64]) -> f64 {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let n = sorted.len() as f64;
+        sorted
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let cdf = self.cdf(*x);
+                (cdf - i as f64 / n)
+                    .abs()
+                    .max((cdf - (i + 1) as f64 / n).abs())
+            })
+            .fold(0., f64::max)
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Model::Exponential { rate } => write!(f, "exponential(rate={})", rate),
+            Model::LogNormal { mu, sigma } => write!(f, "lognormal(mu={}, sigma={})", mu, sigma),
+            Model::Pareto { scale, shape } => write!(f, "pareto(scale={}, shape={})", scale, shape),
+        }
+    }
+}
+
+/// A fitted [`Model`] and how well it fits.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Fit {
+    pub model: Model,
+    /// The Kolmogorov–Smirnov statistic of the samples the model was fitted to.
+    pub ks_statistic: f64,
+}
+
+/// Fit every [`Model`] to `samples`, best fit first. Models which can't be fitted, for lack of
+/// (positive) samples, are left out.
+///
+/// # Example
+/// ```
+/// use common_log_format::{arrivals::{fit, interarrivals}, generator::Generator};
+///
+/// // Generated requests arrive as a Poisson process, here once a minute.
+/// let entries: Vec<_> = Generator::new(3)
+///     .with_rate(1. / 60.)
+///     .with_diurnal_amplitude(0.)
+///     .take(20_000)
+///     .map(|e| e.entry)
+///     .collect();
+/// let fits = fit(&interarrivals(&entries));
+/// assert_eq!(fits[0].model.name(), "exponential");
+/// assert!((fits[0].model.mean() - 60.).abs() < 3., "{}", fits[0].model);
+/// ```
+pub fn fit(samples: &[f64]) -> Vec<Fit> {
+    let mut fits: Vec<Fit> = [
+        Model::fit_exponential(samples).map(|m| (m, samples.to_vec())),
+        Model::fit_lognormal(samples).map(|m| (m, positive(samples).collect())),
+        Model::fit_pareto(samples).map(|m| (m, positive(samples).collect())),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|(model, fitted)| Fit {
+        model,
+        ks_statistic: model.ks_statistic(&fitted),
+    })
+    .collect();
+    fits.sort_by(|a, b| a.ks_statistic.total_cmp(&b.ks_statistic));
+    fits
+}
+
+fn positive(samples: &[f64]) -> impl Iterator<Item = f64> + '_ {
+    samples.iter().copied().filter(|x| *x > 0.)
+}
+
+/// The error function, to within 1.5e-7 (Abramowitz and Stegun 7.1.26).
+fn erf(x: f64) -> f64 {
+    let t = 1. / (1. + 0.327_591_1 * x.abs());
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    (1. - poly * (-x * x).exp()).copysign(x)
+}
@@ -0,0 +1,78 @@
+//! Access logging for axum applications.
+//!
+//! axum records the address each connection came from as [`ConnectInfo`], when the app is served
+//! with [`Router::into_make_service_with_connect_info`], rather than as a bare `SocketAddr`. With
+//! [`access_log`], a router logs requests with that address as the client, the user of HTTP
+//! Basic authentication, and the size of the body it sent:
+//!
+//! ```no_run
+//! # async fn run(listener: tokio::net::TcpListener) {
+//! use std::net::SocketAddr;
+//! use axum::{routing::get, Router};
+//!
+//! let app = Router::new()
+//!     .route("/", get(|| async { "hello" }))
+//!     .layer(common_log_format::axum::access_log(std::io::stdout()));
+//! axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+//! # }
+//! ```
+//!
+//! [`Router::into_make_service_with_connect_info`]: axum::Router::into_make_service_with_connect_info
+
+use std::{
+    io::Write,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::extract::ConnectInfo;
+use http1::request::Parts;
+
+use crate::tower::{socket_addr, AccessLogLayer};
+
+/// The client address of a request, from its [`ConnectInfo`], or a bare [`SocketAddr`] in its
+/// extensions.
+pub fn connect_info(parts: &Parts) -> Option<IpAddr> {
+    match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => Some(addr.ip().to_canonical()),
+        None => socket_addr(parts),
+    }
+}
+
+/// An [`AccessLogLayer`] writing lines to `writer`, with the client addresses axum records.
+///
+/// # Example
+/// ```
+/// use std::{net::SocketAddr, sync::{Arc, Mutex}};
+/// use axum::{body::Body, extract::ConnectInfo, routing::get, Router};
+/// use http_body_util::BodyExt;
+/// use tower::ServiceExt;
+///
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+/// impl std::io::Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let buffer = Buffer::default();
+/// let app = Router::new()
+///     .route("/", get(|| async { "hello" }))
+///     .layer(common_log_format::axum::access_log(buffer.clone()));
+///
+/// let mut request = http1::Request::get("/")
+///     .header("Authorization", "Basic ZnJhbms6c2VjcmV0") // frank:secret
+///     .body(Body::empty())
+///     .unwrap();
+/// request.extensions_mut().insert(ConnectInfo("10.0.0.1:51234".parse::<SocketAddr>().unwrap()));
+/// let response = app.oneshot(request).await.unwrap();
+/// response.into_body().collect().await.unwrap();
+///
+/// let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+/// assert!(line.starts_with("10.0.0.1 - frank ["));
+/// assert!(line.ends_with("] \"GET / HTTP/1.1\" 200 5 \"-\" \"-\"\n"));
+/// # });
+/// ```
+pub fn access_log(writer: impl Write + Send + 'static) -> AccessLogLayer {
+    AccessLogLayer::new(writer).with_client(connect_info)
+}
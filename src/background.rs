@@ -0,0 +1,208 @@
+//! Sending entries to a sink on a background thread.
+//!
+//! Writing a log file, or shipping entries over the network, can stall for as long as the disk
+//! or the network does. [`NonBlocking`] hands entries to a thread which sends them to the real
+//! sink, through a bounded queue, so the code logging them only ever waits for the queue, and
+//! only if it's told to when the queue is full.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{sink::Sink, LogEntry};
+
+/// What [`NonBlocking`] does with an entry when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WhenFull {
+    /// Wait for the background thread to make room.
+    Block,
+    /// Drop the entry, and count it in [`NonBlocking::dropped`].
+    Drop,
+}
+
+enum Message {
+    Entry(LogEntry),
+    Flush(SyncSender<io::Result<()>>),
+    Shutdown,
+}
+
+/// What the handles and the background thread share.
+#[derive(Default)]
+struct Shared {
+    dropped: AtomicU64,
+    /// The first error sending to the sink since the last flush.
+    error: Mutex<Option<io::Error>>,
+}
+
+/// A [`Sink`] which queues entries for a background thread to send to another sink.
+///
+/// Handles can be cloned and used from any number of threads. Errors from the sink are kept
+/// until the next [`Sink::flush`], which waits for everything queued before it to be sent and
+/// flushed, and returns the first. Sending only fails once the background thread has stopped.
+///
+/// The thread stops when the [`WorkerGuard`] is dropped, after sending what's queued and flushing
+/// the sink, so keep the guard until the program ends.
+///
+/// # Example
+/// ```
+/// use common_log_format::{background::{NonBlocking, WhenFull}, sink::Sink, writer::ClfWriter, LogEntry};
+///
+/// let path = std::env::temp_dir().join(format!("clf-background-doctest-{}.log", std::process::id()));
+/// let (sink, guard) = NonBlocking::new(ClfWriter::open(&path).unwrap(), 1024);
+/// let mut sink = sink.with_when_full(WhenFull::Drop);
+///
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// let mut worker = sink.clone();
+/// std::thread::spawn(move || worker.send(&entry).unwrap()).join().unwrap();
+/// sink.flush().unwrap();
+///
+/// drop(guard);
+/// assert_eq!(sink.dropped(), 0);
+/// assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct NonBlocking {
+    sender: SyncSender<Message>,
+    when_full: WhenFull,
+    shared: Arc<Shared>,
+}
+
+impl NonBlocking {
+    /// Start a thread sending entries to `sink`, with room for `capacity` entries in the queue.
+    /// Entries are dropped when it's full, unless set otherwise.
+    pub fn new<S>(sink: S, capacity: usize) -> (Self, WorkerGuard)
+    where
+        S: Sink + Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity.max(1));
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("clf-background".to_owned())
+                .spawn(move || run(sink, receiver, &shared))
+                .expect("failed to spawn background sink thread")
+        };
+        let guard = WorkerGuard {
+            sender: sender.clone(),
+            thread: Some(thread),
+        };
+        let handle = NonBlocking {
+            sender,
+            when_full: WhenFull::Drop,
+            shared,
+        };
+        (handle, guard)
+    }
+
+    pub fn with_when_full(mut self, when_full: WhenFull) -> Self {
+        self.when_full = when_full;
+        self
+    }
+
+    /// The number of entries dropped because the queue was full, by every handle.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "background sink thread has stopped",
+    )
+}
+
+fn into_io_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    match e.into().downcast::<io::Error>() {
+        Ok(e) => *e,
+        Err(e) => io::Error::other(e),
+    }
+}
+
+/// Send what comes from `receiver` to `sink` until told to shut down.
+fn run<S>(mut sink: S, receiver: Receiver<Message>, shared: &Shared)
+where
+    S: Sink,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let keep_error = |e: S::Error| {
+        let mut error = shared.error.lock().unwrap_or_else(|e| e.into_inner());
+        error.get_or_insert_with(|| into_io_error(e));
+    };
+    for message in receiver {
+        match message {
+            Message::Entry(entry) => {
+                if let Err(e) = sink.send(&entry) {
+                    keep_error(e);
+                }
+            }
+            Message::Flush(done) => {
+                if let Err(e) = sink.flush() {
+                    keep_error(e);
+                }
+                let error = shared
+                    .error
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take();
+                let _ = done.send(error.map_or(Ok(()), Err));
+            }
+            Message::Shutdown => break,
+        }
+    }
+    let _ = sink.flush();
+}
+
+impl Sink for NonBlocking {
+    type Error = io::Error;
+
+    /// Queue `entry`, waiting for room or dropping it if the queue is full, as set.
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let message = Message::Entry(entry.clone());
+        match self.when_full {
+            WhenFull::Block => self.sender.send(message).map_err(|_| stopped()),
+            WhenFull::Drop => match self.sender.try_send(message) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+                Err(TrySendError::Disconnected(_)) => Err(stopped()),
+            },
+        }
+    }
+
+    /// Wait for every entry queued so far to be sent, and the sink flushed, and return the first
+    /// error from the sink since the last flush.
+    fn flush(&mut self) -> io::Result<()> {
+        let (done, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Message::Flush(done))
+            .map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+/// Stops a [`NonBlocking`] sink's background thread when dropped, once it has sent what's queued.
+pub struct WorkerGuard {
+    sender: SyncSender<Message>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Building lists of hosts to block, like fail2ban.
+//!
+//! A host which requests page after page that doesn't exist, keeps failing to log in, or sends
+//! requests faster than any person could is worth blocking for a while. A [`BanList`] counts
+//! these offenses per host over sliding windows of log time, bans a host for a set time once it
+//! reaches a threshold, and writes the hosts banned at any moment in a form a firewall or web
+//! server can load: plain addresses, `ipset restore` commands, or nginx `deny` directives.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::{self, Write},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{filter::Cidr, LogEntry};
+
+/// What a host was banned for.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Offense {
+    /// Requests answered with 404 Not Found.
+    NotFound,
+    /// Requests answered with 401 Unauthorized or 403 Forbidden.
+    AuthFailure,
+    /// Requests of any kind.
+    RequestRate,
+}
+
+impl Display for Offense {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Offense::NotFound => "not_found",
+            Offense::AuthFailure => "auth_failure",
+            Offense::RequestRate => "request_rate",
+        })
+    }
+}
+
+const OFFENSES: [Offense; 3] = [
+    Offense::NotFound,
+    Offense::AuthFailure,
+    Offense::RequestRate,
+];
+
+/// A host banned until `until`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Ban {
+    pub host: IpAddr,
+    pub offense: Offense,
+    /// When the host reached the threshold.
+    pub at: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    /// The offenses in the window ending at `at`.
+    pub count: u64,
+}
+
+/// How [`BanList::write`] writes bans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BanFormat {
+    /// One address per line.
+    Plain,
+    /// `add` commands for `ipset restore`, adding each address to the named set with a timeout
+    /// of the rest of its ban. The set has to exist and have timeouts enabled, and sets hold
+    /// either IPv4 or IPv6 addresses, so keep the two apart with [`BanList::bans`] if both occur.
+    Ipset(String),
+    /// nginx `deny` directives, with when each ban ends in a comment, for a file included in a
+    /// `server` or `http` block.
+    NginxDeny,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Host {
+    /// The times of each kind of offense in its window, indexed like [`OFFENSES`].
+    recent: [VecDeque<DateTime<Utc>>; 3],
+}
+
+/// Bans hosts which reach a threshold of offenses within a window.
+///
+/// By default, 50 requests not found within a minute, or 5 authentication failures within ten
+/// minutes, earn a ban of an hour. There's no limit on the request rate unless one is set. While
+/// a host is banned its offenses aren't counted, and when a ban ends the host starts afresh.
+///
+/// # Example
+/// ```
+/// use common_log_format::{banlist::{BanFormat, BanList, Offense}, LogEntry};
+/// let mut bans = BanList::new().with_auth_failures(3, chrono::Duration::minutes(10));
+///
+/// let line = |host: &str, sec: u32, status: u16| -> LogEntry {
+///     format!("{} - - [2024-05-01T13:00:{:02}Z] \"POST /login HTTP/1.1\" {} 0", host, sec, status)
+///         .parse()
+///         .unwrap()
+/// };
+/// assert!(bans.observe(&line("10.0.0.1", 0, 200)).is_none());
+/// let banned: Vec<_> = (1..4).filter_map(|s| bans.observe(&line("203.0.113.9", s, 401))).collect();
+/// assert_eq!(banned.len(), 1);
+/// assert_eq!(banned[0].offense, Offense::AuthFailure);
+///
+/// let now = "2024-05-01T13:30:00Z".parse().unwrap();
+/// let mut out = Vec::new();
+/// bans.write(&mut out, &BanFormat::Ipset("blocked".to_owned()), now).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "add blocked 203.0.113.9 timeout 1803 -exist\n");
+///
+/// let mut out = Vec::new();
+/// bans.write(&mut out, &BanFormat::NginxDeny, now).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "deny 203.0.113.9; # until 2024-05-01T14:00:03Z\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct BanList {
+    /// The threshold and window for each kind of offense, indexed like [`OFFENSES`].
+    limits: [Option<(u64, chrono::Duration)>; 3],
+    ban_time: chrono::Duration,
+    ignored: Vec<Cidr>,
+    hosts: HashMap<IpAddr, Host>,
+    bans: HashMap<IpAddr, Ban>,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        BanList {
+            limits: [
+                Some((50, chrono::Duration::minutes(1))),
+                Some((5, chrono::Duration::minutes(10))),
+                None,
+            ],
+            ban_time: chrono::Duration::hours(1),
+            ignored: Vec::new(),
+            hosts: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ban hosts with `count` requests not found within `window`.
+    pub fn with_not_found(mut self, count: u64, window: chrono::Duration) -> Self {
+        self.limits[0] = Some((count.max(1), window));
+        self
+    }
+
+    /// Ban hosts with `count` requests answered with 401 or 403 within `window`.
+    pub fn with_auth_failures(mut self, count: u64, window: chrono::Duration) -> Self {
+        self.limits[1] = Some((count.max(1), window));
+        self
+    }
+
+    /// Ban hosts sending `count` requests within `window`, such as 100 a second.
+    pub fn with_request_rate(mut self, count: u64, window: chrono::Duration) -> Self {
+        self.limits[2] = Some((count.max(1), window));
+        self
+    }
+
+    /// Stop counting `offense`.
+    pub fn without(mut self, offense: Offense) -> Self {
+        self.limits[offense as usize] = None;
+        self
+    }
+
+    pub fn with_ban_time(mut self, ban_time: chrono::Duration) -> Self {
+        self.ban_time = ban_time;
+        self
+    }
+
+    /// Never ban hosts in `net`, such as the monitoring system or the office.
+    pub fn with_ignored(mut self, net: Cidr) -> Self {
+        self.ignored.push(net);
+        self
+    }
+
+    /// Account for `entry`, returning the ban it earned its host, if any.
+    ///
+    /// Entries without a host or timestamp are ignored. If an entry reaches more than one
+    /// threshold, the ban is for the first offense in the order of [`Offense`].
+    pub fn observe(&mut self, entry: &LogEntry) -> Option<Ban> {
+        let (host, time) = match (entry.host, entry.time) {
+            (Some(h), Some(t)) => (h.to_canonical(), t),
+            _ => return None,
+        };
+        if self.ignored.iter().any(|net| net.contains(host)) {
+            return None;
+        }
+        if self.bans.get(&host).is_some_and(|b| b.until > time) {
+            return None;
+        }
+
+        let status = entry.status_code.map(|s| s.as_u16());
+        let offended = [status == Some(404), matches!(status, Some(401 | 403)), true];
+        let state = self.hosts.entry(host).or_default();
+        let mut ban = None;
+        for (i, offense) in OFFENSES.iter().enumerate() {
+            let (threshold, window) = match self.limits[i] {
+                Some(limit) if offended[i] => limit,
+                _ => continue,
+            };
+            let recent = &mut state.recent[i];
+            recent.push_back(time);
+            while recent.front().is_some_and(|&t| t <= time - window) {
+                recent.pop_front();
+            }
+            if recent.len() as u64 >= threshold && ban.is_none() {
+                ban = Some(Ban {
+                    host,
+                    offense: *offense,
+                    at: time,
+                    until: time + self.ban_time,
+                    count: recent.len() as u64,
+                });
+            }
+        }
+
+        let ban = ban?;
+        self.hosts.remove(&host);
+        self.bans.insert(host, ban.clone());
+        Some(ban)
+    }
+
+    /// The bans still in force at `now`, by host.
+    pub fn bans(&self, now: DateTime<Utc>) -> Vec<&Ban> {
+        let mut bans: Vec<_> = self.bans.values().filter(|b| b.until > now).collect();
+        bans.sort_by_key(|b| b.host);
+        bans
+    }
+
+    /// Write the bans still in force at `now` to `w`, in `format`.
+    pub fn write(
+        &self,
+        mut w: impl Write,
+        format: &BanFormat,
+        now: DateTime<Utc>,
+    ) -> io::Result<()> {
+        for ban in self.bans(now) {
+            match format {
+                BanFormat::Plain => writeln!(w, "{}", ban.host)?,
+                BanFormat::Ipset(set) => {
+                    let timeout = (ban.until - now).num_seconds().max(1);
+                    writeln!(w, "add {} {} timeout {} -exist", set, ban.host, timeout)?
+                }
+                BanFormat::NginxDeny => writeln!(
+                    w,
+                    "deny {}; # until {}",
+                    ban.host,
+                    ban.until.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget bans which have ended by `now`, and hosts with no offenses in their windows, to
+    /// bound memory.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        self.bans.retain(|_, b| b.until > now);
+        let limits = self.limits;
+        self.hosts.retain(|_, h| {
+            h.recent.iter().zip(limits).any(|(recent, limit)| {
+                let window = limit.map_or(chrono::Duration::zero(), |(_, w)| w);
+                recent.back().is_some_and(|&t| t > now - window)
+            })
+        });
+    }
+}
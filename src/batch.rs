@@ -0,0 +1,247 @@
+//! Column-oriented storage for many log entries.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+
+use crate::{
+    warnings::{self, Warning},
+    LogEntry, LogEntryParseError,
+};
+
+/// A column of optional strings, stored back to back in a single buffer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StringColumn {
+    data: String,
+    /// End offset into `data` of each row. Missing values take up no space.
+    ends: Vec<usize>,
+    present: Vec<bool>,
+}
+
+impl StringColumn {
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn push(&mut self, s: Option<&str>) {
+        self.data.push_str(s.unwrap_or(""));
+        self.ends.push(self.data.len());
+        self.present.push(s.is_some());
+    }
+
+    /// The value at row `idx`, or `None` if it is missing or out of bounds.
+    pub fn get(&self, idx: usize) -> Option<&str> {
+        if !*self.present.get(idx)? {
+            return None;
+        }
+
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        Some(&self.data[start..self.ends[idx]])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<&str>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// An estimate of the heap memory taken up by the column, in bytes.
+    fn heap_size(&self) -> usize {
+        self.data.capacity()
+            + self.ends.capacity() * std::mem::size_of::<usize>()
+            + self.present.capacity()
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
+        self.present.clear();
+    }
+}
+
+/// Many [`LogEntry`]s, stored one column per field.
+///
+/// Parsing with [`LogBatch::push_line`] appends straight into the columns without building an
+/// intermediate `LogEntry`, and aggregations which only look at a few fields can scan just those
+/// columns.
+///
+/// # Example
+/// ```
+/// use common_log_format::batch::LogBatch;
+/// let mut batch = LogBatch::default();
+/// batch.push_line("127.0.0.1 - frank [1996-12-19T16:39:57-08:00] \"GET /a HTTP/1.0\" 200 2326").unwrap();
+/// batch.push_line("127.0.0.2 - - [1996-12-19T16:39:58-08:00] \"GET /b HTTP/1.0\" 404 -").unwrap();
+///
+/// assert_eq!(batch.len(), 2);
+/// assert_eq!(batch.object_sizes(), &[Some(2326), None]);
+/// assert_eq!(batch.authusers().iter().collect::<Vec<_>>(), vec![Some("frank"), None]);
+/// assert_eq!(batch.get(1).unwrap().request_line.as_deref(), Some("GET /b HTTP/1.0"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogBatch {
+    hosts: Vec<Option<IpAddr>>,
+    idents: StringColumn,
+    authusers: StringColumn,
+    times: Vec<Option<DateTime<Utc>>>,
+    request_lines: StringColumn,
+    status_codes: Vec<Option<StatusCode>>,
+    object_sizes: Vec<Option<usize>>,
+}
+
+impl LogBatch {
+    pub fn with_capacity(capacity: usize) -> Self {
+        LogBatch {
+            hosts: Vec::with_capacity(capacity),
+            times: Vec::with_capacity(capacity),
+            status_codes: Vec::with_capacity(capacity),
+            object_sizes: Vec::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Parse `line` and append it to the batch.
+    ///
+    /// On error, the batch is left unchanged.
+    pub fn push_line(&mut self, line: &str) -> Result<(), LogEntryParseError> {
+        let (host, remaining) = crate::peel_ip(line)?;
+        let (ident, remaining) = crate::peel_string(remaining)?;
+        let (authuser, remaining) = crate::peel_string(remaining)?;
+        let (time, remaining) = crate::peel_timestamp(remaining)?;
+        let (request_line, remaining) = crate::peel_quoted_string(remaining)?;
+        let (status_code, remaining) = crate::peel_status_code(remaining)?;
+        let (object_size, remaining) = crate::peel_usize(remaining)?;
+        if !remaining.is_empty() {
+            warnings::emit(Warning::TrailingData {
+                len: remaining.len(),
+            });
+        }
+
+        self.hosts.push(host);
+        self.idents.push(ident);
+        self.authusers.push(authuser);
+        self.times.push(time);
+        self.request_lines.push(request_line);
+        self.status_codes.push(status_code);
+        self.object_sizes.push(object_size);
+        Ok(())
+    }
+
+    pub fn push(&mut self, entry: &LogEntry) {
+        self.hosts.push(entry.host);
+        self.idents.push(entry.ident.as_deref());
+        self.authusers.push(entry.authuser.as_deref());
+        self.times.push(entry.time);
+        self.request_lines.push(entry.request_line.as_deref());
+        self.status_codes.push(entry.status_code);
+        self.object_sizes.push(entry.object_size);
+    }
+
+    /// Reassemble row `idx` into a [`LogEntry`].
+    pub fn get(&self, idx: usize) -> Option<LogEntry> {
+        Some(LogEntry {
+            host: *self.hosts.get(idx)?,
+            ident: self.idents.get(idx).map(str::to_owned),
+            authuser: self.authusers.get(idx).map(str::to_owned),
+            time: self.times[idx],
+            request_line: self.request_lines.get(idx).map(str::to_owned),
+            status_code: self.status_codes[idx],
+            object_size: self.object_sizes[idx],
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = LogEntry> + '_ {
+        (0..self.len()).map(|i| self.get(i).unwrap())
+    }
+
+    /// An estimate of the memory taken up by the batch, including space allocated for rows not
+    /// yet pushed, in bytes.
+    ///
+    /// Columns don't store an entry's fields as separate allocations, so a batch takes less than
+    /// the sum of [`LogEntry::approx_mem_size`] of its entries.
+    pub fn approx_mem_size(&self) -> usize {
+        fn vec<T>(v: &Vec<T>) -> usize {
+            v.capacity() * std::mem::size_of::<T>()
+        }
+        std::mem::size_of::<Self>()
+            + vec(&self.hosts)
+            + self.idents.heap_size()
+            + self.authusers.heap_size()
+            + vec(&self.times)
+            + self.request_lines.heap_size()
+            + vec(&self.status_codes)
+            + vec(&self.object_sizes)
+    }
+
+    pub fn clear(&mut self) {
+        self.hosts.clear();
+        self.idents.clear();
+        self.authusers.clear();
+        self.times.clear();
+        self.request_lines.clear();
+        self.status_codes.clear();
+        self.object_sizes.clear();
+    }
+
+    pub fn hosts(&self) -> &[Option<IpAddr>] {
+        &self.hosts
+    }
+
+    pub fn idents(&self) -> &StringColumn {
+        &self.idents
+    }
+
+    pub fn authusers(&self) -> &StringColumn {
+        &self.authusers
+    }
+
+    pub fn times(&self) -> &[Option<DateTime<Utc>>] {
+        &self.times
+    }
+
+    pub fn request_lines(&self) -> &StringColumn {
+        &self.request_lines
+    }
+
+    pub fn status_codes(&self) -> &[Option<StatusCode>] {
+        &self.status_codes
+    }
+
+    pub fn object_sizes(&self) -> &[Option<usize>] {
+        &self.object_sizes
+    }
+}
+
+impl<'a> Extend<&'a LogEntry> for LogBatch {
+    fn extend<T: IntoIterator<Item = &'a LogEntry>>(&mut self, iter: T) {
+        for e in iter {
+            self.push(e);
+        }
+    }
+}
+
+impl Extend<LogEntry> for LogBatch {
+    fn extend<T: IntoIterator<Item = LogEntry>>(&mut self, iter: T) {
+        for e in iter {
+            self.push(&e);
+        }
+    }
+}
+
+impl FromIterator<LogEntry> for LogBatch {
+    fn from_iter<T: IntoIterator<Item = LogEntry>>(iter: T) -> Self {
+        let mut b = LogBatch::default();
+        b.extend(iter);
+        b
+    }
+}
@@ -0,0 +1,597 @@
+//! `clf`: validate, convert, filter, summarize, and watch access logs from the command line.
+//!
+//! ```text
+//! clf parse [--format NAME] [--quiet] [FILE...]
+//! clf convert [--format NAME] --to NAME [--mask PRESET] [FILE...]
+//! clf filter EXPR [--format NAME] [--to NAME] [--mask PRESET] [FILE...]
+//! clf stats [--format NAME] [--top N] [--bucket SECS] [--json | --html] [--mask PRESET] [FILE...]
+//! clf watch [--format NAME] [--top N] [--window SECS] [--filter EXPR] FILE
+//! clf sql QUERY FILE...
+//! ```
+//!
+//! Input is read from the files in turn, or standard input if there are none, and output written
+//! to standard output, so `clf` fits in a pipeline:
+//!
+//! ```text
+//! zcat access.log.*.gz | clf filter 'status >= 500' | clf convert --to jsonl > errors.jsonl
+//! ```
+
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    process::ExitCode,
+    str::FromStr,
+    time::Duration,
+};
+
+#[cfg(feature = "sql")]
+mod sql;
+mod stats;
+mod watch;
+
+use common_log_format::{
+    anonymize::Pseudonymizer,
+    app::{Args, ParserConfig, UsageError},
+    canonical::CanonicalEntry,
+    combined::CombinedLogEntry,
+    filter::Filter,
+    format::Format,
+    privacy::{Masker, Preset},
+    reader,
+    stats::Stats,
+    LogEntry, ParseOptions,
+};
+
+const USAGE: &str = "usage:
+  clf parse [OPTIONS] [FILE...]           report the lines which fail to parse
+  clf convert --to NAME [OPTIONS] [FILE...]
+                                          write entries in another format
+  clf filter EXPR [OPTIONS] [FILE...]     keep the entries matching EXPR
+  clf stats [OPTIONS] [FILE...]           summarize the entries
+  clf watch [OPTIONS] FILE                follow FILE as it grows, showing live traffic
+  clf sql QUERY FILE...                   query the files, as the table `log`, with SQL
+                                          (with the `sql` feature)
+
+options:
+  --format NAME        input format: clf, proxied-clf, forwarded-clf, combined, jsonl, or csv
+                       (default clf)
+  --size-separators    accept thousands separators in sizes
+  --quiet              parse: only report the number of invalid lines
+  --to NAME            convert, filter: output format: clf, combined, jsonl, or csv (filter
+                       writes the input lines unchanged by default)
+  --mask PRESET        convert, filter, stats: mask identifying fields with a privacy preset:
+                       gdpr-strict, debug-share, or internal (filter then writes entries in the
+                       input format, if --to isn't given)
+  --pseudonym-key FILE with --mask: replace the addresses the preset truncates with keyed-hash
+                       pseudonyms, keyed with the contents of FILE
+  --top N              stats, watch: the number of hosts and paths to list (default 10)
+  --bucket SECS        stats: the width of the requests-over-time buckets (default 3600)
+  --json               stats: write the summary as JSON
+  --html               stats: write the summary as a self-contained HTML page
+  --window SECS        watch: the period rates are measured over (default 60)
+  --filter EXPR        watch: count only the entries matching EXPR
+  --from-start         watch: count what is already in the file first
+
+Files are read in turn, decompressing them if needed, or standard input if there are none.
+While watching, type a number of minutes and enter to count again from that long ago; an index
+of FILE saved as FILE.idx is used to find the time.";
+
+/// The columns of CSV input and output, in order.
+const CSV_COLUMNS: [&str; 9] = [
+    "host",
+    "ident",
+    "authuser",
+    "time",
+    "request_line",
+    "status_code",
+    "object_size",
+    "referer",
+    "user_agent",
+];
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(code) => code,
+        // The reader went away, as `head` does once it has read enough.
+        Err(e)
+            if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                == Some(io::ErrorKind::BrokenPipe) =>
+        {
+            ExitCode::SUCCESS
+        }
+        Err(e) if e.is::<UsageError>() => {
+            eprintln!("clf: {}\nrun `clf --help` for usage", e);
+            ExitCode::from(2)
+        }
+        Err(e) => {
+            eprintln!("clf: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: impl Iterator<Item = String>) -> Result<ExitCode, Box<dyn Error>> {
+    let mut args = args.peekable();
+    let command = match args.next_if(|a| !a.starts_with('-')) {
+        Some(command) => command,
+        None if args.peek().is_some_and(|a| a == "--help") => "--help".to_owned(),
+        None => return Err(UsageError("missing command".to_owned()).into()),
+    };
+    let mut args = Args::new(args);
+    if command == "--help" || args.flag("--help") {
+        writeln!(io::stdout(), "{}", USAGE)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let input = Input {
+        format: args
+            .value("--format")?
+            .unwrap_or(InputFormat::Text(Format::Clf)),
+        options: ParseOptions {
+            size_thousands_separators: args.flag("--size-separators"),
+        },
+        mask: masker(&mut args)?,
+    };
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match command.as_str() {
+        "parse" => {
+            let quiet = args.flag("--quiet");
+            let paths = args.finish()?;
+            validate(&input, &paths, quiet, &mut out)
+        }
+        "convert" => {
+            let to = args
+                .value("--to")?
+                .ok_or_else(|| UsageError("convert needs --to".to_owned()))?;
+            let paths = args.finish()?;
+            transform(&input, &paths, None, Some(to), &mut out)
+        }
+        "filter" => {
+            let to = match args.value("--to")? {
+                None if input.mask.is_some() => Some(input.format.output().ok_or_else(|| {
+                    UsageError("filter --mask needs --to for this input format".to_owned())
+                })?),
+                to => to,
+            };
+            let mut paths = args.finish()?;
+            if paths.is_empty() {
+                return Err(UsageError("filter needs an expression".to_owned()).into());
+            }
+            let expr = paths.remove(0);
+            let filter: Filter = expr
+                .parse()
+                .map_err(|e| UsageError(format!("invalid filter {:?}: {}", expr, e)))?;
+            transform(&input, &paths, Some(&filter), to, &mut out)
+        }
+        "stats" => {
+            let top = args.value("--top")?.unwrap_or(10);
+            let bucket: i64 = args.value("--bucket")?.unwrap_or(3600);
+            if bucket <= 0 {
+                return Err(UsageError("--bucket must be positive".to_owned()).into());
+            }
+            let style = stats::Style::from_args(&mut args)?;
+            let paths = args.finish()?;
+            let mut stats = Stats::new()
+                .with_top(top, top.max(1000))
+                .with_bucket_width(chrono::Duration::seconds(bucket));
+            let skipped = read_entries(&input, &paths, |_, mut e| {
+                input.mask(&mut e);
+                stats.observe(&e.entry);
+                Ok(())
+            })?;
+            style.write(&mut out, &stats.summary())?;
+            out.flush()?;
+            report_skipped(skipped);
+            Ok(ExitCode::SUCCESS)
+        }
+        "watch" => {
+            let format = match input.format {
+                InputFormat::Text(format) => format,
+                _ => return Err(UsageError("watch reads log formats only".to_owned()).into()),
+            };
+            let window: u64 = args.value("--window")?.unwrap_or(60);
+            if window == 0 {
+                return Err(UsageError("--window must be positive".to_owned()).into());
+            }
+            let top = args.value("--top")?.unwrap_or(10);
+            let config = ParserConfig {
+                format,
+                options: input.options,
+                filter: args.value("--filter")?,
+                follow: true,
+                from_start: args.flag("--from-start"),
+            };
+            let path = match args.finish()?.as_slice() {
+                [path] if path != "-" => path.clone(),
+                _ => return Err(UsageError("watch needs exactly one file".to_owned()).into()),
+            };
+            watch::Dashboard::new(&path, Duration::from_secs(window), top).run(config)
+        }
+        #[cfg(feature = "sql")]
+        "sql" => {
+            if input.format != InputFormat::Text(Format::Clf)
+                || input.options != ParseOptions::default()
+            {
+                return Err(UsageError("sql reads Common Log Format only".to_owned()).into());
+            }
+            let mut paths = args.finish()?;
+            if paths.len() < 2 {
+                return Err(UsageError("sql needs a query and files to query".to_owned()).into());
+            }
+            let query = paths.remove(0);
+            sql::run(&query, &paths, &mut out)
+        }
+        _ => Err(UsageError(format!("unknown command {}", command)).into()),
+    }
+}
+
+/// Report every line which fails to parse, as `FILE:LINE: error`.
+fn validate(
+    input: &Input,
+    paths: &[String],
+    quiet: bool,
+    out: &mut impl Write,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let (mut lines, mut invalid) = (0u64, 0u64);
+    for_each_line(input, paths, |path, n, parsed| {
+        lines += 1;
+        if let Err(e) = parsed {
+            invalid += 1;
+            if !quiet {
+                writeln!(out, "{}:{}: {}", path, n, e)?;
+            }
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    eprintln!("{} lines, {} invalid", lines, invalid);
+    Ok(match invalid {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    })
+}
+
+/// Write the entries matching `filter` (every entry, without one) in the `to` format, or as the
+/// lines they were read from. Lines which fail to parse are skipped, and counted on standard
+/// error.
+fn transform(
+    input: &Input,
+    paths: &[String],
+    filter: Option<&Filter>,
+    to: Option<OutputFormat>,
+    out: &mut impl Write,
+) -> Result<ExitCode, Box<dyn Error>> {
+    if to == Some(OutputFormat::Csv) {
+        writeln!(out, "{}", CSV_COLUMNS.join(","))?;
+    }
+    let skipped = read_entries(input, paths, |line, mut entry| {
+        if filter.is_some_and(|f| !f.matches(&entry.entry)) {
+            return Ok(());
+        }
+        input.mask(&mut entry);
+        match to {
+            Some(to) => to.write(out, entry),
+            None => {
+                out.write_all(line)?;
+                writeln!(out)
+            }
+        }
+    })?;
+    out.flush()?;
+    report_skipped(skipped);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Call `f` with every line which parses and its entry, returning the number which didn't.
+fn read_entries(
+    input: &Input,
+    paths: &[String],
+    mut f: impl FnMut(&[u8], CanonicalEntry) -> io::Result<()>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut skipped = 0;
+    for_each_line(input, paths, |_, _, parsed| match parsed {
+        Ok((line, entry)) => f(line, entry),
+        Err(_) => {
+            skipped += 1;
+            Ok(())
+        }
+    })?;
+    Ok(skipped)
+}
+
+fn report_skipped(skipped: u64) {
+    if skipped > 0 {
+        eprintln!("clf: skipped {} invalid lines", skipped);
+    }
+}
+
+type Parsed<'a> = Result<(&'a [u8], CanonicalEntry), LineError>;
+
+/// Call `f` with the path, line number, and parse of every non-blank line of the inputs. For CSV,
+/// the header line of each file isn't passed on.
+fn for_each_line(
+    input: &Input,
+    paths: &[String],
+    mut f: impl FnMut(&str, u64, Parsed) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    let stdin = ["-".to_owned()];
+    let paths = if paths.is_empty() { &stdin[..] } else { paths };
+    let mut buf = vec![];
+    for path in paths {
+        let mut reader: Box<dyn BufRead> = match path.as_str() {
+            "-" => Box::new(BufReader::new(io::stdin().lock())),
+            p => reader::open(p).map_err(|e| format!("{}: {}", p, e))?,
+        };
+        let mut header: Option<Vec<String>> = None;
+        let mut n = 0;
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            n += 1;
+            let end = buf.iter().rposition(|b| !matches!(b, b'\n' | b'\r'));
+            let line = &buf[..end.map_or(0, |i| i + 1)];
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            if input.format == InputFormat::Csv && header.is_none() {
+                let fields = split_csv(&String::from_utf8_lossy(line));
+                header = Some(fields.map_err(|e| format!("{}:{}: {}", path, n, e))?);
+                continue;
+            }
+            let parsed = input.parse(line, header.as_deref());
+            f(path, n, parsed.map(|e| (line, e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// A line which failed to parse, and why.
+#[derive(Debug)]
+struct LineError(String);
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for LineError {}
+
+/// Describe `e` and its source, since the parse errors' own messages are generic.
+fn describe(e: &dyn Error) -> LineError {
+    match e.source() {
+        Some(source) => LineError(format!("{}: {}", e, source)),
+        None => LineError(e.to_string()),
+    }
+}
+
+/// The masker for `--mask` and `--pseudonym-key`, if masking was asked for.
+fn masker(args: &mut Args) -> Result<Option<Masker>, Box<dyn Error>> {
+    let preset: Option<Preset> = args.value("--mask")?;
+    let key: Option<String> = args.value("--pseudonym-key")?;
+    let masker = match (preset, key) {
+        (None, None) => return Ok(None),
+        (None, Some(_)) => {
+            return Err(UsageError("--pseudonym-key needs --mask".to_owned()).into());
+        }
+        (Some(preset), None) => Masker::new(preset),
+        (Some(preset), Some(path)) => {
+            let key = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+            if key.trim_ascii().is_empty() {
+                return Err(format!("{}: empty pseudonym key", path).into());
+            }
+            Masker::new(preset).with_pseudonymizer(Pseudonymizer::new(key.trim_ascii()))
+        }
+    };
+    Ok(Some(masker))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Text(Format),
+    Jsonl,
+    Csv,
+}
+
+impl InputFormat {
+    /// The output format which writes entries as this format, if there is one.
+    fn output(self) -> Option<OutputFormat> {
+        match self {
+            InputFormat::Text(Format::Clf) => Some(OutputFormat::Clf),
+            InputFormat::Text(Format::Combined) => Some(OutputFormat::Combined),
+            InputFormat::Text(_) => None,
+            InputFormat::Jsonl => Some(OutputFormat::Jsonl),
+            InputFormat::Csv => Some(OutputFormat::Csv),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(InputFormat::Jsonl),
+            "csv" => Ok(InputFormat::Csv),
+            s => s.parse().map(InputFormat::Text).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+struct Input {
+    format: InputFormat,
+    options: ParseOptions,
+    mask: Option<Masker>,
+}
+
+impl Input {
+    /// Mask `entry` as `--mask` asked, if it did.
+    fn mask(&self, entry: &mut CanonicalEntry) {
+        if let Some(masker) = &self.mask {
+            masker.apply_canonical(entry);
+        }
+    }
+
+    /// Parse a line, given the CSV header of its file.
+    ///
+    /// Lines needn't be valid UTF-8: logs often have Latin-1 in request targets. Invalid
+    /// sequences are replaced with `U+FFFD`, as [`LogEntry::from_bytes`] does.
+    fn parse(&self, line: &[u8], header: Option<&[String]>) -> Result<CanonicalEntry, LineError> {
+        if let InputFormat::Text(Format::Clf) = self.format {
+            return LogEntry::from_bytes_with(line, &self.options)
+                .map(Into::into)
+                .map_err(|e| describe(&e));
+        }
+        let line = String::from_utf8_lossy(line);
+        match self.format {
+            InputFormat::Text(Format::Combined) => {
+                CombinedLogEntry::parse_with(&line, &self.options)
+                    .map(Into::into)
+                    .map_err(|e| describe(&e))
+            }
+            InputFormat::Text(format) => format.parse(&line).map_err(|e| describe(&e)),
+            InputFormat::Jsonl => serde_json::from_str(&line).map_err(|e| describe(&e)),
+            InputFormat::Csv => from_csv(header.unwrap_or_default(), &line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Clf,
+    Combined,
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clf" => Ok(OutputFormat::Clf),
+            "combined" => Ok(OutputFormat::Combined),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "csv" => Ok(OutputFormat::Csv),
+            s => Err(format!("unknown output format {:?}", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    fn write(&self, out: &mut impl Write, e: CanonicalEntry) -> io::Result<()> {
+        match self {
+            OutputFormat::Clf => writeln!(out, "{}", e.entry),
+            OutputFormat::Combined => writeln!(out, "{}", CombinedLogEntry::from(e)),
+            OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut *out, &e)?;
+                writeln!(out)
+            }
+            OutputFormat::Csv => writeln!(out, "{}", to_csv(&e)),
+        }
+    }
+}
+
+/// `e` as a CSV record of [`CSV_COLUMNS`], with missing fields empty.
+fn to_csv(e: &CanonicalEntry) -> String {
+    let fields = [
+        e.entry.host.map(|h| h.to_string()),
+        e.entry.ident.clone(),
+        e.entry.authuser.clone(),
+        e.entry
+            .time
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)),
+        e.entry.request_line.clone(),
+        e.entry.status_code.map(|s| s.as_u16().to_string()),
+        e.entry.object_size.map(|s| s.to_string()),
+        e.referer.clone(),
+        e.user_agent.clone(),
+    ];
+    let quoted: Vec<String> = fields
+        .into_iter()
+        .map(|f| {
+            let f = f.unwrap_or_default();
+            match f.contains([',', '"', '\n', '\r']) {
+                true => format!("\"{}\"", f.replace('"', "\"\"")),
+                false => f,
+            }
+        })
+        .collect();
+    quoted.join(",")
+}
+
+/// The entry in a CSV record, whose columns are named by `header`. Unknown columns are ignored,
+/// and missing or empty ones are missing fields.
+fn from_csv(header: &[String], line: &str) -> Result<CanonicalEntry, LineError> {
+    let values = split_csv(line)?;
+    let get = |name: &str| {
+        header
+            .iter()
+            .position(|h| h == name)
+            .and_then(|i| values.get(i))
+            .filter(|v| !v.is_empty())
+    };
+    let entry = LogEntry {
+        host: parse_column(get("host"), "host")?,
+        ident: get("ident").cloned(),
+        authuser: get("authuser").cloned(),
+        time: parse_column(get("time"), "time")?,
+        request_line: get("request_line").cloned(),
+        status_code: parse_column(get("status_code"), "status_code")?,
+        object_size: parse_column(get("object_size"), "object_size")?,
+    };
+    Ok(CanonicalEntry {
+        referer: get("referer").cloned(),
+        user_agent: get("user_agent").cloned(),
+        ..entry.into()
+    })
+}
+
+fn parse_column<T>(value: Option<&String>, name: &str) -> Result<Option<T>, LineError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .map(|v| v.parse())
+        .transpose()
+        .map_err(|e| LineError(format!("invalid {}: {}", name, e)))
+}
+
+/// Split a CSV record into its fields, unquoting quoted ones. Records can't span lines.
+fn split_csv(line: &str) -> Result<Vec<String>, LineError> {
+    let mut fields = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(LineError("unterminated quoted field".to_owned())),
+                }
+            }
+            if !matches!(chars.peek(), Some(',') | None) {
+                return Err(LineError("text after a quoted field".to_owned()));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
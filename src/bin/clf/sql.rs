@@ -0,0 +1,36 @@
+//! `clf sql`: querying log files with SQL, through the `datafusion` table.
+
+use std::{error::Error, io::Write, path::PathBuf, process::ExitCode, sync::Arc};
+
+use common_log_format::datafusion::ClfTable;
+use datafusion::{arrow::util::pretty::pretty_format_batches, prelude::SessionContext};
+
+/// The name of the table the files are registered as.
+pub const TABLE: &str = "log";
+
+/// Run `query` over the files at `paths`, expanding directories into the files in them, and
+/// write the result as a table.
+pub fn run(
+    query: &str,
+    paths: &[String],
+    out: &mut impl Write,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = vec![];
+    for path in paths {
+        match std::fs::metadata(path)?.is_dir() {
+            true => files.extend_from_slice(ClfTable::open_dir(path)?.paths()),
+            false => files.push(path.into()),
+        }
+    }
+    let table = ClfTable::new(files).with_skip_invalid(true);
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let batches = runtime.block_on(async {
+        let ctx = SessionContext::new();
+        ctx.register_table(TABLE, Arc::new(table))?;
+        ctx.sql(query).await?.collect().await
+    })?;
+    writeln!(out, "{}", pretty_format_batches(&batches)?)?;
+    out.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
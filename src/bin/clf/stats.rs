@@ -0,0 +1,212 @@
+//! `clf stats`: a summary of the input as a terminal table, JSON, or an HTML page.
+
+use std::io::{self, Write};
+
+use common_log_format::{
+    app::{Args, UsageError},
+    stats::Summary,
+};
+
+/// How to write the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Table,
+    Json,
+    Html,
+}
+
+impl Style {
+    pub fn from_args(args: &mut Args) -> Result<Self, UsageError> {
+        match (args.flag("--json"), args.flag("--html")) {
+            (false, false) => Ok(Style::Table),
+            (true, false) => Ok(Style::Json),
+            (false, true) => Ok(Style::Html),
+            (true, true) => Err(UsageError("--json and --html can't be combined".to_owned())),
+        }
+    }
+
+    pub fn write(&self, out: &mut impl Write, summary: &Summary) -> io::Result<()> {
+        match self {
+            Style::Table => write_table(out, summary),
+            Style::Json => {
+                serde_json::to_writer_pretty(&mut *out, summary)?;
+                writeln!(out)
+            }
+            Style::Html => write_html(out, summary),
+        }
+    }
+}
+
+/// The width of the longest bar in the terminal table.
+const BAR_WIDTH: u64 = 40;
+
+fn write_table(out: &mut impl Write, s: &Summary) -> io::Result<()> {
+    let time = |t: Option<chrono::DateTime<chrono::Utc>>| {
+        t.map_or("-".to_owned(), |t| {
+            t.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+        })
+    };
+    writeln!(out, "requests  {}", s.entries)?;
+    writeln!(out, "bytes     {} ({})", human_bytes(s.bytes), s.bytes)?;
+    writeln!(out, "first     {}", time(s.first))?;
+    writeln!(out, "last      {}", time(s.last))?;
+
+    writeln!(out, "\nstatus    requests   share")?;
+    for (status, n) in &s.statuses {
+        writeln!(
+            out,
+            "{:<9} {:>8}  {:>5.1}%",
+            status,
+            n,
+            percent(*n, s.entries)
+        )?;
+    }
+
+    writeln!(out, "\ntop hosts")?;
+    for (host, n) in &s.top_hosts {
+        writeln!(out, "{:>10}  {}", n, host)?;
+    }
+    writeln!(out, "\ntop paths")?;
+    for (path, n) in &s.top_paths {
+        writeln!(out, "{:>10}  {}", n, path)?;
+    }
+
+    writeln!(out, "\nrequests over time")?;
+    let max = s.requests_per_bucket.values().copied().max().unwrap_or(1);
+    for (start, n) in &s.requests_per_bucket {
+        let bar = "#".repeat((n * BAR_WIDTH).div_ceil(max) as usize);
+        writeln!(
+            out,
+            "{}  {:>10}  {}",
+            start.format("%Y-%m-%d %H:%M"),
+            n,
+            bar
+        )?;
+    }
+    Ok(())
+}
+
+fn write_html(out: &mut impl Write, s: &Summary) -> io::Result<()> {
+    let range = match (s.first, s.last) {
+        (Some(first), Some(last)) => format!(
+            "{} to {}",
+            first.format("%Y-%m-%d %H:%M:%S"),
+            last.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        _ => "no timestamps".to_owned(),
+    };
+    write!(
+        out,
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Access log report</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 2em auto; max-width: 60em; color: #222; }}
+h1 {{ font-size: 1.5em; }}
+h2 {{ font-size: 1.1em; margin-top: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td, th {{ padding: 0.2em 0.6em; text-align: left; border-bottom: 1px solid #eee; }}
+td.n {{ text-align: right; font-variant-numeric: tabular-nums; width: 8em; }}
+td.path {{ word-break: break-all; }}
+.bar {{ background: #4a7fb5; height: 0.8em; }}
+.totals td {{ font-size: 1.2em; border: none; }}
+svg rect {{ fill: #4a7fb5; }}
+</style>
+</head>
+<body>
+<h1>Access log report</h1>
+<p>{}</p>
+<table class="totals">
+<tr><td>Requests</td><td class="n">{}</td></tr>
+<tr><td>Bytes</td><td class="n">{}</td></tr>
+</table>
+"#,
+        range,
+        s.entries,
+        human_bytes(s.bytes)
+    )?;
+
+    writeln!(out, "<h2>Requests over time</h2>")?;
+    write_chart(out, s)?;
+
+    writeln!(out, "<h2>Status codes</h2>\n<table>")?;
+    for (status, n) in &s.statuses {
+        let share = percent(*n, s.entries);
+        writeln!(
+            out,
+            r#"<tr><td>{}</td><td class="n">{}</td><td class="n">{:.1}%</td><td><div class="bar" style="width: {:.1}%"></div></td></tr>"#,
+            status, n, share, share
+        )?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<h2>Top hosts</h2>\n<table>")?;
+    for (host, n) in &s.top_hosts {
+        writeln!(out, r#"<tr><td class="n">{}</td><td>{}</td></tr>"#, n, host)?;
+    }
+    writeln!(out, "</table>")?;
+    writeln!(out, "<h2>Top paths</h2>\n<table>")?;
+    for (path, n) in &s.top_paths {
+        writeln!(
+            out,
+            r#"<tr><td class="n">{}</td><td class="path">{}</td></tr>"#,
+            n,
+            escape(path)
+        )?;
+    }
+    writeln!(out, "</table>\n</body>\n</html>")
+}
+
+/// An SVG bar chart of the requests in each bucket, with the bucket and count as a tooltip.
+fn write_chart(out: &mut impl Write, s: &Summary) -> io::Result<()> {
+    let (width, height) = (800., 160.);
+    let buckets = s.requests_per_bucket.len().max(1) as f64;
+    let max = s.requests_per_bucket.values().copied().max().unwrap_or(1) as f64;
+    writeln!(
+        out,
+        r#"<svg viewBox="0 0 {} {}" width="100%" preserveAspectRatio="none">"#,
+        width, height
+    )?;
+    for (i, (start, n)) in s.requests_per_bucket.iter().enumerate() {
+        let h = *n as f64 / max * height;
+        writeln!(
+            out,
+            r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}"><title>{}: {}</title></rect>"#,
+            i as f64 * width / buckets,
+            height - h,
+            (width / buckets * 0.9).max(0.5),
+            h,
+            start.format("%Y-%m-%d %H:%M"),
+            n
+        )?;
+    }
+    writeln!(out, "</svg>")
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    n as f64 * 100. / total.max(1) as f64
+}
+
+/// `bytes` in the largest unit it's at least one of.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000. && unit < UNITS.len() - 1 {
+        value /= 1000.;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
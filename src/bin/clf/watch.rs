@@ -0,0 +1,230 @@
+//! `clf watch`: a live terminal view of the traffic in a growing log.
+//!
+//! Typing a number of minutes and enter rewinds: the counts start again from that long ago,
+//! replaying the entries since then through the filter, and then following carries on.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, BufRead, Write},
+    process::ExitCode,
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use common_log_format::{
+    app::ParserConfig,
+    stats::{HyperLogLog, TopK},
+    LogEntry,
+};
+
+/// How often the screen is redrawn, whether or not entries arrive.
+const REDRAW: Duration = Duration::from_secs(1);
+
+/// How long to wait before checking for new entries again, once there are none.
+const POLL: Duration = Duration::from_millis(250);
+
+/// What the reading thread sends to the screen.
+enum Event {
+    /// An entry, and the number of lines skipped so far.
+    Entry(LogEntry, u64),
+    /// The file was rewound to this time, and the entries since it are about to be replayed.
+    Rewound(DateTime<Utc>),
+}
+
+/// The requests logged in the last `window`: when, how large, and whether they failed.
+///
+/// Requests are timed by their entries rather than by when they were read, so that replayed
+/// entries fall outside the window.
+struct Recent {
+    window: chrono::Duration,
+    arrivals: VecDeque<(DateTime<Utc>, u64, Option<u16>)>,
+}
+
+impl Recent {
+    fn push(&mut self, at: DateTime<Utc>, bytes: u64, status: Option<u16>) {
+        self.arrivals.push_back((at, bytes, status));
+    }
+
+    fn expire(&mut self, now: DateTime<Utc>) {
+        while let Some(&(t, _, _)) = self.arrivals.front() {
+            if now - t <= self.window {
+                break;
+            }
+            self.arrivals.pop_front();
+        }
+    }
+
+    fn per_second(&self, n: f64) -> f64 {
+        n / self.window.as_seconds_f64()
+    }
+
+    /// The fraction of requests whose status is in `range`.
+    fn share(&self, range: std::ops::Range<u16>) -> f64 {
+        let n = self
+            .arrivals
+            .iter()
+            .filter(|(_, _, s)| s.is_some_and(|s| range.contains(&s)))
+            .count();
+        n as f64 / self.arrivals.len().max(1) as f64
+    }
+}
+
+pub struct Dashboard {
+    path: String,
+    recent: Recent,
+    paths: TopK<String>,
+    clients: HyperLogLog,
+    total: u64,
+    skipped: u64,
+    top_n: usize,
+    /// The time counting started from, if rewound.
+    since: Option<DateTime<Utc>>,
+}
+
+impl Dashboard {
+    pub fn new(path: &str, window: Duration, top_n: usize) -> Self {
+        Dashboard {
+            path: path.to_owned(),
+            recent: Recent {
+                window: chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX),
+                arrivals: VecDeque::new(),
+            },
+            paths: TopK::new(top_n.max(1) * 10),
+            clients: HyperLogLog::new(14),
+            total: 0,
+            skipped: 0,
+            top_n,
+            since: None,
+        }
+    }
+
+    /// Start counting again from `since`.
+    fn rewound(&mut self, since: DateTime<Utc>) {
+        self.recent.arrivals.clear();
+        self.paths = TopK::new(self.top_n.max(1) * 10);
+        self.clients = HyperLogLog::new(14);
+        self.total = 0;
+        self.since = Some(since);
+    }
+
+    fn observe(&mut self, entry: &LogEntry) {
+        self.total += 1;
+        self.recent.push(
+            entry.time.unwrap_or_else(Utc::now),
+            entry.object_size.unwrap_or(0) as u64,
+            entry.status_code.map(|s| s.as_u16()),
+        );
+        if let Some(path) = entry.path() {
+            self.paths.observe(path.to_owned());
+        }
+        if let Some(host) = entry.host {
+            self.clients.insert(&host);
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.recent.expire(Utc::now());
+        let recent = &self.recent;
+        let bytes: u64 = recent.arrivals.iter().map(|(_, b, _)| b).sum();
+        // Clear the screen and move to the top left.
+        write!(out, "\x1b[2J\x1b[H")?;
+        writeln!(
+            out,
+            "{}  (ctrl-c to quit, minutes and enter to rewind)\n",
+            self.path
+        )?;
+        writeln!(out, "last {}s", recent.window.num_seconds())?;
+        writeln!(
+            out,
+            "  requests      {:.1}/s",
+            recent.per_second(recent.arrivals.len() as f64)
+        )?;
+        writeln!(
+            out,
+            "  bytes         {:.0}/s",
+            recent.per_second(bytes as f64)
+        )?;
+        writeln!(out, "  4xx           {:.1}%", recent.share(400..500) * 100.)?;
+        writeln!(out, "  5xx           {:.1}%", recent.share(500..600) * 100.)?;
+        match self.since {
+            Some(t) => writeln!(out, "\nsince {}", t.format("%H:%M:%S UTC"))?,
+            None => writeln!(out, "\nsince starting")?,
+        }
+        writeln!(out, "  requests      {}", self.total)?;
+        writeln!(out, "  hosts         ~{}", self.clients.estimate())?;
+        writeln!(out, "  skipped lines {}", self.skipped)?;
+        writeln!(out, "\ntop paths")?;
+        for (path, n) in self.paths.top(self.top_n) {
+            writeln!(out, "  {:>8}  {}", n, path)?;
+        }
+        out.flush()
+    }
+
+    /// Follow the file at `self.path`, redrawing every second until reading it fails, and
+    /// rewinding by the minutes typed on standard input.
+    pub fn run(mut self, config: ParserConfig) -> Result<ExitCode, Box<dyn Error>> {
+        let mut entries = config.open(std::slice::from_ref(&self.path))?;
+        let (rewind_tx, rewind_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let minutes = match line.map(|l| l.trim().parse::<u32>()) {
+                    Ok(Ok(m)) => m,
+                    Ok(Err(_)) => continue,
+                    Err(_) => return,
+                };
+                if rewind_tx
+                    .send(chrono::Duration::minutes(minutes.into()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let event = match rewind_rx.try_recv() {
+                Ok(by) => {
+                    let target = Utc::now() - by;
+                    entries.seek_to_time(target).map(|_| Event::Rewound(target))
+                }
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => match entries.try_next() {
+                    Some(entry) => entry.map(|e| Event::Entry(e.entry, entries.skipped())),
+                    None => {
+                        std::thread::sleep(POLL);
+                        continue;
+                    }
+                },
+            };
+            let failed = event.is_err();
+            if tx.send(event).is_err() || failed {
+                return;
+            }
+        });
+
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut drawn = Instant::now();
+        self.draw(&mut out)?;
+        loop {
+            match rx.recv_timeout((drawn + REDRAW).saturating_duration_since(Instant::now())) {
+                Ok(event) => match event? {
+                    Event::Entry(entry, skipped) => {
+                        self.skipped = skipped;
+                        self.observe(&entry);
+                    }
+                    Event::Rewound(since) => self.rewound(since),
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return Ok(ExitCode::SUCCESS),
+            }
+            let now = Instant::now();
+            if now >= drawn + REDRAW {
+                self.draw(&mut out)?;
+                drawn = now;
+            }
+        }
+    }
+}
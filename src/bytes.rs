@@ -0,0 +1,185 @@
+//! Parsing directly from bytes.
+//!
+//! These mirror the `peel_*` functions at the crate root, but take and return byte slices, so
+//! that a line does not have to be valid UTF-8 as a whole. Only the fields which are converted
+//! to typed values (addresses, timestamps, numbers) are checked; string fields are returned as
+//! raw bytes.
+
+use std::{net::IpAddr, str};
+
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+
+use crate::LogEntryParseError;
+
+/// Split `line` at its first space, and skip any further spaces before the remainder.
+fn split_field(line: &[u8]) -> (&[u8], &[u8]) {
+    match memchr::memchr(b' ', line) {
+        Some(idx) => (&line[..idx], skip_spaces(&line[idx + 1..])),
+        None => (line, &line[line.len()..]),
+    }
+}
+
+/// Skip leading field separators.
+///
+/// Fields are almost always separated by exactly one space, so checking byte by byte beats a
+/// general-purpose `trim_start`.
+fn skip_spaces(line: &[u8]) -> &[u8] {
+    let n = line
+        .iter()
+        .take_while(|b| matches!(b, b' ' | b'\t'))
+        .count();
+    &line[n..]
+}
+
+fn utf8(field: &[u8]) -> Result<&str, LogEntryParseError> {
+    str::from_utf8(field).map_err(LogEntryParseError::Utf8)
+}
+
+/// Take an [`IpAddr`] from the start of `line`.
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_ip(line: &[u8]) -> Result<(Option<IpAddr>, &[u8]), LogEntryParseError> {
+    let (field, rem) = split_field(line);
+    match line.first() {
+        None => return Err(LogEntryParseError::FieldNotFound),
+        Some(b'-') => return Ok((None, rem)),
+        Some(_) => (),
+    }
+    let ip_addr = utf8(field)?
+        .parse()
+        .map_err(LogEntryParseError::IpAddrParse)?;
+    Ok((Some(ip_addr), rem))
+}
+
+/// Take a [`usize`] from the start of `line` until the first whitespace.
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_usize(line: &[u8]) -> Result<(Option<usize>, &[u8]), LogEntryParseError> {
+    let (field, rem) = split_field(line);
+    match line.first() {
+        None => return Err(LogEntryParseError::FieldNotFound),
+        Some(b'-') => return Ok((None, rem)),
+        Some(_) => (),
+    }
+    Ok((
+        Some(
+            utf8(field)?
+                .parse()
+                .map_err(LogEntryParseError::SizeParse)?,
+        ),
+        rem,
+    ))
+}
+
+/// Like [`peel_usize`], but also accept thousands separators, as in `1,234` or `1.234.567`.
+///
+/// Any one of `,`, `.`, `'`, or `_` may be used as the separator, as long as it is used
+/// consistently and every group after the first has exactly three digits.
+pub fn peel_grouped_usize(line: &[u8]) -> Result<(Option<usize>, &[u8]), LogEntryParseError> {
+    let (field, rem) = split_field(line);
+    match line.first() {
+        None => return Err(LogEntryParseError::FieldNotFound),
+        Some(b'-') => return Ok((None, rem)),
+        Some(_) => (),
+    }
+    let digits = strip_thousands_separators(field);
+    let field = digits.as_deref().unwrap_or(field);
+    Ok((
+        Some(
+            utf8(field)?
+                .parse()
+                .map_err(LogEntryParseError::SizeParse)?,
+        ),
+        rem,
+    ))
+}
+
+/// The digits of `field` without thousands separators, or `None` if `field` is not validly
+/// grouped.
+fn strip_thousands_separators(field: &[u8]) -> Option<Vec<u8>> {
+    let sep = *field
+        .iter()
+        .find(|b| matches!(b, b',' | b'.' | b'\'' | b'_'))?;
+    let mut groups = field.split(|b| *b == sep);
+    let first = groups.next()?;
+    if !(1..=3).contains(&first.len()) {
+        return None;
+    }
+
+    let mut digits = first.to_vec();
+    for g in groups {
+        if g.len() != 3 {
+            return None;
+        }
+        digits.extend_from_slice(g);
+    }
+
+    Some(digits)
+}
+
+/// Take the bytes from the start of `line` until the first whitespace.
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_string(line: &[u8]) -> Result<(Option<&[u8]>, &[u8]), LogEntryParseError> {
+    let (field, rem) = split_field(line);
+    match line.first() {
+        None => return Err(LogEntryParseError::FieldNotFound),
+        Some(b'-') => return Ok((None, rem)),
+        Some(_) => (),
+    }
+    Ok((Some(field), rem))
+}
+
+/// Take the bytes from the start of `line` delimited by quotation marks (`"`).
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_quoted_string(line: &[u8]) -> Result<(Option<&[u8]>, &[u8]), LogEntryParseError> {
+    match line.first() {
+        Some(b'-') => {
+            return Ok((None, skip_spaces(&line[1..])));
+        }
+        Some(b'"') => (),
+        None | Some(_) => return Err(LogEntryParseError::FieldNotFound),
+    }
+    let rest = &line[1..];
+    let string_end_idx = memchr::memchr(b'"', rest).ok_or(LogEntryParseError::FieldNotFound)?;
+    Ok((
+        Some(&rest[..string_end_idx]),
+        skip_spaces(&rest[string_end_idx + 1..]),
+    ))
+}
+
+/// Take a [`DateTime`] from the start of `line`, delimited by square brackets.
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_timestamp(line: &[u8]) -> Result<(Option<DateTime<Utc>>, &[u8]), LogEntryParseError> {
+    match line.first() {
+        Some(b'-') => {
+            return Ok((None, skip_spaces(&line[1..])));
+        }
+        Some(b'[') => (),
+        None | Some(_) => return Err(LogEntryParseError::FieldNotFound),
+    }
+
+    let time_end_idx = memchr::memchr(b']', line).ok_or(LogEntryParseError::FieldNotFound)?;
+    let dt = DateTime::parse_from_rfc3339(utf8(&line[1..time_end_idx])?)
+        .map_err(LogEntryParseError::DateTimeParse)?;
+    Ok((Some(dt.into()), skip_spaces(&line[time_end_idx + 1..])))
+}
+
+/// Take a [`StatusCode`] from the start of `line` until the first whitespace.
+///
+/// Return None (and the remainder) if the line starts with `-`
+pub fn peel_status_code(line: &[u8]) -> Result<(Option<StatusCode>, &[u8]), LogEntryParseError> {
+    let (field, rem) = split_field(line);
+    match line.first() {
+        None => return Err(LogEntryParseError::FieldNotFound),
+        Some(b'-') => return Ok((None, rem)),
+        Some(_) => (),
+    }
+    Ok((
+        Some(StatusCode::from_bytes(field).map_err(LogEntryParseError::StatusCodeParse)?),
+        rem,
+    ))
+}
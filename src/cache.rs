@@ -0,0 +1,227 @@
+//! Recently-seen entries, looked up by request.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{memory::MemoryBudget, LogEntry};
+
+/// A request, normalized so that the same request made twice compares equal.
+///
+/// The method is upper-cased and query parameters are sorted, so `get /a?y=2&x=1` and
+/// `GET /a?x=1&y=2` have the same key. The protocol version is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub struct RequestKey {
+    pub method: String,
+    pub target: String,
+}
+
+impl RequestKey {
+    /// The key for `entry`'s request, or `None` if it has no request line.
+    pub fn of(entry: &LogEntry) -> Option<Self> {
+        let method = entry.method()?.to_ascii_uppercase();
+        let target = entry.target().unwrap_or("");
+        let target = match target.split_once('?') {
+            Some((path, query)) => {
+                let mut params: Vec<&str> = query.split('&').collect();
+                params.sort_unstable();
+                format!("{}?{}", path, params.join("&"))
+            }
+            None => target.to_owned(),
+        };
+
+        Some(RequestKey { method, target })
+    }
+}
+
+impl Display for RequestKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.method, self.target)
+    }
+}
+
+/// Entries from the last `ttl` of log time, indexed by [`RequestKey`].
+///
+/// Time is taken from the entries themselves, so replaying an old log behaves the same as
+/// following a live one: inserting an entry evicts everything more than `ttl` older than it.
+/// Entries without a timestamp or request line are not cached.
+///
+/// With a [`MemoryBudget`], the oldest entries are also evicted as needed to keep the cache within
+/// it, and an entry which doesn't fit even in an empty cache isn't cached.
+///
+/// # Example
+/// ```
+/// use common_log_format::{cache::{EntryCache, RequestKey}, LogEntry};
+/// let mut cache = EntryCache::new(chrono::Duration::seconds(30));
+/// let first: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"POST /pay?b=2&a=1 HTTP/1.1\" 502 0".parse().unwrap();
+/// let retry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:10Z] \"POST /pay?a=1&b=2 HTTP/1.1\" 200 10".parse().unwrap();
+/// cache.insert(first);
+/// cache.insert(retry.clone());
+/// assert_eq!(cache.get(&RequestKey::of(&retry).unwrap()).count(), 2);
+///
+/// let later: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:35Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// cache.insert(later);
+/// assert_eq!(cache.get(&RequestKey::of(&retry).unwrap()).count(), 1);
+/// ```
+#[derive(Debug)]
+pub struct EntryCache {
+    ttl: chrono::Duration,
+    entries: HashMap<RequestKey, VecDeque<LogEntry>>,
+    /// Insertion order, for eviction.
+    order: VecDeque<(DateTime<Utc>, RequestKey)>,
+    budget: Option<MemoryBudget>,
+    /// The estimated size of the cached entries, reserved from `budget`.
+    bytes: usize,
+}
+
+impl EntryCache {
+    pub fn new(ttl: chrono::Duration) -> Self {
+        EntryCache {
+            ttl,
+            entries: Default::default(),
+            order: Default::default(),
+            budget: None,
+            bytes: 0,
+        }
+    }
+
+    /// Hold no more than `budget` allows, evicting the oldest entries to make room.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{cache::EntryCache, memory::MemoryBudget, LogEntry};
+    /// let entry = |sec: u32| -> LogEntry {
+    ///     format!("10.0.0.1 - - [2024-05-01T13:00:{:02}Z] \"GET /{} HTTP/1.1\" 200 10", sec, sec)
+    ///         .parse()
+    ///         .unwrap()
+    /// };
+    /// let budget = MemoryBudget::new(3 * entry(0).approx_mem_size());
+    /// let mut cache = EntryCache::new(chrono::Duration::hours(1)).with_budget(budget.clone());
+    /// for sec in 0..10 {
+    ///     cache.insert(entry(sec));
+    /// }
+    /// assert_eq!(cache.len(), 3);
+    /// assert!(budget.used() <= budget.limit());
+    ///
+    /// drop(cache);
+    /// assert_eq!(budget.used(), 0);
+    /// ```
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        if let Some(old) = self.budget.replace(budget.clone()) {
+            old.release(self.bytes);
+        }
+        budget.force_reserve(self.bytes);
+        self
+    }
+
+    /// Cache `entry`, first evicting anything which expired before its timestamp.
+    pub fn insert(&mut self, entry: LogEntry) {
+        let (time, key) = match (entry.time, RequestKey::of(&entry)) {
+            (Some(t), Some(k)) => (t, k),
+            _ => return,
+        };
+
+        self.expire(time);
+        let size = entry.approx_mem_size();
+        if let Some(budget) = self.budget.clone() {
+            while !budget.try_reserve(size) {
+                if !self.evict_oldest() {
+                    return;
+                }
+            }
+        }
+        self.bytes += size;
+        self.order.push_back((time, key.clone()));
+        self.entries.entry(key).or_default().push_back(entry);
+    }
+
+    /// The cached entries for `key`, oldest first.
+    pub fn get(&self, key: &RequestKey) -> impl Iterator<Item = &LogEntry> {
+        self.entries.get(key).into_iter().flatten()
+    }
+
+    /// The cached entries for the same request as `entry`, oldest first.
+    pub fn get_matching(&self, entry: &LogEntry) -> impl Iterator<Item = &LogEntry> {
+        RequestKey::of(entry)
+            .and_then(|k| self.entries.get(&k))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Evict entries older than `ttl` before `now`.
+    ///
+    /// Entries are evicted in insertion order, so an out-of-order entry may outlive its TTL
+    /// until those inserted before it expire.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.ttl;
+        while let Some((t, _)) = self.order.front() {
+            if *t >= cutoff {
+                break;
+            }
+
+            self.evict_oldest();
+        }
+    }
+
+    /// Evict the entry inserted first, returning whether there was one.
+    fn evict_oldest(&mut self) -> bool {
+        let (_, key) = match self.order.pop_front() {
+            Some(o) => o,
+            None => return false,
+        };
+        if let Some(q) = self.entries.get_mut(&key) {
+            if let Some(e) = q.pop_front() {
+                let size = e.approx_mem_size();
+                self.bytes -= size;
+                if let Some(budget) = &self.budget {
+                    budget.release(size);
+                }
+            }
+            if q.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+        true
+    }
+
+    /// The estimated memory taken up by the cached entries, in bytes.
+    pub fn approx_mem_size(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of cached entries.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+impl Clone for EntryCache {
+    /// Clone the cache, reserving the clone's entries from the same budget.
+    fn clone(&self) -> Self {
+        if let Some(budget) = &self.budget {
+            budget.force_reserve(self.bytes);
+        }
+        EntryCache {
+            ttl: self.ttl,
+            entries: self.entries.clone(),
+            order: self.order.clone(),
+            budget: self.budget.clone(),
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl Drop for EntryCache {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.release(self.bytes);
+        }
+    }
+}
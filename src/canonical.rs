@@ -0,0 +1,134 @@
+//! One record type for every supported format.
+//!
+//! A pipeline reading from several kinds of log can convert each format's entry into a
+//! [`CanonicalEntry`], which holds the fields of all of them, and work with that. Converting back
+//! into a particular format drops whatever that format cannot represent:
+//!
+//! | into                  | loses                                                         |
+//! |-----------------------|---------------------------------------------------------------|
+//! | [`LogEntry`]          | `proxy`, `forwarded_for`, `duration`, `referer`, `user_agent` |
+//! | [`ProxiedLogEntry`]   | `forwarded_for`, `duration`, `referer`, `user_agent`          |
+//! | [`ForwardedLogEntry`] | `proxy`, `duration`, `referer`, `user_agent`                  |
+//! | [`CombinedLogEntry`]  | `proxy`, `forwarded_for`, `duration`                          |
+//!
+//! Converting from any of these into a [`CanonicalEntry`] and back is lossless.
+
+use std::net::IpAddr;
+
+#[cfg(doc)]
+use crate::combined::CombinedLogEntry;
+#[cfg(feature = "analytics")]
+use crate::filter::Cidr;
+use crate::{
+    duration::LoggedDuration, forwarded::ForwardedLogEntry, proxy::ProxiedLogEntry,
+    proxy::ProxyHeader, LogEntry,
+};
+
+/// A log entry in any supported format.
+///
+/// # Example
+/// ```
+/// use common_log_format::{canonical::CanonicalEntry, proxy::ProxiedLogEntry, LogEntry};
+/// let line = "PROXY TCP4 203.0.113.7 10.0.0.2 51234 443 10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let proxied: ProxiedLogEntry = line.parse().unwrap();
+/// let plain: LogEntry = "10.0.0.9 - - [1996-12-19T16:39:58-08:00] \"GET / HTTP/1.0\" 200 2326".parse().unwrap();
+///
+/// let merged: Vec<CanonicalEntry> = vec![proxied.into(), plain.into()];
+/// assert_eq!(merged[0].client_ip(), Some("203.0.113.7".parse().unwrap()));
+/// assert_eq!(merged[1].client_ip(), Some("10.0.0.9".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CanonicalEntry {
+    /// The fields every format has.
+    pub entry: LogEntry,
+    pub proxy: Option<ProxyHeader>,
+    /// The addresses from `X-Forwarded-For`, leftmost first.
+    #[serde(default)]
+    pub forwarded_for: Vec<IpAddr>,
+    /// How long the request took to serve.
+    pub duration: Option<LoggedDuration>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl CanonicalEntry {
+    /// The address of the original client, as for [`ProxiedLogEntry::client_ip`].
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.proxy
+            .map(|p| p.source)
+            .or(self.entry.host)
+            .map(|ip| ip.to_canonical())
+    }
+
+    /// The address of the original client, as for [`ForwardedLogEntry::real_client_ip`] but
+    /// starting from the PROXY header's source address if there is one.
+    #[cfg(feature = "analytics")]
+    pub fn real_client_ip(&self, trusted_proxies: &[Cidr]) -> Option<IpAddr> {
+        let peer = self.proxy.map(|p| p.source).or(self.entry.host);
+        crate::forwarded::real_client_ip(&self.forwarded_for, peer, trusted_proxies)
+    }
+
+    /// Whether converting into `LogEntry` would lose any fields.
+    pub fn is_lossless_as_clf(&self) -> bool {
+        self.proxy.is_none()
+            && self.forwarded_for.is_empty()
+            && self.duration.is_none()
+            && self.referer.is_none()
+            && self.user_agent.is_none()
+    }
+}
+
+impl From<LogEntry> for CanonicalEntry {
+    fn from(entry: LogEntry) -> Self {
+        CanonicalEntry {
+            entry,
+            proxy: None,
+            forwarded_for: vec![],
+            duration: None,
+            referer: None,
+            user_agent: None,
+        }
+    }
+}
+
+impl From<ProxiedLogEntry> for CanonicalEntry {
+    fn from(e: ProxiedLogEntry) -> Self {
+        CanonicalEntry {
+            proxy: e.proxy,
+            ..e.entry.into()
+        }
+    }
+}
+
+impl From<ForwardedLogEntry> for CanonicalEntry {
+    fn from(e: ForwardedLogEntry) -> Self {
+        CanonicalEntry {
+            forwarded_for: e.forwarded_for,
+            ..e.entry.into()
+        }
+    }
+}
+
+impl From<CanonicalEntry> for LogEntry {
+    fn from(e: CanonicalEntry) -> Self {
+        e.entry
+    }
+}
+
+impl From<CanonicalEntry> for ProxiedLogEntry {
+    fn from(e: CanonicalEntry) -> Self {
+        ProxiedLogEntry {
+            proxy: e.proxy,
+            entry: e.entry,
+        }
+    }
+}
+
+impl From<CanonicalEntry> for ForwardedLogEntry {
+    fn from(e: CanonicalEntry) -> Self {
+        ForwardedLogEntry {
+            entry: e.entry,
+            forwarded_for: e.forwarded_for,
+        }
+    }
+}
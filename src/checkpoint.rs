@@ -0,0 +1,235 @@
+//! Reading a log file with resumable checkpoints.
+//!
+//! A log shipper which crashes should pick up where it left off, without skipping entries. A
+//! [`CheckpointReader`] records how far it has read as a [`Checkpoint`], which the shipper saves
+//! after it has durably handled the entries before it. On restart, [`CheckpointReader::resume`]
+//! continues from the saved checkpoint, so every entry is delivered at least once.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    reader::{trim_line_end, FileId, ReadError},
+    LogEntry, ParseOptions,
+};
+
+/// A position in a log file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    /// The identity of the file which was at `path` when the checkpoint was taken.
+    pub file_id: Option<FileId>,
+    /// The offset just after the last entry returned.
+    pub offset: u64,
+    /// The number of lines before `offset`.
+    pub line: u64,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint to `path`, atomically replacing any previous checkpoint there.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut f = File::create(&tmp)?;
+        let log_path = self.path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "log path is not valid UTF-8")
+        })?;
+        writeln!(f, "path={}", log_path)?;
+        if let Some(id) = self.file_id {
+            writeln!(f, "dev={}", id.dev)?;
+            writeln!(f, "ino={}", id.ino)?;
+        }
+        writeln!(f, "offset={}", self.offset)?;
+        writeln!(f, "line={}", self.line)?;
+        f.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    /// Read a checkpoint written by [`Checkpoint::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid checkpoint: {}", what),
+            )
+        };
+        let num = |v: &str| v.parse::<u64>().map_err(|_| invalid("bad number"));
+
+        let (mut log_path, mut dev, mut ino, mut offset, mut line) = (None, None, None, 0, 0);
+        for l in fs::read_to_string(path)?.lines() {
+            match l.split_once('=') {
+                Some(("path", v)) => log_path = Some(PathBuf::from(v)),
+                Some(("dev", v)) => dev = Some(num(v)?),
+                Some(("ino", v)) => ino = Some(num(v)?),
+                Some(("offset", v)) => offset = num(v)?,
+                Some(("line", v)) => line = num(v)?,
+                _ => return Err(invalid(l)),
+            }
+        }
+
+        Ok(Checkpoint {
+            path: log_path.ok_or_else(|| invalid("missing path"))?,
+            file_id: dev.zip(ino).map(|(dev, ino)| FileId { dev, ino }),
+            offset,
+            line,
+        })
+    }
+}
+
+/// An iterator over the entries of a log file which can report its position as a [`Checkpoint`].
+///
+/// Only complete lines are read: iteration ends at a partially-written last line, and can be
+/// continued once more has been written.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::checkpoint::{Checkpoint, CheckpointReader};
+/// let mut reader = match Checkpoint::load("access.log.checkpoint") {
+///     Ok(cp) => CheckpointReader::resume(&cp).unwrap(),
+///     Err(_) => CheckpointReader::open("access.log").unwrap(),
+/// };
+///
+/// while let Some(entry) = reader.next() {
+///     // ... ship `entry` somewhere ...
+///     reader.checkpoint().save("access.log.checkpoint").unwrap();
+/// }
+/// ```
+pub struct CheckpointReader {
+    path: PathBuf,
+    file: BufReader<File>,
+    file_id: Option<FileId>,
+    offset: u64,
+    line: u64,
+    /// After finishing the current file (which has been rotated away), continue with this one.
+    then: Option<PathBuf>,
+    opts: ParseOptions,
+    buf: Vec<u8>,
+}
+
+impl CheckpointReader {
+    /// Read `path` from the start.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = File::open(&path)?;
+        Ok(CheckpointReader {
+            file_id: FileId::of(&file.metadata()?),
+            file: BufReader::new(file),
+            path,
+            offset: 0,
+            line: 0,
+            then: None,
+            opts: ParseOptions::default(),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Continue reading from `checkpoint`.
+    ///
+    /// If the file at the checkpoint's path has been replaced since, the old file is looked for
+    /// in the same directory (where rotation without compression would have left it), and the
+    /// rest of it is read before the new file. If it cannot be found, reading starts from the
+    /// beginning of the new file. If the file has been truncated, reading starts from its
+    /// beginning.
+    pub fn resume(checkpoint: &Checkpoint) -> io::Result<Self> {
+        let current = Self::open(&checkpoint.path)?;
+        let mut r = if current.file_id == checkpoint.file_id {
+            current
+        } else {
+            match find_file(&checkpoint.path, checkpoint.file_id) {
+                Some(old) => {
+                    let mut r = Self::open(old)?;
+                    r.then = Some(checkpoint.path.clone());
+                    r
+                }
+                None => return Ok(current),
+            }
+        };
+
+        if r.file.get_ref().metadata()?.len() >= checkpoint.offset {
+            r.file.seek(SeekFrom::Start(checkpoint.offset))?;
+            r.offset = checkpoint.offset;
+            r.line = checkpoint.line;
+        }
+
+        Ok(r)
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// The position just after the last entry returned.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            path: self.path.clone(),
+            file_id: self.file_id,
+            offset: self.offset,
+            line: self.line,
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            self.buf.clear();
+            let n = self.file.read_until(b'\n', &mut self.buf)?;
+            if n > 0 && self.buf.ends_with(b"\n") {
+                self.offset += n as u64;
+                self.line += 1;
+                match trim_line_end(&self.buf) {
+                    [] => continue,
+                    l => return Ok(Some(l.to_vec())),
+                }
+            }
+
+            // Leave any partial line to be read again once it is complete.
+            self.file.seek(SeekFrom::Start(self.offset))?;
+            match self.then.take() {
+                Some(next) => *self = Self::open(next)?.with_options(self.opts),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Iterator for CheckpointReader {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_line() {
+            Ok(Some(l)) => {
+                Some(
+                    LogEntry::from_bytes_with(&l, &self.opts).map_err(|error| ReadError::Parse {
+                        line: self.line,
+                        error,
+                    }),
+                )
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+/// Find the file with identity `id` among the siblings of `path`.
+fn find_file(path: &Path, id: Option<FileId>) -> Option<PathBuf> {
+    let id = id?;
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|d| {
+            d.metadata()
+                .ok()
+                .and_then(|m| FileId::of(&m))
+                .is_some_and(|f| f == id)
+        })
+        .map(|d| d.path())
+}
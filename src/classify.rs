@@ -0,0 +1,179 @@
+//! Telling requests for static assets from requests the application handled.
+//!
+//! Stylesheets, scripts, images, and fonts are usually served from disk or a CDN, quickly and
+//! many to a page. Mixed in with application requests they drown out its error rates and
+//! latencies, so most reports want the two apart. An [`AssetClassifier`] decides which is which
+//! from the path, and [`ByKind`] runs an aggregation separately over each.
+
+use std::collections::HashSet;
+
+use crate::{pattern, window::Aggregate, LogEntry};
+
+const ASSET_EXTENSIONS: &[&str] = &[
+    "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "avif", "bmp",
+    "woff", "woff2", "ttf", "otf", "eot", "mp3", "mp4", "webm", "pdf", "txt",
+];
+
+/// Whether a request was for a static asset or handled by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum RequestKind {
+    Static,
+    Dynamic,
+}
+
+/// Classifies requests as static or dynamic by their paths.
+///
+/// Rules are checked in order: a path matching a dynamic pattern is dynamic; otherwise one
+/// matching a static pattern or ending in a static extension is static; and anything else is
+/// dynamic. Patterns are matched against the whole path, with `*` matching anything.
+///
+/// By default, the extensions are those of stylesheets, scripts, images, fonts, and media, and
+/// paths under `/static/` and `/assets/` are static. Pages, including `.html` files, are dynamic.
+///
+/// # Example
+/// ```
+/// use common_log_format::classify::{AssetClassifier, RequestKind};
+/// let classifier = AssetClassifier::default()
+///     .with_static_path("/media/*")
+///     .with_dynamic_path("/static/generated/*");
+///
+/// assert_eq!(classifier.classify_path("/css/site.CSS"), RequestKind::Static);
+/// assert_eq!(classifier.classify_path("/media/video"), RequestKind::Static);
+/// assert_eq!(classifier.classify_path("/static/generated/thumb.png"), RequestKind::Dynamic);
+/// assert_eq!(classifier.classify_path("/index.html"), RequestKind::Dynamic);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetClassifier {
+    extensions: HashSet<String>,
+    static_paths: Vec<String>,
+    dynamic_paths: Vec<String>,
+}
+
+impl Default for AssetClassifier {
+    fn default() -> Self {
+        AssetClassifier {
+            extensions: ASSET_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            static_paths: vec!["/static/*".to_owned(), "/assets/*".to_owned()],
+            dynamic_paths: vec![],
+        }
+    }
+}
+
+impl AssetClassifier {
+    /// A classifier with no rules, which classifies everything as dynamic until some are added.
+    pub fn empty() -> Self {
+        AssetClassifier {
+            extensions: HashSet::new(),
+            static_paths: vec![],
+            dynamic_paths: vec![],
+        }
+    }
+
+    /// Treat paths ending in `.extension` as static, ignoring case.
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extensions.insert(extension.to_ascii_lowercase());
+        self
+    }
+
+    pub fn without_extension(mut self, extension: &str) -> Self {
+        self.extensions.remove(&extension.to_ascii_lowercase());
+        self
+    }
+
+    /// Treat paths matching `pattern` as static.
+    pub fn with_static_path(mut self, pattern: impl Into<String>) -> Self {
+        self.static_paths.push(pattern.into());
+        self
+    }
+
+    /// Treat paths matching `pattern` as dynamic, whatever the other rules say.
+    pub fn with_dynamic_path(mut self, pattern: impl Into<String>) -> Self {
+        self.dynamic_paths.push(pattern.into());
+        self
+    }
+
+    pub fn classify_path(&self, path: &str) -> RequestKind {
+        let any = |patterns: &[String]| patterns.iter().any(|p| pattern::matches(p, path));
+        if any(&self.dynamic_paths) {
+            return RequestKind::Dynamic;
+        }
+
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let by_extension = name
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| self.extensions.contains(&ext.to_ascii_lowercase()));
+        if by_extension || any(&self.static_paths) {
+            RequestKind::Static
+        } else {
+            RequestKind::Dynamic
+        }
+    }
+
+    /// The kind of `entry`'s request, or `None` if it has no path.
+    pub fn classify(&self, entry: &LogEntry) -> Option<RequestKind> {
+        entry.path().map(|p| self.classify_path(p))
+    }
+}
+
+/// The outputs of an aggregation over static and dynamic requests.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Split<T> {
+    pub static_assets: T,
+    pub dynamic: T,
+}
+
+/// Runs an aggregation separately over static and dynamic requests.
+///
+/// Entries without a path are counted as dynamic.
+///
+/// # Example
+/// ```
+/// use common_log_format::{classify::{AssetClassifier, ByKind}, stats::Stats, window::Aggregate, LogEntry};
+/// let mut by_kind = ByKind::new(AssetClassifier::default(), Stats::new());
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 500 100",
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /site.css HTTP/1.1\" 200 5000",
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /app.js HTTP/1.1\" 200 9000",
+/// ] {
+///     by_kind.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+///
+/// let split = by_kind.finish();
+/// assert_eq!(split.static_assets.entries, 2);
+/// assert_eq!(split.dynamic.entries, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ByKind<A> {
+    classifier: AssetClassifier,
+    static_assets: A,
+    dynamic: A,
+}
+
+impl<A: Clone> ByKind<A> {
+    /// Run `aggregate` over each kind of request, as `classifier` tells them apart.
+    pub fn new(classifier: AssetClassifier, aggregate: A) -> Self {
+        ByKind {
+            classifier,
+            static_assets: aggregate.clone(),
+            dynamic: aggregate,
+        }
+    }
+}
+
+impl<A: Aggregate> Aggregate for ByKind<A> {
+    type Output = Split<A::Output>;
+
+    fn observe(&mut self, entry: &LogEntry) {
+        match self.classifier.classify(entry) {
+            Some(RequestKind::Static) => self.static_assets.observe(entry),
+            _ => self.dynamic.observe(entry),
+        }
+    }
+
+    fn finish(self) -> Self::Output {
+        Split {
+            static_assets: self.static_assets.finish(),
+            dynamic: self.dynamic.finish(),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Inserting entries into [ClickHouse].
+//!
+//! [`ClickHouseSink`] batches entries and inserts each batch with one request to ClickHouse's
+//! [HTTP interface], as `JSONEachRow`. It speaks plain HTTP/1.1 itself, so it needs no async
+//! runtime; it does not support TLS.
+//!
+//! The rows have the columns of [`create_table`]'s table, which suits most access log queries:
+//!
+//! ```sql
+//! SELECT toStartOfMinute(time) AS minute, countIf(status >= 500) / count() AS errors
+//! FROM access_log GROUP BY minute ORDER BY minute;
+//! ```
+//!
+//! [ClickHouse]: https://clickhouse.com
+//! [HTTP interface]: https://clickhouse.com/docs/en/interfaces/http
+
+use std::{io, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    http_post::{Backoff, Endpoint},
+    replay::InvalidUrl,
+    sink::Sink,
+    LogEntry,
+};
+
+/// The statement creating a table `table` for [`ClickHouseSink`] to insert into.
+///
+/// Rows are ordered by time, and host and method are stored as low-cardinality strings. Any
+/// table with columns of these names and compatible types works as well.
+///
+/// # Example
+/// ```
+/// use common_log_format::clickhouse::create_table;
+/// assert!(create_table("logs.access").starts_with("CREATE TABLE IF NOT EXISTS logs.access ("));
+/// ```
+pub fn create_table(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+    host LowCardinality(Nullable(String)),
+    ident Nullable(String),
+    authuser Nullable(String),
+    time Nullable(DateTime64(6, 'UTC')),
+    request_line Nullable(String),
+    method LowCardinality(Nullable(String)),
+    path Nullable(String),
+    status Nullable(UInt16),
+    size Nullable(UInt64)
+)
+ENGINE = MergeTree
+ORDER BY tuple(time)
+SETTINGS allow_nullable_key = 1",
+        table
+    )
+}
+
+/// One row of the table from [`create_table`].
+#[derive(Serialize)]
+struct Row<'a> {
+    host: Option<String>,
+    ident: Option<&'a str>,
+    authuser: Option<&'a str>,
+    time: Option<String>,
+    request_line: Option<&'a str>,
+    method: Option<&'a str>,
+    path: Option<&'a str>,
+    status: Option<u16>,
+    size: Option<usize>,
+}
+
+impl<'a> From<&'a LogEntry> for Row<'a> {
+    fn from(e: &'a LogEntry) -> Self {
+        Row {
+            host: e.host.map(|h| h.to_string()),
+            ident: e.ident.as_deref(),
+            authuser: e.authuser.as_deref(),
+            // The format ClickHouse parses without `date_time_input_format = 'best_effort'`.
+            time: e
+                .time
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+            request_line: e.request_line.as_deref(),
+            method: e.method(),
+            path: e.path(),
+            status: e.status_code.map(|s| s.as_u16()),
+            size: e.object_size,
+        }
+    }
+}
+
+/// A [`Sink`] which inserts entries into a ClickHouse table.
+///
+/// Entries are sent in batches of 10,000 (by default), when a batch fills and on
+/// [`Sink::flush`]. An insert which fails to connect, times out, or is answered with a 5xx or 429
+/// status is retried up to 5 times, waiting 100ms before the first retry and twice as long before
+/// each one after, up to 10s. If it still fails, or ClickHouse rejects it outright, the error is
+/// returned and the batch is kept, so the next flush tries it again.
+///
+/// A retried insert whose first attempt did reach ClickHouse is inserted twice, unless the table
+/// deduplicates inserts (as replicated tables do by default).
+///
+/// # Example
+/// ```
+/// use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener};
+/// use common_log_format::{clickhouse::ClickHouseSink, sink::Sink, LogEntry};
+///
+/// // A server which is unavailable for the first request, and accepts the second.
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// let server = std::thread::spawn(move || {
+///     let mut bodies = vec![];
+///     for (i, stream) in listener.incoming().take(2).enumerate() {
+///         let mut reader = BufReader::new(stream.unwrap());
+///         let mut length = 0;
+///         loop {
+///             let mut line = String::new();
+///             reader.read_line(&mut line).unwrap();
+///             match line.trim_end().split_once(": ") {
+///                 Some(("Content-Length", n)) => length = n.parse().unwrap(),
+///                 None if line.trim_end().is_empty() => break,
+///                 _ => (),
+///             }
+///         }
+///         let mut body = vec![0; length];
+///         reader.read_exact(&mut body).unwrap();
+///         bodies.push(String::from_utf8(body).unwrap());
+///         let status = if i == 0 { "503 Service Unavailable" } else { "200 OK" };
+///         write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+///     }
+///     bodies
+/// });
+///
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10".parse().unwrap();
+/// let mut sink = ClickHouseSink::new(&url, "access_log").unwrap();
+/// sink.send_all([&entry, &entry]).unwrap();
+/// assert_eq!(sink.inserted(), 2);
+///
+/// let bodies = server.join().unwrap();
+/// assert_eq!(bodies[0], bodies[1]);
+/// let mut lines = bodies[1].lines();
+/// assert_eq!(lines.next(), Some("INSERT INTO access_log FORMAT JSONEachRow"));
+/// assert!(lines.next().unwrap().contains(r#""time":"2024-05-01 13:00:00.000000","request_line":"GET /a HTTP/1.1","method":"GET""#));
+/// ```
+pub struct ClickHouseSink {
+    endpoint: Endpoint,
+    table: String,
+    headers: Vec<(&'static str, String)>,
+    backoff: Backoff,
+    batch_size: usize,
+    /// The insert statement and the rows of the current batch.
+    body: Vec<u8>,
+    rows: usize,
+    inserted: u64,
+}
+
+impl ClickHouseSink {
+    /// Insert into `table` through the HTTP interface at `url`, such as `http://localhost:8123`.
+    pub fn new(url: &str, table: &str) -> Result<Self, InvalidUrl> {
+        let mut sink = ClickHouseSink {
+            endpoint: Endpoint::new(url)?,
+            table: table.to_owned(),
+            headers: vec![],
+            backoff: Backoff::default(),
+            batch_size: 10_000,
+            body: vec![],
+            rows: 0,
+            inserted: 0,
+        };
+        sink.start_batch();
+        Ok(sink)
+    }
+
+    /// Insert into `table` in `database`, rather than the user's default database.
+    pub fn with_database(mut self, database: &str) -> Self {
+        self.headers
+            .push(("X-ClickHouse-Database", database.to_owned()));
+        self
+    }
+
+    /// Authenticate as `user` with `password`.
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.headers.push(("X-ClickHouse-User", user.to_owned()));
+        self.headers.push(("X-ClickHouse-Key", password.to_owned()));
+        self
+    }
+
+    /// Insert every `batch_size` entries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Retry a failed insert up to `retries` times, first after `initial`, doubling the wait
+    /// each time up to `max`.
+    pub fn with_backoff(mut self, retries: u32, initial: Duration, max: Duration) -> Self {
+        self.backoff = Backoff {
+            retries,
+            initial,
+            max,
+        };
+        self
+    }
+
+    /// Give up on connecting, sending an insert, or waiting for its response after `timeout`
+    /// (30s by default).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+
+    /// The number of entries inserted so far.
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    /// The number of entries waiting to be inserted.
+    pub fn pending(&self) -> usize {
+        self.rows
+    }
+
+    fn start_batch(&mut self) {
+        self.body.clear();
+        self.body.extend_from_slice(
+            format!("INSERT INTO {} FORMAT JSONEachRow\n", self.table).as_bytes(),
+        );
+        self.rows = 0;
+    }
+}
+
+impl Sink for ClickHouseSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.body, &Row::from(entry)).expect("rows serialize to JSON");
+        self.body.push(b'\n');
+        self.rows += 1;
+        if self.rows >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Insert the current batch, if there is one.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        self.backoff.post("ClickHouse", || {
+            self.endpoint.post("/", &headers, &self.body)
+        })?;
+        self.inserted += self.rows as u64;
+        self.start_batch();
+        Ok(())
+    }
+}
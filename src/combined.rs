@@ -0,0 +1,104 @@
+//! Lines in the Combined Log Format.
+//!
+//! The combined format, Apache's `combined` and nginx's default, is the Common Log Format followed
+//! by the quoted `Referer` and `User-Agent` request headers:
+//!
+//! ```text
+//! 10.0.0.1 - - [...] "GET / HTTP/1.1" 200 512 "https://example.com/" "Mozilla/5.0 (...)"
+//! ```
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    canonical::CanonicalEntry, peel_quoted_string, warnings, warnings::Warning, LogEntry,
+    LogEntryParseError, ParseOptions,
+};
+
+/// A [`LogEntry`] followed by the request's referer and user agent.
+///
+/// Either header may be missing from the end of the line. A header logged as `-` or `"-"` (as
+/// servers log headers which weren't sent) is `None`.
+///
+/// # Example
+/// ```
+/// use common_log_format::combined::CombinedLogEntry;
+/// let line = "10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326 \"-\" \"curl/8.5.0\"";
+/// let e: CombinedLogEntry = line.parse().unwrap();
+/// assert_eq!(e.referer, None);
+/// assert_eq!(e.user_agent.as_deref(), Some("curl/8.5.0"));
+/// assert_eq!(e.to_string().parse::<CombinedLogEntry>().unwrap(), e);
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CombinedLogEntry {
+    pub entry: LogEntry,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl CombinedLogEntry {
+    pub fn parse_with(line: &str, opts: &ParseOptions) -> Result<Self, LogEntryParseError> {
+        let (entry, remaining) = LogEntry::peel(line, opts)?;
+        let (referer, remaining) = peel_header(remaining)?;
+        let (user_agent, remaining) = peel_header(remaining)?;
+        if !remaining.is_empty() {
+            warnings::emit(Warning::TrailingData {
+                len: remaining.len(),
+            });
+        }
+        Ok(CombinedLogEntry {
+            entry,
+            referer,
+            user_agent,
+        })
+    }
+}
+
+/// Take a quoted header value from the start of `line`, if there is one.
+fn peel_header(line: &str) -> Result<(Option<String>, &str), LogEntryParseError> {
+    if line.is_empty() {
+        return Ok((None, line));
+    }
+    let (value, rem) = peel_quoted_string(line)?;
+    Ok((value.filter(|v| *v != "-").map(str::to_owned), rem))
+}
+
+impl FromStr for CombinedLogEntry {
+    type Err = LogEntryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &ParseOptions::default())
+    }
+}
+
+/// Write the entry as a line which parses back into it, with missing headers as `"-"`.
+impl Display for CombinedLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} \"{}\" \"{}\"",
+            self.entry,
+            self.referer.as_deref().unwrap_or("-"),
+            self.user_agent.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+impl From<CombinedLogEntry> for CanonicalEntry {
+    fn from(e: CombinedLogEntry) -> Self {
+        CanonicalEntry {
+            referer: e.referer,
+            user_agent: e.user_agent,
+            ..e.entry.into()
+        }
+    }
+}
+
+impl From<CanonicalEntry> for CombinedLogEntry {
+    fn from(e: CanonicalEntry) -> Self {
+        CombinedLogEntry {
+            entry: e.entry,
+            referer: e.referer,
+            user_agent: e.user_agent,
+        }
+    }
+}
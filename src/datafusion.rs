@@ -0,0 +1,429 @@
+//! Querying raw log files with SQL, through [DataFusion].
+//!
+//! A [`ClfTable`] is a DataFusion table over a set of log files, which may be compressed (see
+//! [`reader`](crate::reader)). Each file is scanned as its own partition. Only the columns a
+//! query uses are built, and conditions on `time` are used to skip reading: through the file's
+//! [`LogIndex`] if it has one (as `access.log.idx` for `access.log`), and otherwise by not
+//! building rows outside the range.
+//!
+//! [DataFusion]: https://datafusion.apache.org
+
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use datafusion::{
+    arrow::{
+        array::{
+            ArrayRef, RecordBatchOptions, StringBuilder, TimestampMicrosecondBuilder,
+            UInt16Builder, UInt64Builder,
+        },
+        datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+        record_batch::RecordBatch,
+    },
+    catalog::{Session, TableProvider},
+    common::{DataFusionError, ScalarValue},
+    datasource::TableType,
+    execution::{SendableRecordBatchStream, TaskContext},
+    logical_expr::{Expr, Operator, TableProviderFilterPushDown},
+    physical_plan::{
+        stream::RecordBatchStreamAdapter,
+        streaming::{PartitionStream, StreamingTableExec},
+        ExecutionPlan,
+    },
+};
+use futures_core::Stream;
+
+use crate::{
+    index::{IndexQuery, LogIndex},
+    reader::{open_log, ReadError},
+    LogEntry,
+};
+
+/// The number of rows in each batch a scan produces.
+const BATCH_ROWS: usize = 8192;
+
+/// A DataFusion table over a set of log files.
+///
+/// The columns are `host`, `ident`, `authuser`, `time` (a UTC timestamp), `request_line`,
+/// `method`, `path`, `status_code`, and `object_size`. Fields which were `-` in the log are
+/// null.
+///
+/// # Example
+/// ```no_run
+/// use std::sync::Arc;
+/// use common_log_format::datafusion::ClfTable;
+/// use datafusion::prelude::SessionContext;
+/// # async fn query() -> datafusion::error::Result<()> {
+/// let ctx = SessionContext::new();
+/// ctx.register_table("access", Arc::new(ClfTable::open_dir("/var/log/nginx")?))?;
+/// ctx.sql(
+///     "SELECT path, count(*) AS hits FROM access \
+///      WHERE time >= '2024-05-01T00:00:00Z' AND status_code >= 500 \
+///      GROUP BY path ORDER BY hits DESC LIMIT 10",
+/// )
+/// .await?
+/// .show()
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClfTable {
+    paths: Vec<PathBuf>,
+    schema: SchemaRef,
+    skip_invalid: bool,
+}
+
+impl ClfTable {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        ClfTable {
+            paths,
+            schema: Arc::new(Schema::new(vec![
+                Field::new("host", DataType::Utf8, true),
+                Field::new("ident", DataType::Utf8, true),
+                Field::new("authuser", DataType::Utf8, true),
+                Field::new(
+                    "time",
+                    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                    true,
+                ),
+                Field::new("request_line", DataType::Utf8, true),
+                Field::new("method", DataType::Utf8, true),
+                Field::new("path", DataType::Utf8, true),
+                Field::new("status_code", DataType::UInt16, true),
+                Field::new("object_size", DataType::UInt64, true),
+            ])),
+            skip_invalid: false,
+        }
+    }
+
+    /// A table over every file in `dir`, apart from index files and hidden files.
+    pub fn open_dir(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut paths = vec![];
+        for e in std::fs::read_dir(dir)? {
+            let e = e?;
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            if e.file_type()?.is_file() && !name.starts_with('.') && !name.ends_with(".idx") {
+                paths.push(e.path());
+            }
+        }
+        paths.sort();
+        Ok(Self::new(paths))
+    }
+
+    /// Skip lines which fail to parse, rather than failing the query.
+    pub fn with_skip_invalid(mut self, skip_invalid: bool) -> Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+#[async_trait]
+impl TableProvider for ClfTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+        // Time ranges only prune what is read, so DataFusion still applies them exactly.
+        Ok(filters
+            .iter()
+            .map(|f| {
+                if time_bound(f).is_some() {
+                    TableProviderFilterPushDown::Inexact
+                } else {
+                    TableProviderFilterPushDown::Unsupported
+                }
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let mut query = IndexQuery::default();
+        for bound in filters.iter().filter_map(time_bound) {
+            match bound {
+                Bound::Start(t) => query.start = query.start.max(Some(t)),
+                Bound::End(t) => query.end = Some(query.end.map_or(t, |e| e.min(t))),
+            }
+        }
+
+        let columns: Arc<[usize]> = match projection {
+            Some(p) => p.as_slice().into(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&columns)?);
+        let partitions = self
+            .paths
+            .iter()
+            .map(|p| {
+                Arc::new(FilePartition {
+                    path: p.clone(),
+                    schema: schema.clone(),
+                    columns: columns.clone(),
+                    query,
+                    skip_invalid: self.skip_invalid,
+                }) as Arc<dyn PartitionStream>
+            })
+            .collect();
+        Ok(Arc::new(StreamingTableExec::try_new(
+            schema,
+            partitions,
+            None,
+            [],
+            false,
+            limit,
+        )?))
+    }
+}
+
+enum Bound {
+    /// Matching rows are at or after this time.
+    Start(DateTime<Utc>),
+    /// Matching rows are before this time.
+    End(DateTime<Utc>),
+}
+
+/// The bound on `time` that `filter` implies, if it is a simple comparison.
+fn time_bound(filter: &Expr) -> Option<Bound> {
+    let Expr::BinaryExpr(b) = filter else {
+        return None;
+    };
+    let (op, lit) = match (&*b.left, &*b.right) {
+        (Expr::Column(c), Expr::Literal(v, _)) if c.name == "time" => (b.op, v),
+        (Expr::Literal(v, _), Expr::Column(c)) if c.name == "time" => (b.op.swap()?, v),
+        _ => return None,
+    };
+
+    let nanos = match lit {
+        ScalarValue::TimestampSecond(Some(v), _) => v.checked_mul(1_000_000_000)?,
+        ScalarValue::TimestampMillisecond(Some(v), _) => v.checked_mul(1_000_000)?,
+        ScalarValue::TimestampMicrosecond(Some(v), _) => v.checked_mul(1_000)?,
+        ScalarValue::TimestampNanosecond(Some(v), _) => *v,
+        _ => return None,
+    };
+    // The column has microsecond precision, so round the bounds outwards to whole microseconds
+    // and include the boundary either way; DataFusion applies the exact condition afterwards.
+    match op {
+        Operator::Gt | Operator::GtEq => Some(Bound::Start(DateTime::from_timestamp_micros(
+            nanos.div_euclid(1_000),
+        )?)),
+        Operator::Lt | Operator::LtEq => Some(Bound::End(DateTime::from_timestamp_micros(
+            nanos.div_euclid(1_000) + 2,
+        )?)),
+        _ => None,
+    }
+}
+
+/// One file of a [`ClfTable`] scan.
+#[derive(Debug)]
+struct FilePartition {
+    path: PathBuf,
+    /// The projected schema, and the indices of its columns in the table's.
+    schema: SchemaRef,
+    columns: Arc<[usize]>,
+    query: IndexQuery,
+    skip_invalid: bool,
+}
+
+impl FilePartition {
+    fn entries(
+        &self,
+    ) -> std::io::Result<Box<dyn Iterator<Item = Result<LogEntry, ReadError>> + Send>> {
+        let mut index_path = self.path.clone().into_os_string();
+        index_path.push(".idx");
+        let bounded = self.query.start.is_some() || self.query.end.is_some();
+        if bounded {
+            if let Ok(index) = LogIndex::load(&index_path) {
+                // The index is ignored if it is stale.
+                if let Ok(entries) = index.query(&self.query) {
+                    return Ok(Box::new(entries));
+                }
+            }
+        }
+
+        let query = self.query;
+        Ok(Box::new(open_log(&self.path)?.filter(move |e| {
+            e.as_ref().map_or(true, |e| !bounded || query.matches(e))
+        })))
+    }
+}
+
+impl PartitionStream for FilePartition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let batches: Box<dyn Iterator<Item = _> + Send> = match self.entries() {
+            Ok(entries) => Box::new(Batches {
+                entries,
+                schema: self.schema.clone(),
+                columns: self.columns.clone(),
+                path: self.path.clone(),
+                skip_invalid: self.skip_invalid,
+                done: false,
+            }),
+            Err(e) => Box::new(std::iter::once(Err(DataFusionError::IoError(e)))),
+        };
+        Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            IterStream(batches),
+        ))
+    }
+}
+
+/// Groups a file's entries into record batches.
+struct Batches {
+    entries: Box<dyn Iterator<Item = Result<LogEntry, ReadError>> + Send>,
+    /// The projected schema, and the indices of its columns in the table's.
+    schema: SchemaRef,
+    columns: Arc<[usize]>,
+    path: PathBuf,
+    skip_invalid: bool,
+    done: bool,
+}
+
+impl Iterator for Batches {
+    type Item = datafusion::error::Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut columns: Vec<ColumnBuilder> = self
+            .columns
+            .iter()
+            .map(|&i| ColumnBuilder::new(i))
+            .collect();
+        let mut rows = 0;
+        while rows < BATCH_ROWS {
+            match self.entries.next() {
+                Some(Ok(e)) => {
+                    rows += 1;
+                    for c in &mut columns {
+                        c.push(&e);
+                    }
+                }
+                Some(Err(ReadError::Parse { .. })) if self.skip_invalid => (),
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(DataFusionError::External(
+                        format!("{}: {}", self.path.display(), e).into(),
+                    )));
+                }
+                None => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        if rows == 0 {
+            return None;
+        }
+        let arrays = columns.iter_mut().map(ColumnBuilder::finish).collect();
+        // Queries such as `count(*)` use no columns, but still need the number of rows.
+        let opts = RecordBatchOptions::new().with_row_count(Some(rows));
+        Some(
+            RecordBatch::try_new_with_options(self.schema.clone(), arrays, &opts)
+                .map_err(Into::into),
+        )
+    }
+}
+
+/// Builds one column of a batch.
+enum ColumnBuilder {
+    Host(StringBuilder),
+    Ident(StringBuilder),
+    Authuser(StringBuilder),
+    Time(TimestampMicrosecondBuilder),
+    RequestLine(StringBuilder),
+    Method(StringBuilder),
+    Path(StringBuilder),
+    StatusCode(UInt16Builder),
+    ObjectSize(UInt64Builder),
+}
+
+impl ColumnBuilder {
+    /// A builder for the column at `index` in the table's schema.
+    fn new(index: usize) -> Self {
+        match index {
+            0 => ColumnBuilder::Host(StringBuilder::new()),
+            1 => ColumnBuilder::Ident(StringBuilder::new()),
+            2 => ColumnBuilder::Authuser(StringBuilder::new()),
+            3 => ColumnBuilder::Time(TimestampMicrosecondBuilder::new()),
+            4 => ColumnBuilder::RequestLine(StringBuilder::new()),
+            5 => ColumnBuilder::Method(StringBuilder::new()),
+            6 => ColumnBuilder::Path(StringBuilder::new()),
+            7 => ColumnBuilder::StatusCode(UInt16Builder::new()),
+            8 => ColumnBuilder::ObjectSize(UInt64Builder::new()),
+            _ => unreachable!("no column {}", index),
+        }
+    }
+
+    fn push(&mut self, e: &LogEntry) {
+        match self {
+            ColumnBuilder::Host(b) => b.append_option(e.host.map(|h| h.to_string())),
+            ColumnBuilder::Ident(b) => b.append_option(e.ident.as_deref()),
+            ColumnBuilder::Authuser(b) => b.append_option(e.authuser.as_deref()),
+            ColumnBuilder::Time(b) => b.append_option(e.time.map(|t| t.timestamp_micros())),
+            ColumnBuilder::RequestLine(b) => b.append_option(e.request_line.as_deref()),
+            ColumnBuilder::Method(b) => b.append_option(e.method()),
+            ColumnBuilder::Path(b) => b.append_option(e.path()),
+            ColumnBuilder::StatusCode(b) => b.append_option(e.status_code.map(|s| s.as_u16())),
+            ColumnBuilder::ObjectSize(b) => b.append_option(e.object_size.map(|s| s as u64)),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Host(b)
+            | ColumnBuilder::Ident(b)
+            | ColumnBuilder::Authuser(b)
+            | ColumnBuilder::RequestLine(b)
+            | ColumnBuilder::Method(b)
+            | ColumnBuilder::Path(b) => Arc::new(b.finish()),
+            ColumnBuilder::Time(b) => Arc::new(b.finish().with_timezone("UTC")),
+            ColumnBuilder::StatusCode(b) => Arc::new(b.finish()),
+            ColumnBuilder::ObjectSize(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// A stream which yields an iterator's items as soon as it is polled.
+///
+/// Reading is blocking, as DataFusion's own file formats are when reading local files.
+struct IterStream<I>(I);
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.0.next())
+    }
+}
@@ -0,0 +1,240 @@
+//! Dropping duplicate entries.
+//!
+//! Shipping pipelines deliver at least once: when an acknowledgement is lost, the batch is sent
+//! again, and every entry in it appears twice downstream. A [`Dedup`] remembers the entries it has
+//! seen, by a hash of the fields which identify them, and drops any it sees again.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::LogEntry;
+
+/// A field which can be part of the identity of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Field {
+    Host,
+    Ident,
+    Authuser,
+    Time,
+    Request,
+    Status,
+    Size,
+}
+
+impl Field {
+    pub const ALL: [Field; 7] = [
+        Field::Host,
+        Field::Ident,
+        Field::Authuser,
+        Field::Time,
+        Field::Request,
+        Field::Status,
+        Field::Size,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Host => "host",
+            Field::Ident => "ident",
+            Field::Authuser => "authuser",
+            Field::Time => "time",
+            Field::Request => "request",
+            Field::Status => "status",
+            Field::Size => "size",
+        }
+    }
+
+    fn hash_into(self, entry: &LogEntry, hasher: &mut impl Hasher) {
+        match self {
+            Field::Host => entry.host.hash(hasher),
+            Field::Ident => entry.ident.hash(hasher),
+            Field::Authuser => entry.authuser.hash(hasher),
+            Field::Time => entry.time.hash(hasher),
+            Field::Request => entry.request_line.hash(hasher),
+            Field::Status => entry.status_code.map(|s| s.as_u16()).hash(hasher),
+            Field::Size => entry.object_size.hash(hasher),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name given to [`Field::from_str`] is not a known field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownField(pub String);
+
+impl Display for UnknownField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown field {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownField {}
+
+impl FromStr for Field {
+    type Err = UnknownField;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Field::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| UnknownField(s.to_owned()))
+    }
+}
+
+/// Drops entries identical, in the chosen fields, to one seen before.
+///
+/// By default every field is compared, so only exact duplicates are dropped, and every entry seen
+/// is remembered. With a window, an entry is only a duplicate of one logged within the window
+/// before or after it, and entries older than the window are forgotten, which bounds memory for
+/// endless streams. Entries without a time are then never duplicates.
+///
+/// Entries are remembered by a 128-bit hash, so different entries are treated as the same with
+/// negligible probability.
+///
+/// # Example
+/// ```
+/// use common_log_format::{dedup::{Dedup, Field}, LogEntry};
+/// let lines = [
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10",
+///     // The same request again, re-logged a second later by a retrying shipper.
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:02Z] \"GET /b HTTP/1.1\" 200 10",
+/// ];
+/// let entries = || lines.iter().map(|l| l.parse::<LogEntry>().unwrap());
+///
+/// assert_eq!(Dedup::new().dedup(entries()).count(), 3);
+///
+/// let mut dedup = Dedup::new()
+///     .with_fields(&[Field::Host, Field::Request, Field::Status, Field::Size])
+///     .with_window(chrono::Duration::seconds(5));
+/// assert_eq!(dedup.dedup(entries()).count(), 2);
+/// assert_eq!(dedup.dropped(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    fields: Vec<Field>,
+    window: Option<chrono::Duration>,
+    /// The last time each key was seen, or `None` without a window.
+    seen: HashMap<u128, Option<DateTime<Utc>>>,
+    /// Keys by the time they were seen, to forget them once out of the window.
+    order: VecDeque<(DateTime<Utc>, u128)>,
+    latest: Option<DateTime<Utc>>,
+    dropped: u64,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dedup {
+    pub fn new() -> Self {
+        Dedup {
+            fields: Field::ALL.to_vec(),
+            window: None,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            latest: None,
+            dropped: 0,
+        }
+    }
+
+    /// Compare only `fields`. Leave out [`Field::Time`] to catch duplicates logged at different
+    /// times, within the window.
+    pub fn with_fields(mut self, fields: &[Field]) -> Self {
+        self.fields = fields.to_vec();
+        self
+    }
+
+    pub fn with_window(mut self, window: chrono::Duration) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Whether `entry` duplicates one seen before. Either way, it is remembered.
+    pub fn is_duplicate(&mut self, entry: &LogEntry) -> bool {
+        let key = self.key(entry);
+        let window = match self.window {
+            Some(w) => w,
+            None => {
+                let duplicate = self.seen.insert(key, None).is_some();
+                self.dropped += duplicate as u64;
+                return duplicate;
+            }
+        };
+        let time = match entry.time {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let latest = *self
+            .latest
+            .insert(self.latest.map_or(time, |l| l.max(time)));
+        while let Some(&(t, k)) = self.order.front() {
+            if t >= latest - window {
+                break;
+            }
+            self.order.pop_front();
+            if self.seen.get(&k).is_some_and(|last| *last == Some(t)) {
+                self.seen.remove(&k);
+            }
+        }
+
+        let previous = self.seen.get(&key).copied().flatten();
+        let duplicate = previous.is_some_and(|p| (time - p).abs() <= window);
+        if previous.is_none_or(|p| time > p) {
+            self.seen.insert(key, Some(time));
+            self.order.push_back((time, key));
+        }
+        self.dropped += duplicate as u64;
+        duplicate
+    }
+
+    /// The entries of `entries` which aren't duplicates.
+    pub fn dedup<'a, I>(&'a mut self, entries: I) -> impl Iterator<Item = LogEntry> + 'a
+    where
+        I: IntoIterator<Item = LogEntry>,
+        I::IntoIter: 'a,
+    {
+        entries.into_iter().filter(move |e| !self.is_duplicate(e))
+    }
+
+    /// The number of duplicates seen so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The number of keys remembered.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn key(&self, entry: &LogEntry) -> u128 {
+        let half = |salt: u64| {
+            let mut hasher = DefaultHasher::new();
+            salt.hash(&mut hasher);
+            for f in &self.fields {
+                f.hash_into(entry, &mut hasher);
+            }
+            hasher.finish() as u128
+        };
+        half(0) << 64 | half(1)
+    }
+}
@@ -0,0 +1,442 @@
+//! Fields computed from each entry, defined in configuration.
+//!
+//! Reports often need a value which isn't in the log but follows from it: the status class, how
+//! deep the path is, whether the request was for a static asset. [`DerivedFields`] defines these
+//! once, one per line:
+//!
+//! ```text
+//! # name = expression
+//! status_class = class(status)
+//! path_depth = depth(path)
+//! section = segment(path, 1)
+//! is_static_asset = path glob "*.css" || path glob "*.js" || path glob "*.png"
+//! ```
+//!
+//! and computes them once per entry into a [`DerivedEntry`]. Filters parsed with
+//! [`DerivedFields::parse_filter`] can then test them like any other field, [`count_by`] groups
+//! by them, [`Template`](crate::sink::Template) placeholders can name them, and they are
+//! serialized alongside the entry's own fields.
+//!
+//! An expression is one of:
+//!
+//! | expression             | type    | value                                                   |
+//! |------------------------|---------|---------------------------------------------------------|
+//! | a field                | as field | `host`, `ident`, `authuser`, `method`, `path`, `target`, `request` (text) or `status`, `size` (numbers) |
+//! | `class(status)`        | text    | the status class, such as `5xx`                         |
+//! | `depth(field)`         | number  | the number of non-empty `/`-separated segments          |
+//! | `segment(field, n)`    | text    | the `n`th non-empty segment, counting from 1            |
+//! | `extension(field)`     | text    | the lower-cased extension of the last segment           |
+//! | `lower(field)`         | text    | the field, lower-cased                                  |
+//! | `len(field)`           | number  | the field's length in bytes                             |
+//! | `hour(time)`           | number  | the hour of the day, in UTC                             |
+//! | `weekday(time)`        | text    | the day of the week, such as `Mon`                      |
+//! | a [`Filter`] expression | boolean | whether the entry matches                              |
+//!
+//! A derived field is missing from an entry when the fields it is computed from are, except for
+//! boolean fields, which are always present.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::Display,
+    str::FromStr,
+};
+
+use chrono::{Datelike, Timelike};
+
+use crate::{
+    filter::{parse_filter, Filter, FilterParseError},
+    store::Counters,
+    LogEntry,
+};
+
+/// The value of a derived field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Num(u64),
+    Text(String),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// The type of a derived field's values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Bool,
+    Num,
+    Text,
+}
+
+/// A field of the entry itself, which a derived field is computed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Host,
+    Ident,
+    Authuser,
+    Method,
+    Path,
+    Target,
+    Request,
+    Status,
+    Size,
+    Time,
+}
+
+impl Source {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "host" => Source::Host,
+            "ident" => Source::Ident,
+            "authuser" => Source::Authuser,
+            "method" => Source::Method,
+            "path" => Source::Path,
+            "target" => Source::Target,
+            "request" => Source::Request,
+            "status" => Source::Status,
+            "size" => Source::Size,
+            "time" => Source::Time,
+            _ => return None,
+        })
+    }
+
+    fn is_text(self) -> bool {
+        !matches!(self, Source::Status | Source::Size | Source::Time)
+    }
+
+    fn text(self, entry: &LogEntry) -> Option<String> {
+        match self {
+            Source::Host => entry.host.map(|h| h.to_string()),
+            Source::Ident => entry.ident.clone(),
+            Source::Authuser => entry.authuser.clone(),
+            Source::Method => entry.method().map(str::to_owned),
+            Source::Path => entry.path().map(str::to_owned),
+            Source::Target => entry.target().map(str::to_owned),
+            Source::Request => entry.request_line.clone(),
+            Source::Status | Source::Size | Source::Time => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Field(Source),
+    Class,
+    Depth(Source),
+    Segment(Source, usize),
+    Extension(Source),
+    Lower(Source),
+    Len(Source),
+    Hour,
+    Weekday,
+    Test(Filter),
+}
+
+impl Expr {
+    fn kind(&self) -> ValueKind {
+        match self {
+            Expr::Field(s) if s.is_text() => ValueKind::Text,
+            Expr::Field(_) | Expr::Depth(_) | Expr::Len(_) | Expr::Hour => ValueKind::Num,
+            Expr::Test(_) => ValueKind::Bool,
+            _ => ValueKind::Text,
+        }
+    }
+
+    fn eval(&self, entry: &LogEntry) -> Option<Value> {
+        let segments = |s: String| -> Vec<String> {
+            s.split('/')
+                .filter(|seg| !seg.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        Some(match self {
+            Expr::Field(Source::Status) => Value::Num(entry.status_code?.as_u16() as u64),
+            Expr::Field(Source::Size) => Value::Num(entry.object_size? as u64),
+            Expr::Field(s) => Value::Text(s.text(entry)?),
+            Expr::Class => Value::Text(format!("{}xx", entry.status_code?.as_u16() / 100)),
+            Expr::Depth(s) => Value::Num(segments(s.text(entry)?).len() as u64),
+            Expr::Segment(s, n) => Value::Text(segments(s.text(entry)?).get(n - 1)?.clone()),
+            Expr::Extension(s) => {
+                let text = s.text(entry)?;
+                let last = text.rsplit('/').next()?;
+                Value::Text(last.rsplit_once('.')?.1.to_ascii_lowercase())
+            }
+            Expr::Lower(s) => Value::Text(s.text(entry)?.to_lowercase()),
+            Expr::Len(s) => Value::Num(s.text(entry)?.len() as u64),
+            Expr::Hour => Value::Num(entry.time?.hour() as u64),
+            Expr::Weekday => Value::Text(entry.time?.weekday().to_string()),
+            Expr::Test(f) => Value::Bool(f.matches(entry)),
+        })
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(field) = Source::named(s) {
+            return match field {
+                Source::Time => Err("`time` can only be used in `hour` or `weekday`".to_owned()),
+                f => Ok(Expr::Field(f)),
+            };
+        }
+
+        let call = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .filter(|(f, _)| {
+                !f.is_empty() && f.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+            });
+        let (function, args) = match call {
+            Some((f, args)) => (f, args.split(',').map(str::trim).collect::<Vec<_>>()),
+            None => {
+                return s
+                    .parse()
+                    .map(Expr::Test)
+                    .map_err(|e: FilterParseError| e.to_string())
+            }
+        };
+
+        let source =
+            Source::named(args[0]).ok_or_else(|| format!("unknown field `{}`", args[0]))?;
+        let arity = if function == "segment" { 2 } else { 1 };
+        if args.len() != arity {
+            return Err(format!("`{}` takes {} argument(s)", function, arity));
+        }
+        let expect = |ok: bool, what: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("`{}` needs {}", function, what))
+            }
+        };
+        match function {
+            "class" => expect(source == Source::Status, "`status`").map(|_| Expr::Class),
+            "hour" => expect(source == Source::Time, "`time`").map(|_| Expr::Hour),
+            "weekday" => expect(source == Source::Time, "`time`").map(|_| Expr::Weekday),
+            "depth" | "segment" | "extension" | "lower" | "len" => {
+                expect(source.is_text(), "a text field")?;
+                Ok(match function {
+                    "depth" => Expr::Depth(source),
+                    "extension" => Expr::Extension(source),
+                    "lower" => Expr::Lower(source),
+                    "len" => Expr::Len(source),
+                    _ => {
+                        let n = args[1]
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or("`segment` counts from 1")?;
+                        Expr::Segment(source, n)
+                    }
+                })
+            }
+            f => Err(format!("unknown function `{}`", f)),
+        }
+    }
+}
+
+/// One derived field: a name and the expression which computes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedField {
+    name: String,
+    expr: Expr,
+}
+
+impl DerivedField {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        self.expr.kind()
+    }
+
+    /// The field's value for `entry`, or `None` if what it is computed from is missing.
+    pub fn compute(&self, entry: &LogEntry) -> Option<Value> {
+        self.expr.eval(entry)
+    }
+}
+
+impl FromStr for DerivedField {
+    type Err = String;
+
+    /// Parse `name = expression`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = s.split_once('=').ok_or("expected `name = expression`")?;
+        let name = name.trim();
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!("invalid field name {:?}", name));
+        }
+        if Source::named(name).is_some() {
+            return Err(format!("`{}` is already a field", name));
+        }
+        Ok(DerivedField {
+            name: name.to_owned(),
+            expr: expr.parse()?,
+        })
+    }
+}
+
+/// An error in a derived field definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedFieldError {
+    /// The line of the definition, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for DerivedFieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid derived field on line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl Error for DerivedFieldError {}
+
+/// A set of derived fields.
+///
+/// # Example
+/// ```
+/// use common_log_format::{derived::{DerivedFields, Value}, LogEntry};
+/// let fields: DerivedFields = "
+///     status_class = class(status)
+///     path_depth = depth(path)
+///     is_static_asset = path glob \"*.css\" || path glob \"*.js\"
+/// ".parse().unwrap();
+///
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /api/v1/users HTTP/1.1\" 503 0".parse().unwrap();
+/// let derived = fields.apply(entry);
+/// assert_eq!(derived.get("status_class"), Some(&Value::Text("5xx".to_owned())));
+/// assert_eq!(derived.get("path_depth"), Some(&Value::Num(3)));
+///
+/// let f = fields.parse_filter("!is_static_asset && path_depth >= 3").unwrap();
+/// assert!(f.matches_derived(&derived));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivedFields(Vec<DerivedField>);
+
+impl DerivedFields {
+    /// `status_class`, `path_depth`, and `is_static_asset` (by the path's extension).
+    pub fn standard() -> Self {
+        "status_class = class(status)
+         path_depth = depth(path)
+         is_static_asset = path glob \"*.css\" || path glob \"*.js\" || path glob \"*.png\" || path glob \"*.jpg\" || path glob \"*.jpeg\" || path glob \"*.gif\" || path glob \"*.svg\" || path glob \"*.ico\" || path glob \"*.webp\" || path glob \"*.woff\" || path glob \"*.woff2\""
+            .parse()
+            .unwrap()
+    }
+
+    /// Add `field`, replacing any field with the same name.
+    pub fn with(mut self, field: DerivedField) -> Self {
+        self.0.retain(|f| f.name != field.name);
+        self.0.push(field);
+        self
+    }
+
+    pub fn fields(&self) -> &[DerivedField] {
+        &self.0
+    }
+
+    /// Compute every field for `entry`.
+    pub fn apply(&self, entry: LogEntry) -> DerivedEntry {
+        let fields = self
+            .0
+            .iter()
+            .filter_map(|f| Some((f.name.clone(), f.compute(&entry)?)))
+            .collect();
+        DerivedEntry { entry, fields }
+    }
+
+    /// Parse a [`Filter`] expression which may also compare these fields.
+    ///
+    /// Number fields take the numeric operators, text fields the string operators, and boolean
+    /// fields `== true`, `== false`, or may stand alone, as in `is_static_asset && status >= 400`.
+    pub fn parse_filter(&self, s: &str) -> Result<Filter, FilterParseError> {
+        let kinds: HashMap<String, ValueKind> =
+            self.0.iter().map(|f| (f.name.clone(), f.kind())).collect();
+        parse_filter(s, &kinds)
+    }
+}
+
+impl FromStr for DerivedFields {
+    type Err = DerivedFieldError;
+
+    /// Parse one `name = expression` per line. Blank lines and lines starting with `#` are
+    /// ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = DerivedFields::default();
+        for (i, l) in s.lines().enumerate() {
+            let l = l.trim();
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+            let field = l.parse().map_err(|message| DerivedFieldError {
+                line: i + 1,
+                message,
+            })?;
+            fields = fields.with(field);
+        }
+        Ok(fields)
+    }
+}
+
+/// An entry with its derived fields.
+///
+/// Serializes as the entry's fields followed by the derived ones.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DerivedEntry {
+    #[serde(flatten)]
+    pub entry: LogEntry,
+    /// The derived fields which could be computed for this entry.
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl DerivedEntry {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+}
+
+/// [`Counters`] for each value of the derived field `name`. Entries without it are skipped.
+///
+/// # Example
+/// ```
+/// use common_log_format::{derived::{count_by, DerivedFields, Value}, LogEntry};
+/// let fields = DerivedFields::standard();
+/// let entries: Vec<_> = ["/", "/site.css", "/app.js"]
+///     .iter()
+///     .map(|p| format!("10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET {} HTTP/1.1\" 200 100", p))
+///     .map(|l| fields.apply(l.parse::<LogEntry>().unwrap()))
+///     .collect();
+///
+/// let by_kind = count_by(&entries, "is_static_asset");
+/// assert_eq!(by_kind[&Value::Bool(true)].requests, 2);
+/// assert_eq!(by_kind[&Value::Bool(false)].bytes, 100);
+/// ```
+pub fn count_by<'a>(
+    entries: impl IntoIterator<Item = &'a DerivedEntry>,
+    name: &str,
+) -> BTreeMap<Value, Counters> {
+    let mut counts: BTreeMap<Value, Counters> = BTreeMap::new();
+    for e in entries {
+        if let Some(v) = e.get(name) {
+            counts.entry(v.clone()).or_default().observe(&e.entry);
+        }
+    }
+    counts
+}
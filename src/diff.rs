@@ -0,0 +1,183 @@
+//! Comparing two logs of the same traffic.
+//!
+//! When a canary takes a share of the traffic, or a site moves to a new server, the old and new
+//! logs over the same period should tell the same story. [`diff`] counts the requests for each
+//! method and path in both, and a [`LogDiff`] picks out where they disagree: paths requested in
+//! only one, paths whose usual status changed, and paths whose volume moved.
+//!
+//! Paths are compared without their query strings. Entries without a request line are counted
+//! in the totals only.
+
+use std::collections::BTreeMap;
+
+use crate::LogEntry;
+
+/// The requests for a path (or a whole log) on one side of a diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Counts {
+    pub requests: u64,
+    pub bytes: u64,
+    /// Requests by status code, without those which had none.
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+impl Counts {
+    fn add(&mut self, entry: &LogEntry) {
+        self.requests += 1;
+        self.bytes += entry.object_size.unwrap_or(0) as u64;
+        if let Some(status) = entry.status_code {
+            *self.statuses.entry(status.as_u16()).or_default() += 1;
+        }
+    }
+
+    /// The most common status, the lowest of those tied.
+    pub fn usual_status(&self) -> Option<u16> {
+        let mut usual: Option<(u16, u64)> = None;
+        for (&status, &n) in &self.statuses {
+            if usual.is_none_or(|(_, most)| n > most) {
+                usual = Some((status, n));
+            }
+        }
+        usual.map(|(status, _)| status)
+    }
+
+    /// The fraction of requests which failed with a 5xx status.
+    pub fn error_rate(&self) -> f64 {
+        let errors: u64 = self.statuses.range(500..600).map(|(_, n)| n).sum();
+        errors as f64 / self.requests.max(1) as f64
+    }
+}
+
+/// The requests for one method and path in each log.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PathDiff {
+    pub method: String,
+    pub path: String,
+    pub a: Counts,
+    pub b: Counts,
+}
+
+impl PathDiff {
+    /// How many more requests `b` has than `a`.
+    pub fn delta(&self) -> i64 {
+        self.b.requests as i64 - self.a.requests as i64
+    }
+
+    /// The change in requests relative to `a`, such as `0.5` for half as many again, or
+    /// infinite if only `b` has any.
+    pub fn relative_delta(&self) -> f64 {
+        self.delta() as f64 / self.a.requests as f64
+    }
+}
+
+/// How two logs differ, from [`diff`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct LogDiff {
+    /// All the entries of each log.
+    pub a: Counts,
+    pub b: Counts,
+    /// Every method and path requested in either log, by path and then method.
+    pub paths: Vec<PathDiff>,
+}
+
+impl LogDiff {
+    /// The paths requested in `a` but not in `b`.
+    pub fn only_in_a(&self) -> impl Iterator<Item = &PathDiff> {
+        self.paths.iter().filter(|p| p.b.requests == 0)
+    }
+
+    /// The paths requested in `b` but not in `a`.
+    pub fn only_in_b(&self) -> impl Iterator<Item = &PathDiff> {
+        self.paths.iter().filter(|p| p.a.requests == 0)
+    }
+
+    /// The paths requested in both whose [usual status](Counts::usual_status) differs.
+    pub fn status_changes(&self) -> impl Iterator<Item = &PathDiff> {
+        self.paths.iter().filter(|p| {
+            p.a.requests > 0 && p.b.requests > 0 && p.a.usual_status() != p.b.usual_status()
+        })
+    }
+
+    /// The paths requested in both at least `min_requests` times (in either log), whose volume
+    /// changed by at least `min_change` of `a`'s, largest change first. The minimum keeps paths
+    /// requested a handful of times, which swing by chance, out of the way.
+    pub fn volume_changes(&self, min_requests: u64, min_change: f64) -> Vec<&PathDiff> {
+        let mut changes: Vec<_> = self
+            .paths
+            .iter()
+            .filter(|p| p.a.requests > 0 && p.b.requests > 0)
+            .filter(|p| p.a.requests.max(p.b.requests) >= min_requests)
+            .filter(|p| p.relative_delta().abs() >= min_change)
+            .collect();
+        changes.sort_by(|x, y| {
+            let (x, y) = (x.relative_delta().abs(), y.relative_delta().abs());
+            y.total_cmp(&x)
+        });
+        changes
+    }
+
+    /// How many more requests `b` has than `a`, over all entries.
+    pub fn delta(&self) -> i64 {
+        self.b.requests as i64 - self.a.requests as i64
+    }
+}
+
+/// Compare the entries of two logs over the same period, such as those of the old and new server
+/// during a migration.
+///
+/// # Example
+/// ```
+/// use common_log_format::{diff::diff, LogEntry};
+/// let parse = |requests: &[(&str, u16)]| -> Vec<LogEntry> {
+///     requests
+///         .iter()
+///         .map(|(target, status)| {
+///             format!("10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET {} HTTP/1.1\" {} 10", target, status)
+///                 .parse()
+///                 .unwrap()
+///         })
+///         .collect()
+/// };
+/// let old = parse(&[("/", 200), ("/about", 200), ("/feed", 200)]);
+/// let new = parse(&[("/", 200), ("/about?ref=x", 404), ("/api", 200)]);
+///
+/// let d = diff(&old, &new);
+/// assert_eq!(d.only_in_a().map(|p| p.path.as_str()).collect::<Vec<_>>(), ["/feed"]);
+/// assert_eq!(d.only_in_b().map(|p| p.path.as_str()).collect::<Vec<_>>(), ["/api"]);
+/// let changed: Vec<_> = d.status_changes().collect();
+/// assert_eq!(changed.len(), 1);
+/// assert_eq!((changed[0].a.usual_status(), changed[0].b.usual_status()), (Some(200), Some(404)));
+/// assert_eq!(d.delta(), 0);
+/// ```
+pub fn diff<'a>(
+    a: impl IntoIterator<Item = &'a LogEntry>,
+    b: impl IntoIterator<Item = &'a LogEntry>,
+) -> LogDiff {
+    let mut totals = [Counts::default(), Counts::default()];
+    let mut paths: BTreeMap<(&str, &str), [Counts; 2]> = BTreeMap::new();
+    let sides = a
+        .into_iter()
+        .map(|e| (0, e))
+        .chain(b.into_iter().map(|e| (1, e)));
+    for (side, entry) in sides {
+        totals[side].add(entry);
+        if let (Some(method), Some(path)) = (entry.method(), entry.path()) {
+            paths.entry((path, method)).or_default()[side].add(entry);
+        }
+    }
+
+    let [a, b] = totals;
+    LogDiff {
+        a,
+        b,
+        paths: paths
+            .into_iter()
+            .map(|((path, method), [a, b])| PathDiff {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                a,
+                b,
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,180 @@
+//! Field-by-field breakdowns of how a line parses, for troubleshooting.
+//!
+//! When a line parses differently than expected, or not at all, [`dump`] shows where each field
+//! was taken from, the text it was taken from, and what it was parsed into, one field per line:
+//!
+//! ```text
+//! line: 127.0.0.1 - frank [1996-12-20T00:39:57Z] "GET / HTTP/1.0" 200 2326
+//!   host     @0   "127.0.0.1"               => 127.0.0.1
+//!   ident    @10  "-"                       => (none)
+//!   authuser @12  "frank"                   => "frank"
+//!   time     @18  "[1996-12-20T00:39:57Z]"  => 1996-12-20T00:39:57Z
+//!   request  @41  "\"GET / HTTP/1.0\""      => method "GET", target "/", protocol "HTTP/1.0"
+//!   status   @58  "200"                     => 200 OK
+//!   size     @62  "2326"                    => 2326
+//! ```
+//!
+//! Parsing stops at the first field which fails, which is shown with its error.
+
+use std::{error::Error, fmt::Display};
+
+use crate::{
+    peel_grouped_usize, peel_ip, peel_quoted_string, peel_status_code, peel_string, peel_timestamp,
+    peel_usize, LogEntryParseError, ParseOptions,
+};
+
+/// One field of a [`Dump`].
+#[derive(Debug)]
+pub struct DumpedField<'a> {
+    pub name: &'static str,
+    /// The byte offset of `raw` in the line.
+    pub offset: usize,
+    /// The text the field was taken from, or for a field which failed to parse, the rest of the
+    /// line.
+    pub raw: &'a str,
+    /// What the field was parsed into, described for people.
+    pub parsed: Result<String, LogEntryParseError>,
+}
+
+/// How a line parses, field by field. Displays as one line per field.
+#[derive(Debug)]
+pub struct Dump<'a> {
+    pub line: &'a str,
+    /// The fields parsed, up to and including the first which failed.
+    pub fields: Vec<DumpedField<'a>>,
+    /// What follows the last field, if every field parsed.
+    pub trailing: &'a str,
+}
+
+impl Dump<'_> {
+    /// Whether every field parsed.
+    pub fn is_ok(&self) -> bool {
+        self.fields.iter().all(|f| f.parsed.is_ok())
+    }
+}
+
+/// Break `line` down into its fields, as [`crate::LogEntry::parse_with`] parses it.
+///
+/// # Example
+/// ```
+/// use common_log_format::{dump::dump, ParseOptions};
+/// let line = "127.0.0.1 - - [1996-12-20T00:39:57Z] \"GET / HTTP/1.0\" 2OO 2326";
+/// let d = dump(line, &ParseOptions::default());
+/// assert!(!d.is_ok());
+///
+/// let status = d.fields.last().unwrap();
+/// assert_eq!((status.name, status.offset, status.raw), ("status", 54, "2OO 2326"));
+/// assert!(d.to_string().contains("status   @54  \"2OO 2326\""));
+/// ```
+pub fn dump<'a>(line: &'a str, opts: &ParseOptions) -> Dump<'a> {
+    let mut cursor = Cursor {
+        line,
+        remaining: line,
+        fields: vec![],
+    };
+    let parsed = (|| {
+        cursor.field("host", peel_ip, |h| h.to_string())?;
+        cursor.field("ident", peel_string, |s| format!("{:?}", s))?;
+        cursor.field("authuser", peel_string, |s| format!("{:?}", s))?;
+        cursor.field("time", peel_timestamp, |t| {
+            t.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+        })?;
+        cursor.field("request", peel_quoted_string, describe_request)?;
+        cursor.field("status", peel_status_code, |s| s.to_string())?;
+        if opts.size_thousands_separators {
+            cursor.field("size", peel_grouped_usize, |s| s.to_string())
+        } else {
+            cursor.field("size", peel_usize, |s| s.to_string())
+        }
+    })();
+
+    Dump {
+        line,
+        trailing: if parsed.is_some() {
+            cursor.remaining
+        } else {
+            ""
+        },
+        fields: cursor.fields,
+    }
+}
+
+fn describe_request(request: &str) -> String {
+    let mut words = request.split(' ');
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (Some(method), Some(target), Some(protocol), None) => format!(
+            "method {:?}, target {:?}, protocol {:?}",
+            method, target, protocol
+        ),
+        _ => format!("{:?} (not a method, target, and protocol)", request),
+    }
+}
+
+struct Cursor<'a> {
+    line: &'a str,
+    remaining: &'a str,
+    fields: Vec<DumpedField<'a>>,
+}
+
+impl<'a> Cursor<'a> {
+    /// Peel the next field with `peel`, recording it. Returns `None` if it failed.
+    fn field<T>(
+        &mut self,
+        name: &'static str,
+        peel: impl Fn(&'a str) -> Result<(Option<T>, &'a str), LogEntryParseError>,
+        describe: impl Fn(T) -> String,
+    ) -> Option<()> {
+        let start = self.remaining.trim_start();
+        let offset = self.line.len() - start.len();
+        match peel(self.remaining) {
+            Ok((value, rest)) => {
+                let raw = start[..start.len() - rest.len()].trim_end();
+                self.fields.push(DumpedField {
+                    name,
+                    offset,
+                    raw,
+                    parsed: Ok(value.map_or_else(|| "(none)".to_owned(), describe)),
+                });
+                self.remaining = rest;
+                Some(())
+            }
+            Err(e) => {
+                self.fields.push(DumpedField {
+                    name,
+                    offset,
+                    raw: start,
+                    parsed: Err(e),
+                });
+                None
+            }
+        }
+    }
+}
+
+impl Display for Dump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "line: {}", self.line)?;
+        let raw_width = self
+            .fields
+            .iter()
+            .map(|field| format!("{:?}", field.raw).len())
+            .max()
+            .unwrap_or(0);
+        for field in &self.fields {
+            let offset = format!("@{}", field.offset);
+            let raw = format!("{:?}", field.raw);
+            write!(f, "  {:<8} {:<4} {:<raw_width$}  ", field.name, offset, raw)?;
+            match &field.parsed {
+                Ok(parsed) => writeln!(f, "=> {}", parsed)?,
+                Err(e) => match e.source() {
+                    Some(source) => writeln!(f, "!! {}: {}", e, source)?,
+                    None => writeln!(f, "!! {}: field not found", e)?,
+                },
+            }
+        }
+        if !self.trailing.trim().is_empty() {
+            writeln!(f, "  trailing {:?}", self.trailing)?;
+        }
+        Ok(())
+    }
+}
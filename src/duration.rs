@@ -0,0 +1,167 @@
+//! Request durations, as logged by servers which append them to each line.
+//!
+//! Servers log how long a request took in different units: Apache's `%D` is whole microseconds
+//! and `%T` whole seconds, while nginx's `$request_time` is seconds with millisecond precision
+//! (`0.123`). A [`LoggedDuration`] holds the value as a [`Duration`] along with the unit it was
+//! logged in, so that it displays exactly as it was read.
+//!
+//! [`parse_timed`] reads lines with a duration appended, into [`CanonicalEntry::duration`].
+
+use std::{fmt::Display, time::Duration};
+
+use crate::{
+    canonical::CanonicalEntry, combined::CombinedLogEntry, peel_string, LogEntryParseError,
+    ParseOptions,
+};
+
+/// The unit a duration is logged in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum DurationUnit {
+    /// Whole microseconds, as Apache's `%D`.
+    Micros,
+    /// Whole milliseconds, as Apache's `%{ms}T`.
+    Millis,
+    /// Whole seconds, as Apache's `%T`.
+    Seconds,
+    /// Seconds with the given number of decimal places (at most 9), as nginx's `$request_time`
+    /// (3 places).
+    FractionalSeconds(u8),
+}
+
+impl DurationUnit {
+    /// nginx's `$request_time` and `$upstream_response_time`.
+    pub const NGINX: Self = DurationUnit::FractionalSeconds(3);
+}
+
+/// A duration together with the unit it was logged in.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use common_log_format::duration::{DurationUnit, LoggedDuration};
+/// let d = LoggedDuration::parse("0.125", DurationUnit::NGINX).unwrap();
+/// assert_eq!(d.duration, Duration::from_millis(125));
+/// assert_eq!(d.to_string(), "0.125");
+///
+/// let d = LoggedDuration::parse("1500", DurationUnit::Micros).unwrap();
+/// assert_eq!(d.duration, Duration::from_micros(1500));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct LoggedDuration {
+    pub duration: Duration,
+    pub unit: DurationUnit,
+}
+
+impl LoggedDuration {
+    /// Parse `s`, which is in `unit`.
+    ///
+    /// For [`DurationUnit::FractionalSeconds`], the number of decimal places in `s` is recorded
+    /// in the result's unit, whatever the number given. Digits beyond nanoseconds are rejected.
+    pub fn parse(s: &str, unit: DurationUnit) -> Result<Self, LogEntryParseError> {
+        let whole = |s: &str| s.parse::<u64>().map_err(LogEntryParseError::DurationParse);
+        let (duration, unit) = match unit {
+            DurationUnit::Micros => (Duration::from_micros(whole(s)?), unit),
+            DurationUnit::Millis => (Duration::from_millis(whole(s)?), unit),
+            DurationUnit::Seconds => (Duration::from_secs(whole(s)?), unit),
+            DurationUnit::FractionalSeconds(_) => {
+                let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+                if frac.len() > 9 || frac.starts_with(['+', '-']) {
+                    return Err(LogEntryParseError::FieldNotFound);
+                }
+
+                let nanos = if frac.is_empty() {
+                    0
+                } else {
+                    whole(frac)? * 10u64.pow(9 - frac.len() as u32)
+                };
+                (
+                    Duration::new(whole(secs)?, nanos as u32),
+                    DurationUnit::FractionalSeconds(frac.len() as u8),
+                )
+            }
+        };
+
+        Ok(LoggedDuration { duration, unit })
+    }
+}
+
+impl Display for LoggedDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.unit {
+            DurationUnit::Micros => write!(f, "{}", self.duration.as_micros()),
+            DurationUnit::Millis => write!(f, "{}", self.duration.as_millis()),
+            DurationUnit::Seconds => write!(f, "{}", self.duration.as_secs()),
+            DurationUnit::FractionalSeconds(0) => write!(f, "{}", self.duration.as_secs()),
+            DurationUnit::FractionalSeconds(places) => {
+                let places = places.min(9) as u32;
+                let frac = self.duration.subsec_nanos() / 10u32.pow(9 - places);
+                write!(
+                    f,
+                    "{}.{:0width$}",
+                    self.duration.as_secs(),
+                    frac,
+                    width = places as usize
+                )
+            }
+        }
+    }
+}
+
+/// Take a [`LoggedDuration`] in `unit` from the start of `line` until the first whitespace.
+///
+/// Return None (and the remainder) if the string starts with `-`
+///
+/// # Example
+/// ```
+/// use common_log_format::duration::{peel_duration, DurationUnit};
+/// let (d, rem) = peel_duration("0.002 \"curl/8.0\"", DurationUnit::NGINX).unwrap();
+/// assert_eq!(d.unwrap().duration.as_millis(), 2);
+/// assert_eq!(rem, "\"curl/8.0\"");
+/// ```
+pub fn peel_duration(
+    line: &str,
+    unit: DurationUnit,
+) -> Result<(Option<LoggedDuration>, &str), LogEntryParseError> {
+    let (field, rem) = peel_string(line)?;
+    Ok((
+        field.map(|f| LoggedDuration::parse(f, unit)).transpose()?,
+        rem,
+    ))
+}
+
+/// Parse a Common or Combined Log Format line with a duration in `unit` appended, such as nginx's
+/// `$request_time` or Apache's `%D`, or as the request-logging middleware writes with a duration
+/// unit set.
+///
+/// A duration logged as `-` is `None`.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use common_log_format::{duration::{parse_timed, DurationUnit}, ParseOptions};
+/// let line = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512 \"-\" \"curl/8.5.0\" 0.042";
+/// let e = parse_timed(line, DurationUnit::NGINX, &ParseOptions::default()).unwrap();
+/// assert_eq!(e.duration.unwrap().duration, Duration::from_millis(42));
+/// assert_eq!(e.user_agent.as_deref(), Some("curl/8.5.0"));
+///
+/// let line = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512 1500";
+/// let e = parse_timed(line, DurationUnit::Micros, &ParseOptions::default()).unwrap();
+/// assert_eq!(e.duration.unwrap().duration, Duration::from_micros(1500));
+/// ```
+pub fn parse_timed(
+    line: &str,
+    unit: DurationUnit,
+    opts: &ParseOptions,
+) -> Result<CanonicalEntry, LogEntryParseError> {
+    let (rest, field) = line
+        .trim_end()
+        .rsplit_once(' ')
+        .ok_or(LogEntryParseError::FieldNotFound)?;
+    let duration = match field {
+        "-" => None,
+        f => Some(LoggedDuration::parse(f, unit)?),
+    };
+    let mut entry: CanonicalEntry = CombinedLogEntry::parse_with(rest, opts)?.into();
+    entry.duration = duration;
+    Ok(entry)
+}
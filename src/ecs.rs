@@ -0,0 +1,225 @@
+//! Serializing entries with [Elastic Common Schema] field names.
+//!
+//! `LogEntry`'s own serialization uses its field names, which no log store knows. Wrapping an
+//! entry in [`Ecs`] serializes it as an ECS document instead, which Elasticsearch, OpenSearch,
+//! Kibana, and most ingest pipelines map without configuration:
+//!
+//! | field                          | from                                                   |
+//! |--------------------------------|--------------------------------------------------------|
+//! | `@timestamp`                   | [`LogEntry::time`], in RFC 3339 to the microsecond     |
+//! | `client.ip`                    | [`LogEntry::host`], or [`CanonicalEntry::client_ip`]   |
+//! | `user.name`                    | [`LogEntry::authuser`]                                 |
+//! | `http.request.method`          | [`LogEntry::method`]                                   |
+//! | `http.request.referrer`        | [`CanonicalEntry::referer`]                            |
+//! | `http.response.status_code`    | [`LogEntry::status_code`]                              |
+//! | `http.response.body.bytes`     | [`LogEntry::object_size`]                              |
+//! | `http.version`                 | the request line's protocol, without `HTTP/`           |
+//! | `url.original`, `url.path`     | [`LogEntry::target`], [`LogEntry::path`]               |
+//! | `user_agent.original`          | [`CanonicalEntry::user_agent`]                         |
+//! | `event.duration`               | [`CanonicalEntry::duration`], in nanoseconds           |
+//!
+//! Fields the entry doesn't have are left out, and so is `ident`, which has no ECS field.
+//! Objects are nested, as in `{"client": {"ip": "10.0.0.1"}}`, rather than dotted.
+//!
+//! [Elastic Common Schema]: https://www.elastic.co/guide/en/ecs/current/index.html
+
+use std::net::IpAddr;
+
+use serde::{Serialize, Serializer};
+
+use crate::{canonical::CanonicalEntry, LogEntry};
+
+/// An entry which serializes as an ECS document.
+///
+/// # Example
+/// ```
+/// use common_log_format::{canonical::CanonicalEntry, ecs::Ecs, LogEntry};
+/// let entry: LogEntry = "10.0.0.1 - frank [2024-05-01T13:00:00Z] \"GET /a?b=1 HTTP/1.1\" 200 10".parse().unwrap();
+/// assert_eq!(
+///     serde_json::to_value(Ecs::new(&entry)).unwrap(),
+///     serde_json::json!({
+///         "@timestamp": "2024-05-01T13:00:00.000000Z",
+///         "client": { "ip": "10.0.0.1" },
+///         "user": { "name": "frank" },
+///         "http": {
+///             "request": { "method": "GET" },
+///             "response": { "status_code": 200, "body": { "bytes": 10 } },
+///             "version": "1.1",
+///         },
+///         "url": { "original": "/a?b=1", "path": "/a" },
+///     }),
+/// );
+///
+/// let mut canonical = CanonicalEntry::from(entry);
+/// canonical.user_agent = Some("curl/8.5.0".to_owned());
+/// let doc = serde_json::to_value(Ecs::from(&canonical)).unwrap();
+/// assert_eq!(doc["user_agent"]["original"], "curl/8.5.0");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Ecs<'a> {
+    entry: &'a LogEntry,
+    client: Option<IpAddr>,
+    referer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    duration_nanos: Option<u128>,
+}
+
+impl<'a> Ecs<'a> {
+    pub fn new(entry: &'a LogEntry) -> Self {
+        Ecs {
+            entry,
+            client: entry.host,
+            referer: None,
+            user_agent: None,
+            duration_nanos: None,
+        }
+    }
+}
+
+impl<'a> From<&'a LogEntry> for Ecs<'a> {
+    fn from(entry: &'a LogEntry) -> Self {
+        Ecs::new(entry)
+    }
+}
+
+impl<'a> From<&'a CanonicalEntry> for Ecs<'a> {
+    fn from(c: &'a CanonicalEntry) -> Self {
+        Ecs {
+            entry: &c.entry,
+            client: c.client_ip(),
+            referer: c.referer.as_deref(),
+            user_agent: c.user_agent.as_deref(),
+            duration_nanos: c.duration.map(|d| d.duration.as_nanos()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    #[serde(rename = "@timestamp", skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<Ip>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<Name<'a>>,
+    #[serde(skip_serializing_if = "Http::is_empty")]
+    http: Http<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<Url<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<Original<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<Event>,
+}
+
+#[derive(Serialize)]
+struct Ip {
+    ip: IpAddr,
+}
+
+#[derive(Serialize)]
+struct Name<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct Original<'a> {
+    original: &'a str,
+}
+
+#[derive(Serialize)]
+struct Http<'a> {
+    #[serde(skip_serializing_if = "Request::is_empty")]
+    request: Request<'a>,
+    #[serde(skip_serializing_if = "Response::is_empty")]
+    response: Response,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<&'a str>,
+}
+
+impl Http<'_> {
+    fn is_empty(&self) -> bool {
+        self.request.is_empty() && self.response.is_empty() && self.version.is_none()
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    referrer: Option<&'a str>,
+}
+
+impl Request<'_> {
+    fn is_empty(&self) -> bool {
+        self.method.is_none() && self.referrer.is_none()
+    }
+}
+
+#[derive(Serialize)]
+struct Response {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<Bytes>,
+}
+
+impl Response {
+    fn is_empty(&self) -> bool {
+        self.status_code.is_none() && self.body.is_none()
+    }
+}
+
+#[derive(Serialize)]
+struct Bytes {
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct Url<'a> {
+    original: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Event {
+    duration: u128,
+}
+
+impl Serialize for Ecs<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let e = self.entry;
+        let version = e
+            .request_line
+            .as_deref()
+            .and_then(|l| l.split(' ').nth(2))
+            .and_then(|p| p.strip_prefix("HTTP/"));
+        Document {
+            timestamp: e
+                .time
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+            client: self.client.map(|ip| Ip { ip }),
+            user: e.authuser.as_deref().map(|name| Name { name }),
+            http: Http {
+                request: Request {
+                    method: e.method(),
+                    referrer: self.referer,
+                },
+                response: Response {
+                    status_code: e.status_code.map(|s| s.as_u16()),
+                    body: e.object_size.map(|bytes| Bytes { bytes }),
+                },
+                version,
+            },
+            url: e.target().map(|original| Url {
+                original,
+                path: e.path(),
+            }),
+            user_agent: self.user_agent.map(|original| Original { original }),
+            event: self.duration_nanos.map(|duration| Event { duration }),
+        }
+        .serialize(serializer)
+    }
+}
@@ -0,0 +1,275 @@
+//! Indexing entries in Elasticsearch or OpenSearch.
+//!
+//! [`BulkSink`] sends batches of entries with the [`_bulk` API], as [`Ecs`] documents, whose
+//! Elastic Common Schema field names Kibana, OpenSearch Dashboards, and most ingest pipelines
+//! expect. Like the other HTTP sinks, it speaks plain HTTP/1.1 itself, without TLS.
+//!
+//! [`_bulk` API]: https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-bulk.html
+
+use std::{fmt::Write as _, io, time::Duration};
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{
+    ecs::Ecs,
+    http_post::{Backoff, Endpoint},
+    replay::InvalidUrl,
+    sink::Sink,
+    LogEntry,
+};
+
+/// A [`Sink`] which indexes entries in Elasticsearch or OpenSearch with `_bulk` requests.
+///
+/// Each entry goes to the index named by formatting its time (or the current time, if it has
+/// none) with an index pattern, `access-%Y.%m.%d` by default, whose `%` specifiers are
+/// [chrono's](chrono::format::strftime). Entries are created with the `create` action, so the
+/// pattern may name a data stream. An invalid pattern makes [`Sink::send`] fail.
+///
+/// Entries are sent in batches of 1,000 (by default), when a batch fills and on
+/// [`Sink::flush`]. A request which fails to connect, times out, or is answered with a 5xx or 429
+/// status is retried, as are the entries the cluster rejected with 429 because it was busy: up
+/// to 5 times, waiting 100ms before the first retry and twice as long before each one after, up
+/// to 10s. If that still fails, the error is returned and the entries not yet indexed are kept,
+/// so the next flush tries them again. Entries rejected for any other reason, such as a mapping
+/// conflict, are dropped and counted in [`BulkSink::rejected`].
+///
+/// # Example
+/// ```
+/// use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener};
+/// use common_log_format::{elasticsearch::BulkSink, sink::Sink, LogEntry};
+///
+/// // A cluster which is too busy for the first item of the first request.
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// let server = std::thread::spawn(move || {
+///     let mut bodies = vec![];
+///     for (i, stream) in listener.incoming().take(2).enumerate() {
+///         let mut reader = BufReader::new(stream.unwrap());
+///         let mut length = 0;
+///         loop {
+///             let mut line = String::new();
+///             reader.read_line(&mut line).unwrap();
+///             match line.trim_end().split_once(": ") {
+///                 Some(("Content-Length", n)) => length = n.parse().unwrap(),
+///                 None if line.trim_end().is_empty() => break,
+///                 _ => (),
+///             }
+///         }
+///         let mut body = vec![0; length];
+///         reader.read_exact(&mut body).unwrap();
+///         bodies.push(String::from_utf8(body).unwrap());
+///         let response = match i {
+///             0 => r#"{"errors":true,"items":[{"create":{"status":429}},{"create":{"status":201}}]}"#,
+///             _ => r#"{"errors":false,"items":[{"create":{"status":201}}]}"#,
+///         };
+///         write!(reader.get_mut(), "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", response.len(), response).unwrap();
+///     }
+///     bodies
+/// });
+///
+/// let a: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10".parse().unwrap();
+/// let b: LogEntry = "10.0.0.1 - - [2024-05-02T13:00:00Z] \"GET /b HTTP/1.1\" 200 10".parse().unwrap();
+/// let mut sink = BulkSink::new(&url).unwrap();
+/// sink.send_all([&a, &b]).unwrap();
+/// assert_eq!((sink.indexed(), sink.rejected()), (2, 0));
+///
+/// let bodies = server.join().unwrap();
+/// let lines: Vec<&str> = bodies[0].lines().collect();
+/// assert_eq!(lines[0], r#"{"create":{"_index":"access-2024.05.01"}}"#);
+/// assert!(lines[1].contains(r#""http":{"request":{"method":"GET"},"response":{"status_code":200,"body":{"bytes":10}},"version":"1.1"}"#));
+/// assert_eq!(lines[2], r#"{"create":{"_index":"access-2024.05.02"}}"#);
+/// // Only the rejected entry is sent again.
+/// assert_eq!(bodies[1], format!("{}\n{}\n", lines[0], lines[1]));
+/// ```
+pub struct BulkSink {
+    endpoint: Endpoint,
+    index: String,
+    headers: Vec<(&'static str, String)>,
+    backoff: Backoff,
+    batch_size: usize,
+    /// The actions and documents of the current batch.
+    body: Vec<u8>,
+    /// Where each entry's action starts in `body`.
+    starts: Vec<usize>,
+    indexed: u64,
+    rejected: u64,
+    last_rejection: Option<String>,
+}
+
+impl BulkSink {
+    /// Index entries through the cluster at `url`, such as `http://localhost:9200`.
+    pub fn new(url: &str) -> Result<Self, InvalidUrl> {
+        Ok(BulkSink {
+            endpoint: Endpoint::new(url)?,
+            index: "access-%Y.%m.%d".to_owned(),
+            headers: vec![("Content-Type", "application/x-ndjson".to_owned())],
+            backoff: Backoff::default(),
+            batch_size: 1_000,
+            body: vec![],
+            starts: vec![],
+            indexed: 0,
+            rejected: 0,
+            last_rejection: None,
+        })
+    }
+
+    /// Name each entry's index by formatting its time with `pattern`, such as `logs-%Y.%m` for
+    /// monthly indices or `access` for a single one.
+    pub fn with_index(mut self, pattern: &str) -> Self {
+        self.index = pattern.to_owned();
+        self
+    }
+
+    /// Authenticate with an API key, in the base64 encoding Elasticsearch returns as `encoded`.
+    pub fn with_api_key(mut self, key: &str) -> Self {
+        self.headers
+            .push(("Authorization", format!("ApiKey {}", key)));
+        self
+    }
+
+    /// Send every `batch_size` entries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Retry a failed request, or entries rejected with 429, up to `retries` times, first after
+    /// `initial`, doubling the wait each time up to `max`.
+    pub fn with_backoff(mut self, retries: u32, initial: Duration, max: Duration) -> Self {
+        self.backoff = Backoff {
+            retries,
+            initial,
+            max,
+        };
+        self
+    }
+
+    /// Give up on connecting, sending a request, or waiting for its response after `timeout`
+    /// (30s by default).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+
+    /// The number of entries indexed so far.
+    pub fn indexed(&self) -> u64 {
+        self.indexed
+    }
+
+    /// The number of entries the cluster rejected and which were dropped.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// The reason the cluster gave for the latest rejection.
+    pub fn last_rejection(&self) -> Option<&str> {
+        self.last_rejection.as_deref()
+    }
+
+    /// The number of entries waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Send the batch once, returning the positions of the entries rejected with 429.
+    fn send_batch(&mut self) -> io::Result<Vec<usize>> {
+        let headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        let response = self.backoff.post("Elasticsearch", || {
+            self.endpoint.post("/_bulk", &headers, &self.body)
+        })?;
+        let response: Value = serde_json::from_str(&response.body).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid _bulk response: {}", e),
+            )
+        })?;
+        let items = response["items"].as_array().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "_bulk response has no items")
+        })?;
+
+        let mut busy = vec![];
+        for (i, item) in items.iter().enumerate() {
+            // Each item is an object whose only key is the action.
+            let result = item.as_object().and_then(|o| o.values().next());
+            match result.and_then(|r| r["status"].as_u64()) {
+                Some(status) if status < 300 => self.indexed += 1,
+                Some(429) => busy.push(i),
+                _ => {
+                    self.rejected += 1;
+                    self.last_rejection = result.map(|r| r["error"].to_string());
+                }
+            }
+        }
+        Ok(busy)
+    }
+
+    /// Keep only the entries at `positions` in the batch.
+    fn retain(&mut self, positions: &[usize]) {
+        let mut body = Vec::new();
+        let mut starts = Vec::with_capacity(positions.len());
+        for &i in positions {
+            let end = self.starts.get(i + 1).copied().unwrap_or(self.body.len());
+            starts.push(body.len());
+            body.extend_from_slice(&self.body[self.starts[i]..end]);
+        }
+        self.body = body;
+        self.starts = starts;
+    }
+}
+
+impl Sink for BulkSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let mut index = String::new();
+        write!(
+            index,
+            "{}",
+            entry.time.unwrap_or_else(Utc::now).format(&self.index)
+        )
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid index pattern {:?}", self.index),
+            )
+        })?;
+        let action = json!({ "create": { "_index": index } });
+        self.starts.push(self.body.len());
+        serde_json::to_writer(&mut self.body, &action).expect("actions serialize to JSON");
+        self.body.push(b'\n');
+        serde_json::to_writer(&mut self.body, &Ecs::new(entry))
+            .expect("documents serialize to JSON");
+        self.body.push(b'\n');
+        if self.starts.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send the current batch, if there is one, retrying the entries rejected with 429.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut attempt = 0;
+        while !self.starts.is_empty() {
+            let busy = self.send_batch()?;
+            self.retain(&busy);
+            if busy.is_empty() {
+                break;
+            }
+            if attempt >= self.backoff.retries {
+                return Err(io::Error::other(format!(
+                    "Elasticsearch rejected {} entries with 429 after {} retries",
+                    busy.len(),
+                    attempt
+                )));
+            }
+            std::thread::sleep(self.backoff.delay(attempt));
+            attempt += 1;
+        }
+        Ok(())
+    }
+}
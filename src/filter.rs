@@ -0,0 +1,819 @@
+//! Predicates over log entries, built in code or parsed from expressions.
+//!
+//! A [`Filter`] can be built with a fluent API:
+//!
+//! ```
+//! use common_log_format::filter::Filter;
+//! let f = Filter::status().ge(500)
+//!     .and(Filter::path().contains("/api/"))
+//!     .and(Filter::host().in_net("10.0.0.0/8".parse().unwrap()));
+//! ```
+//!
+//! or parsed from an expression, such as one given on a command line:
+//!
+//! ```
+//! use common_log_format::{filter::Filter, LogEntry};
+//! let f: Filter = r#"status >= 500 && path ~ "/api/" && host in 10.0.0.0/8"#.parse().unwrap();
+//! let entry: LogEntry = "10.1.2.3 - - [2024-05-01T13:00:00Z] \"GET /api/users HTTP/1.1\" 503 0".parse().unwrap();
+//! assert!(f.matches(&entry));
+//! ```
+//!
+//! Expressions combine comparisons with `&&`, `||`, `!`, and parentheses, nested up to 256
+//! deep. A comparison is a field, an operator, and a value:
+//!
+//! | fields                           | operators                          | values              |
+//! |----------------------------------|------------------------------------|---------------------|
+//! | `status`, `size`                 | `==` `!=` `<` `<=` `>` `>=`        | integers            |
+//! | `time`                           | `==` `!=` `<` `<=` `>` `>=`        | RFC 3339 timestamps |
+//! | `method`, `path`, `target`, `request`, `ident`, `authuser` | `==` `!=` `~` `!~` `glob` | strings |
+//! | `host`                           | `==` `!=` `in`                     | addresses, networks |
+//!
+//! `~` tests whether the field contains the value, and `glob` whether it matches the value as a
+//! pattern in which `*` matches anything. `host == net` and `host in net` are the same, as are
+//! `host != net` and `!(host in net)` for entries with a host.
+//!
+//! Strings may be quoted with `"`. A comparison with a field which is missing from an entry is
+//! false, whatever the operator.
+//!
+//! Expressions parsed with [`DerivedFields::parse_filter`] may also compare derived fields (see
+//! the [`derived`](crate::derived) module), which [`Filter::matches_derived`] tests.
+//!
+//! [`DerivedFields::parse_filter`]: crate::derived::DerivedFields::parse_filter
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::Display,
+    net::IpAddr,
+    ops::{BitAnd, BitOr, Not},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    derived::{DerivedEntry, Value, ValueKind},
+    pattern, LogEntry,
+};
+
+/// An IP network, such as `10.0.0.0/8`.
+///
+/// # Example
+/// ```
+/// use common_log_format::filter::Cidr;
+/// let net: Cidr = "192.168.0.0/16".parse().unwrap();
+/// assert!(net.contains("192.168.4.1".parse().unwrap()));
+/// assert!(!net.contains("10.0.0.1".parse().unwrap()));
+/// assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` is in the network. IPv4 addresses and IPv4-mapped IPv6 addresses are the
+    /// same: `::ffff:10.0.0.0/104` is `10.0.0.0/8`, and contains both `10.1.2.3` and
+    /// `::ffff:10.1.2.3`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::filter::Cidr;
+    /// let mapped: Cidr = "::ffff:10.0.0.0/104".parse().unwrap();
+    /// assert!(mapped.contains("10.1.2.3".parse().unwrap()));
+    /// assert!(mapped.contains("::ffff:10.1.2.3".parse().unwrap()));
+    /// assert!(!mapped.contains("11.0.0.1".parse().unwrap()));
+    ///
+    /// let v6: Cidr = "2001:db8::/32".parse().unwrap();
+    /// assert!(!v6.contains("10.1.2.3".parse().unwrap()));
+    /// ```
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32, len: u8| match (len as u32).min(bits) {
+            0 => 0,
+            l => u128::MAX << (bits - l),
+        };
+        let (net, prefix_len) = match self.addr {
+            IpAddr::V6(v6) if self.prefix_len >= 96 => match v6.to_ipv4_mapped() {
+                Some(v4) => (IpAddr::V4(v4), self.prefix_len - 96),
+                None => (self.addr, self.prefix_len),
+            },
+            addr => (addr, self.prefix_len),
+        };
+        match (net, ip) {
+            (IpAddr::V4(net), ip) => match ip.to_canonical() {
+                IpAddr::V4(ip) => {
+                    let m = mask(32, prefix_len) as u32;
+                    u32::from(net) & m == u32::from(ip) & m
+                }
+                IpAddr::V6(_) => false,
+            },
+            (IpAddr::V6(net), ip) => {
+                let ip = match ip {
+                    IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                    IpAddr::V6(v6) => v6,
+                };
+                let m = mask(128, prefix_len);
+                u128::from(net) & m == u128::from(ip) & m
+            }
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        Cidr {
+            addr,
+            prefix_len: if addr.is_ipv4() { 32 } else { 128 },
+        }
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// The string given to [`Cidr::from_str`] is not an address or network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCidr(pub String);
+
+impl Display for InvalidCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid network {:?}", self.0)
+    }
+}
+
+impl Error for InvalidCidr {}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    /// Parse `addr/prefix_len`, or a bare address as a network of just that address. The
+    /// prefix may be no longer than the address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_owned());
+        let (addr, len) = match s.split_once('/') {
+            Some((a, l)) => (a, Some(l)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match len {
+            Some(l) => l.parse().ok().filter(|l| *l <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+/// How a value is compared with a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn holds(self, ord: Ordering) -> bool {
+        match self {
+            Cmp::Eq => ord.is_eq(),
+            Cmp::Ne => ord.is_ne(),
+            Cmp::Lt => ord.is_lt(),
+            Cmp::Le => ord.is_le(),
+            Cmp::Gt => ord.is_gt(),
+            Cmp::Ge => ord.is_ge(),
+        }
+    }
+}
+
+/// A string field of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextField {
+    Method,
+    Path,
+    Target,
+    Request,
+    Ident,
+    Authuser,
+}
+
+impl TextField {
+    fn get(self, entry: &LogEntry) -> Option<&str> {
+        match self {
+            TextField::Method => entry.method(),
+            TextField::Path => entry.path(),
+            TextField::Target => entry.target(),
+            TextField::Request => entry.request_line.as_deref(),
+            TextField::Ident => entry.ident.as_deref(),
+            TextField::Authuser => entry.authuser.as_deref(),
+        }
+    }
+
+    /// `field == value`.
+    pub fn eq(self, value: impl Into<String>) -> Filter {
+        Filter::Text(self, TextOp::Eq, value.into())
+    }
+
+    /// `field != value`.
+    pub fn ne(self, value: impl Into<String>) -> Filter {
+        Filter::Text(self, TextOp::Ne, value.into())
+    }
+
+    /// `field ~ value`: the field contains `value`.
+    pub fn contains(self, value: impl Into<String>) -> Filter {
+        Filter::Text(self, TextOp::Contains, value.into())
+    }
+
+    /// `field glob value`: the field matches `value`, in which `*` matches anything.
+    pub fn glob(self, pattern: impl Into<String>) -> Filter {
+        Filter::Text(self, TextOp::Glob, pattern.into())
+    }
+}
+
+/// How a string is compared with a [`TextField`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextOp {
+    Eq,
+    Ne,
+    Contains,
+    NotContains,
+    Glob,
+}
+
+impl TextOp {
+    fn holds(self, x: &str, v: &str) -> bool {
+        match self {
+            TextOp::Eq => x == v,
+            TextOp::Ne => x != v,
+            TextOp::Contains => x.contains(v),
+            TextOp::NotContains => !x.contains(v),
+            TextOp::Glob => pattern::matches(v, x),
+        }
+    }
+}
+
+/// A numeric field of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumField {
+    Status,
+    Size,
+}
+
+impl NumField {
+    fn get(self, entry: &LogEntry) -> Option<u64> {
+        match self {
+            NumField::Status => entry.status_code.map(|s| s.as_u16() as u64),
+            NumField::Size => entry.object_size.map(|s| s as u64),
+        }
+    }
+
+    pub fn eq(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Eq, value)
+    }
+
+    pub fn ne(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Ne, value)
+    }
+
+    pub fn lt(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Lt, value)
+    }
+
+    pub fn le(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Le, value)
+    }
+
+    pub fn gt(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Gt, value)
+    }
+
+    pub fn ge(self, value: u64) -> Filter {
+        Filter::Num(self, Cmp::Ge, value)
+    }
+}
+
+/// The `time` field, for the fluent API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeField;
+
+impl TimeField {
+    pub fn before(self, t: DateTime<Utc>) -> Filter {
+        Filter::Time(Cmp::Lt, t)
+    }
+
+    pub fn at_or_after(self, t: DateTime<Utc>) -> Filter {
+        Filter::Time(Cmp::Ge, t)
+    }
+
+    /// `start <= time < end`.
+    pub fn between(self, start: DateTime<Utc>, end: DateTime<Utc>) -> Filter {
+        self.at_or_after(start).and(self.before(end))
+    }
+}
+
+/// The `host` field, for the fluent API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HostField;
+
+impl HostField {
+    pub fn eq(self, ip: IpAddr) -> Filter {
+        Filter::HostIn(ip.into())
+    }
+
+    pub fn ne(self, ip: IpAddr) -> Filter {
+        Filter::HostNotIn(ip.into())
+    }
+
+    pub fn in_net(self, net: Cidr) -> Filter {
+        Filter::HostIn(net)
+    }
+
+    pub fn not_in_net(self, net: Cidr) -> Filter {
+        Filter::HostNotIn(net)
+    }
+}
+
+/// A comparison with a derived field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DerivedTest {
+    Bool(bool),
+    Num(Cmp, u64),
+    Text(TextOp, String),
+}
+
+/// A predicate over [`LogEntry`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Matches every entry.
+    #[default]
+    True,
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Num(NumField, Cmp, u64),
+    Time(Cmp, DateTime<Utc>),
+    Text(TextField, TextOp, String),
+    HostIn(Cidr),
+    HostNotIn(Cidr),
+    /// A comparison with the derived field of the given name.
+    Derived(String, DerivedTest),
+}
+
+impl Filter {
+    pub fn status() -> NumField {
+        NumField::Status
+    }
+
+    pub fn size() -> NumField {
+        NumField::Size
+    }
+
+    pub fn time() -> TimeField {
+        TimeField
+    }
+
+    pub fn host() -> HostField {
+        HostField
+    }
+
+    pub fn method() -> TextField {
+        TextField::Method
+    }
+
+    /// The request target without its query string.
+    pub fn path() -> TextField {
+        TextField::Path
+    }
+
+    pub fn target() -> TextField {
+        TextField::Target
+    }
+
+    /// The whole request line.
+    pub fn request() -> TextField {
+        TextField::Request
+    }
+
+    pub fn ident() -> TextField {
+        TextField::Ident
+    }
+
+    pub fn authuser() -> TextField {
+        TextField::Authuser
+    }
+
+    pub fn and(self, other: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Whether `entry` matches. Comparisons with derived fields are false.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.eval(entry, None)
+    }
+
+    /// Whether `entry` matches, including comparisons with its derived fields.
+    pub fn matches_derived(&self, entry: &DerivedEntry) -> bool {
+        self.eval(&entry.entry, Some(&entry.fields))
+    }
+
+    fn eval(&self, entry: &LogEntry, derived: Option<&BTreeMap<String, Value>>) -> bool {
+        match self {
+            Filter::True => true,
+            Filter::And(a, b) => a.eval(entry, derived) && b.eval(entry, derived),
+            Filter::Or(a, b) => a.eval(entry, derived) || b.eval(entry, derived),
+            Filter::Not(f) => !f.eval(entry, derived),
+            Filter::Num(field, cmp, v) => field.get(entry).is_some_and(|x| cmp.holds(x.cmp(v))),
+            Filter::Time(cmp, t) => entry.time.is_some_and(|x| cmp.holds(x.cmp(t))),
+            Filter::Text(field, op, v) => field.get(entry).is_some_and(|x| op.holds(x, v)),
+            Filter::HostIn(net) => entry.host.is_some_and(|h| net.contains(h)),
+            Filter::HostNotIn(net) => entry.host.is_some_and(|h| !net.contains(h)),
+            Filter::Derived(name, test) => match (derived.and_then(|d| d.get(name)), test) {
+                (Some(Value::Bool(x)), DerivedTest::Bool(v)) => x == v,
+                (Some(Value::Num(x)), DerivedTest::Num(cmp, v)) => cmp.holds(x.cmp(v)),
+                (Some(Value::Text(x)), DerivedTest::Text(op, v)) => op.holds(x, v),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Self::Output {
+        Filter::Not(Box::new(self))
+    }
+}
+
+impl BitAnd for Filter {
+    type Output = Filter;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.and(rhs)
+    }
+}
+
+impl BitOr for Filter {
+    type Output = Filter;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.or(rhs)
+    }
+}
+
+/// An error parsing a [`Filter`] expression.
+///
+/// # Example
+/// ```
+/// use common_log_format::filter::Filter;
+/// let err = "status == 200 && (path ~ /api".parse::<Filter>().unwrap_err();
+/// assert_eq!(err.message, "unexpected end of filter");
+///
+/// let deep = format!("{}status == 200{}", "(".repeat(100_000), ")".repeat(100_000));
+/// let err = deep.parse::<Filter>().unwrap_err();
+/// assert_eq!((err.position, err.message.as_str()), (256, "filter is nested too deeply"));
+///
+/// // Long chains of `&&` and `||` aren't nesting.
+/// let long = vec!["status >= 200"; 100_000].join(" && ");
+/// assert!(long.parse::<Filter>().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterParseError {
+    /// The byte offset in the expression at which the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid filter at {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for FilterParseError {}
+
+impl FromStr for Filter {
+    type Err = FilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_filter(s, &HashMap::new())
+    }
+}
+
+/// Parse `s`, in which the fields in `derived` may also be compared.
+pub(crate) fn parse_filter(
+    s: &str,
+    derived: &HashMap<String, ValueKind>,
+) -> Result<Filter, FilterParseError> {
+    let mut p = Parser {
+        tokens: tokenize(s)?,
+        next: 0,
+        end: s.len(),
+        depth: 0,
+        derived,
+    };
+    let f = p.or()?;
+    match p.peek() {
+        None => Ok(f),
+        Some((pos, t)) => Err(p.error_at(pos, format!("unexpected {}", t))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A bare word: a field name, operator keyword, or unquoted value.
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "`{}`", w),
+            Token::Quoted(s) => write!(f, "{:?}", s),
+            Token::Op(o) => write!(f, "`{}`", o),
+        }
+    }
+}
+
+const OPS: [&str; 13] = [
+    "&&", "||", "==", "!=", "<=", ">=", "!~", "<", ">", "~", "!", "(", ")",
+];
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, FilterParseError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => value.push(c),
+                        None => break,
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => {
+                        return Err(FilterParseError {
+                            position: pos,
+                            message: "unterminated string".to_owned(),
+                        })
+                    }
+                }
+            }
+            tokens.push((pos, Token::Quoted(value)));
+        } else if let Some(op) = OPS.iter().find(|op| s[pos..].starts_with(*op)) {
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push((pos, Token::Op(op)));
+        } else {
+            let mut end = pos;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() || c == '"' || OPS.iter().any(|op| s[i..].starts_with(op)) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((pos, Token::Word(s[pos..end].to_owned())));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Join `terms` with `join` into a balanced tree, so a long chain of `&&` or `||` doesn't make
+/// a tree too deep to evaluate. Terms are still tested from left to right.
+fn balanced(mut terms: Vec<Filter>, join: fn(Filter, Filter) -> Filter) -> Filter {
+    while terms.len() > 1 {
+        let mut pairs = Vec::with_capacity(terms.len().div_ceil(2));
+        let mut rest = terms.into_iter();
+        while let Some(left) = rest.next() {
+            pairs.push(match rest.next() {
+                Some(right) => join(left, right),
+                None => left,
+            });
+        }
+        terms = pairs;
+    }
+    terms.pop().expect("at least one term")
+}
+
+/// The most `!` and `(` which may enclose part of a filter, to bound the parser's recursion.
+const MAX_DEPTH: usize = 256;
+
+/// A recursive descent parser, from lowest precedence (`||`) to highest (comparisons).
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    /// How many `!` and `(` enclose the current token.
+    depth: usize,
+    derived: &'a HashMap<String, ValueKind>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.next).map(|(p, t)| (*p, t))
+    }
+
+    fn take(&mut self) -> Result<(usize, Token), FilterParseError> {
+        let t = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| self.error_at(self.end, "unexpected end of filter".to_owned()))?;
+        self.next += 1;
+        Ok(t)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = matches!(self.peek(), Some((_, Token::Op(o))) if *o == op);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn error_at(&self, position: usize, message: String) -> FilterParseError {
+        FilterParseError { position, message }
+    }
+
+    fn or(&mut self) -> Result<Filter, FilterParseError> {
+        let mut terms = vec![self.and()?];
+        while self.eat("||") {
+            terms.push(self.and()?);
+        }
+        Ok(balanced(terms, Filter::or))
+    }
+
+    fn and(&mut self) -> Result<Filter, FilterParseError> {
+        let mut terms = vec![self.unary()?];
+        while self.eat("&&") {
+            terms.push(self.unary()?);
+        }
+        Ok(balanced(terms, Filter::and))
+    }
+
+    fn unary(&mut self) -> Result<Filter, FilterParseError> {
+        let nested = matches!(self.peek(), Some((_, Token::Op("!" | "("))));
+        if nested && self.depth == MAX_DEPTH {
+            let pos = self.peek().map_or(self.end, |(p, _)| p);
+            return Err(self.error_at(pos, "filter is nested too deeply".to_owned()));
+        }
+
+        if self.eat("!") {
+            self.depth += 1;
+            let f = self.unary();
+            self.depth -= 1;
+            return Ok(!f?);
+        }
+
+        if self.eat("(") {
+            self.depth += 1;
+            let f = self.or();
+            self.depth -= 1;
+            let f = f?;
+            return match self.take()? {
+                (_, Token::Op(")")) => Ok(f),
+                (pos, t) => Err(self.error_at(pos, format!("expected `)`, found {}", t))),
+            };
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Filter, FilterParseError> {
+        let (field_pos, field) = match self.take()? {
+            (pos, Token::Word(w)) => (pos, w),
+            (pos, t) => return Err(self.error_at(pos, format!("expected a field, found {}", t))),
+        };
+        let derived = self.derived.get(&field).copied();
+        let compared = matches!(self.peek(), Some((_, Token::Op("==" | "!="))));
+        if derived == Some(ValueKind::Bool) && !compared {
+            return Ok(Filter::Derived(field, DerivedTest::Bool(true)));
+        }
+        let (op_pos, op) = match self.take()? {
+            (pos, Token::Op(o)) => (pos, o.to_owned()),
+            (pos, Token::Word(w)) if w == "in" || w == "glob" => (pos, w),
+            (pos, t) => {
+                return Err(self.error_at(pos, format!("expected an operator, found {}", t)))
+            }
+        };
+        let (value_pos, value) = match self.take()? {
+            (pos, Token::Word(w)) | (pos, Token::Quoted(w)) => (pos, w),
+            (pos, t) => return Err(self.error_at(pos, format!("expected a value, found {}", t))),
+        };
+
+        let bad_op = || self.error_at(op_pos, format!("`{}` can't be used with `{}`", op, field));
+        let cmp = match op.as_str() {
+            "==" => Some(Cmp::Eq),
+            "!=" => Some(Cmp::Ne),
+            "<" => Some(Cmp::Lt),
+            "<=" => Some(Cmp::Le),
+            ">" => Some(Cmp::Gt),
+            ">=" => Some(Cmp::Ge),
+            _ => None,
+        };
+
+        let text_op = match op.as_str() {
+            "==" => Some(TextOp::Eq),
+            "!=" => Some(TextOp::Ne),
+            "~" => Some(TextOp::Contains),
+            "!~" => Some(TextOp::NotContains),
+            "glob" => Some(TextOp::Glob),
+            _ => None,
+        };
+
+        match derived {
+            Some(ValueKind::Bool) => {
+                let v: bool = value.parse().map_err(|_| {
+                    self.error_at(
+                        value_pos,
+                        format!("expected `true` or `false` for `{}`", field),
+                    )
+                })?;
+                return match cmp {
+                    Some(Cmp::Eq) => Ok(Filter::Derived(field, DerivedTest::Bool(v))),
+                    Some(Cmp::Ne) => Ok(Filter::Derived(field, DerivedTest::Bool(!v))),
+                    _ => Err(bad_op()),
+                };
+            }
+            Some(ValueKind::Num) => {
+                let v = value.parse().map_err(|_| {
+                    self.error_at(value_pos, format!("expected a number for `{}`", field))
+                })?;
+                let cmp = cmp.ok_or_else(bad_op)?;
+                return Ok(Filter::Derived(field, DerivedTest::Num(cmp, v)));
+            }
+            Some(ValueKind::Text) => {
+                let op = text_op.ok_or_else(bad_op)?;
+                return Ok(Filter::Derived(field, DerivedTest::Text(op, value)));
+            }
+            None => (),
+        }
+
+        let num_field = match field.as_str() {
+            "status" => Some(NumField::Status),
+            "size" => Some(NumField::Size),
+            _ => None,
+        };
+        if let Some(nf) = num_field {
+            let v = value.parse().map_err(|_| {
+                self.error_at(value_pos, format!("expected a number for `{}`", field))
+            })?;
+            return Ok(Filter::Num(nf, cmp.ok_or_else(bad_op)?, v));
+        }
+
+        let text_field = match field.as_str() {
+            "method" => Some(TextField::Method),
+            "path" => Some(TextField::Path),
+            "target" => Some(TextField::Target),
+            "request" => Some(TextField::Request),
+            "ident" => Some(TextField::Ident),
+            "authuser" => Some(TextField::Authuser),
+            _ => None,
+        };
+        if let Some(tf) = text_field {
+            return Ok(Filter::Text(tf, text_op.ok_or_else(bad_op)?, value));
+        }
+
+        match field.as_str() {
+            "time" => {
+                let t = DateTime::parse_from_rfc3339(&value).map_err(|_| {
+                    self.error_at(value_pos, "expected an RFC 3339 timestamp".to_owned())
+                })?;
+                Ok(Filter::Time(cmp.ok_or_else(bad_op)?, t.with_timezone(&Utc)))
+            }
+            "host" => {
+                let net: Cidr = value
+                    .parse()
+                    .map_err(|e: InvalidCidr| self.error_at(value_pos, e.to_string()))?;
+                match op.as_str() {
+                    "==" | "in" => Ok(Filter::HostIn(net)),
+                    "!=" => Ok(Filter::HostNotIn(net)),
+                    _ => Err(bad_op()),
+                }
+            }
+            _ => Err(self.error_at(field_pos, format!("unknown field `{}`", field))),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! Following a log file as it is written, like `tail -F`.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    index::LogIndex,
+    reader::{parse_salvaged, trim_line_end, FileId, ReadError},
+    seek, LogEntry, LogEntryRef, ParseOptions,
+};
+
+/// An endless iterator over the entries appended to a log file.
+///
+/// Like `tail -F`, this survives the file being truncated (it starts again from the beginning)
+/// and being rotated (it finishes the old file, then switches to the new file at the same path).
+/// If the file does not exist yet, it waits for it to appear. Lines are only parsed once their
+/// terminating newline has been written.
+///
+/// Iteration blocks, polling the file every [`Follow::with_poll_interval`], and never ends. With
+/// the `async` feature, `Follow::into_stream` runs the iterator on a background thread and
+/// returns a `Stream` instead.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::follow::Follow;
+/// for entry in Follow::new("/var/log/nginx/access.log").unwrap() {
+///     println!("{:?}", entry.unwrap());
+/// }
+/// ```
+pub struct Follow {
+    path: PathBuf,
+    file: Option<(BufReader<File>, Option<FileId>)>,
+    offset: u64,
+    line: u64,
+    poll_interval: Duration,
+    opts: ParseOptions,
+    index: Option<LogIndex>,
+    buf: Vec<u8>,
+}
+
+impl Follow {
+    /// Follow `path`, starting from its current end.
+    ///
+    /// Lines are numbered from there, until a seek goes back before it.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut f = Self::from_start(path)?;
+        if let Some((file, _)) = &mut f.file {
+            f.offset = file.seek(SeekFrom::End(0))?;
+        }
+        Ok(f)
+    }
+
+    /// Follow `path`, starting with the entries already in it.
+    pub fn from_start(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut f = Follow {
+            path: path.as_ref().to_owned(),
+            file: None,
+            offset: 0,
+            line: 0,
+            poll_interval: Duration::from_millis(250),
+            opts: ParseOptions::default(),
+            index: None,
+            buf: Vec::new(),
+        };
+        f.reopen()?;
+        Ok(f)
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Find times in [`Follow::seek_to_time`] with `index`, an index of the followed file, rather
+    /// than by binary search. The index is only used while it still
+    /// [describes](LogIndex::describes) the current file, and only for the times in it.
+    pub fn with_index(mut self, index: LogIndex) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The offset in the current file up to which entries have been returned.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Move to `offset` in the current file, which should be the start of a line.
+    ///
+    /// Line numbers in errors stay right: the lines between the old and new offsets are
+    /// counted, which reads that part of the file.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{follow::Follow, reader::ReadError};
+    /// let path = std::env::temp_dir().join(format!("clf-follow-seek-{}.log", std::process::id()));
+    /// std::fs::write(&path, "\
+    /// 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10
+    /// 10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 200 10
+    /// not a log line
+    /// ").unwrap();
+    ///
+    /// let mut follow = Follow::from_start(&path).unwrap();
+    /// follow.next().unwrap().unwrap();
+    /// let second = follow.offset();
+    /// follow.next().unwrap().unwrap();
+    /// assert!(matches!(follow.next(), Some(Err(ReadError::Parse { line: 3, .. }))));
+    ///
+    /// // Go back to the second line and read on: the bad line is still line 3.
+    /// follow.seek(second).unwrap();
+    /// assert_eq!(follow.next().unwrap().unwrap().path(), Some("/b"));
+    /// assert!(matches!(follow.next(), Some(Err(ReadError::Parse { line: 3, .. }))));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn seek(&mut self, offset: u64) -> io::Result<()> {
+        if let Some((file, _)) = &mut self.file {
+            self.line = match offset {
+                0 => 0,
+                _ if offset >= self.offset => {
+                    file.seek(SeekFrom::Start(self.offset))?;
+                    self.line + count_lines(file.by_ref().take(offset - self.offset))?
+                }
+                _ => {
+                    file.seek(SeekFrom::Start(offset))?;
+                    let lines = count_lines(file.by_ref().take(self.offset - offset))?;
+                    match self.line.checked_sub(lines) {
+                        Some(line) => line,
+                        // Back past where `Follow::new` started counting: count from the start.
+                        None => {
+                            file.seek(SeekFrom::Start(0))?;
+                            count_lines(file.by_ref().take(offset))?
+                        }
+                    }
+                }
+            };
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        self.offset = offset;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Move back (or forward) to the first entry in the current file at or after `target`, and
+    /// return its offset, as with [`seek::seek_to_time`].
+    ///
+    /// Iteration replays the entries from there, then carries on following the file. Entries
+    /// from before a rotation are not replayed. Line numbers are kept as by [`Follow::seek`].
+    ///
+    /// With an index ([`Follow::with_index`]), only the indexed block holding `target` is read,
+    /// and line numbers are taken from the index.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{follow::Follow, index::LogIndex, reader::ReadError};
+    /// let path = std::env::temp_dir().join(format!("clf-follow-time-{}.log", std::process::id()));
+    /// std::fs::write(&path, "\
+    /// 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10
+    /// 10.0.0.1 - - [2024-05-01T13:05:00Z] \"GET /b HTTP/1.1\" 200 10
+    /// not a log line
+    /// 10.0.0.1 - - [2024-05-01T13:10:00Z] \"GET /c HTTP/1.1\" 200 10
+    /// ").unwrap();
+    ///
+    /// let index = LogIndex::build(&path, 2).unwrap();
+    /// let mut follow = Follow::new(&path).unwrap().with_index(index);
+    /// follow.seek_to_time("2024-05-01T13:03:00Z".parse().unwrap()).unwrap();
+    /// assert_eq!(follow.next().unwrap().unwrap().path(), Some("/b"));
+    /// assert!(matches!(follow.next(), Some(Err(ReadError::Parse { line: 3, .. }))));
+    /// assert_eq!(follow.next().unwrap().unwrap().path(), Some("/c"));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn seek_to_time(&mut self, target: DateTime<Utc>) -> io::Result<u64> {
+        if let Some((offset, line)) = self.indexed_position(target)? {
+            if let Some((file, _)) = &mut self.file {
+                file.seek(SeekFrom::Start(offset))?;
+            }
+            self.offset = offset;
+            self.line = line;
+            self.buf.clear();
+            return Ok(offset);
+        }
+
+        let offset = match &mut self.file {
+            Some((file, _)) => seek::seek_to_time(file, target)?,
+            None => 0,
+        };
+        self.seek(offset)?;
+        Ok(offset)
+    }
+
+    /// The offset and number of the first line at or after `target`, found with the index, if
+    /// there is one which describes the current file and has an entry that late.
+    fn indexed_position(&mut self, target: DateTime<Utc>) -> io::Result<Option<(u64, u64)>> {
+        let (file, index) = match (&mut self.file, &self.index) {
+            (Some((file, _)), Some(index)) => (file, index),
+            _ => return Ok(None),
+        };
+        if !index.describes(&file.get_ref().metadata()?) {
+            return Ok(None);
+        }
+        let block = match index.block_at(target) {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+
+        // Lines which can't be parsed or have no time are skipped, as by `seek::seek_to_time`.
+        file.seek(SeekFrom::Start(block.offset))?;
+        let (mut offset, mut line) = (block.offset, block.line);
+        let mut buf = Vec::new();
+        for _ in 0..block.lines {
+            buf.clear();
+            let n = file.read_until(b'\n', &mut buf)?;
+            let time = LogEntryRef::from_bytes_with(trim_line_end(&buf), &self.opts)
+                .ok()
+                .and_then(|e| e.time);
+            if n == 0 || time.is_some_and(|t| t >= target) {
+                break;
+            }
+            offset += n as u64;
+            line += 1;
+        }
+        Ok(Some((offset, line)))
+    }
+
+    /// Replay the entries from the last `by` of wall-clock time, as with
+    /// [`Follow::seek_to_time`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use common_log_format::follow::Follow;
+    /// let mut follow = Follow::new("/var/log/nginx/access.log").unwrap();
+    /// // Something just went wrong: look at the last five minutes, then keep following.
+    /// follow.rewind(chrono::Duration::minutes(5)).unwrap();
+    /// for entry in follow {
+    ///     println!("{:?}", entry.unwrap());
+    /// }
+    /// ```
+    pub fn rewind(&mut self, by: chrono::Duration) -> io::Result<u64> {
+        self.seek_to_time(Utc::now() - by)
+    }
+
+    /// Wait for the next non-blank line, without its line terminator.
+    ///
+    /// This is for reading formats other than the Common Log Format, which the iterator parses.
+    pub fn next_line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            match self.try_next_line()? {
+                Some(l) => return Ok(l),
+                None => std::thread::sleep(self.poll_interval),
+            }
+        }
+    }
+
+    /// The next non-blank line, as from [`Follow::next_line`], if one has been written; `None`
+    /// rather than waiting if not.
+    pub fn try_next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.poll_line()? {
+                Some(l) if l.is_empty() => continue,
+                l => return Ok(l),
+            }
+        }
+    }
+
+    /// Open the file at `path` from the start, if it exists.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = match File::open(&self.path) {
+            Ok(f) => {
+                let id = FileId::of(&f.metadata()?);
+                Some((BufReader::new(f), id))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        self.offset = 0;
+        self.line = 0;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Read the next complete line if one is available, without waiting.
+    fn poll_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let (file, id) = match &mut self.file {
+            Some(f) => f,
+            None => {
+                self.reopen()?;
+                return Ok(None);
+            }
+        };
+
+        let n = file.read_until(b'\n', &mut self.buf)?;
+        if self.buf.ends_with(b"\n") {
+            self.offset += self.buf.len() as u64;
+            self.line += 1;
+            let line = trim_line_end(&self.buf).to_vec();
+            self.buf.clear();
+            return Ok(Some(line));
+        }
+
+        if n > 0 {
+            // A partial line; wait for the rest of it.
+            return Ok(None);
+        }
+
+        // At the end of the file. Check whether it has been truncated or replaced.
+        let len = file.get_ref().metadata()?.len();
+        let current_id = match std::fs::metadata(&self.path) {
+            Ok(m) => FileId::of(&m),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if current_id != *id {
+            self.reopen()?;
+        } else if len < self.offset + self.buf.len() as u64 {
+            self.seek(0)?;
+        }
+
+        Ok(None)
+    }
+}
+
+/// The number of newlines in `reader`.
+fn count_lines(mut reader: impl BufRead) -> io::Result<u64> {
+    let mut lines = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(lines);
+        }
+        lines += memchr::memchr_iter(b'\n', buf).count() as u64;
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+impl Iterator for Follow {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll_line() {
+                Ok(Some(l)) if l.is_empty() => continue,
+                Ok(Some(l)) => {
+                    // Binary data left by an interrupted write is skipped, as by `LogReader`.
+                    let entry = match parse_salvaged(&l, &self.opts) {
+                        (_, Some(e)) => e,
+                        (_, None) => continue,
+                    };
+                    return Some(entry.map_err(|error| ReadError::Parse {
+                        line: self.line,
+                        error,
+                    }));
+                }
+                Ok(None) => std::thread::sleep(self.poll_interval),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+mod stream {
+    use std::{
+        pin::Pin,
+        sync::{mpsc, Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+
+    use futures_core::Stream;
+
+    use super::Follow;
+    use crate::{reader::ReadError, LogEntry};
+
+    /// Entries from a [`Follow`] running on a background thread.
+    ///
+    /// The thread exits once the stream has been dropped and the next entry arrives.
+    pub struct FollowStream {
+        rx: mpsc::Receiver<Result<LogEntry, ReadError>>,
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl Follow {
+        /// Run this iterator on a new thread, buffering up to `capacity` entries (at least one).
+        pub fn into_stream(self, capacity: usize) -> FollowStream {
+            // With no buffer the thread would block in `send` until a receive, and never get
+            // to wake the task waiting for one.
+            let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+            let waker: Arc<Mutex<Option<Waker>>> = Default::default();
+            let thread_waker = Arc::clone(&waker);
+            std::thread::spawn(move || {
+                for e in self {
+                    if tx.send(e).is_err() {
+                        return;
+                    }
+
+                    if let Some(w) = thread_waker.lock().unwrap().take() {
+                        w.wake();
+                    }
+                }
+            });
+
+            FollowStream { rx, waker }
+        }
+    }
+
+    impl Stream for FollowStream {
+        type Item = Result<LogEntry, ReadError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            match self.rx.try_recv() {
+                Ok(e) => return Poll::Ready(Some(e)),
+                Err(mpsc::TryRecvError::Disconnected) => return Poll::Ready(None),
+                Err(mpsc::TryRecvError::Empty) => (),
+            }
+
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            // An entry may have arrived before the waker was registered.
+            match self.rx.try_recv() {
+                Ok(e) => Poll::Ready(Some(e)),
+                Err(mpsc::TryRecvError::Disconnected) => Poll::Ready(None),
+                Err(mpsc::TryRecvError::Empty) => Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use stream::FollowStream;
@@ -0,0 +1,186 @@
+//! Descriptions of the supported formats, for choosing between them at runtime.
+//!
+//! A tool which lets its user pick a log format from configuration can look the format up by
+//! name with [`Format::from_str`], list the fields it will provide with [`Format::fields`], and
+//! then parse lines in it into [`CanonicalEntry`]s.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    canonical::CanonicalEntry, combined::CombinedLogEntry, forwarded::ForwardedLogEntry,
+    proxy::ProxiedLogEntry, LogEntry, LogEntryParseError,
+};
+
+/// A supported log format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Format {
+    /// The Common Log Format, parsed as [`LogEntry`].
+    Clf,
+    /// Common Log Format lines which may start with a PROXY protocol header, parsed as
+    /// [`ProxiedLogEntry`].
+    ProxiedClf,
+    /// Common Log Format lines which may end with a quoted `X-Forwarded-For` field, parsed as
+    /// [`ForwardedLogEntry`].
+    ForwardedClf,
+    /// Combined Log Format lines, ending with the quoted `Referer` and `User-Agent`, parsed as
+    /// [`CombinedLogEntry`].
+    Combined,
+}
+
+/// The type of a field's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum FieldKind {
+    IpAddr,
+    /// A list of addresses, such as an `X-Forwarded-For` chain.
+    IpAddrList,
+    Port,
+    String,
+    Timestamp,
+    StatusCode,
+    Integer,
+}
+
+/// A field provided by a [`Format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FieldSpec {
+    /// The field's name in the format's parsed type, with `.` separating nested fields.
+    pub name: &'static str,
+    pub kind: FieldKind,
+    /// Whether the field may be missing (logged as `-`, or absent) from a line.
+    pub optional: bool,
+}
+
+const fn field(name: &'static str, kind: FieldKind, optional: bool) -> FieldSpec {
+    FieldSpec {
+        name,
+        kind,
+        optional,
+    }
+}
+
+const CLF_FIELDS: [FieldSpec; 7] = [
+    field("host", FieldKind::IpAddr, true),
+    field("ident", FieldKind::String, true),
+    field("authuser", FieldKind::String, true),
+    field("time", FieldKind::Timestamp, true),
+    field("request_line", FieldKind::String, true),
+    field("status_code", FieldKind::StatusCode, true),
+    field("object_size", FieldKind::Integer, true),
+];
+
+const PROXIED_CLF_FIELDS: [FieldSpec; 11] = [
+    field("proxy.source", FieldKind::IpAddr, true),
+    field("proxy.destination", FieldKind::IpAddr, true),
+    field("proxy.source_port", FieldKind::Port, true),
+    field("proxy.destination_port", FieldKind::Port, true),
+    field("entry.host", FieldKind::IpAddr, true),
+    field("entry.ident", FieldKind::String, true),
+    field("entry.authuser", FieldKind::String, true),
+    field("entry.time", FieldKind::Timestamp, true),
+    field("entry.request_line", FieldKind::String, true),
+    field("entry.status_code", FieldKind::StatusCode, true),
+    field("entry.object_size", FieldKind::Integer, true),
+];
+
+const FORWARDED_CLF_FIELDS: [FieldSpec; 8] = [
+    field("entry.host", FieldKind::IpAddr, true),
+    field("entry.ident", FieldKind::String, true),
+    field("entry.authuser", FieldKind::String, true),
+    field("entry.time", FieldKind::Timestamp, true),
+    field("entry.request_line", FieldKind::String, true),
+    field("entry.status_code", FieldKind::StatusCode, true),
+    field("entry.object_size", FieldKind::Integer, true),
+    field("forwarded_for", FieldKind::IpAddrList, true),
+];
+
+const COMBINED_FIELDS: [FieldSpec; 9] = [
+    field("entry.host", FieldKind::IpAddr, true),
+    field("entry.ident", FieldKind::String, true),
+    field("entry.authuser", FieldKind::String, true),
+    field("entry.time", FieldKind::Timestamp, true),
+    field("entry.request_line", FieldKind::String, true),
+    field("entry.status_code", FieldKind::StatusCode, true),
+    field("entry.object_size", FieldKind::Integer, true),
+    field("referer", FieldKind::String, true),
+    field("user_agent", FieldKind::String, true),
+];
+
+impl Format {
+    pub const ALL: [Format; 4] = [
+        Format::Clf,
+        Format::ProxiedClf,
+        Format::ForwardedClf,
+        Format::Combined,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Clf => "clf",
+            Format::ProxiedClf => "proxied-clf",
+            Format::ForwardedClf => "forwarded-clf",
+            Format::Combined => "combined",
+        }
+    }
+
+    /// The fields this format provides.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::format::{FieldKind, Format};
+    /// let format: Format = "proxied-clf".parse().unwrap();
+    /// let ip_fields: Vec<&str> = format
+    ///     .fields()
+    ///     .iter()
+    ///     .filter(|f| f.kind == FieldKind::IpAddr)
+    ///     .map(|f| f.name)
+    ///     .collect();
+    /// assert_eq!(ip_fields, ["proxy.source", "proxy.destination", "entry.host"]);
+    /// ```
+    pub fn fields(&self) -> &'static [FieldSpec] {
+        match self {
+            Format::Clf => &CLF_FIELDS,
+            Format::ProxiedClf => &PROXIED_CLF_FIELDS,
+            Format::ForwardedClf => &FORWARDED_CLF_FIELDS,
+            Format::Combined => &COMBINED_FIELDS,
+        }
+    }
+
+    /// Parse a line in this format.
+    pub fn parse(&self, line: &str) -> Result<CanonicalEntry, LogEntryParseError> {
+        Ok(match self {
+            Format::Clf => line.parse::<LogEntry>()?.into(),
+            Format::ProxiedClf => line.parse::<ProxiedLogEntry>()?.into(),
+            Format::ForwardedClf => line.parse::<ForwardedLogEntry>()?.into(),
+            Format::Combined => line.parse::<CombinedLogEntry>()?.into(),
+        })
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name given to [`Format::from_str`] is not a known format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFormat(pub String);
+
+impl Display for UnknownFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown log format {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| UnknownFormat(s.to_owned()))
+    }
+}
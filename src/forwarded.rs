@@ -0,0 +1,164 @@
+//! Lines ending with the `X-Forwarded-For` chain.
+//!
+//! Behind a load balancer or reverse proxy, the `host` field of each entry is the address of the
+//! last proxy, not the client. HTTP proxies instead pass the client address on in the
+//! `X-Forwarded-For` header, each appending the address it received the request from, and
+//! servers can log the header after the usual fields, as nginx does with
+//! `"$http_x_forwarded_for"`:
+//!
+//! ```text
+//! 10.0.0.1 - - [...] "GET / HTTP/1.1" 200 512 "203.0.113.7, 10.0.0.2"
+//! ```
+//!
+//! Clients can send the header too, so only the addresses appended by proxies you run can be
+//! believed. [`ForwardedLogEntry::real_client_ip`] walks the chain from the right, skipping those
+//! proxies, to find the address the first of them saw.
+
+use std::{net::IpAddr, str::FromStr};
+
+#[cfg(feature = "analytics")]
+use crate::filter::Cidr;
+use crate::{
+    peel_quoted_string, warnings, warnings::Warning, LogEntry, LogEntryParseError, ParseOptions,
+};
+
+/// Parse the value of an `X-Forwarded-For` header into its addresses, leftmost first.
+///
+/// Entries may carry a port (`203.0.113.7:51234`, `[2001:db8::1]:443`), which is dropped. An
+/// entry which isn't an address, such as `unknown` or a garbled value sent by a client, is
+/// dropped along with everything to its left: the proxies you run only append real addresses, so
+/// nothing before it can be trusted to be what a proxy saw.
+///
+/// # Example
+/// ```
+/// use common_log_format::forwarded::parse_forwarded_for;
+/// let chain = parse_forwarded_for("203.0.113.7:51234, [2001:db8::1]:443,10.0.0.2");
+/// assert_eq!(chain.len(), 3);
+/// assert_eq!(chain[0], "203.0.113.7".parse::<std::net::IpAddr>().unwrap());
+///
+/// let chain = parse_forwarded_for("198.51.100.1, unknown, 10.0.0.2");
+/// assert_eq!(chain, ["10.0.0.2".parse::<std::net::IpAddr>().unwrap()]);
+/// assert!(parse_forwarded_for("-").is_empty());
+/// ```
+pub fn parse_forwarded_for(value: &str) -> Vec<IpAddr> {
+    let mut chain = vec![];
+    for part in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match parse_forwarded_addr(part) {
+            Some(ip) => chain.push(ip),
+            None => chain.clear(),
+        }
+    }
+    chain
+}
+
+fn parse_forwarded_addr(s: &str) -> Option<IpAddr> {
+    if let Ok(ip) = s.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = s.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        return (port.is_empty() || port.strip_prefix(':')?.parse::<u16>().is_ok())
+            .then(|| ip.parse().ok())
+            .flatten();
+    }
+    match s.split_once(':') {
+        Some((ip, port)) if port.parse::<u16>().is_ok() => ip.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Take a quoted `X-Forwarded-For` value from the start of `line`, as parsed by
+/// [`parse_forwarded_for`].
+///
+/// Return an empty chain (and the remainder) if the field is `-`, or `line` is empty.
+///
+/// # Example
+/// ```
+/// use common_log_format::forwarded::peel_forwarded_for;
+/// let (chain, rem) = peel_forwarded_for("\"203.0.113.7, 10.0.0.2\" 0.002").unwrap();
+/// assert_eq!(chain.len(), 2);
+/// assert_eq!(rem, "0.002");
+/// ```
+pub fn peel_forwarded_for(line: &str) -> Result<(Vec<IpAddr>, &str), LogEntryParseError> {
+    if line.is_empty() {
+        return Ok((vec![], line));
+    }
+    let (value, rem) = peel_quoted_string(line)?;
+    Ok((value.map(parse_forwarded_for).unwrap_or_default(), rem))
+}
+
+/// The address to report for a request which reached `peer` with the forwarded `chain`.
+///
+/// See [`ForwardedLogEntry::real_client_ip`].
+#[cfg(feature = "analytics")]
+pub fn real_client_ip(
+    chain: &[IpAddr],
+    peer: Option<IpAddr>,
+    trusted_proxies: &[Cidr],
+) -> Option<IpAddr> {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|c| c.contains(*ip));
+    let hops = chain
+        .iter()
+        .chain(peer.as_ref())
+        .map(|ip| ip.to_canonical());
+    let leftmost = hops.clone().next();
+    hops.rev().find(|ip| !trusted(ip)).or(leftmost)
+}
+
+/// A [`LogEntry`] followed by the request's `X-Forwarded-For` chain.
+///
+/// # Example
+/// ```
+/// use common_log_format::forwarded::ForwardedLogEntry;
+/// let line = "10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326 \"198.51.100.4, 203.0.113.7, 10.0.0.2\"";
+/// let e: ForwardedLogEntry = line.parse().unwrap();
+/// assert_eq!(e.entry.host, Some("10.0.0.1".parse().unwrap()));
+/// assert_eq!(e.forwarded_for.len(), 3);
+///
+/// // The client claimed to be forwarding for 198.51.100.4, but only the load balancers in
+/// // 10.0.0.0/8 are trusted, so the address they saw is the client's.
+/// let trusted = ["10.0.0.0/8".parse().unwrap()];
+/// assert_eq!(e.real_client_ip(&trusted), Some("203.0.113.7".parse().unwrap()));
+///
+/// // Lines without the field parse as usual.
+/// let line = "10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let e: ForwardedLogEntry = line.parse().unwrap();
+/// assert_eq!(e.real_client_ip(&trusted), Some("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ForwardedLogEntry {
+    pub entry: LogEntry,
+    /// The addresses from `X-Forwarded-For`, leftmost first. Empty if the header was missing.
+    pub forwarded_for: Vec<IpAddr>,
+}
+
+impl ForwardedLogEntry {
+    /// The address of the original client, ignoring any forwarded addresses a client could have
+    /// made up.
+    ///
+    /// Starting from the entry's `host` and moving left through `forwarded_for`, return the first
+    /// address not in `trusted_proxies`. If every address is trusted, return the leftmost. With
+    /// no trusted proxies this is the `host`.
+    #[cfg(feature = "analytics")]
+    pub fn real_client_ip(&self, trusted_proxies: &[Cidr]) -> Option<IpAddr> {
+        real_client_ip(&self.forwarded_for, self.entry.host, trusted_proxies)
+    }
+}
+
+impl FromStr for ForwardedLogEntry {
+    type Err = LogEntryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (entry, remaining) = LogEntry::peel(s, &ParseOptions::default())?;
+        let (forwarded_for, remaining) = peel_forwarded_for(remaining)?;
+        if !remaining.is_empty() {
+            warnings::emit(Warning::TrailingData {
+                len: remaining.len(),
+            });
+        }
+        Ok(ForwardedLogEntry {
+            entry,
+            forwarded_for,
+        })
+    }
+}
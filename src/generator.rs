@@ -0,0 +1,295 @@
+//! Synthetic logs, for load-testing parsers and whatever sits downstream of them.
+//!
+//! A [`Generator`] produces an endless stream of plausible traffic: a few popular pages and a long
+//! tail of rare ones (Zipf-distributed), a request rate which rises by day and falls by night,
+//! mostly successful responses with some redirects and errors, and log-normally distributed
+//! response sizes. Output is reproducible from the seed, so a benchmark can be rerun on the same
+//! data without storing it.
+
+use std::{
+    f64::consts::PI,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use chrono::{DateTime, Timelike, Utc};
+use http::StatusCode;
+
+use crate::{canonical::CanonicalEntry, combined::CombinedLogEntry, LogEntry};
+
+const SECTIONS: &[(&str, &str)] = &[
+    ("products", ""),
+    ("blog", ".html"),
+    ("api/v1/items", ""),
+    ("static/img", ".png"),
+    ("static/js", ".js"),
+    ("static/css", ".css"),
+];
+
+const USER_AGENTS: &[(&str, f64)] = &[
+    ("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36", 0.35),
+    ("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15", 0.15),
+    ("Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1", 0.2),
+    ("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36", 0.15),
+    ("Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0", 0.08),
+    ("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)", 0.04),
+    ("curl/8.5.0", 0.03),
+];
+
+/// A small, fast, seedable pseudo-random number generator (SplitMix64).
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..1`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn normal(&mut self) -> f64 {
+        let u = 1. - self.next_f64();
+        let v = self.next_f64();
+        (-2. * u.ln()).sqrt() * (2. * PI * v).cos()
+    }
+
+    /// The index chosen by `weights`, which must not be empty.
+    fn weighted<T>(&mut self, weights: &[(T, f64)]) -> usize {
+        let total: f64 = weights.iter().map(|(_, w)| w).sum();
+        let mut x = self.next_f64() * total;
+        for (i, (_, w)) in weights.iter().enumerate() {
+            if x < *w {
+                return i;
+            }
+            x -= w;
+        }
+        weights.len() - 1
+    }
+}
+
+/// The cumulative distribution of ranks `0..n` under Zipf's law with exponent `s`.
+fn zipf_cdf(n: usize, s: f64) -> Vec<f64> {
+    let mut cdf: Vec<f64> = (1..=n.max(1))
+        .scan(0., |acc, k| {
+            *acc += 1. / (k as f64).powf(s);
+            Some(*acc)
+        })
+        .collect();
+    let total = *cdf.last().unwrap();
+    cdf.iter_mut().for_each(|c| *c /= total);
+    cdf
+}
+
+fn sample_cdf(rng: &mut Rng, cdf: &[f64]) -> usize {
+    let x = rng.next_f64();
+    cdf.partition_point(|c| *c <= x).min(cdf.len() - 1)
+}
+
+/// An endless stream of synthetic entries.
+///
+/// By default, traffic averages 10 requests per second, peaking at 14:00 UTC at half again the
+/// average and bottoming out at 02:00 at half of it. 10,000 clients request 1,000 paths, both
+/// chosen with Zipf exponent 1. Statuses are 90% `200`, 4% `304`, 3% `404`, 1% each of `301`,
+/// `500`, and `503`, and sizes are log-normal around a median of 5 KB.
+///
+/// Entries are [`CanonicalEntry`]s with a referer and user agent, which
+/// [`Generator::lines`] writes as Combined Log Format lines, or as Common Log Format without them.
+///
+/// # Example
+/// ```
+/// use common_log_format::{generator::Generator, LogEntry};
+/// let start = "2024-05-01T00:00:00Z".parse().unwrap();
+/// let lines: Vec<String> = Generator::new(42).with_start(start).lines(false).take(1000).collect();
+///
+/// let entries: Vec<LogEntry> = lines.iter().map(|l| l.parse().unwrap()).collect();
+/// let ok = entries.iter().filter(|e| e.status_code.unwrap().as_u16() == 200).count();
+/// assert!(ok > 850);
+///
+/// // The same seed gives the same lines.
+/// let again: Vec<String> = Generator::new(42).with_start(start).lines(false).take(1000).collect();
+/// assert_eq!(lines, again);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: Rng,
+    now: DateTime<Utc>,
+    rate: f64,
+    diurnal: f64,
+    paths: Vec<String>,
+    path_cdf: Vec<f64>,
+    clients: Vec<IpAddr>,
+    client_cdf: Vec<f64>,
+    statuses: Vec<(StatusCode, f64)>,
+    median_size: f64,
+    size_sigma: f64,
+}
+
+impl Generator {
+    /// A generator starting at 2024-01-01T00:00:00Z, with the default distributions.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng(seed);
+        let clients = (0..10_000)
+            .map(|_| {
+                let [a, b, c, d, ..] = rng.next_u64().to_be_bytes();
+                IpAddr::V4(Ipv4Addr::new(a % 223 + 1, b, c, d.max(1)))
+            })
+            .collect();
+        let status = |s| StatusCode::from_u16(s).unwrap();
+        Generator {
+            rng,
+            now: DateTime::from_timestamp(1_704_067_200, 0).unwrap(),
+            rate: 10.,
+            diurnal: 0.5,
+            paths: vec![],
+            path_cdf: vec![],
+            clients,
+            client_cdf: zipf_cdf(10_000, 1.),
+            statuses: vec![
+                (status(200), 0.90),
+                (status(304), 0.04),
+                (status(404), 0.03),
+                (status(301), 0.01),
+                (status(500), 0.01),
+                (status(503), 0.01),
+            ],
+            median_size: 5000.,
+            size_sigma: 1.,
+        }
+        .with_path_count(1000, 1.)
+    }
+
+    /// Log the first entry shortly after `start`.
+    pub fn with_start(mut self, start: DateTime<Utc>) -> Self {
+        self.now = start;
+        self
+    }
+
+    /// Average `rate` requests per second over a day.
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        self.rate = rate.max(f64::MIN_POSITIVE);
+        self
+    }
+
+    /// Vary the rate over the day by `amplitude`, between 0 (flat) and 1 (no requests at the
+    /// quietest time).
+    pub fn with_diurnal_amplitude(mut self, amplitude: f64) -> Self {
+        self.diurnal = amplitude.clamp(0., 1.);
+        self
+    }
+
+    /// Request `count` generated paths, with popularity following Zipf's law with exponent `s`.
+    pub fn with_path_count(mut self, count: usize, s: f64) -> Self {
+        let paths = std::iter::once("/".to_owned())
+            .chain((1..count).map(|i| {
+                let (section, ext) = SECTIONS[i % SECTIONS.len()];
+                format!("/{}/{}{}", section, i, ext)
+            }))
+            .collect();
+        self.paths = paths;
+        self.path_cdf = zipf_cdf(count, s);
+        self
+    }
+
+    /// Request `paths`, most popular first, with popularity following Zipf's law with exponent
+    /// `s`. Empty `paths` are ignored.
+    pub fn with_paths(mut self, paths: Vec<String>, s: f64) -> Self {
+        if !paths.is_empty() {
+            self.path_cdf = zipf_cdf(paths.len(), s);
+            self.paths = paths;
+        }
+        self
+    }
+
+    /// Answer with each status in proportion to its weight. Empty `mix`es are ignored.
+    pub fn with_status_mix(mut self, mix: &[(StatusCode, f64)]) -> Self {
+        if !mix.is_empty() {
+            self.statuses = mix.to_vec();
+        }
+        self
+    }
+
+    /// Draw sizes from a log-normal distribution with median `median` bytes and shape `sigma`.
+    pub fn with_size_distribution(mut self, median: usize, sigma: f64) -> Self {
+        self.median_size = median.max(1) as f64;
+        self.size_sigma = sigma.max(0.);
+        self
+    }
+
+    /// The request rate at `t`, in requests per second.
+    fn rate_at(&self, t: DateTime<Utc>) -> f64 {
+        let hour = t.num_seconds_from_midnight() as f64 / 3600.;
+        let rate = self.rate * (1. + self.diurnal * (2. * PI * (hour - 14.) / 24.).cos());
+        rate.max(self.rate * 1e-3)
+    }
+
+    /// The next entry, which is logged after the previous one by an exponentially distributed
+    /// gap.
+    pub fn entry(&mut self) -> CanonicalEntry {
+        let gap = -(1. - self.rng.next_f64()).ln() / self.rate_at(self.now);
+        self.now += chrono::Duration::microseconds((gap * 1e6) as i64);
+
+        let host = self.clients[sample_cdf(&mut self.rng, &self.client_cdf)];
+        let path = &self.paths[sample_cdf(&mut self.rng, &self.path_cdf)];
+        let method = match self.rng.next_f64() {
+            x if x < 0.05 && path.starts_with("/api") => "POST",
+            x if x < 0.01 => "HEAD",
+            _ => "GET",
+        };
+        let status = self.statuses[self.rng.weighted(&self.statuses)].0;
+        let size = match status.as_u16() {
+            204 | 304 => 0,
+            _ if method == "HEAD" => 0,
+            _ => (self.median_size * (self.size_sigma * self.rng.normal()).exp()) as usize,
+        };
+        let referer = match self.rng.next_f64() {
+            x if x < 0.4 => None,
+            x if x < 0.5 => Some("https://www.google.com/".to_owned()),
+            _ => {
+                let from = &self.paths[sample_cdf(&mut self.rng, &self.path_cdf)];
+                Some(format!("https://example.com{}", from))
+            }
+        };
+        let user_agent = USER_AGENTS[self.rng.weighted(USER_AGENTS)].0.to_owned();
+
+        let entry = LogEntry {
+            host: Some(host),
+            ident: None,
+            authuser: None,
+            time: Some(self.now.with_nanosecond(0).unwrap_or(self.now)),
+            request_line: Some(format!("{} {} HTTP/1.1", method, path)),
+            status_code: Some(status),
+            object_size: Some(size),
+        };
+        CanonicalEntry {
+            referer,
+            user_agent: Some(user_agent),
+            ..entry.into()
+        }
+    }
+
+    /// Lines in Combined Log Format if `combined`, and Common Log Format otherwise.
+    pub fn lines(mut self, combined: bool) -> impl Iterator<Item = String> {
+        std::iter::repeat_with(move || {
+            let e = self.entry();
+            if combined {
+                CombinedLogEntry::from(e).to_string()
+            } else {
+                e.entry.to_string()
+            }
+        })
+    }
+}
+
+impl Iterator for Generator {
+    type Item = CanonicalEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.entry())
+    }
+}
@@ -0,0 +1,127 @@
+//! Looking up where clients are, from [MaxMind] databases.
+//!
+//! A [`GeoIp`] reads a GeoIP2 or GeoLite2 City (or Country) database, and optionally an ASN
+//! database, and attaches what they say about each entry's `host` as an [`EnrichedEntry`]. The
+//! databases are read into memory when opened; lookups do not touch the disk.
+//!
+//! [MaxMind]: https://dev.maxmind.com/geoip/docs/databases
+
+use std::{net::IpAddr, path::Path};
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::LogEntry;
+
+/// What the databases know about an address. Fields are `None` when it is not in a database, or
+/// the database does not have that field.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Geo {
+    /// The ISO 3166-1 alpha-2 code of the country, such as `SE`.
+    pub country: Option<String>,
+    /// The English name of the city.
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// The autonomous system number of the network.
+    pub asn: Option<u32>,
+    /// The organization which operates the autonomous system.
+    pub as_organization: Option<String>,
+}
+
+/// An entry with what the databases know about its `host`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EnrichedEntry {
+    pub entry: LogEntry,
+    pub geo: Geo,
+}
+
+/// Location and network lookups for client addresses.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{geoip::GeoIp, LogEntry};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let geoip = GeoIp::open("/usr/share/GeoIP/GeoLite2-City.mmdb")?
+///     .with_asn_database("/usr/share/GeoIP/GeoLite2-ASN.mmdb")?;
+/// let entry: LogEntry = "89.160.20.128 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse()?;
+/// let enriched = geoip.enrich(entry);
+/// println!("{:?} {:?}", enriched.geo.country, enriched.geo.as_organization);
+/// # Ok(())
+/// # }
+/// ```
+pub struct GeoIp {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    /// Read the City or Country database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        Ok(GeoIp {
+            city: Some(Reader::open_readfile(path)?),
+            asn: None,
+        })
+    }
+
+    /// Read the ASN database at `path` only, for network lookups without locations.
+    pub fn open_asn(path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        Ok(GeoIp {
+            city: None,
+            asn: Some(Reader::open_readfile(path)?),
+        })
+    }
+
+    /// Also look up autonomous systems in the ASN database at `path`.
+    pub fn with_asn_database(mut self, path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        self.asn = Some(Reader::open_readfile(path)?);
+        Ok(self)
+    }
+
+    /// What the databases know about `ip`. Addresses they do not cover, such as private ones,
+    /// give an empty [`Geo`].
+    pub fn lookup(&self, ip: IpAddr) -> Geo {
+        let ip = ip.to_canonical();
+        let mut geo = Geo::default();
+        if let Some(city) = self
+            .city
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::City>(ip).ok())
+        {
+            geo.country = city.country.and_then(|c| c.iso_code).map(str::to_owned);
+            geo.city = city
+                .city
+                .and_then(|c| c.names)
+                .and_then(|n| n.get("en").map(|n| n.to_string()));
+            if let Some(l) = city.location {
+                geo.latitude = l.latitude;
+                geo.longitude = l.longitude;
+            }
+        }
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::Asn>(ip).ok())
+        {
+            geo.asn = asn.autonomous_system_number;
+            geo.as_organization = asn.autonomous_system_organization.map(str::to_owned);
+        }
+        geo
+    }
+
+    /// `entry` with what the databases know about its `host`.
+    pub fn enrich(&self, entry: LogEntry) -> EnrichedEntry {
+        let geo = entry.host.map(|h| self.lookup(h)).unwrap_or_default();
+        EnrichedEntry { entry, geo }
+    }
+}
+
+impl std::fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind =
+            |r: &Option<Reader<Vec<u8>>>| r.as_ref().map(|r| r.metadata.database_type.clone());
+        f.debug_struct("GeoIp")
+            .field("city", &kind(&self.city))
+            .field("asn", &kind(&self.asn))
+            .finish()
+    }
+}
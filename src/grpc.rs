@@ -0,0 +1,279 @@
+//! Streaming entries between machines over gRPC.
+//!
+//! The service is defined in `proto/ingest.proto`: a client streams [`proto::Entry`] messages to
+//! `Ingest/Push`, and the server replies with how many it accepted. Edge nodes can parse their
+//! own logs and push the entries to a central [`IngestServer`] with an [`IngestClient`], where
+//! they arrive on a channel for aggregation.
+//!
+//! The message types and service glue are written out here rather than generated, so building
+//! the crate does not need `protoc`. Both run on tokio.
+
+use std::{
+    net::IpAddr,
+    task::{Context, Poll},
+};
+
+use chrono::{TimeZone, Utc};
+use tokio::sync::mpsc;
+use tonic::{
+    codegen::{http as grpc_http, tokio_stream, Body, BoxFuture, GrpcMethod, Service, StdError},
+    transport::Channel,
+    Code, Request, Response, Status, Streaming,
+};
+
+use crate::LogEntry;
+
+/// The messages in `proto/ingest.proto`.
+pub mod proto {
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct Entry {
+        #[prost(string, optional, tag = "1")]
+        pub host: Option<String>,
+        #[prost(string, optional, tag = "2")]
+        pub ident: Option<String>,
+        #[prost(string, optional, tag = "3")]
+        pub authuser: Option<String>,
+        #[prost(int64, optional, tag = "4")]
+        pub time_seconds: Option<i64>,
+        #[prost(uint32, tag = "5")]
+        pub time_nanos: u32,
+        #[prost(string, optional, tag = "6")]
+        pub request_line: Option<String>,
+        #[prost(uint32, optional, tag = "7")]
+        pub status_code: Option<u32>,
+        #[prost(uint64, optional, tag = "8")]
+        pub object_size: Option<u64>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+    pub struct PushSummary {
+        #[prost(uint64, tag = "1")]
+        pub accepted: u64,
+    }
+}
+
+const SERVICE: &str = "common_log_format.Ingest";
+const PUSH: &str = "/common_log_format.Ingest/Push";
+
+impl From<&LogEntry> for proto::Entry {
+    fn from(e: &LogEntry) -> Self {
+        proto::Entry {
+            host: e.host.map(|h| h.to_string()),
+            ident: e.ident.clone(),
+            authuser: e.authuser.clone(),
+            time_seconds: e.time.map(|t| t.timestamp()),
+            time_nanos: e.time.map_or(0, |t| t.timestamp_subsec_nanos()),
+            request_line: e.request_line.clone(),
+            status_code: e.status_code.map(|s| s.as_u16() as u32),
+            object_size: e.object_size.map(|s| s as u64),
+        }
+    }
+}
+
+impl TryFrom<proto::Entry> for LogEntry {
+    type Error = Status;
+
+    fn try_from(e: proto::Entry) -> Result<Self, Status> {
+        let invalid = |field: &str| Status::invalid_argument(format!("invalid {}", field));
+        Ok(LogEntry {
+            host: e
+                .host
+                .map(|h| h.parse::<IpAddr>())
+                .transpose()
+                .map_err(|_| invalid("host"))?,
+            ident: e.ident,
+            authuser: e.authuser,
+            time: e
+                .time_seconds
+                .map(|s| Utc.timestamp_opt(s, e.time_nanos).single())
+                .map(|t| t.ok_or_else(|| invalid("time")))
+                .transpose()?,
+            request_line: e.request_line,
+            status_code: e
+                .status_code
+                .map(|s| {
+                    u16::try_from(s)
+                        .ok()
+                        .and_then(|s| http::StatusCode::from_u16(s).ok())
+                        .ok_or_else(|| invalid("status code"))
+                })
+                .transpose()?,
+            object_size: e
+                .object_size
+                .map(|s| usize::try_from(s).map_err(|_| invalid("object size")))
+                .transpose()?,
+        })
+    }
+}
+
+/// A client for pushing entries to an [`IngestServer`].
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{grpc::IngestClient, LogEntry};
+/// # async fn push() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut client = IngestClient::connect("http://collector:50051").await?;
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse()?;
+/// let accepted = client.push_iter(vec![entry]).await?;
+/// assert_eq!(accepted, 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IngestClient {
+    inner: tonic::client::Grpc<Channel>,
+}
+
+impl IngestClient {
+    /// Connect to the server at `dst`, such as `http://127.0.0.1:50051`.
+    pub async fn connect(
+        dst: impl TryInto<tonic::transport::Endpoint, Error = impl Into<StdError>>,
+    ) -> Result<Self, tonic::transport::Error> {
+        let channel = tonic::transport::Endpoint::new(dst)?.connect().await?;
+        Ok(Self::new(channel))
+    }
+
+    pub fn new(channel: Channel) -> Self {
+        IngestClient {
+            inner: tonic::client::Grpc::new(channel),
+        }
+    }
+
+    /// Send `entries` as one stream, and return how many the server accepted.
+    ///
+    /// The stream ends when `entries` does, so this can forward entries as they are read, e.g.
+    /// from a [`tokio::sync::mpsc::Receiver`] wrapped as a stream.
+    pub async fn push<S>(&mut self, entries: S) -> Result<u64, Status>
+    where
+        S: tokio_stream::Stream<Item = LogEntry> + Send + 'static,
+    {
+        use tokio_stream::StreamExt;
+
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| Status::unknown(format!("service was not ready: {}", e)))?;
+        let mut req = Request::new(entries.map(|e| proto::Entry::from(&e)));
+        req.extensions_mut()
+            .insert(GrpcMethod::new(SERVICE, "Push"));
+        let summary: Response<proto::PushSummary> = self
+            .inner
+            .client_streaming(
+                req,
+                grpc_http::uri::PathAndQuery::from_static(PUSH),
+                tonic_prost::ProstCodec::default(),
+            )
+            .await?;
+        Ok(summary.into_inner().accepted)
+    }
+
+    /// Send every entry in `entries`; see [`IngestClient::push`].
+    pub async fn push_iter<I>(&mut self, entries: I) -> Result<u64, Status>
+    where
+        I: IntoIterator<Item = LogEntry>,
+        I::IntoIter: Send + 'static,
+    {
+        self.push(tokio_stream::iter(entries)).await
+    }
+}
+
+/// A gRPC service which receives pushed entries and forwards them to a channel.
+///
+/// A push is accepted only once all of its entries are in the channel, so a slow consumer slows
+/// down the clients. If the receiving half of the channel is dropped, pushes fail with
+/// `UNAVAILABLE`; entries with fields that cannot be represented fail with `INVALID_ARGUMENT`.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::grpc::IngestServer;
+/// # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+/// let (server, mut entries) = IngestServer::channel(1024);
+/// tokio::spawn(async move {
+///     while let Some(entry) = entries.recv().await {
+///         println!("{:?}", entry);
+///     }
+/// });
+/// tonic::transport::Server::builder()
+///     .add_service(server)
+///     .serve("0.0.0.0:50051".parse()?)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IngestServer {
+    tx: mpsc::Sender<LogEntry>,
+}
+
+impl IngestServer {
+    pub fn new(tx: mpsc::Sender<LogEntry>) -> Self {
+        IngestServer { tx }
+    }
+
+    /// A server, and the receiving end of its channel, which buffers up to `buffer` entries.
+    pub fn channel(buffer: usize) -> (Self, mpsc::Receiver<LogEntry>) {
+        let (tx, rx) = mpsc::channel(buffer);
+        (Self::new(tx), rx)
+    }
+}
+
+impl tonic::server::NamedService for IngestServer {
+    const NAME: &'static str = SERVICE;
+}
+
+struct Push(mpsc::Sender<LogEntry>);
+
+impl tonic::server::ClientStreamingService<proto::Entry> for Push {
+    type Response = proto::PushSummary;
+    type Future = BoxFuture<Response<proto::PushSummary>, Status>;
+
+    fn call(&mut self, request: Request<Streaming<proto::Entry>>) -> Self::Future {
+        let tx = self.0.clone();
+        Box::pin(async move {
+            let mut entries = request.into_inner();
+            let mut accepted = 0;
+            while let Some(e) = entries.message().await? {
+                tx.send(LogEntry::try_from(e)?)
+                    .await
+                    .map_err(|_| Status::unavailable("the server is shutting down"))?;
+                accepted += 1;
+            }
+            Ok(Response::new(proto::PushSummary { accepted }))
+        })
+    }
+}
+
+impl<B> Service<grpc_http::Request<B>> for IngestServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = grpc_http::Response<tonic::body::Body>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: grpc_http::Request<B>) -> Self::Future {
+        if req.uri().path() != PUSH {
+            return Box::pin(async move {
+                let mut response = grpc_http::Response::new(tonic::body::Body::default());
+                let headers = response.headers_mut();
+                headers.insert(Status::GRPC_STATUS, (Code::Unimplemented as i32).into());
+                headers.insert(
+                    grpc_http::header::CONTENT_TYPE,
+                    tonic::metadata::GRPC_CONTENT_TYPE,
+                );
+                Ok(response)
+            });
+        }
+
+        let push = Push(self.tx.clone());
+        Box::pin(async move {
+            let mut grpc = tonic::server::Grpc::new(tonic_prost::ProstCodec::default());
+            Ok(grpc.client_streaming(push, req).await)
+        })
+    }
+}
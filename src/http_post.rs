@@ -0,0 +1,208 @@
+//! A minimal HTTP/1.1 client for the sinks which ship batches of entries to an HTTP API.
+//!
+//! Each request is a `POST` on its own connection, over plain TCP. That's enough for the log
+//! stores these sinks talk to, which are usually reached over a private network or a local
+//! agent, and it keeps them free of an async runtime.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::replay::InvalidUrl;
+
+/// Where requests are sent.
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    /// The `host:port` to connect to, and the value of the `Host` header.
+    authority: String,
+    /// Prepended to every request path.
+    base_path: String,
+    pub timeout: Duration,
+}
+
+impl Endpoint {
+    /// Parse an `http://host[:port][/path]` URL.
+    pub fn new(base_url: &str) -> Result<Self, InvalidUrl> {
+        let invalid = || InvalidUrl(base_url.to_owned());
+        let rest = base_url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Endpoint {
+            authority: authority.to_owned(),
+            base_path: path.trim_end_matches('/').to_owned(),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Send `body` to `path`, and read the response.
+    pub fn post(&self, path: &str, headers: &[(&str, &str)], body: &[u8]) -> io::Result<Response> {
+        let addr = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut head = format!(
+            "POST {}{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: common-log-format\r\nConnection: close\r\nContent-Length: {}\r\n",
+            self.base_path,
+            path,
+            self.authority,
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        Response::read(BufReader::new(stream))
+    }
+}
+
+/// The parts of a response the sinks look at.
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn read(mut reader: impl BufRead) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response");
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let mut chunked = false;
+        let mut length = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                return Err(invalid());
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.parse::<u64>().map_err(|_| invalid())?);
+            }
+        }
+
+        let mut body = Vec::new();
+        if chunked {
+            loop {
+                line.clear();
+                reader.read_line(&mut line)?;
+                let size = line.trim_end().split(';').next().unwrap_or("");
+                let size = u64::from_str_radix(size, 16).map_err(|_| invalid())?;
+                if size == 0 {
+                    break;
+                }
+                (&mut reader).take(size).read_to_end(&mut body)?;
+                line.clear();
+                reader.read_line(&mut line)?;
+            }
+        } else {
+            match length {
+                Some(n) => (&mut reader).take(n).read_to_end(&mut body)?,
+                None => reader.read_to_end(&mut body)?,
+            };
+        }
+
+        Ok(Response {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+
+    /// Whether sending the same request again might succeed: the server is overloaded or
+    /// temporarily unavailable.
+    pub fn is_transient(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+}
+
+/// How often, and how patiently, failed requests are retried.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Backoff {
+    pub retries: u32,
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            retries: 5,
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before retry number `attempt`, counting from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max)
+    }
+
+    /// Send a request with `send` until the server accepts it, retrying connection failures and
+    /// [transient](Response::is_transient) errors after waits which double each time. Other error
+    /// responses are returned straight away, as an error described by `service`.
+    pub fn post(
+        &self,
+        service: &str,
+        mut send: impl FnMut() -> io::Result<Response>,
+    ) -> io::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let error = match send() {
+                Ok(r) if r.status < 300 => return Ok(r),
+                Ok(r) => {
+                    let transient = r.is_transient();
+                    let error = io::Error::other(format!(
+                        "{} answered {}: {}",
+                        service,
+                        r.status,
+                        r.body.trim()
+                    ));
+                    if !transient {
+                        return Err(error);
+                    }
+                    error
+                }
+                Err(e) => e,
+            };
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            std::thread::sleep(self.delay(attempt));
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! A sidecar index of a log file, for answering repeated queries over it quickly.
+//!
+//! A [`LogIndex`] splits a file into blocks of a fixed number of lines and records, for each, its
+//! offset, the range of times in it, and how many entries it has of each status class. A query
+//! then only reads the blocks which could contain matching entries. The index is small enough
+//! (a line per block) to keep next to the log, as `access.log.idx` say, and reuse.
+//!
+//! An index stays valid as the log is appended to: lines after the indexed part are read in full
+//! by every query.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::StatusCode;
+
+use crate::{
+    reader::{trim_line_end, FileId, ReadError},
+    LogEntry, LogEntryRef,
+};
+
+/// Statistics for a run of lines in a log file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Block {
+    /// The offset of the block's first line.
+    pub offset: u64,
+    /// The (0-based) number of the block's first line.
+    pub line: u64,
+    /// The number of lines in the block, including blank and unparseable ones.
+    pub lines: u64,
+    pub min_time: Option<DateTime<Utc>>,
+    pub max_time: Option<DateTime<Utc>>,
+    /// The number of entries of each status class: `statuses[1]` counts 1xx responses, and so on
+    /// up to 5xx. `statuses[0]` counts entries without a status, or with one outside these.
+    pub statuses: [u64; 6],
+}
+
+impl Block {
+    fn new(offset: u64, line: u64) -> Self {
+        Block {
+            offset,
+            line,
+            lines: 0,
+            min_time: None,
+            max_time: None,
+            statuses: [0; 6],
+        }
+    }
+
+    /// Whether the block may contain entries matching `query`.
+    pub fn may_match(&self, query: &IndexQuery) -> bool {
+        let times = match (query.start, query.end) {
+            (None, None) => true,
+            (start, end) => match (self.min_time, self.max_time) {
+                (Some(min), Some(max)) => {
+                    start.is_none_or(|s| max >= s) && end.is_none_or(|e| min < e)
+                }
+                _ => false,
+            },
+        };
+
+        times
+            && query
+                .status_class
+                .is_none_or(|c| self.statuses[c as usize] > 0)
+    }
+}
+
+/// The entries a query over a [`LogIndex`] returns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexQuery {
+    /// Only return entries at or after this time.
+    pub start: Option<DateTime<Utc>>,
+    /// Only return entries before this time.
+    pub end: Option<DateTime<Utc>>,
+    /// Only return entries with statuses in this class, such as 5 for 5xx.
+    pub status_class: Option<u8>,
+}
+
+impl IndexQuery {
+    pub fn with_time_range(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.start = Some(start);
+        self.end = Some(end);
+        self
+    }
+
+    /// Only return entries with statuses in `class`, which must be from 1 to 5.
+    pub fn with_status_class(mut self, class: u8) -> Self {
+        assert!((1..=5).contains(&class), "status class must be 1-5");
+        self.status_class = Some(class);
+        self
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        let time_ok = match (self.start, self.end) {
+            (None, None) => true,
+            (start, end) => entry
+                .time
+                .is_some_and(|t| start.is_none_or(|s| t >= s) && end.is_none_or(|e| t < e)),
+        };
+
+        time_ok
+            && self
+                .status_class
+                .is_none_or(|c| status_class(entry.status_code) == c)
+    }
+}
+
+fn status_class(status_code: Option<StatusCode>) -> u8 {
+    match status_code.map(|s| s.as_u16() / 100) {
+        Some(c @ 1..=5) => c as u8,
+        _ => 0,
+    }
+}
+
+/// An index of a log file.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::index::{IndexQuery, LogIndex};
+/// let index = match LogIndex::load("access.log.idx") {
+///     Ok(index) => index,
+///     Err(_) => {
+///         let index = LogIndex::build("access.log", 4096).unwrap();
+///         index.save("access.log.idx").unwrap();
+///         index
+///     }
+/// };
+///
+/// let query = IndexQuery::default().with_status_class(5);
+/// for entry in index.query(&query).unwrap() {
+///     println!("{:?}", entry.unwrap());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LogIndex {
+    /// The indexed file.
+    pub path: PathBuf,
+    pub file_id: Option<FileId>,
+    /// The length of the indexed part of the file.
+    pub len: u64,
+    pub blocks: Vec<Block>,
+}
+
+impl LogIndex {
+    /// Index the file at `path`, in blocks of `block_lines` lines.
+    pub fn build(path: impl AsRef<Path>, block_lines: u64) -> io::Result<Self> {
+        assert!(block_lines > 0, "blocks must have at least one line");
+        let path = path.as_ref().to_owned();
+        let file = File::open(&path)?;
+        let file_id = FileId::of(&file.metadata()?);
+        let mut r = BufReader::new(file);
+
+        let (mut offset, mut line) = (0, 0);
+        let mut blocks = vec![];
+        let mut block = Block::new(0, 0);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let n = r.read_until(b'\n', &mut buf)?;
+            // Leave any partial last line for when it is complete.
+            if n == 0 || !buf.ends_with(b"\n") {
+                break;
+            }
+
+            offset += n as u64;
+            line += 1;
+            block.lines += 1;
+            match trim_line_end(&buf) {
+                [] => (),
+                l => {
+                    if let Ok(e) = LogEntryRef::from_bytes(l) {
+                        if let Some(t) = e.time {
+                            block.min_time = Some(block.min_time.map_or(t, |m| m.min(t)));
+                            block.max_time = Some(block.max_time.map_or(t, |m| m.max(t)));
+                        }
+                        block.statuses[status_class(e.status_code) as usize] += 1;
+                    }
+                }
+            }
+
+            if block.lines == block_lines {
+                blocks.push(std::mem::replace(&mut block, Block::new(offset, line)));
+            }
+        }
+
+        if block.lines > 0 {
+            blocks.push(block);
+        }
+
+        Ok(LogIndex {
+            path,
+            file_id,
+            len: offset,
+            blocks,
+        })
+    }
+
+    /// Write the index to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let log_path = self.path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "log path is not valid UTF-8")
+        })?;
+        let time = |t: Option<DateTime<Utc>>| {
+            t.map_or("-".to_owned(), |t| {
+                t.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            })
+        };
+
+        let mut f = io::BufWriter::new(File::create(path)?);
+        writeln!(f, "path={}", log_path)?;
+        if let Some(id) = self.file_id {
+            writeln!(f, "dev={}", id.dev)?;
+            writeln!(f, "ino={}", id.ino)?;
+        }
+        writeln!(f, "len={}", self.len)?;
+        for b in &self.blocks {
+            write!(
+                f,
+                "block={} {} {} {} {}",
+                b.offset,
+                b.line,
+                b.lines,
+                time(b.min_time),
+                time(b.max_time)
+            )?;
+            for s in b.statuses {
+                write!(f, " {}", s)?;
+            }
+            writeln!(f)?;
+        }
+        f.into_inner()?.sync_all()
+    }
+
+    /// Read an index written by [`LogIndex::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid index: {}", what),
+            )
+        };
+        let num = |v: &str| v.parse::<u64>().map_err(|_| invalid("bad number"));
+        let time = |v: &str| match v {
+            "-" => Ok(None),
+            v => DateTime::parse_from_rfc3339(v)
+                .map(|t| Some(t.with_timezone(&Utc)))
+                .map_err(|_| invalid("bad time")),
+        };
+
+        let (mut log_path, mut dev, mut ino, mut len) = (None, None, None, 0);
+        let mut blocks = vec![];
+        for l in fs::read_to_string(path)?.lines() {
+            match l.split_once('=') {
+                Some(("path", v)) => log_path = Some(PathBuf::from(v)),
+                Some(("dev", v)) => dev = Some(num(v)?),
+                Some(("ino", v)) => ino = Some(num(v)?),
+                Some(("len", v)) => len = num(v)?,
+                Some(("block", v)) => {
+                    let fields: Vec<&str> = v.split(' ').collect();
+                    if fields.len() != 11 {
+                        return Err(invalid(l));
+                    }
+
+                    let mut statuses = [0; 6];
+                    for (s, f) in statuses.iter_mut().zip(&fields[5..]) {
+                        *s = num(f)?;
+                    }
+                    blocks.push(Block {
+                        offset: num(fields[0])?,
+                        line: num(fields[1])?,
+                        lines: num(fields[2])?,
+                        min_time: time(fields[3])?,
+                        max_time: time(fields[4])?,
+                        statuses,
+                    });
+                }
+                _ => return Err(invalid(l)),
+            }
+        }
+
+        Ok(LogIndex {
+            path: log_path.ok_or_else(|| invalid("missing path"))?,
+            file_id: dev.zip(ino).map(|(dev, ino)| FileId { dev, ino }),
+            len,
+            blocks,
+        })
+    }
+
+    /// Whether this is an index of the file with metadata `meta`, as it was indexed or with
+    /// lines appended since.
+    pub fn describes(&self, meta: &fs::Metadata) -> bool {
+        FileId::of(meta) == self.file_id && meta.len() >= self.len
+    }
+
+    /// The first block with an entry at or after `target`, if any has one.
+    ///
+    /// Reading a log in time order from the start of this block finds every entry from `target`
+    /// on.
+    pub fn block_at(&self, target: DateTime<Utc>) -> Option<&Block> {
+        self.blocks
+            .iter()
+            .find(|b| b.max_time.is_some_and(|t| t >= target))
+    }
+
+    /// The entries of the indexed file which match `query`, in file order.
+    ///
+    /// Fails if the file has been replaced or truncated since it was indexed.
+    pub fn query(&self, query: &IndexQuery) -> io::Result<IndexedEntries> {
+        let file = File::open(&self.path)?;
+        if !self.describes(&file.metadata()?) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "log file has changed since it was indexed",
+            ));
+        }
+
+        let mut ranges: Vec<(u64, u64, Option<u64>)> = self
+            .blocks
+            .iter()
+            .filter(|b| b.may_match(query))
+            .map(|b| (b.offset, b.line, Some(b.lines)))
+            .collect();
+        let indexed_lines = self.blocks.last().map_or(0, |b| b.line + b.lines);
+        ranges.push((self.len, indexed_lines, None));
+        ranges.reverse();
+
+        Ok(IndexedEntries {
+            file: BufReader::new(file),
+            query: *query,
+            ranges,
+            current: None,
+            buf: Vec::new(),
+        })
+    }
+}
+
+/// The iterator returned by [`LogIndex::query`].
+pub struct IndexedEntries {
+    file: BufReader<File>,
+    query: IndexQuery,
+    /// `(offset, first line, number of lines)` of each range left to read, last first. The
+    /// unindexed end of the file has no limit on its number of lines.
+    ranges: Vec<(u64, u64, Option<u64>)>,
+    /// The next line number in the range being read, and how many lines are left in it.
+    current: Option<(u64, Option<u64>)>,
+    buf: Vec<u8>,
+}
+
+impl Iterator for IndexedEntries {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, left) = match &mut self.current {
+                Some((_, Some(0))) | None => {
+                    let (offset, line, lines) = self.ranges.pop()?;
+                    if let Err(e) = self.file.seek(SeekFrom::Start(offset)) {
+                        return Some(Err(e.into()));
+                    }
+                    self.current.insert((line, lines))
+                }
+                Some(c) => c,
+            };
+
+            self.buf.clear();
+            match self.file.read_until(b'\n', &mut self.buf) {
+                Ok(0) => {
+                    self.current = None;
+                    continue;
+                }
+                Ok(_) => (),
+                Err(e) => return Some(Err(e.into())),
+            }
+
+            *line += 1;
+            if let Some(left) = left {
+                *left -= 1;
+            }
+
+            let l = trim_line_end(&self.buf);
+            if l.is_empty() {
+                continue;
+            }
+
+            match LogEntry::from_bytes(l) {
+                Ok(e) if self.query.matches(&e) => return Some(Ok(e)),
+                Ok(_) => (),
+                Err(error) => return Some(Err(ReadError::Parse { line: *line, error })),
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Producing entries to Kafka.
+//!
+//! [`KafkaSink`] wraps an [`rdkafka`] producer, which batches, compresses, and retries messages
+//! according to its [configuration], so most tuning is done there rather than here.
+//!
+//! [configuration]: https://github.com/confluentinc/librdkafka/blob/master/CONFIGURATION.md
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use rdkafka::{
+    config::ClientConfig,
+    error::{KafkaError, KafkaResult, RDKafkaErrorCode},
+    producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext},
+    ClientContext,
+};
+
+use crate::{
+    sink::{Sink, Template},
+    LogEntry,
+};
+
+/// How [`KafkaSink`] encodes each entry as a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// The entry's JSON serialization.
+    Json,
+    /// The entry's Common Log Format line.
+    Line,
+    /// The protobuf encoding of the entry as a [`grpc::proto::Entry`](crate::grpc::proto::Entry),
+    /// the most compact of the three.
+    #[cfg(feature = "grpc")]
+    Protobuf,
+}
+
+impl Payload {
+    fn encode(&self, entry: &LogEntry) -> Vec<u8> {
+        match self {
+            Payload::Json => serde_json::to_vec(entry).expect("entries serialize to JSON"),
+            Payload::Line => entry.to_string().into_bytes(),
+            #[cfg(feature = "grpc")]
+            Payload::Protobuf => {
+                prost::Message::encode_to_vec(&crate::grpc::proto::Entry::from(entry))
+            }
+        }
+    }
+}
+
+/// Counts the messages the producer failed to deliver.
+#[derive(Default)]
+struct Deliveries {
+    failed: AtomicU64,
+    last_error: Mutex<Option<KafkaError>>,
+}
+
+impl ClientContext for Deliveries {}
+
+impl ProducerContext for Deliveries {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((e, _)) = result {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock().unwrap() = Some(e.clone());
+        }
+    }
+}
+
+/// A [`Sink`] which produces entries to Kafka topics.
+///
+/// The topic for each entry is rendered from a [`Template`], and so is its key, if it has one:
+/// Kafka puts messages with the same key in the same partition, so a key of `{host}` keeps each
+/// client's requests in order. Without a key, messages are spread over the partitions.
+///
+/// [`Sink::send`] only queues a message in the producer, waiting for room if the queue is full,
+/// and the producer delivers it in the background. Messages it gives up on are counted in
+/// [`KafkaSink::failed`]. [`Sink::flush`] waits until every queued message has been delivered or
+/// has failed, for up to 30 seconds by default.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{kafka::{KafkaSink, Payload}, sink::{Sink, Template}, LogEntry};
+/// let mut config = rdkafka::ClientConfig::new();
+/// config.set("bootstrap.servers", "127.0.0.1:9092").set("compression.type", "zstd");
+/// let mut sink = KafkaSink::new(&config, Template::new("access-log"))
+///     .unwrap()
+///     .with_key(Template::new("{host}"))
+///     .with_payload(Payload::Line);
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// sink.send(&entry).unwrap();
+/// sink.flush().unwrap();
+/// assert_eq!(sink.failed(), 0);
+/// ```
+pub struct KafkaSink {
+    producer: BaseProducer<Deliveries>,
+    topic: Template,
+    key: Option<Template>,
+    payload: Payload,
+    flush_timeout: Duration,
+}
+
+impl KafkaSink {
+    /// Create a producer from `config`, which must at least set `bootstrap.servers`, sending
+    /// entries as JSON.
+    pub fn new(config: &ClientConfig, topic: Template) -> KafkaResult<Self> {
+        Ok(KafkaSink {
+            producer: config.create_with_context(Deliveries::default())?,
+            topic,
+            key: None,
+            payload: Payload::Json,
+            flush_timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Key each message with `key`, rendered from its entry.
+    pub fn with_key(mut self, key: Template) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Encode entries as `payload`.
+    pub fn with_payload(mut self, payload: Payload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Wait up to `timeout` for queued messages on [`Sink::flush`].
+    pub fn with_flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// The number of messages the producer failed to deliver.
+    pub fn failed(&self) -> u64 {
+        self.producer.context().failed.load(Ordering::Relaxed)
+    }
+
+    /// Why the latest failed message failed.
+    pub fn last_error(&self) -> Option<KafkaError> {
+        self.producer.context().last_error.lock().unwrap().clone()
+    }
+}
+
+impl Sink for KafkaSink {
+    type Error = KafkaError;
+
+    fn send(&mut self, entry: &LogEntry) -> KafkaResult<()> {
+        let topic = self.topic.render(entry);
+        let key = self.key.as_ref().map(|k| k.render(entry));
+        let payload = self.payload.encode(entry);
+        let mut record = BaseRecord::to(&topic).payload(&payload);
+        if let Some(key) = &key {
+            record = record.key(key);
+        }
+        if let Some(time) = entry.time {
+            record = record.timestamp(time.timestamp_millis());
+        }
+
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                    // Serve delivery reports, which makes room in the queue.
+                    self.producer.poll(Duration::from_millis(100));
+                    record = r;
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> KafkaResult<()> {
+        self.producer.flush(self.flush_timeout)
+    }
+}
@@ -3,17 +3,257 @@
 //! See [clf] for more information about the format.
 //!
 //! [clf]: https://en.wikipedia.org/wiki/Common_Log_Format
+//!
+//! # Features
+//!
+//! The parser itself ([`LogEntry`], the `peel_*` functions, [`bytes`], and [`warnings`]) is
+//! always available. Everything else is grouped behind features, so that embedding the parser
+//! alone needs only `default-features = false`.
+//!
+//! The default features use nothing beyond the parser's own dependencies:
+//!
+//! | feature     | modules                                                  |
+//! |-------------|----------------------------------------------------------|
+//! | `formats`   | [`canonical`], [`combined`], [`dump`], [`duration`], [`ecs`], [`format`](mod@format), [`forwarded`], [`generator`], [`proxy`], [`siem`] |
+//! | `io`        | [`background`], [`checkpoint`], [`follow`], [`index`], [`merge`], [`pipeline`], [`progress`], [`reader`], [`repair`], [`replay`], [`rotated`], [`seek`], [`sink`], [`sort`], [`writer`] |
+//! | `analytics` | [`anomaly`], [`arrivals`], [`banlist`], [`batch`], [`cache`], [`classify`], [`dedup`], [`derived`], [`diff`], [`filter`], [`memory`], [`popularity`], [`privacy`], [`rollup`], [`sample`], [`scanner`], [`security`], [`session`], [`simulate`], [`skew`], [`slo`], [`slowloris`], [`stats`], [`store`], [`topk`], [`validate`], [`watch`], [`window`] |
+//!
+//! The others pull in extra dependencies and are off by default:
+//!
+//! | feature                          | provides                                      |
+//! |----------------------------------|-----------------------------------------------|
+//! | `gzip`, `zstd`, `bzip2`, `xz`    | decompression in [`reader`], and compression of rotated and split files in [`writer`] (implies `io`) |
+//! | `mmap`                           | the `mmap` module (implies `io`)              |
+//! | `rayon`                          | the `parallel` module (implies `io`)          |
+//! | `actix`                          | the `actix` access logging middleware for actix-web apps (implies `formats`) |
+//! | `anonymize`                      | keyed-hash pseudonymization of addresses in the `anonymize` module (implies `analytics`) |
+//! | `app`                            | argument parsing and input handling for command-line tools in the `app` module |
+//! | `arbitrary`, `proptest`          | generating entries for fuzzing and property tests in the `testing` module |
+//! | `async`                          | `Stream` interfaces to [`follow`] (implies `io`) |
+//! | `axum`                           | the `axum` access logging layer, with the client addresses axum records (implies `tower`) |
+//! | `clickhouse`                     | the `clickhouse` sink, batching inserts over ClickHouse's HTTP interface (implies `io`) |
+//! | `cli`                            | the `clf` command-line tool, to validate, convert, filter, summarize, and watch logs (implies `app`, `anonymize`) |
+//! | `datafusion`                     | the `datafusion` SQL table over log files (implies `io`) |
+//! | `elasticsearch`                  | the `elasticsearch` sink, indexing ECS documents in Elasticsearch or OpenSearch (implies `formats`, `io`) |
+//! | `geoip`                          | country, city, and ASN lookups from MaxMind databases in the `geoip` module |
+//! | `grpc`                           | the `grpc` ingest service and client (implies `io`) |
+//! | `kafka`                          | the `kafka` sink, producing entries to Kafka topics through librdkafka (implies `io`) |
+//! | `listener`                       | the `listener` server, receiving lines over TCP, UDP, or Unix sockets (implies `io`) |
+//! | `log`                            | the `log` logger, writing access records' key-value pairs as log lines (implies `formats`) |
+//! | `loki`                           | the `loki` sink, pushing entries to Grafana Loki streams (implies `io`) |
+//! | `mqtt`, `nats`                   | sinks publishing to MQTT and NATS (imply `io`) |
+//! | `object_store`                   | reading logs from `s3://`, `gs://`, and `az://` URLs in the `object_store` module (implies `io`) |
+//! | `opentelemetry`                  | conversion to OpenTelemetry log records, and the `opentelemetry` OTLP/HTTP sink (implies `io`) |
+//! | `parquet`                        | writing Parquet files and partitioned datasets in the `parquet` module (implies `io`) |
+//! | `polars`                         | conversion to and from Polars data frames in the `polars` module |
+//! | `prometheus`                     | the `prometheus` exporter, serving request metrics from a followed log (implies `io`) |
+//! | `rdns`                           | cached reverse DNS lookups of client addresses in the `rdns` module |
+//! | `redis`                          | the `redis` sink and rate limiter (implies `io`, `analytics`) |
+//! | `regex`                          | regular expression rules for [`privacy::QueryRedactor`] (implies `analytics`) |
+//! | `seal`                           | sealing logs against tampering with rotating HMAC keys in the `seal` module (implies `io`) |
+//! | `sql`                            | the `clf sql` subcommand, querying log files with SQL through the `datafusion` table (implies `cli`, `datafusion`) |
+//! | `sqlite`                         | exporting entries to SQLite databases in the `sqlite` module (implies `io`) |
+//! | `syslog`                         | the `syslog` sink, sending RFC 5424 messages over UDP, TCP, or TLS (implies `io`) |
+//! | `tower`                          | the `tower` access logging middleware for HTTP services (implies `formats`) |
+//! | `tracing`                        | the `tracing` subscriber layer, writing a line for each request span (implies `formats`) |
+//! | `useragent`                      | user agent parsing and bot detection in the `useragent` module (implies `formats`) |
 
 use std::{
+    borrow::Cow,
     error::Error,
     fmt::Display,
     net::{AddrParseError, IpAddr},
     num::ParseIntError,
-    str::FromStr,
+    str::{FromStr, Utf8Error},
 };
 
 use chrono::{DateTime, ParseError, Utc};
 use http::{status::InvalidStatusCode, StatusCode};
+use warnings::Warning;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "analytics")]
+pub mod anomaly;
+#[cfg(feature = "anonymize")]
+pub mod anonymize;
+#[cfg(feature = "app")]
+pub mod app;
+#[cfg(feature = "analytics")]
+pub mod arrivals;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "io")]
+pub mod background;
+#[cfg(feature = "analytics")]
+pub mod banlist;
+#[cfg(feature = "analytics")]
+pub mod batch;
+pub mod bytes;
+#[cfg(feature = "analytics")]
+pub mod cache;
+#[cfg(feature = "formats")]
+pub mod canonical;
+#[cfg(feature = "io")]
+pub mod checkpoint;
+#[cfg(feature = "analytics")]
+pub mod classify;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "formats")]
+pub mod combined;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+#[cfg(feature = "analytics")]
+pub mod dedup;
+#[cfg(feature = "analytics")]
+pub mod derived;
+#[cfg(feature = "analytics")]
+pub mod diff;
+#[cfg(feature = "formats")]
+pub mod dump;
+#[cfg(feature = "formats")]
+pub mod duration;
+#[cfg(feature = "formats")]
+pub mod ecs;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
+#[cfg(feature = "analytics")]
+pub mod filter;
+#[cfg(feature = "io")]
+pub mod follow;
+#[cfg(feature = "formats")]
+pub mod format;
+#[cfg(feature = "formats")]
+pub mod forwarded;
+#[cfg(feature = "formats")]
+pub mod generator;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(any(
+    feature = "clickhouse",
+    feature = "elasticsearch",
+    feature = "loki",
+    feature = "opentelemetry"
+))]
+mod http_post;
+#[cfg(feature = "io")]
+pub mod index;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "listener")]
+pub mod listener;
+#[cfg(feature = "log")]
+pub mod log;
+#[cfg(feature = "loki")]
+pub mod loki;
+#[cfg(feature = "analytics")]
+pub mod memory;
+#[cfg(feature = "io")]
+pub mod merge;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "object_store")]
+pub mod object_store;
+#[cfg(feature = "opentelemetry")]
+pub mod opentelemetry;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(any(feature = "io", feature = "analytics"))]
+mod pattern;
+#[cfg(feature = "io")]
+pub mod pipeline;
+#[cfg(feature = "polars")]
+pub mod polars;
+#[cfg(feature = "analytics")]
+pub mod popularity;
+#[cfg(feature = "analytics")]
+pub mod privacy;
+#[cfg(feature = "io")]
+pub mod progress;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "formats")]
+pub mod proxy;
+#[cfg(feature = "rdns")]
+pub mod rdns;
+#[cfg(feature = "io")]
+pub mod reader;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "io")]
+pub mod repair;
+#[cfg(feature = "io")]
+pub mod replay;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod request_fields;
+#[cfg(any(feature = "actix", feature = "tower"))]
+mod request_log;
+#[cfg(feature = "analytics")]
+pub mod rollup;
+#[cfg(feature = "io")]
+pub mod rotated;
+#[cfg(feature = "analytics")]
+pub mod sample;
+#[cfg(feature = "analytics")]
+pub mod scanner;
+#[cfg(feature = "seal")]
+pub mod seal;
+#[cfg(feature = "analytics")]
+pub mod security;
+#[cfg(feature = "io")]
+pub mod seek;
+#[cfg(feature = "analytics")]
+pub mod session;
+#[cfg(feature = "formats")]
+pub mod siem;
+#[cfg(feature = "analytics")]
+pub mod simulate;
+#[cfg(feature = "io")]
+pub mod sink;
+#[cfg(feature = "analytics")]
+pub mod skew;
+#[cfg(feature = "analytics")]
+pub mod slo;
+#[cfg(feature = "analytics")]
+pub mod slowloris;
+#[cfg(feature = "io")]
+pub mod sort;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "analytics")]
+pub mod stats;
+#[cfg(feature = "analytics")]
+pub mod store;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+pub mod testing;
+#[cfg(feature = "analytics")]
+pub mod topk;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+#[cfg(feature = "useragent")]
+pub mod useragent;
+#[cfg(feature = "analytics")]
+pub mod validate;
+pub mod warnings;
+#[cfg(feature = "analytics")]
+pub mod watch;
+#[cfg(feature = "analytics")]
+pub mod window;
+#[cfg(feature = "io")]
+pub mod writer;
 
 /// A single line in Common Log Format.
 ///
@@ -56,6 +296,241 @@ pub struct LogEntry {
     pub object_size: Option<usize>,
 }
 
+/// Leniency settings for parsing a [`LogEntry`].
+///
+/// The default is to accept only well-formed Common Log Format, as [`LogEntry`]'s [`FromStr`]
+/// implementation does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Accept thousands separators in the object size, as in `1,234`. See [`peel_grouped_usize`].
+    pub size_thousands_separators: bool,
+}
+
+/// A [`LogEntry`] which borrows its string fields from the line it was parsed from.
+///
+/// String fields are only copied if they contain invalid UTF-8, which is replaced with `U+FFFD`.
+///
+/// # Example
+/// ```
+/// use common_log_format::LogEntryRef;
+/// let line = "127.0.0.1 - frank [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let entry = LogEntryRef::from_bytes(line.as_bytes()).unwrap();
+/// assert_eq!(entry.authuser.as_deref(), Some("frank"));
+/// assert_eq!(entry.into_owned(), line.parse().unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntryRef<'a> {
+    pub host: Option<IpAddr>,
+    pub ident: Option<Cow<'a, str>>,
+    pub authuser: Option<Cow<'a, str>>,
+    pub time: Option<chrono::DateTime<Utc>>,
+    pub request_line: Option<Cow<'a, str>>,
+    pub status_code: Option<StatusCode>,
+    pub object_size: Option<usize>,
+}
+
+impl<'a> LogEntryRef<'a> {
+    pub fn from_bytes(line: &'a [u8]) -> Result<Self, LogEntryParseError> {
+        Self::from_bytes_with(line, &ParseOptions::default())
+    }
+
+    pub fn from_bytes_with(
+        line: &'a [u8],
+        opts: &ParseOptions,
+    ) -> Result<Self, LogEntryParseError> {
+        let (host, remaining) = bytes::peel_ip(line)?;
+        let (ident, remaining) = bytes::peel_string(remaining)?;
+        let (authuser, remaining) = bytes::peel_string(remaining)?;
+        let (time, remaining) = bytes::peel_timestamp(remaining)?;
+        let (request_line, remaining) = bytes::peel_quoted_string(remaining)?;
+        let (status_code, remaining) = bytes::peel_status_code(remaining)?;
+        let (object_size, remaining) = if opts.size_thousands_separators {
+            bytes::peel_grouped_usize(remaining)?
+        } else {
+            bytes::peel_usize(remaining)?
+        };
+        if !remaining.is_empty() {
+            warnings::emit(Warning::TrailingData {
+                len: remaining.len(),
+            });
+        }
+
+        let lossy = |field: &str, b: &'a [u8]| {
+            let s = String::from_utf8_lossy(b);
+            if let Cow::Owned(_) = s {
+                warnings::emit(Warning::InvalidUtf8 {
+                    field: field.to_owned(),
+                });
+            }
+            s
+        };
+        Ok(LogEntryRef {
+            host,
+            ident: ident.map(|b| lossy("ident", b)),
+            authuser: authuser.map(|b| lossy("authuser", b)),
+            time,
+            request_line: request_line.map(|b| lossy("request_line", b)),
+            status_code,
+            object_size,
+        })
+    }
+
+    pub fn into_owned(self) -> LogEntry {
+        LogEntry {
+            host: self.host,
+            ident: self.ident.map(Cow::into_owned),
+            authuser: self.authuser.map(Cow::into_owned),
+            time: self.time,
+            request_line: self.request_line.map(Cow::into_owned),
+            status_code: self.status_code,
+            object_size: self.object_size,
+        }
+    }
+}
+
+impl<'a> From<LogEntryRef<'a>> for LogEntry {
+    fn from(e: LogEntryRef<'a>) -> Self {
+        e.into_owned()
+    }
+}
+
+impl LogEntry {
+    /// Parse a line which is not necessarily valid UTF-8.
+    ///
+    /// Only the fields converted to typed values need to be UTF-8. Invalid sequences in `ident`,
+    /// `authuser`, and `request_line` are replaced with `U+FFFD`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::LogEntry;
+    /// let line = b"127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /caf\xe9 HTTP/1.0\" 200 2326";
+    /// let entry = LogEntry::from_bytes(line).unwrap();
+    /// assert_eq!(entry.request_line.as_deref(), Some("GET /caf\u{FFFD} HTTP/1.0"));
+    /// ```
+    pub fn from_bytes(line: &[u8]) -> Result<Self, LogEntryParseError> {
+        Self::from_bytes_with(line, &ParseOptions::default())
+    }
+
+    /// [`LogEntry::from_bytes`], with non-default [`ParseOptions`].
+    pub fn from_bytes_with(line: &[u8], opts: &ParseOptions) -> Result<Self, LogEntryParseError> {
+        LogEntryRef::from_bytes_with(line, opts).map(LogEntryRef::into_owned)
+    }
+
+    /// Parse `line`, with non-default [`ParseOptions`].
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{LogEntry, ParseOptions};
+    /// let line = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 1,234,567";
+    /// assert!(line.parse::<LogEntry>().is_err());
+    ///
+    /// let opts = ParseOptions { size_thousands_separators: true, ..Default::default() };
+    /// let entry = LogEntry::parse_with(line, &opts).unwrap();
+    /// assert_eq!(entry.object_size, Some(1234567));
+    /// ```
+    pub fn parse_with(line: &str, opts: &ParseOptions) -> Result<Self, LogEntryParseError> {
+        let (entry, remaining) = Self::peel(line, opts)?;
+        if !remaining.is_empty() {
+            warnings::emit(Warning::TrailingData {
+                len: remaining.len(),
+            });
+        }
+        Ok(entry)
+    }
+
+    /// Take an entry from the start of `line`, returning it and whatever follows it.
+    pub(crate) fn peel<'a>(
+        line: &'a str,
+        opts: &ParseOptions,
+    ) -> Result<(Self, &'a str), LogEntryParseError> {
+        let (host, remaining) = peel_ip(line)?;
+        let (ident, remaining) = peel_string(remaining)?;
+        let (authuser, remaining) = peel_string(remaining)?;
+        let (time, remaining) = peel_timestamp(remaining)?;
+        let (request_line, remaining) = peel_quoted_string(remaining)?;
+        let (status_code, remaining) = peel_status_code(remaining)?;
+        let (object_size, remaining) = if opts.size_thousands_separators {
+            peel_grouped_usize(remaining)?
+        } else {
+            peel_usize(remaining)?
+        };
+
+        let entry = LogEntry {
+            host,
+            ident: ident.map(str::to_owned),
+            authuser: authuser.map(str::to_owned),
+            time,
+            request_line: request_line.map(str::to_owned),
+            status_code,
+            object_size,
+        };
+        Ok((entry, remaining))
+    }
+
+    /// Parse `line`, also returning any [`Warning`]s about data which was not kept as written.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{warnings::Warning, LogEntry};
+    /// let line = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326 extra";
+    /// let (_, ws) = LogEntry::parse_with_warnings(line).unwrap();
+    /// assert_eq!(ws, vec![Warning::TrailingData { len: 5 }]);
+    /// ```
+    pub fn parse_with_warnings(line: &str) -> Result<(Self, Vec<Warning>), LogEntryParseError> {
+        let (entry, ws) = warnings::collect(|| line.parse());
+        Ok((entry?, ws))
+    }
+
+    /// An estimate of the memory taken up by the entry, in bytes: its own size and the heap
+    /// space of its strings. Allocator overhead isn't counted.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::LogEntry;
+    /// let line = "127.0.0.1 - frank [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+    /// let entry: LogEntry = line.parse().unwrap();
+    /// let strings = "frank".len() + "GET / HTTP/1.0".len();
+    /// assert_eq!(entry.approx_mem_size(), std::mem::size_of::<LogEntry>() + strings);
+    /// ```
+    pub fn approx_mem_size(&self) -> usize {
+        let heap = |s: &Option<String>| s.as_ref().map_or(0, String::capacity);
+        std::mem::size_of::<Self>()
+            + heap(&self.ident)
+            + heap(&self.authuser)
+            + heap(&self.request_line)
+    }
+
+    /// The request method, i.e. the first word of `request_line`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::LogEntry;
+    /// let line = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /a/b?c=d HTTP/1.0\" 200 2326";
+    /// let entry: LogEntry = line.parse().unwrap();
+    /// assert_eq!(entry.method(), Some("GET"));
+    /// assert_eq!(entry.target(), Some("/a/b?c=d"));
+    /// assert_eq!(entry.path(), Some("/a/b"));
+    /// ```
+    pub fn method(&self) -> Option<&str> {
+        self.request_line
+            .as_deref()?
+            .split(' ')
+            .next()
+            .filter(|m| !m.is_empty())
+    }
+
+    /// The request target, i.e. the second word of `request_line`.
+    pub fn target(&self) -> Option<&str> {
+        self.request_line.as_deref()?.split(' ').nth(1)
+    }
+
+    /// The request target without its query string.
+    pub fn path(&self) -> Option<&str> {
+        let target = self.target()?;
+        Some(target.split_once('?').map_or(target, |(p, _)| p))
+    }
+}
+
 fn serialize_status_code<S>(sc: &Option<StatusCode>, ser: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -71,7 +546,10 @@ where
     match <Option<u16> as serde::Deserialize<'de>>::deserialize(de)? {
         Some(sc) => match StatusCode::from_u16(sc) {
             Ok(s) => Ok(Some(s)),
-            Err(_) => Ok(None),
+            Err(_) => {
+                warnings::emit(Warning::InvalidStatusCode(sc));
+                Ok(None)
+            }
         },
         None => Ok(None),
     }
@@ -85,6 +563,9 @@ pub enum LogEntryParseError {
     DateTimeParse(ParseError),
     StatusCodeParse(InvalidStatusCode),
     SizeParse(ParseIntError),
+    Utf8(Utf8Error),
+    PortParse(ParseIntError),
+    DurationParse(ParseIntError),
 }
 
 impl Display for LogEntryParseError {
@@ -101,103 +582,119 @@ impl Error for LogEntryParseError {
             Self::DateTimeParse(ref e) => Some(e),
             Self::StatusCodeParse(ref e) => Some(e),
             Self::SizeParse(ref e) => Some(e),
+            Self::Utf8(ref e) => Some(e),
+            Self::PortParse(ref e) => Some(e),
+            Self::DurationParse(ref e) => Some(e),
         }
     }
 }
 
+/// Write the entry as a line which parses back into it.
+///
+/// Missing fields are written as `-`, and the time in RFC 3339 format, in UTC. Fields containing
+/// their own delimiters (spaces in `ident` and `authuser`, quotes in `request_line`) are written
+/// as they are, and won't parse back.
+///
+/// # Example
+/// ```
+/// use common_log_format::LogEntry;
+/// let line = "127.0.0.1 - frank [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 -";
+/// let entry: LogEntry = line.parse().unwrap();
+/// let written = entry.to_string();
+/// assert_eq!(written, "127.0.0.1 - frank [1996-12-20T00:39:57Z] \"GET / HTTP/1.0\" 200 -");
+/// assert_eq!(written.parse::<LogEntry>().unwrap(), entry);
+/// ```
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn or_dash(f: &mut std::fmt::Formatter<'_>, v: Option<impl Display>) -> std::fmt::Result {
+            match v {
+                Some(v) => write!(f, "{}", v),
+                None => f.write_str("-"),
+            }
+        }
+
+        or_dash(f, self.host)?;
+        f.write_str(" ")?;
+        or_dash(f, self.ident.as_deref())?;
+        f.write_str(" ")?;
+        or_dash(f, self.authuser.as_deref())?;
+        f.write_str(" ")?;
+        match self.time {
+            Some(t) => write!(
+                f,
+                "[{}]",
+                t.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+            )?,
+            None => f.write_str("-")?,
+        }
+        f.write_str(" ")?;
+        match &self.request_line {
+            Some(r) => write!(f, "\"{}\"", r)?,
+            None => f.write_str("-")?,
+        }
+        f.write_str(" ")?;
+        or_dash(f, self.status_code.map(|s| s.as_u16()))?;
+        f.write_str(" ")?;
+        or_dash(f, self.object_size)
+    }
+}
+
 impl FromStr for LogEntry {
     type Err = LogEntryParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (host, remaining) = peel_ip(s)?;
-        let (ident, remaining) = peel_string(remaining)?;
-        let (authuser, remaining) = peel_string(remaining)?;
-        let (time, remaining) = peel_timestamp(remaining)?;
-        let (request_line, remaining) = peel_quoted_string(remaining)?;
-        let (status_code, remaining) = peel_status_code(remaining)?;
-        let (object_size, _remaining) = peel_usize(remaining)?;
-
-        Ok(LogEntry {
-            host,
-            ident: ident.map(str::to_owned),
-            authuser: authuser.map(str::to_owned),
-            time,
-            request_line: request_line.map(str::to_owned),
-            status_code,
-            object_size,
-        })
+        Self::parse_with(s, &ParseOptions::default())
     }
 }
 
+/// The part of `line` which `part`, a subslice of `line.as_bytes()`, covers.
+///
+/// The byte-level peelers only ever split at ASCII delimiters, so this is always on a character
+/// boundary.
+fn subslice<'a>(line: &'a str, part: &[u8]) -> &'a str {
+    let start = part.as_ptr() as usize - line.as_ptr() as usize;
+    &line[start..start + part.len()]
+}
+
 /// Take an [`IpAddr`] from the start of `line`.
 ///
 /// Return None (and the remainder) if the string starts with `-`
 pub fn peel_ip(line: &str) -> Result<(Option<IpAddr>, &str), LogEntryParseError> {
-    let first_space_idx = line.find(' ').unwrap_or(line.len());
-    let rem = line[first_space_idx..].trim_start();
-    match line.chars().next() {
-        None => unreachable!(),
-        Some(x) if x == '-' => return Ok((None, rem)),
-        Some(_) => (),
-    }
-    let ip_addr = line[..first_space_idx]
-        .parse()
-        .map_err(LogEntryParseError::IpAddrParse)?;
-    Ok((Some(ip_addr), rem))
+    let (ip, rem) = bytes::peel_ip(line.as_bytes())?;
+    Ok((ip, subslice(line, rem)))
 }
 
 /// Take a [`usize`] from the start of `line` until the first whitespace.
 ///
 /// Return None (and the remainder) if the string starts with `-`
 pub fn peel_usize(line: &str) -> Result<(Option<usize>, &str), LogEntryParseError> {
-    let first_space_idx = line.find(' ').unwrap_or(line.len());
-    let rem = line[first_space_idx..].trim_start();
-    match line.chars().next() {
-        None => unreachable!(),
-        Some(x) if x == '-' => return Ok((None, rem)),
-        Some(_) => (),
-    }
-    Ok((
-        Some(
-            line[..first_space_idx]
-                .parse()
-                .map_err(LogEntryParseError::SizeParse)?,
-        ),
-        rem,
-    ))
+    let (size, rem) = bytes::peel_usize(line.as_bytes())?;
+    Ok((size, subslice(line, rem)))
+}
+
+/// Like [`peel_usize`], but also accept thousands separators, as in `1,234` or `1.234.567`.
+///
+/// Any one of `,`, `.`, `'`, or `_` may be used as the separator, as long as it is used
+/// consistently and every group after the first has exactly three digits.
+pub fn peel_grouped_usize(line: &str) -> Result<(Option<usize>, &str), LogEntryParseError> {
+    let (size, rem) = bytes::peel_grouped_usize(line.as_bytes())?;
+    Ok((size, subslice(line, rem)))
 }
 
 /// Take a [`str`] from the start of `line` until the first whitespace.
 ///
 /// Return None (and the remainder) if the string starts with `-`
 pub fn peel_string(line: &str) -> Result<(Option<&str>, &str), LogEntryParseError> {
-    let first_space_idx = line.find(' ').unwrap_or(line.len());
-    let rem = line[first_space_idx..].trim_start();
-    match line.chars().next() {
-        None => unreachable!(),
-        Some(x) if x == '-' => return Ok((None, rem)),
-        Some(_) => (),
-    }
-    Ok((Some(&line[..first_space_idx]), rem))
+    let (field, rem) = bytes::peel_string(line.as_bytes())?;
+    Ok((field.map(|f| subslice(line, f)), subslice(line, rem)))
 }
 
 /// Take a [`str`] from the start of `line` delimited by quotation marks (`"`).
 ///
 /// Return None (and the remainder) if the string starts with `-`
 pub fn peel_quoted_string(line: &str) -> Result<(Option<&str>, &str), LogEntryParseError> {
-    match line.chars().next() {
-        Some(x) if x == '-' => {
-            return Ok((None, line[1..].trim_start()));
-        }
-        Some(x) if x == '"' => (),
-        None | Some(_) => return Err(LogEntryParseError::FieldNotFound),
-    }
-    let rest = &line[1..];
-    let string_end_idx = rest.find('"').ok_or(LogEntryParseError::FieldNotFound)?;
-    Ok((
-        Some(&rest[..string_end_idx]),
-        rest[string_end_idx + 1..].trim_start(),
-    ))
+    let (field, rem) = bytes::peel_quoted_string(line.as_bytes())?;
+    Ok((field.map(|f| subslice(line, f)), subslice(line, rem)))
 }
 
 /// Take a [`DateTime`] from the start of `line` until the first whitespace.
@@ -205,18 +702,8 @@ pub fn peel_quoted_string(line: &str) -> Result<(Option<&str>, &str), LogEntryPa
 /// Use the strftime format "%d/%b/%Y:%H:%M:%S %z". Return None (and the remainder) if the string
 /// starts with `-`
 pub fn peel_timestamp(line: &str) -> Result<(Option<DateTime<Utc>>, &str), LogEntryParseError> {
-    match line.chars().next() {
-        Some(x) if x == '-' => {
-            return Ok((None, line[1..].trim_start()));
-        }
-        Some(x) if x == '[' => (),
-        None | Some(_) => return Err(LogEntryParseError::FieldNotFound),
-    }
-
-    let time_end_idx = line.find(']').ok_or(LogEntryParseError::FieldNotFound)?;
-    let dt = DateTime::parse_from_rfc3339(&line[1..time_end_idx])
-        .map_err(LogEntryParseError::DateTimeParse)?;
-    Ok((Some(dt.into()), line[time_end_idx + 1..].trim_start()))
+    let (time, rem) = bytes::peel_timestamp(line.as_bytes())?;
+    Ok((time, subslice(line, rem)))
 }
 
 /// Take a [`StatusCode`] from the start of `line` until the first whitespace.
@@ -232,19 +719,6 @@ pub fn peel_timestamp(line: &str) -> Result<(Option<DateTime<Utc>>, &str), LogEn
 /// assert_eq!(rem, "2326");
 /// ```
 pub fn peel_status_code(line: &str) -> Result<(Option<StatusCode>, &str), LogEntryParseError> {
-    let first_space_idx = line.find(' ').unwrap_or(line.len());
-    let rem = line[first_space_idx..].trim_start();
-    match line.chars().next() {
-        None => unreachable!(),
-        Some(x) if x == '-' => return Ok((None, rem)),
-        Some(_) => (),
-    }
-    Ok((
-        Some(
-            line[..first_space_idx]
-                .parse()
-                .map_err(LogEntryParseError::StatusCodeParse)?,
-        ),
-        rem,
-    ))
+    let (sc, rem) = bytes::peel_status_code(line.as_bytes())?;
+    Ok((sc, subslice(line, rem)))
 }
@@ -0,0 +1,226 @@
+//! Receiving log lines over the network.
+//!
+//! Fleets of servers often ship their access logs as raw lines, with `nc`, a syslog relay
+//! configured to pass messages through untouched, or a few lines of script. A [`Listener`]
+//! accepts those lines over TCP, UDP, or a Unix socket, parses them, and hands each entry to a
+//! [`Handler`]: a closure, or the sending half of a channel.
+//!
+//! On streams, lines end with `\n` (or `\r\n`), and each connection is read on its own thread. A
+//! UDP datagram holds one or more whole lines. Lines which can't be parsed are passed to
+//! [`Handler::error`], which ignores them unless implemented otherwise.
+
+use std::{
+    io::{self, BufReader, Read},
+    net::{SocketAddr, TcpListener, ToSocketAddrs, UdpSocket},
+    sync::{
+        mpsc::{Sender, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::Path};
+
+use crate::{
+    reader::{LogReader, ReadError},
+    LogEntry, ParseOptions,
+};
+
+/// The largest UDP datagram there can be.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// What a [`Listener`] does with what it receives.
+///
+/// It's called from every connection's thread at once, so it has to be shareable.
+pub trait Handler: Send + Sync + 'static {
+    fn entry(&self, entry: LogEntry);
+
+    /// Called with each line which couldn't be parsed, from the peer at `from`, if it has an
+    /// address. Line numbers count from the start of the connection, or of the datagram.
+    fn error(&self, from: Option<SocketAddr>, error: ReadError) {
+        let _ = (from, error);
+    }
+}
+
+impl<F: Fn(LogEntry) + Send + Sync + 'static> Handler for F {
+    fn entry(&self, entry: LogEntry) {
+        self(entry)
+    }
+}
+
+/// Entries are dropped once the receiver is.
+impl Handler for Sender<LogEntry> {
+    fn entry(&self, entry: LogEntry) {
+        let _ = self.send(entry);
+    }
+}
+
+/// The connection sending an entry waits while the channel is full, which holds back its peer
+/// through TCP's flow control. On UDP, datagrams are dropped by the system instead.
+impl Handler for SyncSender<LogEntry> {
+    fn entry(&self, entry: LogEntry) {
+        let _ = self.send(entry);
+    }
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// A socket receiving log lines.
+///
+/// # Example
+/// ```
+/// use std::{io::Write, net::TcpStream, sync::mpsc};
+/// use common_log_format::listener::Listener;
+///
+/// let listener = Listener::tcp("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let (sender, receiver) = mpsc::sync_channel(1024);
+/// listener.spawn(sender).unwrap();
+///
+/// let mut stream = TcpStream::connect(addr).unwrap();
+/// stream.write_all(b"10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10\n").unwrap();
+///
+/// let entry = receiver.recv().unwrap();
+/// assert_eq!(entry.host, Some("10.0.0.1".parse().unwrap()));
+/// assert_eq!(entry.object_size, Some(10));
+/// ```
+pub struct Listener {
+    socket: Socket,
+    opts: ParseOptions,
+}
+
+impl Listener {
+    /// Listen for TCP connections on `addr`.
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Listener::from_socket(Socket::Tcp(TcpListener::bind(addr)?)))
+    }
+
+    /// Receive UDP datagrams on `addr`.
+    pub fn udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Listener::from_socket(Socket::Udp(UdpSocket::bind(addr)?)))
+    }
+
+    /// Listen for connections on a Unix stream socket at `path`, which must not exist yet.
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Listener::from_socket(Socket::Unix(UnixListener::bind(
+            path,
+        )?)))
+    }
+
+    fn from_socket(socket: Socket) -> Self {
+        Listener {
+            socket,
+            opts: ParseOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// The address the socket is bound to, which is how to find the port when binding to port
+    /// 0. Unix sockets have none.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(l) => l.local_addr(),
+            Socket::Udp(s) => s.local_addr(),
+            #[cfg(unix)]
+            Socket::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets have no socket address",
+            )),
+        }
+    }
+
+    /// Receive entries and pass them to `handler`, until receiving fails.
+    ///
+    /// Errors on a connection close only that connection, as do errors accepting it which only
+    /// concern it, such as its peer resetting it.
+    pub fn run(self, handler: impl Handler) -> io::Result<()> {
+        let handler = Arc::new(handler);
+        let opts = self.opts;
+        match self.socket {
+            Socket::Tcp(listener) => loop {
+                match listener.accept() {
+                    Ok((stream, peer)) => serve(stream, Some(peer), &handler, opts),
+                    Err(e) if is_transient(&e) => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+            Socket::Udp(socket) => {
+                let mut buf = vec![0; MAX_DATAGRAM];
+                loop {
+                    let (n, peer) = match socket.recv_from(&mut buf) {
+                        Ok(received) => received,
+                        Err(e) if is_transient(&e) => continue,
+                        Err(e) => return Err(e),
+                    };
+                    read_lines(&buf[..n], Some(peer), &*handler, opts);
+                }
+            }
+            #[cfg(unix)]
+            Socket::Unix(listener) => loop {
+                match listener.accept() {
+                    Ok((stream, _)) => serve(stream, None, &handler, opts),
+                    Err(e) if is_transient(&e) => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+        }
+    }
+
+    /// [`Listener::run`] on a thread of its own.
+    pub fn spawn(self, handler: impl Handler) -> io::Result<JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("clf-listener".to_owned())
+            .spawn(move || self.run(handler))
+    }
+}
+
+/// Whether an error from `accept` or `recv_from` is about one connection or datagram, rather than
+/// the socket.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Read lines from `stream` on a thread of its own, or close it if there can't be one.
+fn serve<S, H>(stream: S, peer: Option<SocketAddr>, handler: &Arc<H>, opts: ParseOptions)
+where
+    S: Read + Send + 'static,
+    H: Handler,
+{
+    let handler = handler.clone();
+    let _ = thread::Builder::new()
+        .name("clf-listener-conn".to_owned())
+        .spawn(move || read_lines(BufReader::new(stream), peer, &*handler, opts));
+}
+
+/// Pass the entries in `input` to `handler`, until it ends or can't be read.
+fn read_lines(
+    input: impl io::BufRead,
+    peer: Option<SocketAddr>,
+    handler: &impl Handler,
+    opts: ParseOptions,
+) {
+    for entry in LogReader::new(input).with_options(opts) {
+        match entry {
+            Ok(entry) => handler.entry(entry),
+            Err(ReadError::Io(_)) => return,
+            Err(error) => handler.error(peer, error),
+        }
+    }
+}
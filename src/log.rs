@@ -0,0 +1,160 @@
+//! Writing access logs through the [`log`] crate.
+//!
+//! Applications which already log with `log` can log each request they answer as a record with
+//! the target [`TARGET`] (`access`) and its fields as key-value pairs. [`ClfLogger`] writes those
+//! records as Common or Combined Log Format lines, and passes every other record on to the
+//! application's usual logger, if it has one:
+//!
+//! | field            | keys                                                                  |
+//! |------------------|-----------------------------------------------------------------------|
+//! | request line     | `request`, `request_line`, or else the record's message               |
+//! | method           | `method`, `http.method`, `http.request.method`                        |
+//! | target           | `uri`, `url`, `target`, `path`, `http.target`, `http.uri`, `url.path` |
+//! | protocol version | `version`, `http.version`, `http.flavor`, `network.protocol.version`  |
+//! | status           | `status`, `status_code`, `http.status_code`, `http.response.status_code` |
+//! | response size    | `bytes`, `size`, `http.response.body.size`, `http.response_content_length` |
+//! | client address   | `client_addr`, `remote_addr`, `peer_addr`, `client.address`, `http.client_ip` |
+//! | ident            | `ident`                                                               |
+//! | user             | `user`, `authuser`, `enduser.id`                                      |
+//! | referer          | `referer`, `http.referer`, `http.request.header.referer`              |
+//! | user agent       | `user_agent`, `http.user_agent`, `user_agent.original`                |
+//! | time             | `time`, `timestamp`, in RFC 3339, or else when the record was logged  |
+//!
+//! These are the same names the `tracing` layer recognizes. The request line is made of the
+//! method, target, and version if it isn't given whole, as a key or as the message.
+//!
+//! [`log`]: https://docs.rs/log
+
+use std::{fmt::Write as _, io::Write, sync::Mutex};
+
+use ::log::{
+    kv::{self, Key, Value, VisitSource},
+    LevelFilter, Log, Metadata, Record, SetLoggerError,
+};
+use chrono::Utc;
+
+use crate::request_fields::Fields;
+
+/// The target of access records.
+pub const TARGET: &str = "access";
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.set(key.as_str(), value.to_string());
+        Ok(())
+    }
+}
+
+/// A logger which writes access records as log lines, as described in the [module docs](self).
+///
+/// Lines are in the Common Log Format unless [`ClfLogger::with_combined`] is set, each written
+/// with a single `write_all` while holding the writer's lock. Errors writing are ignored.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use common_log_format::log::ClfLogger;
+///
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+/// impl std::io::Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// let buffer = Buffer::default();
+/// ClfLogger::new(buffer.clone()).with_combined(true).init().unwrap();
+///
+/// log::info!(
+///     target: "access",
+///     client_addr = "10.0.0.1", user = "frank", status = 200, bytes = 512, user_agent = "curl/8.5.0";
+///     "GET /index.html HTTP/1.1"
+/// );
+/// log::info!("not an access record");
+///
+/// let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+/// assert!(line.starts_with("10.0.0.1 - frank ["));
+/// assert!(line.ends_with("] \"GET /index.html HTTP/1.1\" 200 512 \"-\" \"curl/8.5.0\"\n"));
+/// ```
+pub struct ClfLogger {
+    writer: Mutex<Box<dyn Write + Send>>,
+    combined: bool,
+    fallback: Option<Box<dyn Log>>,
+}
+
+impl ClfLogger {
+    /// Write lines to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        ClfLogger {
+            writer: Mutex::new(Box::new(writer)),
+            combined: false,
+            fallback: None,
+        }
+    }
+
+    /// Write lines in the Combined Log Format, with the referer and user agent.
+    pub fn with_combined(mut self, combined: bool) -> Self {
+        self.combined = combined;
+        self
+    }
+
+    /// Pass records with targets other than [`TARGET`] on to `logger`. Without one, they're
+    /// dropped.
+    pub fn with_fallback(mut self, logger: impl Log + 'static) -> Self {
+        self.fallback = Some(Box::new(logger));
+        self
+    }
+
+    /// Install the logger as the global logger, with every level enabled, leaving the fallback to
+    /// filter its own records.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        ::log::set_boxed_logger(Box::new(self))?;
+        ::log::set_max_level(LevelFilter::Trace);
+        Ok(())
+    }
+}
+
+impl Log for ClfLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        match &self.fallback {
+            _ if metadata.target() == TARGET => true,
+            Some(fallback) => fallback.enabled(metadata),
+            None => false,
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if record.target() != TARGET {
+            if let Some(fallback) = &self.fallback {
+                fallback.log(record);
+            }
+            return;
+        }
+
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        if !fields.is_request() {
+            fields.set("request_line", record.args().to_string());
+        }
+        fields.time.get_or_insert_with(Utc::now);
+        let entry = fields.into_entry();
+
+        let mut line = String::new();
+        let _ = match self.combined {
+            true => writeln!(line, "{}", entry),
+            false => writeln!(line, "{}", entry.entry),
+        };
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+        if let Some(fallback) = &self.fallback {
+            fallback.flush();
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Pushing entries to [Grafana Loki].
+//!
+//! Loki stores log lines in streams, each identified by a set of labels, and indexes only the
+//! labels. [`LokiSink`] sends each entry as its log line, to the stream whose labels are rendered
+//! from the entry by [`Template`]s, through Loki's [push API]. Labels should have few distinct
+//! values, such as a status class or a fixed virtual host name; a label per client address or
+//! path makes a stream per value, which Loki handles poorly.
+//!
+//! Like the other HTTP sinks, it speaks plain HTTP/1.1 itself, without TLS.
+//!
+//! [Grafana Loki]: https://grafana.com/oss/loki/
+//! [push API]: https://grafana.com/docs/loki/latest/reference/loki-http-api/#ingest-logs
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde_json::json;
+
+use crate::{
+    http_post::{Backoff, Endpoint},
+    replay::InvalidUrl,
+    sink::{Sink, Template},
+    LogEntry,
+};
+
+/// What [`LokiSink`] does with an entry older than the latest one already pushed to its stream.
+///
+/// Loki accepts such entries if they are within its out-of-order window (an hour, by default)
+/// and rejects the whole push otherwise; older versions reject them all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfOrder {
+    /// Push it as it is, relying on Loki's out-of-order window.
+    Send,
+    /// Push it with the time of the latest entry in its stream instead of its own.
+    Clamp,
+    /// Don't push it, counting it in [`LokiSink::dropped`].
+    Drop,
+}
+
+/// The labels of a stream, sorted by name.
+type Labels = Vec<(String, String)>;
+
+/// A [`Sink`] which pushes entries to Loki streams.
+///
+/// Each entry's line is its Common Log Format line, and its timestamp is its time, or the
+/// current time if it has none. Its stream's labels are rendered from the templates given to
+/// [`LokiSink::with_label`], starting with `job="access_log"`.
+///
+/// Entries are pushed in batches of 1,000 (by default), when a batch fills and on
+/// [`Sink::flush`], sorted by time within each stream. Entries older than the latest one already
+/// pushed to their stream are handled according to [`OutOfOrder`], [`OutOfOrder::Send`] by
+/// default. A push which fails to connect, times out, or is answered with a 5xx or 429 status is
+/// retried up to 5 times, waiting 100ms before the first retry and twice as long before each one
+/// after, up to 10s. If it still fails, or Loki rejects it outright, the error is returned and the
+/// batch is kept, so the next flush tries it again.
+///
+/// # Example
+/// ```
+/// use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener};
+/// use common_log_format::{loki::{LokiSink, OutOfOrder}, sink::{Sink, Template}, LogEntry};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// let server = std::thread::spawn(move || {
+///     let mut reader = BufReader::new(listener.accept().unwrap().0);
+///     let mut length = 0;
+///     loop {
+///         let mut line = String::new();
+///         reader.read_line(&mut line).unwrap();
+///         match line.trim_end().split_once(": ") {
+///             Some(("Content-Length", n)) => length = n.parse().unwrap(),
+///             None if line.trim_end().is_empty() => break,
+///             _ => (),
+///         }
+///     }
+///     let mut body = vec![0; length];
+///     reader.read_exact(&mut body).unwrap();
+///     write!(reader.get_mut(), "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+///     String::from_utf8(body).unwrap()
+/// });
+///
+/// let log = [
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:02Z] \"GET /c HTTP/1.1\" 503 0",
+/// ];
+/// let entries: Vec<LogEntry> = log.iter().map(|l| l.parse().unwrap()).collect();
+/// let mut sink = LokiSink::new(&url)
+///     .unwrap()
+///     .with_label("vhost", Template::new("www.example.com"))
+///     .with_label("status", Template::new("{status_class}"));
+/// sink.send_all(&entries).unwrap();
+/// assert_eq!(sink.pushed(), 3);
+///
+/// let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+/// let streams = body["streams"].as_array().unwrap();
+/// assert_eq!(streams[0]["stream"]["status"], "2xx");
+/// assert_eq!(streams[0]["stream"]["vhost"], "www.example.com");
+/// assert_eq!(streams[0]["values"][0], serde_json::json!(["1714568400000000000", log[1]]));
+/// assert_eq!(streams[1]["stream"]["status"], "5xx");
+/// ```
+pub struct LokiSink {
+    endpoint: Endpoint,
+    labels: Vec<(String, Template)>,
+    headers: Vec<(&'static str, String)>,
+    backoff: Backoff,
+    batch_size: usize,
+    out_of_order: OutOfOrder,
+    /// The lines of the current batch, by stream, with their times in nanoseconds.
+    batch: BTreeMap<Labels, Vec<(i64, String)>>,
+    batched: usize,
+    /// The time of the latest entry pushed to each stream.
+    latest: HashMap<Labels, i64>,
+    pushed: u64,
+    dropped: u64,
+}
+
+impl LokiSink {
+    /// Push entries to the Loki at `url`, such as `http://localhost:3100`.
+    pub fn new(url: &str) -> Result<Self, InvalidUrl> {
+        Ok(LokiSink {
+            endpoint: Endpoint::new(url)?,
+            labels: vec![("job".to_owned(), Template::new("access_log"))],
+            headers: vec![("Content-Type", "application/json".to_owned())],
+            backoff: Backoff::default(),
+            batch_size: 1_000,
+            out_of_order: OutOfOrder::Send,
+            batch: BTreeMap::new(),
+            batched: 0,
+            latest: HashMap::new(),
+            pushed: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Label each entry's stream with `name`, rendering the value from the entry with `value`. A
+    /// label with the same name as an earlier one replaces it, so this can change `job`.
+    ///
+    /// Label names must be letters, digits, and underscores, and not start with a digit.
+    pub fn with_label(mut self, name: &str, value: Template) -> Self {
+        self.labels.retain(|(n, _)| n != name);
+        self.labels.push((name.to_owned(), value));
+        self
+    }
+
+    /// Push to the tenant `tenant` of a multi-tenant Loki.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.headers.push(("X-Scope-OrgID", tenant.to_owned()));
+        self
+    }
+
+    /// Handle entries older than their stream's latest according to `out_of_order`.
+    pub fn with_out_of_order(mut self, out_of_order: OutOfOrder) -> Self {
+        self.out_of_order = out_of_order;
+        self
+    }
+
+    /// Push every `batch_size` entries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Retry a failed push up to `retries` times, first after `initial`, doubling the wait each
+    /// time up to `max`.
+    pub fn with_backoff(mut self, retries: u32, initial: Duration, max: Duration) -> Self {
+        self.backoff = Backoff {
+            retries,
+            initial,
+            max,
+        };
+        self
+    }
+
+    /// Give up on connecting, sending a push, or waiting for its response after `timeout` (30s by
+    /// default).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+
+    /// The number of entries pushed so far.
+    pub fn pushed(&self) -> u64 {
+        self.pushed
+    }
+
+    /// The number of entries dropped for being out of order, with [`OutOfOrder::Drop`].
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The number of entries waiting to be pushed.
+    pub fn pending(&self) -> usize {
+        self.batched
+    }
+}
+
+impl Sink for LokiSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let mut labels: Labels = self
+            .labels
+            .iter()
+            .map(|(name, value)| (name.clone(), value.render(entry)))
+            .collect();
+        labels.sort();
+        let time = entry.time.unwrap_or_else(Utc::now);
+        // Times after 2262 don't fit, and are pushed as the latest time which does.
+        let nanos = time.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        self.batch
+            .entry(labels)
+            .or_default()
+            .push((nanos, entry.to_string()));
+        self.batched += 1;
+        if self.batched >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Push the current batch, if there is one.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.batched == 0 {
+            return Ok(());
+        }
+
+        let mut streams = vec![];
+        let mut latest = vec![];
+        let mut dropped = 0;
+        for (labels, lines) in &mut self.batch {
+            lines.sort_by_key(|(t, _)| *t);
+            let previous = self.latest.get(labels).copied().unwrap_or(i64::MIN);
+            let mut values = vec![];
+            for (t, line) in lines.iter() {
+                let t = match self.out_of_order {
+                    _ if *t >= previous => *t,
+                    OutOfOrder::Send => *t,
+                    OutOfOrder::Clamp => previous,
+                    OutOfOrder::Drop => {
+                        dropped += 1;
+                        continue;
+                    }
+                };
+                values.push(json!([t.to_string(), line]));
+            }
+            if let Some(&(last, _)) = lines.last() {
+                latest.push((labels.clone(), last.max(previous)));
+            }
+            if values.is_empty() {
+                continue;
+            }
+            let stream: serde_json::Map<_, _> = labels
+                .iter()
+                .map(|(name, value)| (name.clone(), json!(value)))
+                .collect();
+            streams.push(json!({ "stream": stream, "values": values }));
+        }
+        let body = serde_json::to_vec(&json!({ "streams": streams })).expect("pushes serialize");
+
+        if !streams.is_empty() {
+            let headers: Vec<(&str, &str)> = self
+                .headers
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect();
+            self.backoff.post("Loki", || {
+                self.endpoint.post("/loki/api/v1/push", &headers, &body)
+            })?;
+        }
+
+        self.pushed += (self.batched - dropped) as u64;
+        self.dropped += dropped as u64;
+        self.latest.extend(latest);
+        self.batch.clear();
+        self.batched = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Byte budgets shared between the components of a pipeline.
+//!
+//! Components which hold entries, such as [`crate::cache::EntryCache`], can be limited by the
+//! memory their entries take up rather than by how many there are, which says little when request
+//! lines range from a few bytes to kilobytes. A [`MemoryBudget`] is a limit which any number of
+//! components can draw on: each reserves what it holds, and makes room by evicting when a
+//! reservation fails. Sizes are estimated with [`LogEntry::approx_mem_size`](crate::LogEntry).
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// A limit on the bytes held by the components sharing it.
+///
+/// Clones share the same limit and usage.
+///
+/// # Example
+/// ```
+/// use common_log_format::memory::MemoryBudget;
+/// let budget = MemoryBudget::new(1000);
+/// let shared = budget.clone();
+/// assert!(budget.try_reserve(600));
+/// assert!(!shared.try_reserve(600));
+/// budget.release(600);
+/// assert!(shared.try_reserve(600));
+/// assert_eq!(budget.used(), 600);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// A budget which never runs out, for counting usage alone.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The bytes reserved so far, by every component sharing the budget.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn available(&self) -> usize {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// Reserve `bytes` if they fit in the budget, returning whether they did.
+    pub fn try_reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|u| *u <= self.limit)
+            })
+            .is_ok()
+    }
+
+    /// Reserve `bytes` whether or not they fit, for memory which is already in use.
+    pub fn force_reserve(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Give back `bytes` previously reserved.
+    pub fn release(&self, bytes: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+}
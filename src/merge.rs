@@ -0,0 +1,195 @@
+//! Merging several logs into one, in time order.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::LogEntry;
+
+/// An item of a log which can be merged by time: an entry, or the result of reading one.
+pub trait Timed {
+    fn time(&self) -> Option<DateTime<Utc>>;
+}
+
+impl Timed for LogEntry {
+    fn time(&self) -> Option<DateTime<Utc>> {
+        self.time
+    }
+}
+
+/// Errors have no time, so are merged as soon as they are read.
+impl<E> Timed for Result<LogEntry, E> {
+    fn time(&self) -> Option<DateTime<Utc>> {
+        self.as_ref().ok().and_then(|e| e.time)
+    }
+}
+
+/// Merge the entries of several logs, each already in time order, into one stream in time order.
+///
+/// Entries with the same time are taken from the earlier of `logs` first, and entries without a
+/// time sort before all others. If an input is not in order, the output won't be either, but
+/// every entry is still returned. Readers, which return `Result`s, can be merged too; see
+/// [`Timed`].
+///
+/// # Example
+/// ```
+/// use common_log_format::{merge::merge_by_time, LogEntry};
+/// let parse = |t: &str, host: &str| -> LogEntry {
+///     format!("{} - - [2024-05-01T13:00:{}Z] \"GET / HTTP/1.1\" 200 0", host, t).parse().unwrap()
+/// };
+/// let frontend = vec![parse("01", "10.0.0.1"), parse("05", "10.0.0.1")];
+/// let backend = vec![parse("02", "10.0.0.2"), parse("03", "10.0.0.2"), parse("06", "10.0.0.2")];
+///
+/// let hosts: Vec<String> = merge_by_time([frontend.into_iter(), backend.into_iter()])
+///     .map(|e| e.host.unwrap().to_string())
+///     .collect();
+/// assert_eq!(hosts, ["10.0.0.1", "10.0.0.2", "10.0.0.2", "10.0.0.1", "10.0.0.2"]);
+/// ```
+pub fn merge_by_time<I>(logs: impl IntoIterator<Item = I>) -> MergeByTime<I>
+where
+    I: Iterator,
+    I::Item: Timed,
+{
+    let mut logs: Vec<I> = logs.into_iter().collect();
+    let heads = logs
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(source, log)| Some(Reverse(Head::new(log.next()?, source))))
+        .collect();
+    MergeByTime { logs, heads }
+}
+
+/// [`merge_by_time`], moving the entries of each log back by its offset first, such as the skew
+/// of its server's clock from [`crate::skew::SkewReport::offset`].
+///
+/// Each log need only be in order by its own clock.
+///
+/// # Example
+/// ```
+/// use chrono::Duration;
+/// use common_log_format::{merge::merge_by_time_shifted, LogEntry};
+/// let parse = |t: &str, host: &str| -> LogEntry {
+///     format!("{} - - [2024-05-01T13:00:{}Z] \"GET / HTTP/1.1\" 200 0", host, t).parse().unwrap()
+/// };
+/// let frontend = vec![parse("01", "10.0.0.1"), parse("05", "10.0.0.1")];
+/// // The backend's clock is 3 seconds fast.
+/// let backend = vec![parse("05", "10.0.0.2"), parse("06", "10.0.0.2")];
+///
+/// let merged: Vec<LogEntry> = merge_by_time_shifted([
+///     (frontend.into_iter(), Duration::zero()),
+///     (backend.into_iter(), Duration::seconds(3)),
+/// ])
+/// .collect();
+/// let hosts: Vec<String> = merged.iter().map(|e| e.host.unwrap().to_string()).collect();
+/// assert_eq!(hosts, ["10.0.0.1", "10.0.0.2", "10.0.0.2", "10.0.0.1"]);
+/// assert_eq!(merged[1].time, parse("02", "10.0.0.2").time);
+/// ```
+pub fn merge_by_time_shifted<I>(
+    logs: impl IntoIterator<Item = (I, chrono::Duration)>,
+) -> MergeByTime<Shifted<I>>
+where
+    I: Iterator<Item = LogEntry>,
+{
+    merge_by_time(
+        logs.into_iter()
+            .map(|(log, offset)| Shifted { log, offset }),
+    )
+}
+
+/// A log with its entries moved back by an offset, from [`merge_by_time_shifted`].
+pub struct Shifted<I> {
+    log: I,
+    offset: chrono::Duration,
+}
+
+impl<I> Iterator for Shifted<I>
+where
+    I: Iterator<Item = LogEntry>,
+{
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut entry = self.log.next()?;
+        entry.time = entry.time.map(|t| t - self.offset);
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.log.size_hint()
+    }
+}
+
+/// The iterator returned by [`merge_by_time`].
+pub struct MergeByTime<I: Iterator> {
+    logs: Vec<I>,
+    /// The next entry from each log which has not ended.
+    heads: BinaryHeap<Reverse<Head<I::Item>>>,
+}
+
+impl<I> Iterator for MergeByTime<I>
+where
+    I: Iterator,
+    I::Item: Timed,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse(head) = self.heads.pop()?;
+        if let Some(next) = self.logs[head.source].next() {
+            self.heads.push(Reverse(Head::new(next, head.source)));
+        }
+
+        Some(head.entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.logs.iter().map(Iterator::size_hint).fold(
+            (self.heads.len(), Some(self.heads.len())),
+            |(lo, hi), (l, h)| {
+                (
+                    lo.saturating_add(l),
+                    hi.zip(h).and_then(|(a, b)| a.checked_add(b)),
+                )
+            },
+        )
+    }
+}
+
+struct Head<T> {
+    time: Option<DateTime<Utc>>,
+    source: usize,
+    entry: T,
+}
+
+impl<T: Timed> Head<T> {
+    fn new(entry: T, source: usize) -> Self {
+        Head {
+            time: entry.time(),
+            source,
+            entry,
+        }
+    }
+}
+
+impl<T> PartialEq for Head<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Head<T> {}
+
+impl<T> PartialOrd for Head<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Head<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.source).cmp(&(other.time, other.source))
+    }
+}
@@ -0,0 +1,64 @@
+//! Reading log files through a memory map.
+
+use std::{fs::File, io, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{LogEntryParseError, LogEntryRef, ParseOptions};
+
+/// A memory-mapped log file, whose lines are parsed into [`LogEntryRef`]s without copying.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::mmap::MmapReader;
+/// let reader = unsafe { MmapReader::open("access.log") }.unwrap();
+/// let bytes: usize = reader
+///     .entries()
+///     .filter_map(Result::ok)
+///     .filter_map(|e| e.object_size)
+///     .sum();
+/// ```
+pub struct MmapReader {
+    map: Mmap,
+    opts: ParseOptions,
+}
+
+impl MmapReader {
+    /// Map the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified in place while the reader, or any entry
+    /// borrowed from it, is alive. Appending is fine, but appended lines will not be seen.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(MmapReader {
+            map: Mmap::map(&file)?,
+            opts: ParseOptions::default(),
+        })
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// The non-empty lines of the file, without line terminators.
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.map
+            .split(|b| *b == b'\n')
+            .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+            .filter(|l| !l.is_empty())
+    }
+
+    /// Parse each non-empty line of the file.
+    pub fn entries(&self) -> impl Iterator<Item = Result<LogEntryRef<'_>, LogEntryParseError>> {
+        let opts = self.opts;
+        self.lines()
+            .map(move |l| LogEntryRef::from_bytes_with(l, &opts))
+    }
+}
@@ -0,0 +1,78 @@
+//! Publishing entries to an MQTT broker.
+
+use std::{thread, time::Duration};
+
+use rumqttc::{Client, ClientError, MqttOptions, QoS};
+
+use crate::{
+    sink::{Sink, Template},
+    LogEntry,
+};
+
+/// A [`Sink`] which publishes entries as JSON to MQTT topics.
+///
+/// The topic for each entry is rendered from a [`Template`]. The connection to the broker is
+/// driven by a background thread, which reconnects if the connection drops; `send` only fails
+/// once that thread has stopped, or if its queue (of 100 messages by default) is full.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{mqtt::MqttSink, sink::{Sink, Template}, LogEntry};
+/// let options = rumqttc::MqttOptions::new("edge-shipper", "127.0.0.1", 1883);
+/// let mut sink = MqttSink::new(options, Template::new("logs/access/{status_class}"));
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// sink.send(&entry).unwrap();
+/// ```
+pub struct MqttSink {
+    client: Client,
+    topic: Template,
+    qos: QoS,
+}
+
+impl MqttSink {
+    /// Connect to the broker described by `options`, publishing at least once.
+    pub fn new(options: MqttOptions, topic: Template) -> Self {
+        Self::with_capacity(options, topic, 100)
+    }
+
+    /// Like [`MqttSink::new`], but queueing up to `capacity` messages for the background thread.
+    pub fn with_capacity(options: MqttOptions, topic: Template, capacity: usize) -> Self {
+        let (client, mut connection) = Client::new(options, capacity);
+        thread::spawn(move || {
+            for event in connection.iter() {
+                if let Err(rumqttc::ConnectionError::RequestsDone) = event {
+                    return;
+                }
+                if event.is_err() {
+                    // Back off before the event loop reconnects.
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        });
+
+        MqttSink {
+            client,
+            topic,
+            qos: QoS::AtLeastOnce,
+        }
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl Sink for MqttSink {
+    type Error = ClientError;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        let payload = serde_json::to_vec(entry).expect("entries serialize to JSON");
+        self.client
+            .publish(self.topic.render(entry), self.qos, false, payload)
+    }
+}
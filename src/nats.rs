@@ -0,0 +1,133 @@
+//! Publishing entries to NATS.
+//!
+//! This speaks just enough of the [NATS client protocol] to publish, over a plain TCP
+//! connection, so it needs no async runtime. It does not support TLS or authentication.
+//!
+//! [NATS client protocol]: https://docs.nats.io/reference/reference-protocols/nats-protocol
+
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    sink::{Sink, Template},
+    LogEntry,
+};
+
+/// A [`Sink`] which publishes entries as JSON to NATS subjects.
+///
+/// The subject for each entry is rendered from a [`Template`]. Messages are buffered until
+/// [`Sink::flush`], or until the buffer fills. Flushing also answers the server's keep-alive
+/// pings, so a sink which sits idle should still be flushed every minute or so.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{nats::NatsSink, sink::{Sink, Template}, LogEntry};
+/// let mut sink = NatsSink::connect("127.0.0.1:4222", Template::new("access.{status_class}")).unwrap();
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// sink.send(&entry).unwrap();
+/// sink.flush().unwrap();
+/// ```
+pub struct NatsSink {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    subject: Template,
+    line: String,
+}
+
+impl NatsSink {
+    /// Connect to the NATS server at `addr`.
+    pub fn connect(addr: impl ToSocketAddrs, subject: Template) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut sink = NatsSink {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            subject,
+            line: String::new(),
+        };
+
+        // The server starts with an INFO line, and answers our PING once it has processed our
+        // CONNECT, or reports an error.
+        sink.read_line()?;
+        if !sink.line.starts_with("INFO ") {
+            return Err(protocol_error(&sink.line));
+        }
+        sink.writer.write_all(
+            b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"common-log-format\"}\r\nPING\r\n",
+        )?;
+        sink.writer.flush()?;
+        sink.read_line()?;
+        if sink.line.trim_end() != "PONG" {
+            return Err(protocol_error(&sink.line));
+        }
+
+        // From here on, only check for messages from the server in passing.
+        sink.line.clear();
+        sink.reader
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(1)))?;
+        Ok(sink)
+    }
+
+    fn read_line(&mut self) -> io::Result<()> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    /// Answer the server's keep-alive PINGs, and report any error it has sent.
+    fn poll_server(&mut self) -> io::Result<()> {
+        loop {
+            // Read whatever has arrived, without waiting for more.
+            let mut buf = [0; 512];
+            let available = match self.reader.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => n,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            };
+
+            self.line
+                .push_str(&String::from_utf8_lossy(&buf[..available]));
+            while let Some(end) = self.line.find("\r\n") {
+                let msg: String = self.line.drain(..end + 2).collect();
+                match msg.trim_end() {
+                    "PING" => self.writer.write_all(b"PONG\r\n")?,
+                    m if m.starts_with("-ERR") => return Err(protocol_error(m)),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::other(format!("NATS server said {:?}", msg.trim_end()))
+}
+
+impl Sink for NatsSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        let payload = serde_json::to_vec(entry).expect("entries serialize to JSON");
+        let subject = self.subject.render(entry);
+        write!(self.writer, "PUB {} {}\r\n", subject, payload.len())?;
+        self.writer.write_all(&payload)?;
+        self.writer.write_all(b"\r\n")
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.writer.flush()?;
+        self.poll_server()?;
+        self.writer.flush()
+    }
+}
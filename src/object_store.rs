@@ -0,0 +1,240 @@
+//! Reading logs from object storage.
+//!
+//! Access logs are usually archived to S3, Google Cloud Storage, or Azure Blob Storage, and
+//! copying them to local disk first just to read them once is slow and needs the space. An
+//! [`ObjectSource`] reads an object in ranged requests of a few megabytes at a time, retrying a
+//! range which fails, and decompresses it as [`crate::reader::open`] does for files.
+//!
+//! Requests are made through the `object_store` crate, blocking on a runtime of the reader's
+//! own, so readers must not be used from async code: move them to a blocking thread instead.
+
+use std::{
+    io::{self, BufRead, Read},
+    sync::Arc,
+    time::Duration,
+};
+
+use ::object_store::{
+    aws::AmazonS3Builder, azure::MicrosoftAzureBuilder, gcp::GoogleCloudStorageBuilder, path::Path,
+    ObjectStore, ObjectStoreExt,
+};
+use bytes::{Buf, Bytes};
+use tokio::runtime::Runtime;
+
+use crate::reader::{decompress, Compression, LogReader};
+
+/// An object to read logs from, and how to read it.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use common_log_format::object_store::ObjectSource;
+/// use object_store::{memory::InMemory, path::Path, ObjectStoreExt};
+///
+/// let store = Arc::new(InMemory::new());
+/// let log = "\
+/// 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512
+/// 10.0.0.2 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 404 10
+/// ";
+/// let path = Path::from("logs/access.log");
+/// let put = store.put(&path, log.into());
+/// tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(put).unwrap();
+///
+/// // Ranges of 16 bytes, so that lines span several requests.
+/// let source = ObjectSource::new(store, path).with_chunk_size(16);
+/// let entries: Vec<_> = source.open_log().unwrap().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries[1].path(), Some("/a"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObjectSource {
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+    chunk_size: u64,
+    retries: u32,
+}
+
+impl ObjectSource {
+    /// Read the object at `path` in `store`.
+    pub fn new(store: Arc<dyn ObjectStore>, path: Path) -> Self {
+        ObjectSource {
+            store,
+            path,
+            chunk_size: 8 << 20,
+            retries: 3,
+        }
+    }
+
+    /// Read the object at `url`, such as `s3://bucket/logs/access.log.gz`, `gs://bucket/...`,
+    /// or `az://container/...`.
+    ///
+    /// Credentials, the region, and other settings are read from the environment, as the
+    /// provider's own tools do: `AWS_*`, `GOOGLE_*`, or `AZURE_*`.
+    pub fn from_url(url: &str) -> io::Result<Self> {
+        Self::from_url_opts(url, std::iter::empty::<(&str, &str)>())
+    }
+
+    /// [`ObjectSource::from_url`], with settings from `options` taking precedence over the
+    /// environment. Keys are those of the provider's builder in `object_store`, such as
+    /// `aws_region` or `google_service_account`.
+    pub fn from_url_opts<K, V>(
+        url: &str,
+        options: impl IntoIterator<Item = (K, V)>,
+    ) -> io::Result<Self>
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let invalid = |e: ::object_store::Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("not a URL: {}", url))
+        })?;
+        let path = Path::from_url_path(rest.split_once('/').map_or("", |(_, p)| p))
+            .map_err(|e| invalid(e.into()))?;
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" | "s3a" => {
+                let mut builder = AmazonS3Builder::from_env().with_url(url);
+                for (key, value) in options {
+                    builder = builder.with_config(key.as_ref().parse().map_err(invalid)?, value);
+                }
+                Arc::new(builder.build().map_err(invalid)?)
+            }
+            "gs" => {
+                let mut builder = GoogleCloudStorageBuilder::from_env().with_url(url);
+                for (key, value) in options {
+                    builder = builder.with_config(key.as_ref().parse().map_err(invalid)?, value);
+                }
+                Arc::new(builder.build().map_err(invalid)?)
+            }
+            "az" | "azure" | "adl" | "abfs" | "abfss" => {
+                let mut builder = MicrosoftAzureBuilder::from_env().with_url(url);
+                for (key, value) in options {
+                    builder = builder.with_config(key.as_ref().parse().map_err(invalid)?, value);
+                }
+                Arc::new(builder.build().map_err(invalid)?)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported object store scheme: {}", scheme),
+                ))
+            }
+        };
+        Ok(Self::new(store, path))
+    }
+
+    /// Request `chunk_size` bytes of the object at a time. Larger chunks make fewer requests,
+    /// but hold more in memory, and retry more when one fails.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1) as u64;
+        self
+    }
+
+    /// Retry a chunk up to `retries` times if the request for it fails, waiting longer each
+    /// time. This is on top of the retries `object_store` makes for each request, and covers
+    /// connections which drop while the body is being read. The default is 3.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Read the object as it's stored, without decompressing it.
+    pub fn reader(&self) -> io::Result<ObjectReader> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let size = runtime.block_on(self.store.head(&self.path))?.size;
+        Ok(ObjectReader {
+            source: self.clone(),
+            runtime,
+            size,
+            pos: 0,
+            chunk: Bytes::new(),
+        })
+    }
+
+    /// Read the object, decompressing it if necessary.
+    ///
+    /// The format is detected from the object's contents, falling back to its extension.
+    pub fn open(&self) -> io::Result<Box<dyn BufRead + Send>> {
+        let mut reader = self.reader()?;
+        let compression = match Compression::from_magic(reader.fill_buf()?) {
+            Compression::None => Compression::from_path(self.path.as_ref()),
+            c => c,
+        };
+        decompress(reader, compression)
+    }
+
+    /// Read entries from the object, decompressing it if necessary.
+    pub fn open_log(&self) -> io::Result<LogReader<Box<dyn BufRead + Send>>> {
+        Ok(LogReader::new(self.open()?))
+    }
+}
+
+/// Reads an object in ranges, from [`ObjectSource::reader`].
+pub struct ObjectReader {
+    source: ObjectSource,
+    runtime: Runtime,
+    size: u64,
+    /// The offset of the end of `chunk`.
+    pos: u64,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    /// The size of the object, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn fetch(&mut self) -> io::Result<()> {
+        let range = self.pos..self.size.min(self.pos + self.source.chunk_size);
+        let mut backoff = Duration::from_millis(100);
+        let mut attempt = 0;
+        loop {
+            let get = self
+                .source
+                .store
+                .get_range(&self.source.path, range.clone());
+            match self.runtime.block_on(get) {
+                Ok(chunk) => {
+                    self.pos += chunk.len() as u64;
+                    self.chunk = chunk;
+                    return Ok(());
+                }
+                Err(::object_store::Error::Generic { .. }) if attempt < self.source.retries => {
+                    attempt += 1;
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ObjectReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.chunk.is_empty() && self.pos < self.size {
+            self.fetch()?;
+            if self.chunk.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(&self.chunk)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
@@ -0,0 +1,321 @@
+//! Converting entries to OpenTelemetry log records, and exporting them over OTLP.
+//!
+//! Each entry becomes an OTLP [`LogRecord`] whose body is the entry's Common Log Format line,
+//! with the [HTTP semantic convention] attributes an OpenTelemetry pipeline expects of a server
+//! request:
+//!
+//! | attribute                    | from                                        |
+//! |------------------------------|---------------------------------------------|
+//! | `client.address`             | [`LogEntry::host`]                          |
+//! | `user.name`                  | [`LogEntry::authuser`]                      |
+//! | `http.request.method`        | [`LogEntry::method`]                        |
+//! | `url.path`, `url.query`      | [`LogEntry::target`], split at the `?`      |
+//! | `network.protocol.name`, `network.protocol.version` | the request line's protocol, such as `http` and `1.1` |
+//! | `http.response.status_code`  | [`LogEntry::status_code`]                   |
+//! | `http.response.body.size`    | [`LogEntry::object_size`]                   |
+//!
+//! The record's severity follows the status: `ERROR` for 5xx, `WARN` for 4xx, and `INFO`
+//! otherwise.
+//!
+//! [`OtlpSink`] exports records to a collector with OTLP/HTTP, in protobuf. Like the other HTTP
+//! sinks, it speaks plain HTTP/1.1 itself, without TLS; a collector is usually a local agent.
+//!
+//! [HTTP semantic convention]: https://opentelemetry.io/docs/specs/semconv/http/http-spans/
+
+use std::{io, time::Duration};
+
+use opentelemetry_proto::tonic::{
+    collector::logs::v1::ExportLogsServiceRequest,
+    common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue},
+    logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber},
+    resource::v1::Resource,
+};
+use prost::Message;
+
+use crate::{
+    http_post::{Backoff, Endpoint},
+    replay::InvalidUrl,
+    sink::Sink,
+    LogEntry,
+};
+
+fn attribute(key: &str, value: any_value::Value) -> KeyValue {
+    KeyValue {
+        key: key.to_owned(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn string(s: &str) -> any_value::Value {
+    any_value::Value::StringValue(s.to_owned())
+}
+
+/// # Example
+/// ```
+/// use common_log_format::LogEntry;
+/// use opentelemetry_proto::tonic::logs::v1::{LogRecord, SeverityNumber};
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a?b=1 HTTP/1.1\" 503 10".parse().unwrap();
+/// let record = LogRecord::from(&entry);
+/// assert_eq!(record.time_unix_nano, 1_714_568_400_000_000_000);
+/// assert_eq!(record.severity_number, SeverityNumber::Error as i32);
+/// let keys: Vec<&str> = record.attributes.iter().map(|a| a.key.as_str()).collect();
+/// assert_eq!(keys, [
+///     "client.address", "http.request.method", "url.path", "url.query", "network.protocol.name",
+///     "network.protocol.version", "http.response.status_code", "http.response.body.size",
+/// ]);
+/// ```
+impl From<&LogEntry> for LogRecord {
+    fn from(e: &LogEntry) -> Self {
+        let mut attributes = vec![];
+        if let Some(host) = e.host {
+            attributes.push(attribute("client.address", string(&host.to_string())));
+        }
+        if let Some(user) = &e.authuser {
+            attributes.push(attribute("user.name", string(user)));
+        }
+        if let Some(method) = e.method() {
+            attributes.push(attribute("http.request.method", string(method)));
+        }
+        if let Some(target) = e.target() {
+            let (path, query) = match target.split_once('?') {
+                Some((path, query)) => (path, Some(query)),
+                None => (target, None),
+            };
+            attributes.push(attribute("url.path", string(path)));
+            if let Some(query) = query {
+                attributes.push(attribute("url.query", string(query)));
+            }
+        }
+        let protocol = e
+            .request_line
+            .as_deref()
+            .and_then(|l| l.split(' ').nth(2))
+            .and_then(|p| p.split_once('/'));
+        if let Some((name, version)) = protocol {
+            attributes.push(attribute(
+                "network.protocol.name",
+                string(&name.to_ascii_lowercase()),
+            ));
+            attributes.push(attribute("network.protocol.version", string(version)));
+        }
+        if let Some(status) = e.status_code {
+            attributes.push(attribute(
+                "http.response.status_code",
+                any_value::Value::IntValue(status.as_u16().into()),
+            ));
+        }
+        if let Some(size) = e.object_size {
+            attributes.push(attribute(
+                "http.response.body.size",
+                any_value::Value::IntValue(size as i64),
+            ));
+        }
+
+        let severity = match e.status_code.map(|s| s.as_u16()) {
+            Some(500..) => SeverityNumber::Error,
+            Some(400..=499) => SeverityNumber::Warn,
+            _ => SeverityNumber::Info,
+        };
+        LogRecord {
+            time_unix_nano: e
+                .time
+                .and_then(|t| t.timestamp_nanos_opt())
+                .map_or(0, |t| t.max(0) as u64),
+            severity_number: severity as i32,
+            severity_text: severity.as_str_name().to_owned(),
+            body: Some(AnyValue {
+                value: Some(string(&e.to_string())),
+            }),
+            attributes,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<LogEntry> for LogRecord {
+    fn from(e: LogEntry) -> Self {
+        LogRecord::from(&e)
+    }
+}
+
+/// A [`Sink`] which exports entries as log records to an OpenTelemetry collector, with OTLP/HTTP.
+///
+/// Records are exported in batches of 1,000 (by default), when a batch fills and on
+/// [`Sink::flush`], from a resource whose `service.name` is `access-log` unless set otherwise.
+/// An export which fails to connect, times out, or is answered with a 5xx or 429 status is
+/// retried up to 5 times, waiting 100ms before the first retry and twice as long before each one
+/// after, up to 10s. If it still fails, or the collector rejects it outright, the error is
+/// returned and the batch is kept, so the next flush tries it again.
+///
+/// # Example
+/// ```
+/// use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener};
+/// use common_log_format::{opentelemetry::OtlpSink, sink::Sink, LogEntry};
+/// use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+/// use prost::Message;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// let collector = std::thread::spawn(move || {
+///     let mut reader = BufReader::new(listener.accept().unwrap().0);
+///     let mut length = 0;
+///     loop {
+///         let mut line = String::new();
+///         reader.read_line(&mut line).unwrap();
+///         match line.trim_end().split_once(": ") {
+///             Some(("Content-Length", n)) => length = n.parse().unwrap(),
+///             None if line.trim_end().is_empty() => break,
+///             _ => (),
+///         }
+///     }
+///     let mut body = vec![0; length];
+///     reader.read_exact(&mut body).unwrap();
+///     write!(reader.get_mut(), "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+///     ExportLogsServiceRequest::decode(&body[..]).unwrap()
+/// });
+///
+/// let mut sink = OtlpSink::new(&url).unwrap().with_service_name("www-frontend");
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// sink.send(&entry).unwrap();
+/// sink.flush().unwrap();
+/// assert_eq!(sink.exported(), 1);
+///
+/// let request = collector.join().unwrap();
+/// let resource = &request.resource_logs[0];
+/// assert_eq!(resource.resource.as_ref().unwrap().attributes[0].key, "service.name");
+/// assert_eq!(resource.scope_logs[0].log_records.len(), 1);
+/// ```
+pub struct OtlpSink {
+    endpoint: Endpoint,
+    resource: Vec<KeyValue>,
+    headers: Vec<(String, String)>,
+    backoff: Backoff,
+    batch_size: usize,
+    batch: Vec<LogRecord>,
+    exported: u64,
+}
+
+impl OtlpSink {
+    /// Export to the collector whose OTLP/HTTP endpoint is `url`, such as
+    /// `http://localhost:4318`. Records are posted to `/v1/logs` under it.
+    pub fn new(url: &str) -> Result<Self, InvalidUrl> {
+        Ok(OtlpSink {
+            endpoint: Endpoint::new(url)?,
+            resource: vec![attribute("service.name", string("access-log"))],
+            headers: vec![],
+            backoff: Backoff::default(),
+            batch_size: 1_000,
+            batch: vec![],
+            exported: 0,
+        })
+    }
+
+    /// Set the `service.name` of the resource the records come from.
+    pub fn with_service_name(self, name: &str) -> Self {
+        self.with_resource_attribute("service.name", name)
+    }
+
+    /// Set a string attribute of the resource the records come from, such as `host.name` or
+    /// `deployment.environment.name`.
+    pub fn with_resource_attribute(mut self, key: &str, value: &str) -> Self {
+        self.resource.retain(|a| a.key != key);
+        self.resource.push(attribute(key, string(value)));
+        self
+    }
+
+    /// Send the header `name: value` with each export, for authentication.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Export every `batch_size` entries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Retry a failed export up to `retries` times, first after `initial`, doubling the wait each
+    /// time up to `max`.
+    pub fn with_backoff(mut self, retries: u32, initial: Duration, max: Duration) -> Self {
+        self.backoff = Backoff {
+            retries,
+            initial,
+            max,
+        };
+        self
+    }
+
+    /// Give up on connecting, sending an export, or waiting for its response after `timeout`
+    /// (30s by default).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+
+    /// The number of entries exported so far.
+    pub fn exported(&self) -> u64 {
+        self.exported
+    }
+
+    /// The number of entries waiting to be exported.
+    pub fn pending(&self) -> usize {
+        self.batch.len()
+    }
+}
+
+impl Sink for OtlpSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        self.batch.push(LogRecord::from(entry));
+        if self.batch.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Export the current batch, if there is one.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let mut request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: self.resource.clone(),
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").to_owned(),
+                        version: env!("CARGO_PKG_VERSION").to_owned(),
+                        ..Default::default()
+                    }),
+                    log_records: std::mem::take(&mut self.batch),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let body = request.encode_to_vec();
+
+        let mut headers = vec![("Content-Type", "application/x-protobuf")];
+        headers.extend(self.headers.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+        let result = self.backoff.post("OTLP collector", || {
+            self.endpoint.post("/v1/logs", &headers, &body)
+        });
+
+        let records = &mut request.resource_logs[0].scope_logs[0].log_records;
+        match result {
+            Ok(_) => {
+                self.exported += records.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.batch = std::mem::take(records);
+                Err(e)
+            }
+        }
+    }
+}
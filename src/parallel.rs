@@ -0,0 +1,183 @@
+//! Parsing large files on all cores.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    reader::{parse_salvaged, trim_line_end, ReadError},
+    LogEntry, LogEntryParseError, ParseOptions,
+};
+
+/// Parse every line of the file at `path` in parallel, with `opts`.
+///
+/// The file is read in newline-aligned chunks, a batch of chunks at a time, so memory use is
+/// bounded however large the file is. Entries come out in the order of the lines of the file.
+/// Blank lines are skipped.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{parallel::parse_file_parallel, ParseOptions};
+/// let mut errors = 0;
+/// for entry in parse_file_parallel("trace.log", ParseOptions::default()).unwrap() {
+///     match entry {
+///         Ok(entry) => println!("{:?}", entry.path()),
+///         Err(_) => errors += 1,
+///     }
+/// }
+/// ```
+pub fn parse_file_parallel(
+    path: impl AsRef<Path>,
+    opts: ParseOptions,
+) -> io::Result<ParallelReader<File>> {
+    Ok(ParallelReader::new(File::open(path)?).with_options(opts))
+}
+
+/// Parse every line of `contents` in parallel.
+///
+/// # Example
+/// ```
+/// let contents = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /a HTTP/1.0\" 200 1\n\
+///                 127.0.0.1 - - [1996-12-19T16:39:58-08:00] \"GET /b HTTP/1.0\" 200 2\n";
+/// let entries = common_log_format::parallel::parse_lines_parallel(contents);
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries[1].as_ref().unwrap().object_size, Some(2));
+/// ```
+pub fn parse_lines_parallel(contents: &str) -> Vec<Result<LogEntry, LogEntryParseError>> {
+    contents
+        .par_lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Reads entries from a log, parsing a batch of chunks of it at a time on rayon's thread pool.
+///
+/// Each batch has a chunk for each thread in the pool. Lines longer than a chunk are read whole.
+/// Lines which aren't valid UTF-8 are parsed as [`LogEntry::from_bytes_with`] does, and binary
+/// data is skipped as by [`LogReader`](crate::reader::LogReader).
+///
+/// # Example
+/// ```
+/// use common_log_format::{parallel::ParallelReader, reader::ReadError};
+/// let log = b"10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10\n\
+///             10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /caf\xe9 HTTP/1.1\" 200 10\n\
+///             \0\0\0\0\0\0\n\
+///             not a log line\n\
+///             10.0.0.1 - - [2024-05-01T13:00:02Z] \"GET /c HTTP/1.1\" 200 10";
+/// // Chunks of 32 bytes, so that lines span several.
+/// let entries: Vec<_> = ParallelReader::new(&log[..]).with_chunk_size(32).collect();
+/// assert_eq!(entries.len(), 4);
+/// assert_eq!(entries[1].as_ref().unwrap().path(), Some("/caf\u{fffd}"));
+/// assert!(matches!(entries[2], Err(ReadError::Parse { line: 4, .. })));
+/// assert_eq!(entries[3].as_ref().unwrap().path(), Some("/c"));
+/// ```
+pub struct ParallelReader<R> {
+    reader: R,
+    opts: ParseOptions,
+    chunk_size: usize,
+    /// The number of lines read before the current batch.
+    line: u64,
+    /// The start of a line which the last chunk cut off.
+    partial: Vec<u8>,
+    parsed: VecDeque<Result<LogEntry, ReadError>>,
+    done: bool,
+}
+
+impl<R: Read> ParallelReader<R> {
+    pub fn new(reader: R) -> Self {
+        ParallelReader {
+            reader,
+            opts: ParseOptions::default(),
+            chunk_size: 4 << 20,
+            line: 0,
+            partial: Vec::new(),
+            parsed: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Read `chunk_size` bytes into each chunk, up to the last newline in them. The default is
+    /// 4 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Read the next chunk, ending at a newline unless the log ended first.
+    fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = std::mem::take(&mut self.partial);
+        loop {
+            let start = chunk.len();
+            (&mut self.reader)
+                .take(self.chunk_size as u64)
+                .read_to_end(&mut chunk)?;
+            if chunk.len() == start {
+                self.done = true;
+                return Ok(chunk);
+            }
+            if let Some(end) = memchr::memrchr(b'\n', &chunk[start..]) {
+                self.partial = chunk.split_off(start + end + 1);
+                return Ok(chunk);
+            }
+        }
+    }
+
+    fn read_batch(&mut self) -> io::Result<()> {
+        let mut chunks = Vec::new();
+        while !self.done && chunks.len() < rayon::current_num_threads() {
+            let chunk = self.read_chunk()?;
+            if !chunk.is_empty() {
+                chunks.push(chunk);
+            }
+        }
+
+        let opts = self.opts;
+        let parsed: Vec<Vec<(u64, Result<LogEntry, LogEntryParseError>)>> = chunks
+            .par_iter()
+            .map(|chunk| {
+                chunk
+                    .split_inclusive(|b| *b == b'\n')
+                    .enumerate()
+                    .map(|(i, l)| (i as u64, trim_line_end(l)))
+                    .filter(|(_, l)| !l.trim_ascii().is_empty())
+                    .filter_map(|(i, l)| Some((i, parse_salvaged(l, &opts).1?)))
+                    .collect()
+            })
+            .collect();
+        for (chunk, entries) in chunks.iter().zip(parsed) {
+            for (i, entry) in entries {
+                let line = self.line + i + 1;
+                self.parsed
+                    .push_back(entry.map_err(|error| ReadError::Parse { line, error }));
+            }
+            let unterminated = !chunk.ends_with(b"\n");
+            self.line += (memchr::memchr_iter(b'\n', chunk).count() + unterminated as usize) as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ParallelReader<R> {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.parsed.is_empty() && !self.done {
+            if let Err(e) = self.read_batch() {
+                self.done = true;
+                return Some(Err(e.into()));
+            }
+        }
+        self.parsed.pop_front()
+    }
+}
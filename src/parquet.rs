@@ -0,0 +1,425 @@
+//! Writing entries as [Parquet] files, for querying with DuckDB, Spark, Polars, or DataFusion.
+//!
+//! The columns are those of [`schema`]: `host`, `ident`, `authuser`, `time` (a UTC timestamp, to
+//! the microsecond), `request_line`, `method`, `path`, `status_code`, and `object_size`, as in the
+//! `datafusion` module's table. Fields which were `-` in the log are null.
+//!
+//! [`ParquetSink`] writes one file. [`PartitionedSink`] writes a dataset of files divided by
+//! date and hour, in the directory layout Hive, Spark, Athena, and DuckDB read as partitions.
+//!
+//! [Parquet]: https://parquet.apache.org
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arrow::{
+    array::{ArrayRef, StringBuilder, TimestampMicrosecondBuilder, UInt16Builder, UInt64Builder},
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    errors::ParquetError,
+    file::properties::WriterProperties,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{sink::Sink, LogEntry};
+
+/// The number of rows [`ParquetSink`] buffers before writing them out.
+const BATCH_ROWS: usize = 8192;
+
+/// The value Hive gives a partition column for rows where it's null.
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// The Arrow schema of the columns written.
+pub fn schema() -> SchemaRef {
+    let utc = Some(Arc::from("UTC"));
+    Arc::new(Schema::new(vec![
+        Field::new("host", DataType::Utf8, true),
+        Field::new("ident", DataType::Utf8, true),
+        Field::new("authuser", DataType::Utf8, true),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Microsecond, utc),
+            true,
+        ),
+        Field::new("request_line", DataType::Utf8, true),
+        Field::new("method", DataType::Utf8, true),
+        Field::new("path", DataType::Utf8, true),
+        Field::new("status_code", DataType::UInt16, true),
+        Field::new("object_size", DataType::UInt64, true),
+    ]))
+}
+
+#[derive(Default)]
+struct Columns {
+    host: StringBuilder,
+    ident: StringBuilder,
+    authuser: StringBuilder,
+    time: TimestampMicrosecondBuilder,
+    request_line: StringBuilder,
+    method: StringBuilder,
+    path: StringBuilder,
+    status_code: UInt16Builder,
+    object_size: UInt64Builder,
+    rows: usize,
+}
+
+impl Columns {
+    fn push(&mut self, e: &LogEntry) {
+        self.host.append_option(e.host.map(|h| h.to_string()));
+        self.ident.append_option(e.ident.as_deref());
+        self.authuser.append_option(e.authuser.as_deref());
+        self.time
+            .append_option(e.time.map(|t| t.timestamp_micros()));
+        self.request_line.append_option(e.request_line.as_deref());
+        self.method.append_option(e.method());
+        self.path.append_option(e.path());
+        self.status_code
+            .append_option(e.status_code.map(|s| s.as_u16()));
+        self.object_size
+            .append_option(e.object_size.map(|s| s as u64));
+        self.rows += 1;
+    }
+
+    fn finish(&mut self) -> RecordBatch {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.host.finish()),
+            Arc::new(self.ident.finish()),
+            Arc::new(self.authuser.finish()),
+            Arc::new(self.time.finish().with_timezone("UTC")),
+            Arc::new(self.request_line.finish()),
+            Arc::new(self.method.finish()),
+            Arc::new(self.path.finish()),
+            Arc::new(self.status_code.finish()),
+            Arc::new(self.object_size.finish()),
+        ];
+        self.rows = 0;
+        RecordBatch::try_new(schema(), columns).expect("columns match the schema")
+    }
+}
+
+/// A record batch of `entries`, with the columns of [`schema`].
+pub fn to_record_batch<'a>(entries: impl IntoIterator<Item = &'a LogEntry>) -> RecordBatch {
+    let mut columns = Columns::default();
+    for e in entries {
+        columns.push(e);
+    }
+    columns.finish()
+}
+
+/// A [`Sink`] writing a Parquet file, compressed with Zstandard.
+///
+/// Entries are buffered and written in batches. The file is only complete, and readable, once
+/// [`ParquetSink::finish`] has been called.
+///
+/// # Example
+/// ```
+/// use common_log_format::{parquet::ParquetSink, sink::Sink, LogEntry};
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10".parse().unwrap();
+///
+/// let mut sink = ParquetSink::new(Vec::new()).unwrap();
+/// sink.send_all([&entry, &entry]).unwrap();
+/// assert_eq!(sink.rows(), 2);
+/// let file = sink.finish().unwrap();
+/// assert_eq!(&file[..4], b"PAR1");
+/// ```
+pub struct ParquetSink<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    columns: Columns,
+    rows: u64,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W) -> Result<Self, ParquetError> {
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        Ok(ParquetSink {
+            writer: ArrowWriter::try_new(writer, schema(), Some(props))?,
+            columns: Columns::default(),
+            rows: 0,
+        })
+    }
+
+    /// The number of entries sent so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Write out any buffered entries and the file's footer, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W, ParquetError> {
+        self.flush()?;
+        self.writer.into_inner()
+    }
+}
+
+impl<W: Write + Send> Sink for ParquetSink<W> {
+    type Error = ParquetError;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        self.columns.push(entry);
+        self.rows += 1;
+        if self.columns.rows >= BATCH_ROWS {
+            self.writer.write(&self.columns.finish())?;
+        }
+        Ok(())
+    }
+
+    /// Write buffered entries to the current row group. Row groups are ended, and written to the
+    /// underlying writer, as they fill up.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.columns.rows > 0 {
+            self.writer.write(&self.columns.finish())?;
+        }
+        Ok(())
+    }
+}
+
+/// The entries written to one partition of a [`PartitionedSink`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Partition {
+    /// The date, as `2024-05-01`, or `__HIVE_DEFAULT_PARTITION__` for entries without a time.
+    pub dt: String,
+    /// The hour, as `13`, or `__HIVE_DEFAULT_PARTITION__`.
+    pub hour: String,
+    /// The files written, relative to the dataset's root.
+    pub files: Vec<PathBuf>,
+    pub rows: u64,
+    /// The earliest and latest times of the entries, which can also be read from the files'
+    /// column statistics.
+    pub min_time: Option<DateTime<Utc>>,
+    pub max_time: Option<DateTime<Utc>>,
+}
+
+impl Partition {
+    /// The partition's directory, relative to the dataset's root: `dt=2024-05-01/hour=13`.
+    pub fn location(&self) -> PathBuf {
+        Path::new(&format!("dt={}", self.dt)).join(format!("hour={}", self.hour))
+    }
+
+    /// An `ALTER TABLE` statement registering the partition with `table`, whose location is
+    /// `root`, such as `s3://bucket/logs`, for catalogs which don't discover partitions
+    /// themselves, like Athena's without partition projection.
+    pub fn add_partition_sql(&self, table: &str, root: &str) -> String {
+        format!(
+            "ALTER TABLE {} ADD IF NOT EXISTS PARTITION (dt = '{}', hour = '{}') LOCATION '{}/dt={}/hour={}/'",
+            table,
+            self.dt,
+            self.hour,
+            root.trim_end_matches('/'),
+            self.dt,
+            self.hour
+        )
+    }
+}
+
+/// A file being written in a partition.
+struct OpenPart {
+    /// The partition's index in `PartitionedSink::partitions`.
+    partition: usize,
+    sink: ParquetSink<BufWriter<File>>,
+    /// The path being written, and the one to rename it to when it's complete.
+    writing: PathBuf,
+    done: PathBuf,
+}
+
+/// A [`Sink`] writing a dataset of Parquet files partitioned by the date and hour of each
+/// entry, in UTC: `dt=2024-05-01/hour=13/part-00000-….parquet`.
+///
+/// The partition columns are in the directory names rather than the files, as Hive lays them
+/// out, so engines reading the dataset prune partitions a query's `dt` and `hour` filters rule
+/// out without opening them, and skip files within a partition by the statistics in their
+/// footers. In DuckDB, for example:
+///
+/// ```sql
+/// SELECT count(*) FROM read_parquet('logs/*/*/*.parquet', hive_partitioning = true)
+/// WHERE dt = '2024-05-01' AND hour = '13';
+/// ```
+///
+/// Each file is written under a hidden name, which readers ignore, and renamed when it's
+/// complete. Up to 8 files are open at a time by default, closing the least recently written
+/// when another partition is needed; a file is also closed once it holds a million rows, and
+/// the partition continues in a new one. [`PartitionedSink::finish`] closes the rest, writes an
+/// empty `_SUCCESS` file as Spark does, and returns what was written to each partition. Files
+/// left open when the sink is dropped are not completed.
+///
+/// # Example
+/// ```
+/// use common_log_format::{parquet::PartitionedSink, sink::Sink, LogEntry};
+///
+/// let root = std::env::temp_dir().join(format!("clf-partitioned-doctest-{}", std::process::id()));
+/// let mut sink = PartitionedSink::new(&root).unwrap();
+/// for time in ["2024-05-01T13:00:00Z", "2024-05-01T13:59:00Z", "2024-05-02T00:10:00+01:00"] {
+///     let line = format!("10.0.0.1 - - [{}] \"GET / HTTP/1.1\" 200 10", time);
+///     sink.send(&line.parse::<LogEntry>().unwrap()).unwrap();
+/// }
+/// let partitions = sink.finish().unwrap();
+///
+/// let summary: Vec<_> = partitions.iter().map(|p| (p.location(), p.rows)).collect();
+/// assert_eq!(summary, [("dt=2024-05-01/hour=13".into(), 2), ("dt=2024-05-01/hour=23".into(), 1)]);
+/// assert!(root.join(&partitions[0].files[0]).exists());
+/// assert!(root.join("_SUCCESS").exists());
+/// assert_eq!(
+///     partitions[1].add_partition_sql("logs", "s3://bucket/logs"),
+///     "ALTER TABLE logs ADD IF NOT EXISTS PARTITION (dt = '2024-05-01', hour = '23') \
+///      LOCATION 's3://bucket/logs/dt=2024-05-01/hour=23/'"
+/// );
+/// # std::fs::remove_dir_all(&root).unwrap();
+/// ```
+pub struct PartitionedSink {
+    root: PathBuf,
+    /// Distinguishes this sink's files from those of other runs writing to the same dataset.
+    id: u64,
+    max_open: usize,
+    max_rows: u64,
+    /// Every partition written to, in the order they were first written.
+    partitions: Vec<Partition>,
+    /// The files being written, least recently written first.
+    open: Vec<OpenPart>,
+    files: u64,
+}
+
+impl PartitionedSink {
+    /// Write a dataset in the directory `root`, creating it if it doesn't exist. Files already in
+    /// it are left alone, so a dataset can be added to.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, ParquetError> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Ok(PartitionedSink {
+            root,
+            id: now ^ ((std::process::id() as u64) << 32),
+            max_open: 8,
+            max_rows: 1_000_000,
+            partitions: Vec::new(),
+            open: Vec::new(),
+            files: 0,
+        })
+    }
+
+    /// Keep up to `max_open` files open at once. Each buffers a row group in memory.
+    pub fn with_max_open(mut self, max_open: usize) -> Self {
+        self.max_open = max_open.max(1);
+        self
+    }
+
+    /// Start a new file in a partition once one holds `max_rows` rows.
+    pub fn with_max_rows(mut self, max_rows: u64) -> Self {
+        self.max_rows = max_rows.max(1);
+        self
+    }
+
+    /// Close every file, write `_SUCCESS`, and return what was written to each partition, by
+    /// date and hour.
+    pub fn finish(mut self) -> Result<Vec<Partition>, ParquetError> {
+        while !self.open.is_empty() {
+            self.close(0)?;
+        }
+        File::create(self.root.join("_SUCCESS"))?;
+        let mut partitions = std::mem::take(&mut self.partitions);
+        partitions.sort_by(|a, b| (&a.dt, &a.hour).cmp(&(&b.dt, &b.hour)));
+        Ok(partitions)
+    }
+
+    /// The index in `self.open` of the file to write `entry` to, opening one if needed.
+    fn part_for(&mut self, entry: &LogEntry) -> Result<usize, ParquetError> {
+        let (dt, hour) = match entry.time {
+            Some(t) => (t.format("%Y-%m-%d").to_string(), t.format("%H").to_string()),
+            None => (DEFAULT_PARTITION.to_owned(), DEFAULT_PARTITION.to_owned()),
+        };
+        let partition = match self
+            .partitions
+            .iter()
+            .position(|p| p.dt == dt && p.hour == hour)
+        {
+            Some(i) => i,
+            None => {
+                self.partitions.push(Partition {
+                    dt,
+                    hour,
+                    files: Vec::new(),
+                    rows: 0,
+                    min_time: None,
+                    max_time: None,
+                });
+                self.partitions.len() - 1
+            }
+        };
+        if let Some(i) = self.open.iter().position(|o| o.partition == partition) {
+            if self.open[i].sink.rows() < self.max_rows {
+                return Ok(i);
+            }
+            self.close(i)?;
+        }
+        if self.open.len() >= self.max_open {
+            self.close(0)?;
+        }
+
+        let dir = self.root.join(self.partitions[partition].location());
+        fs::create_dir_all(&dir)?;
+        let name = format!("part-{:05}-{:016x}.parquet", self.files, self.id);
+        self.files += 1;
+        let writing = dir.join(format!(".{}", name));
+        let sink = ParquetSink::new(BufWriter::new(File::create(&writing)?))?;
+        self.open.push(OpenPart {
+            partition,
+            sink,
+            writing,
+            done: dir.join(name),
+        });
+        Ok(self.open.len() - 1)
+    }
+
+    /// Complete the `i`th open file, and give it its name.
+    fn close(&mut self, i: usize) -> Result<(), ParquetError> {
+        let part = self.open.remove(i);
+        let file = part
+            .sink
+            .finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&part.writing, &part.done)?;
+        let relative = part
+            .done
+            .strip_prefix(&self.root)
+            .unwrap_or(&part.done)
+            .to_owned();
+        self.partitions[part.partition].files.push(relative);
+        Ok(())
+    }
+}
+
+impl Sink for PartitionedSink {
+    type Error = ParquetError;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        let i = self.part_for(entry)?;
+        let part = self.open.remove(i);
+        let p = &mut self.partitions[part.partition];
+        p.rows += 1;
+        if let Some(t) = entry.time {
+            p.min_time = Some(p.min_time.map_or(t, |m| m.min(t)));
+            p.max_time = Some(p.max_time.map_or(t, |m| m.max(t)));
+        }
+        self.open.push(part);
+        let last = self.open.len() - 1;
+        self.open[last].sink.send(entry)
+    }
+
+    /// Write buffered entries to each open file's current row group.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.open.iter_mut().try_for_each(|o| o.sink.flush())
+    }
+}
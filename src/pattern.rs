@@ -0,0 +1,26 @@
+//! Wildcard matching for path and file name patterns.
+
+/// Match `s` against `pattern`, where `*` in the pattern matches any run of characters.
+pub(crate) fn matches(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match s.strip_prefix(first) {
+        Some(r) => r,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(x) => x,
+        None => return rest.is_empty(),
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
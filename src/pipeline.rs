@@ -0,0 +1,385 @@
+//! Reading, transforming, and writing entries on several threads at once.
+//!
+//! Converting a large log is a chain of steps: read lines, parse them, change or drop entries
+//! (anonymize, filter, enrich), and write what's left. A [`Pipeline`] runs each step on threads
+//! of its own, as many as it's given, connected by bounded queues: a slow step fills the queue
+//! in front of it and holds back the steps before it, rather than letting entries pile up in
+//! memory.
+//!
+//! Lines move through the pipeline in batches, so threads hand each other work rarely, and the
+//! sink receives the entries in the order of the input however many threads each step has.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    io::{self, BufRead},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    progress::{Progress, Tracker},
+    reader::{parse_salvaged, trim_line_end},
+    sink::Sink,
+    LogEntry, ParseOptions,
+};
+
+/// An error which stopped a [`Pipeline`].
+#[derive(Debug)]
+pub enum PipelineError<E> {
+    /// The input couldn't be read. Entries before the error were still sent to the sink.
+    Read(io::Error),
+    Sink(E),
+}
+
+impl<E> Display for PipelineError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(_) => write!(f, "error reading pipeline input"),
+            Self::Sink(_) => write!(f, "error sending entries to pipeline sink"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for PipelineError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read(ref e) => Some(e),
+            Self::Sink(ref e) => Some(e),
+        }
+    }
+}
+
+/// What a [`Pipeline`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineReport {
+    /// Lines read, including blank ones.
+    pub lines: u64,
+    /// Lines which couldn't be parsed, or held only binary data.
+    pub parse_errors: u64,
+    /// Entries dropped by a stage.
+    pub filtered: u64,
+    /// Entries sent to the sink.
+    pub written: u64,
+}
+
+#[derive(Default)]
+struct Counts {
+    bytes: AtomicU64,
+    lines: AtomicU64,
+    entries: AtomicU64,
+    parse_errors: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl Counts {
+    fn progress(&self) -> Progress {
+        Progress {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            lines: self.lines.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+type Transform = Arc<dyn Fn(LogEntry) -> Option<LogEntry> + Send + Sync>;
+
+type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+struct Stage {
+    workers: usize,
+    transform: Transform,
+}
+
+/// A chain of threads reading lines from a stream, parsing them, passing the entries through
+/// each stage in turn, and sending what's left to a sink.
+///
+/// The input is read on one thread, and parsed on as many as there are cores unless set
+/// otherwise. Each stage has the number of threads it's added with, and the sink runs on the
+/// thread calling [`Pipeline::run`], so it needn't be `Send`. Between each step is a queue of 16
+/// batches of 1024 lines, by default.
+///
+/// # Example
+/// ```
+/// use common_log_format::{pipeline::Pipeline, LogEntry};
+///
+/// let log = "10.0.0.1 frank - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10\n\
+///            10.0.0.2 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 404 0\n\
+///            not a log line\n\
+///            10.0.0.3 alice - [2024-05-01T13:00:02Z] \"GET /c HTTP/1.1\" 200 30\n";
+///
+/// let mut written: Vec<LogEntry> = Vec::new();
+/// let report = Pipeline::new(log.as_bytes())
+///     .with_stage(2, |e: LogEntry| (e.status_code.map(|s| s.as_u16()) != Some(404)).then_some(e))
+///     .with_stage(4, |e: LogEntry| Some(LogEntry { ident: None, ..e }))
+///     .run(&mut written)
+///     .unwrap();
+///
+/// assert_eq!((report.lines, report.parse_errors, report.filtered, report.written), (4, 1, 1, 2));
+/// assert_eq!(written[0].host, Some("10.0.0.1".parse().unwrap()));
+/// assert_eq!(written[1].ident, None);
+/// ```
+pub struct Pipeline<R> {
+    input: R,
+    opts: ParseOptions,
+    parsers: usize,
+    stages: Vec<Stage>,
+    capacity: usize,
+    batch_size: usize,
+    progress: Option<(Duration, ProgressCallback)>,
+}
+
+impl<R: BufRead + Send + 'static> Pipeline<R> {
+    pub fn new(input: R) -> Self {
+        Pipeline {
+            input,
+            opts: ParseOptions::default(),
+            parsers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            stages: Vec::new(),
+            capacity: 16,
+            batch_size: 1024,
+            progress: None,
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Parse on `parsers` threads.
+    pub fn with_parsers(mut self, parsers: usize) -> Self {
+        self.parsers = parsers.max(1);
+        self
+    }
+
+    /// Add a stage after the others, which passes each entry to `transform` on `workers`
+    /// threads, and keeps what it returns. Returning `None` drops the entry.
+    pub fn with_stage<F>(mut self, workers: usize, transform: F) -> Self
+    where
+        F: Fn(LogEntry) -> Option<LogEntry> + Send + Sync + 'static,
+    {
+        self.stages.push(Stage {
+            workers: workers.max(1),
+            transform: Arc::new(transform),
+        });
+        self
+    }
+
+    /// Queue up to `batches` batches between each step.
+    pub fn with_capacity(mut self, batches: usize) -> Self {
+        self.capacity = batches.max(1);
+        self
+    }
+
+    /// Read `lines` lines at a time.
+    pub fn with_batch_size(mut self, lines: usize) -> Self {
+        self.batch_size = lines.max(1);
+        self
+    }
+
+    /// Call `callback` with how far the pipeline has got every `interval`, and once more when it
+    /// ends, on the thread running it. Entries are counted as they're parsed.
+    pub fn with_progress(
+        mut self,
+        interval: Duration,
+        callback: impl FnMut(&Progress) + Send + 'static,
+    ) -> Self {
+        self.progress = Some((interval, Box::new(callback)));
+        self
+    }
+
+    /// Run the pipeline until the input ends, then flush the sink.
+    ///
+    /// If the sink fails, this returns straight away, and the other threads stop once they next
+    /// hand on a batch. A panic in a stage is resumed here once the other threads have stopped.
+    pub fn run<S: Sink>(self, mut sink: S) -> Result<PipelineReport, PipelineError<S::Error>> {
+        let counts = Arc::new(Counts::default());
+        let mut progress = self
+            .progress
+            .map(|(interval, callback)| Tracker::new(interval, callback));
+        let (lines_tx, lines_rx) = mpsc::sync_channel(self.capacity);
+        let reader = {
+            let (input, batch_size, counts) = (self.input, self.batch_size, counts.clone());
+            thread::Builder::new()
+                .name("clf-pipeline-read".to_owned())
+                .spawn(move || read_batches(input, batch_size, &lines_tx, &counts))
+                .expect("failed to spawn pipeline thread")
+        };
+
+        let (tx, mut rx) = mpsc::sync_channel(self.capacity);
+        let mut workers = {
+            let (opts, counts) = (self.opts, counts.clone());
+            spawn_workers(self.parsers, lines_rx, tx, move |lines| {
+                parse_batch(lines, &opts, &counts)
+            })
+        };
+        for stage in self.stages {
+            let (tx, next) = mpsc::sync_channel(self.capacity);
+            let counts = counts.clone();
+            let transform = stage.transform;
+            let stage_workers = spawn_workers(stage.workers, rx, tx, move |entries: Vec<_>| {
+                let before = entries.len();
+                let entries: Vec<_> = entries.into_iter().filter_map(&*transform).collect();
+                let filtered = (before - entries.len()) as u64;
+                counts.filtered.fetch_add(filtered, Ordering::Relaxed);
+                entries
+            });
+            workers.extend(stage_workers);
+            rx = next;
+        }
+
+        // Batches arrive out of order when a step has several threads; hold each until those
+        // before it have been sent.
+        let mut written = 0;
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        loop {
+            let timeout = progress.as_ref().map_or(Duration::MAX, Tracker::until_next);
+            match rx.recv_timeout(timeout) {
+                Ok((seq, entries)) => {
+                    pending.insert(seq, entries);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            while let Some(entries) = pending.remove(&next) {
+                next += 1;
+                for entry in &entries {
+                    sink.send(entry).map_err(PipelineError::Sink)?;
+                }
+                written += entries.len() as u64;
+            }
+            if let Some(tracker) = &mut progress {
+                tracker.tick(counts.progress());
+            }
+        }
+
+        for worker in workers {
+            if let Err(panic) = worker.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+        let read = reader
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        sink.flush().map_err(PipelineError::Sink)?;
+        if let Some(tracker) = &mut progress {
+            tracker.finish(counts.progress());
+        }
+        read.map_err(PipelineError::Read)?;
+
+        Ok(PipelineReport {
+            lines: counts.lines.load(Ordering::Relaxed),
+            parse_errors: counts.parse_errors.load(Ordering::Relaxed),
+            filtered: counts.filtered.load(Ordering::Relaxed),
+            written,
+        })
+    }
+}
+
+/// A batch of lines or entries, numbered in the order it was read.
+type Batch<T> = (u64, Vec<T>);
+
+/// Read batches of lines from `input` and send them on, until it ends, or nothing is receiving.
+fn read_batches(
+    mut input: impl BufRead,
+    batch_size: usize,
+    tx: &SyncSender<Batch<Vec<u8>>>,
+    counts: &Counts,
+) -> io::Result<()> {
+    for seq in 0.. {
+        let mut batch = Vec::with_capacity(batch_size);
+        let end = loop {
+            if batch.len() == batch_size {
+                break Ok(false);
+            }
+            let mut line = Vec::new();
+            match input.read_until(b'\n', &mut line) {
+                Ok(0) => break Ok(true),
+                Ok(n) => {
+                    counts.bytes.fetch_add(n as u64, Ordering::Relaxed);
+                    batch.push(line);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+        };
+
+        counts
+            .lines
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        if !batch.is_empty() && tx.send((seq, batch)).is_err() {
+            return Ok(());
+        }
+        if end? {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Parse each line in `lines`, skipping blank ones, and counting those which can't be parsed.
+fn parse_batch(lines: Vec<Vec<u8>>, opts: &ParseOptions, counts: &Counts) -> Vec<LogEntry> {
+    let mut entries = Vec::with_capacity(lines.len());
+    for line in &lines {
+        let line = trim_line_end(line);
+        if line.is_empty() {
+            continue;
+        }
+        match parse_salvaged(line, opts).1 {
+            Some(Ok(entry)) => entries.push(entry),
+            _ => {
+                counts.parse_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    counts
+        .entries
+        .fetch_add(entries.len() as u64, Ordering::Relaxed);
+    entries
+}
+
+/// Start `n` threads which take batches from `rx`, pass their contents through `f`, and send the
+/// results on to `tx`, until `rx` is empty and closed, or nothing is receiving from `tx`.
+fn spawn_workers<I, O, F>(
+    n: usize,
+    rx: Receiver<Batch<I>>,
+    tx: SyncSender<Batch<O>>,
+    f: F,
+) -> Vec<JoinHandle<()>>
+where
+    I: Send + 'static,
+    O: Send + 'static,
+    F: Fn(Vec<I>) -> Vec<O> + Send + Sync + 'static,
+{
+    let rx = Arc::new(Mutex::new(rx));
+    let f = Arc::new(f);
+    (0..n)
+        .map(|_| {
+            let (rx, tx, f) = (rx.clone(), tx.clone(), f.clone());
+            thread::Builder::new()
+                .name("clf-pipeline".to_owned())
+                .spawn(move || loop {
+                    let batch = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    let (seq, items) = match batch {
+                        Ok(batch) => batch,
+                        Err(_) => return,
+                    };
+                    if tx.send((seq, f(items))).is_err() {
+                        return;
+                    }
+                })
+                .expect("failed to spawn pipeline thread")
+        })
+        .collect()
+}
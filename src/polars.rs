@@ -0,0 +1,191 @@
+//! Converting entries to and from [Polars] data frames.
+//!
+//! [`to_polars`] builds a data frame with one column per [`LogEntry`] field:
+//!
+//! | column         | dtype                     |
+//! |----------------|---------------------------|
+//! | `host`         | `str`                     |
+//! | `ident`        | `str`                     |
+//! | `authuser`     | `str`                     |
+//! | `time`         | `datetime[μs, UTC]`       |
+//! | `request_line` | `str`                     |
+//! | `status_code`  | `u16`                     |
+//! | `object_size`  | `u64`                     |
+//!
+//! Fields which were `-` in the log are null. [`from_polars`] reads these columns back, casting
+//! them to these types first, so e.g. a status column of `i64` works too.
+//!
+//! [`to_dataframe`] builds a frame for analysis rather than for round trips: the same columns,
+//! with `host` categorical, and `method` and `protocol` (categorical) and `path` (`str`) split
+//! out of the request line.
+//!
+//! [Polars]: https://pola.rs
+
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+
+use crate::LogEntry;
+
+/// A data frame of `entries`, with the columns described in the [module docs](self).
+///
+/// # Example
+/// ```
+/// use common_log_format::{polars::{from_polars, to_polars}, LogEntry};
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET /a HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:00:20Z] \"GET /b HTTP/1.1\" 404 -",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let df = to_polars(&entries).unwrap();
+/// assert_eq!(df.shape(), (2, 7));
+/// assert_eq!(df.column("object_size").unwrap().null_count(), 1);
+/// assert_eq!(from_polars(&df).unwrap(), entries);
+/// ```
+pub fn to_polars<'a>(entries: impl IntoIterator<Item = &'a LogEntry>) -> PolarsResult<DataFrame> {
+    let entries: Vec<&LogEntry> = entries.into_iter().collect();
+    let strings = |name: &str, f: fn(&LogEntry) -> Option<&str>| {
+        Column::new(
+            name.into(),
+            entries.iter().map(|e| f(e)).collect::<Vec<_>>(),
+        )
+    };
+    let hosts: Vec<Option<String>> = entries
+        .iter()
+        .map(|e| e.host.map(|h| h.to_string()))
+        .collect();
+
+    let columns = vec![
+        Column::new("host".into(), hosts),
+        strings("ident", |e| e.ident.as_deref()),
+        strings("authuser", |e| e.authuser.as_deref()),
+        Int64Chunked::from_iter_options(
+            "time".into(),
+            entries.iter().map(|e| e.time.map(|t| t.timestamp_micros())),
+        )
+        .into_datetime(TimeUnit::Microseconds, Some(TimeZone::UTC))
+        .into_column(),
+        strings("request_line", |e| e.request_line.as_deref()),
+        UInt16Chunked::from_iter_options(
+            "status_code".into(),
+            entries.iter().map(|e| e.status_code.map(|s| s.as_u16())),
+        )
+        .into_column(),
+        UInt64Chunked::from_iter_options(
+            "object_size".into(),
+            entries.iter().map(|e| e.object_size.map(|s| s as u64)),
+        )
+        .into_column(),
+    ];
+    DataFrame::new(entries.len(), columns)
+}
+
+/// A data frame of `entries` for analysis, with the columns of [`to_polars`] and those described
+/// in the [module docs](self).
+///
+/// Categorical columns share Polars' global string cache, so frames built by separate calls can
+/// be joined and concatenated on them.
+///
+/// # Example
+/// ```
+/// use common_log_format::{polars::{from_polars, to_dataframe}, LogEntry};
+/// use polars::prelude::*;
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET /a?x=1 HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:00:20Z] \"POST /b HTTP/2.0\" 201 -",
+///     "10.0.0.1 - - [2024-05-01T13:00:30Z] \"GET /a HTTP/1.1\" 304 0",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let df = to_dataframe(&entries).unwrap();
+/// assert_eq!(df.shape(), (3, 10));
+/// assert!(df.column("method").unwrap().dtype().is_categorical());
+/// assert_eq!(df.column("path").unwrap().str().unwrap().get(0), Some("/a"));
+/// assert_eq!(from_polars(&df).unwrap(), entries);
+/// ```
+pub fn to_dataframe<'a>(
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+) -> PolarsResult<DataFrame> {
+    let entries: Vec<&LogEntry> = entries.into_iter().collect();
+    let mut df = to_polars(entries.iter().copied())?;
+    let categorical = DataType::from_categories(Categories::global());
+    let derived = |name: &str, f: fn(&LogEntry) -> Option<&str>| {
+        Column::new(
+            name.into(),
+            entries.iter().map(|e| f(e)).collect::<Vec<_>>(),
+        )
+    };
+
+    let host = df.column("host")?.cast(&categorical)?;
+    df.replace("host", host)?;
+    df.with_column(derived("method", LogEntry::method).cast(&categorical)?)?;
+    df.with_column(derived("path", LogEntry::path))?;
+    df.with_column(
+        derived("protocol", |e| e.request_line.as_deref()?.split(' ').nth(2)).cast(&categorical)?,
+    )?;
+    Ok(df)
+}
+
+/// The entries in a data frame with the columns [`to_polars`] produces. Other columns are ignored.
+///
+/// Fails if a column is missing or cannot be cast to its type, or if a value is out of range,
+/// such as a host which is not an IP address.
+pub fn from_polars(df: &DataFrame) -> PolarsResult<Vec<LogEntry>> {
+    let column = |name: &str, dtype: DataType| df.column(name)?.cast(&dtype);
+    let host = column("host", DataType::String)?;
+    let ident = column("ident", DataType::String)?;
+    let authuser = column("authuser", DataType::String)?;
+    let time = column(
+        "time",
+        DataType::Datetime(TimeUnit::Microseconds, Some(TimeZone::UTC)),
+    )?;
+    let request_line = column("request_line", DataType::String)?;
+    let status_code = column("status_code", DataType::UInt16)?;
+    let object_size = column("object_size", DataType::UInt64)?;
+
+    let owned = |s: Option<&str>| s.map(str::to_owned);
+    host.str()?
+        .iter()
+        .zip(ident.str()?.iter())
+        .zip(authuser.str()?.iter())
+        .zip(time.datetime()?.physical().iter())
+        .zip(request_line.str()?.iter())
+        .zip(status_code.u16()?.iter())
+        .zip(object_size.u64()?.iter())
+        .map(
+            |((((((host, ident), authuser), time), request_line), status_code), object_size)| {
+                Ok(LogEntry {
+                    host: host
+                        .map(|h| h.parse())
+                        .transpose()
+                        .map_err(|_| polars_err!(ComputeError: "invalid host {:?}", host))?,
+                    ident: owned(ident),
+                    authuser: owned(authuser),
+                    time: time
+                        .map(|t| {
+                            DateTime::<Utc>::from_timestamp_micros(t)
+                                .ok_or_else(|| polars_err!(ComputeError: "invalid time {}", t))
+                        })
+                        .transpose()?,
+                    request_line: owned(request_line),
+                    status_code: status_code
+                        .map(|s| {
+                            http::StatusCode::from_u16(s)
+                                .map_err(|_| polars_err!(ComputeError: "invalid status code {}", s))
+                        })
+                        .transpose()?,
+                    object_size: object_size
+                        .map(|s| {
+                            usize::try_from(s)
+                                .map_err(|_| polars_err!(ComputeError: "invalid object size {}", s))
+                        })
+                        .transpose()?,
+                })
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,178 @@
+//! Object popularity, and how well a Zipf distribution describes it.
+//!
+//! Web traffic is famously skewed: a few objects get most of the requests, and the rest trail off
+//! in a long tail. The usual model is Zipf's law, where the object of rank `r` is requested in
+//! proportion to `1 / r^s`. [`Popularity`] counts the requests for each target in a trace, ranks
+//! them, and fits the exponent `s`, which can then drive a
+//! [`Generator`](crate::generator::Generator) producing synthetic traffic shaped like the real
+//! thing.
+
+use std::collections::HashMap;
+
+use crate::LogEntry;
+
+/// An object's place in the popularity ranking.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RankedObject {
+    /// 1 for the most requested object.
+    pub rank: usize,
+    pub target: String,
+    pub requests: u64,
+    /// The bytes sent in response to requests for the object.
+    pub bytes: u64,
+}
+
+/// A Zipf distribution fitted to a rank-frequency table, by least squares on a log-log scale.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ZipfFit {
+    /// The exponent `s`: requests fall off as `1 / rank^s`. Web objects typically have an
+    /// exponent between 0.6 and 1.
+    pub exponent: f64,
+    /// The requests the fit predicts for the most popular object.
+    pub scale: f64,
+    /// The coefficient of determination of the fit, on the log-log scale: 1 for a perfect Zipf
+    /// distribution, lower the worse it fits.
+    pub r_squared: f64,
+    /// The number of ranks fitted.
+    pub ranks: usize,
+}
+
+impl ZipfFit {
+    /// Fit request counts in descending order, one per rank. Ranks with no requests are ignored.
+    /// Returns `None` if fewer than two ranks have requests, or they all have the same count.
+    ///
+    /// The tail of a trace, objects requested once or twice, often flattens out; fitting only a
+    /// prefix of the counts leaves it out.
+    pub fn over(counts: &[u64]) -> Option<ZipfFit> {
+        let points: Vec<(f64, f64)> = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c > 0)
+            .map(|(i, c)| (((i + 1) as f64).ln(), (*c as f64).ln()))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let sxy: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let syy: f64 = points.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+        if syy == 0. {
+            return None;
+        }
+
+        let slope = sxy / sxx;
+        let intercept = mean_y - slope * mean_x;
+        let residual: f64 = points
+            .iter()
+            .map(|(x, y)| (y - (intercept + slope * x)).powi(2))
+            .sum();
+        Some(ZipfFit {
+            exponent: -slope,
+            scale: intercept.exp(),
+            r_squared: 1. - residual / syy,
+            ranks: points.len(),
+        })
+    }
+
+    /// The requests the fit predicts for the object of `rank`.
+    pub fn predict(&self, rank: usize) -> f64 {
+        self.scale / (rank as f64).powf(self.exponent)
+    }
+}
+
+/// Counts the requests for each target in a trace.
+///
+/// Every entry with a request target is counted, whatever its method or status.
+///
+/// # Example
+/// ```
+/// use common_log_format::{generator::Generator, popularity::Popularity};
+/// let mut popularity = Popularity::new();
+/// for e in Generator::new(7).with_path_count(200, 0.9).take(50_000) {
+///     popularity.observe(&e.entry);
+/// }
+///
+/// let ranked = popularity.ranked();
+/// assert_eq!(ranked[0].rank, 1);
+/// assert!(ranked[0].requests >= ranked[1].requests);
+///
+/// let fit = popularity.fit_zipf().unwrap();
+/// assert!((fit.exponent - 0.9).abs() < 0.1, "{:?}", fit);
+/// assert!(fit.r_squared > 0.9);
+///
+/// // Synthetic traffic shaped like the trace.
+/// let paths = ranked.into_iter().map(|o| o.target).collect();
+/// let generator = Generator::new(8).with_paths(paths, fit.exponent);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Popularity {
+    objects: HashMap<String, (u64, u64)>,
+    requests: u64,
+}
+
+impl Popularity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, entry: &LogEntry) {
+        if let Some(target) = entry.target() {
+            let (requests, bytes) = match self.objects.get_mut(target) {
+                Some(counts) => counts,
+                None => self.objects.entry(target.to_owned()).or_default(),
+            };
+            *requests += 1;
+            *bytes += entry.object_size.unwrap_or(0) as u64;
+            self.requests += 1;
+        }
+    }
+
+    /// The number of distinct targets requested.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// The number of requests counted.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Every target, most requested first. Ties are broken by target, so the ranking is stable.
+    pub fn ranked(&self) -> Vec<RankedObject> {
+        let mut objects: Vec<_> = self.objects.iter().collect();
+        objects.sort_by(|(a, (ra, _)), (b, (rb, _))| rb.cmp(ra).then_with(|| a.cmp(b)));
+        objects
+            .into_iter()
+            .enumerate()
+            .map(|(i, (target, (requests, bytes)))| RankedObject {
+                rank: i + 1,
+                target: target.clone(),
+                requests: *requests,
+                bytes: *bytes,
+            })
+            .collect()
+    }
+
+    /// The request counts, most requested first.
+    pub fn counts(&self) -> Vec<u64> {
+        let mut counts: Vec<u64> = self.objects.values().map(|(r, _)| *r).collect();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        counts
+    }
+
+    /// Fit a Zipf distribution to every rank. See [`ZipfFit::over`].
+    pub fn fit_zipf(&self) -> Option<ZipfFit> {
+        ZipfFit::over(&self.counts())
+    }
+}
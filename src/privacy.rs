@@ -0,0 +1,585 @@
+//! Masking personal data out of log entries.
+//!
+//! A [`MaskProfile`] says what to do with each identifying field. Teams which share logs
+//! usually want one of a few standard policies, so these are available by name as [`Preset`]s:
+//!
+//! | preset        | addresses | ident, authuser | query strings             |
+//! |---------------|-----------|-----------------|---------------------------|
+//! | `gdpr-strict` | truncated | removed         | removed                   |
+//! | `debug-share` | truncated | removed         | all values redacted       |
+//! | `internal`    | kept      | kept            | sensitive values redacted |
+//!
+//! Addresses are the host, any addresses from a proxy header or `X-Forwarded-For`, and with
+//! `gdpr-strict` and `debug-share`, addresses written out in the request line. Query strings are
+//! those of the request line and the referer.
+//!
+//! Redacting every query value loses parameters which are useful and harmless, such as page
+//! numbers and search filters. A [`QueryRedactor`] redacts just those which look sensitive.
+//!
+//! A [`Masker`] applies a profile, and with the `anonymize` feature can replace the addresses a
+//! profile truncates with keyed-hash pseudonyms instead, so that clients can still be told apart.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+#[cfg(feature = "anonymize")]
+use crate::anonymize::Pseudonymizer;
+#[cfg(feature = "formats")]
+use crate::canonical::CanonicalEntry;
+use crate::{pattern, LogEntry};
+
+/// The placeholder which replaces redacted values.
+pub const REDACTED: &str = "REDACTED";
+
+/// What to do with the client address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HostMask {
+    Keep,
+    /// Zero the last octet of IPv4 addresses and the last 80 bits of IPv6 addresses.
+    Truncate,
+    Remove,
+}
+
+/// What to do with the query string of the request target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum QueryMask {
+    Keep,
+    /// Keep parameter names but replace every value with [`REDACTED`].
+    RedactValues,
+    /// Replace the values of the parameters [`QueryRedactor::standard`] finds sensitive.
+    RedactSensitive,
+    Remove,
+}
+
+/// A masking policy for every identifying field of a [`LogEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MaskProfile {
+    /// What to do with the host, and with the other client addresses of a `CanonicalEntry`.
+    pub host: HostMask,
+    /// Whether to also mask addresses written out in the request line, as in
+    /// `/whois?ip=203.0.113.7`, as the host is masked. Removed addresses are truncated instead.
+    pub request_line_addresses: bool,
+    pub remove_ident: bool,
+    pub remove_authuser: bool,
+    /// What to do with the query string of the request target, and of a referer.
+    pub query: QueryMask,
+}
+
+/// Named [`MaskProfile`]s. See the [module documentation](self) for what each one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    GdprStrict,
+    DebugShare,
+    Internal,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::GdprStrict, Preset::DebugShare, Preset::Internal];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::GdprStrict => "gdpr-strict",
+            Preset::DebugShare => "debug-share",
+            Preset::Internal => "internal",
+        }
+    }
+
+    pub fn profile(&self) -> MaskProfile {
+        match self {
+            Preset::GdprStrict => MaskProfile {
+                host: HostMask::Truncate,
+                request_line_addresses: true,
+                remove_ident: true,
+                remove_authuser: true,
+                query: QueryMask::Remove,
+            },
+            Preset::DebugShare => MaskProfile {
+                host: HostMask::Truncate,
+                request_line_addresses: true,
+                remove_ident: true,
+                remove_authuser: true,
+                query: QueryMask::RedactValues,
+            },
+            Preset::Internal => MaskProfile {
+                host: HostMask::Keep,
+                request_line_addresses: false,
+                remove_ident: false,
+                remove_authuser: false,
+                query: QueryMask::RedactSensitive,
+            },
+        }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name given to [`Preset::from_str`] is not a known preset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl Display for UnknownPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown masking preset {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPreset {}
+
+impl FromStr for Preset {
+    type Err = UnknownPreset;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Preset::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| UnknownPreset(s.to_owned()))
+    }
+}
+
+impl From<Preset> for MaskProfile {
+    fn from(p: Preset) -> Self {
+        p.profile()
+    }
+}
+
+impl MaskProfile {
+    /// Mask `entry` in place.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{privacy::Preset, LogEntry};
+    /// let line = "192.0.2.77 - frank [1996-12-19T16:39:57-08:00] \"GET /a?user=frank&x=1 HTTP/1.0\" 200 2326";
+    /// let mut entry: LogEntry = line.parse().unwrap();
+    /// let preset: Preset = "debug-share".parse().unwrap();
+    /// preset.profile().apply(&mut entry);
+    /// assert_eq!(entry.host, Some("192.0.2.0".parse().unwrap()));
+    /// assert_eq!(entry.authuser, None);
+    /// assert_eq!(entry.request_line.as_deref(), Some("GET /a?user=REDACTED&x=REDACTED HTTP/1.0"));
+    ///
+    /// let line = "192.0.2.77 - - [1996-12-19T16:39:57-08:00] \"GET /login?next=/&password=hunter2 HTTP/1.0\" 302 0";
+    /// let mut entry: LogEntry = line.parse().unwrap();
+    /// Preset::Internal.profile().apply(&mut entry);
+    /// assert_eq!(entry.request_line.as_deref(), Some("GET /login?next=/&password=REDACTED HTTP/1.0"));
+    /// ```
+    pub fn apply(&self, entry: &mut LogEntry) {
+        self.apply_with(entry, truncate_ip);
+    }
+
+    /// Mask `entry`, replacing the addresses which are to be truncated with `hide` of them.
+    fn apply_with(&self, entry: &mut LogEntry, hide: impl Fn(IpAddr) -> IpAddr) {
+        entry.host = match self.host {
+            HostMask::Keep => entry.host,
+            HostMask::Truncate => entry.host.map(&hide),
+            HostMask::Remove => None,
+        };
+
+        if self.request_line_addresses && self.host != HostMask::Keep {
+            if let Some(rl) = entry.request_line.as_deref() {
+                entry.request_line = Some(replace_ips(rl, &hide));
+            }
+        }
+
+        if self.remove_ident {
+            entry.ident = None;
+        }
+
+        if self.remove_authuser {
+            entry.authuser = None;
+        }
+
+        rewrite_query(entry, |q| self.mask_query(q));
+    }
+
+    /// `query`, without its leading `?`, masked; or `None` to drop it.
+    fn mask_query(&self, query: &str) -> Option<String> {
+        match self.query {
+            QueryMask::Keep => Some(query.to_owned()),
+            QueryMask::RedactValues => Some(
+                query
+                    .split('&')
+                    .map(|param| match param.split_once('=') {
+                        Some((k, _)) => format!("{}={}", k, REDACTED),
+                        None => param.to_owned(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("&"),
+            ),
+            QueryMask::RedactSensitive => {
+                static STANDARD: OnceLock<QueryRedactor> = OnceLock::new();
+                Some(
+                    STANDARD
+                        .get_or_init(QueryRedactor::standard)
+                        .redact_query(query),
+                )
+            }
+            QueryMask::Remove => None,
+        }
+    }
+
+    /// Mask `entry` in place, including its proxy and forwarded addresses and its referer.
+    #[cfg(feature = "formats")]
+    pub fn apply_canonical(&self, entry: &mut CanonicalEntry) {
+        self.apply_canonical_with(entry, truncate_ip);
+    }
+
+    #[cfg(feature = "formats")]
+    fn apply_canonical_with(&self, entry: &mut CanonicalEntry, hide: impl Fn(IpAddr) -> IpAddr) {
+        self.apply_with(&mut entry.entry, &hide);
+        match self.host {
+            HostMask::Keep => (),
+            HostMask::Truncate => {
+                if let Some(proxy) = &mut entry.proxy {
+                    proxy.source = hide(proxy.source);
+                }
+                for ip in &mut entry.forwarded_for {
+                    *ip = hide(*ip);
+                }
+            }
+            HostMask::Remove => {
+                entry.proxy = None;
+                entry.forwarded_for.clear();
+            }
+        }
+        if let Some(referer) = &mut entry.referer {
+            if let Some((url, query)) = referer.split_once('?') {
+                let (query, fragment) = match query.split_once('#') {
+                    Some((q, f)) => (q, Some(f)),
+                    None => (query, None),
+                };
+                let mut masked = url.to_owned();
+                if let Some(q) = self.mask_query(query) {
+                    masked.push('?');
+                    masked.push_str(&q);
+                }
+                if let Some(f) = fragment {
+                    masked.push('#');
+                    masked.push_str(f);
+                }
+                *referer = masked;
+            }
+        }
+    }
+}
+
+/// Applies a [`MaskProfile`] to entries.
+///
+/// With the `anonymize` feature and a pseudonymizer, the addresses the profile truncates are
+/// replaced with pseudonyms instead, which keeps clients apart without identifying them.
+///
+/// # Example
+/// ```
+/// use common_log_format::{privacy::{Masker, Preset}, LogEntry};
+/// let line = "192.0.2.77 - frank [1996-12-19T16:39:57-08:00] \"GET /hosts/198.51.100.23?token=x HTTP/1.0\" 200 2326";
+/// let mut entry: LogEntry = line.parse().unwrap();
+/// Masker::new(Preset::GdprStrict).apply(&mut entry);
+/// assert_eq!(entry.host, Some("192.0.2.0".parse().unwrap()));
+/// assert_eq!(entry.request_line.as_deref(), Some("GET /hosts/198.51.100.0 HTTP/1.0"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Masker {
+    profile: MaskProfile,
+    #[cfg(feature = "anonymize")]
+    pseudonymizer: Option<Pseudonymizer>,
+}
+
+impl Masker {
+    pub fn new(profile: impl Into<MaskProfile>) -> Self {
+        Masker {
+            profile: profile.into(),
+            #[cfg(feature = "anonymize")]
+            pseudonymizer: None,
+        }
+    }
+
+    /// Replace the addresses the profile truncates with pseudonyms from `pseudonymizer`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{anonymize::Pseudonymizer, privacy::{Masker, Preset}, LogEntry};
+    /// let masker = Masker::new(Preset::DebugShare)
+    ///     .with_pseudonymizer(Pseudonymizer::new(b"a secret key"));
+    /// let parse = |ip: &str| -> LogEntry {
+    ///     format!("{} - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326", ip).parse().unwrap()
+    /// };
+    /// let (mut a, mut b) = (parse("192.0.2.77"), parse("192.0.2.78"));
+    /// masker.apply(&mut a);
+    /// masker.apply(&mut b);
+    /// assert_ne!(a.host, Some("192.0.2.0".parse().unwrap()));
+    /// assert_ne!(a.host, b.host);
+    /// ```
+    #[cfg(feature = "anonymize")]
+    pub fn with_pseudonymizer(mut self, pseudonymizer: Pseudonymizer) -> Self {
+        self.pseudonymizer = Some(pseudonymizer);
+        self
+    }
+
+    pub fn profile(&self) -> &MaskProfile {
+        &self.profile
+    }
+
+    /// Mask `entry` in place.
+    pub fn apply(&self, entry: &mut LogEntry) {
+        #[cfg(feature = "anonymize")]
+        if let Some(p) = &self.pseudonymizer {
+            let time = entry.time;
+            return self
+                .profile
+                .apply_with(entry, |ip| p.pseudonymize(ip, time));
+        }
+        self.profile.apply(entry);
+    }
+
+    /// Mask `entry` in place, including its proxy and forwarded addresses and its referer.
+    #[cfg(feature = "formats")]
+    pub fn apply_canonical(&self, entry: &mut CanonicalEntry) {
+        #[cfg(feature = "anonymize")]
+        if let Some(p) = &self.pseudonymizer {
+            let time = entry.entry.time;
+            return self
+                .profile
+                .apply_canonical_with(entry, |ip| p.pseudonymize(ip, time));
+        }
+        self.profile.apply_canonical(entry);
+    }
+}
+
+/// Parameter names [`QueryRedactor::standard`] treats as sensitive.
+const SENSITIVE_PARAMS: &[&str] = &[
+    "*token*",
+    "*password*",
+    "passwd",
+    "pwd",
+    "*secret*",
+    "*api_key*",
+    "apikey",
+    "key",
+    "auth",
+    "authorization",
+    "code",
+    "sig",
+    "signature",
+    "session*",
+    "sessid",
+    "*email*",
+    "phone",
+    "ssn",
+];
+
+/// Rules for which query parameters hold sensitive values, to redact them from request lines.
+///
+/// A parameter's value is replaced with [`REDACTED`] if its name matches one of the name
+/// patterns, ignoring case, where `*` matches any run of characters. With the `regex` feature,
+/// names can also be matched by regular expression, and values (after percent-decoding) by
+/// regular expressions whatever the parameter is called, to catch e.g. email addresses.
+///
+/// # Example
+/// ```
+/// use common_log_format::{privacy::QueryRedactor, LogEntry};
+/// let line = "192.0.2.77 - - [1996-12-19T16:39:57-08:00] \"GET /cb?code=abc&Access_Token=xyz&page=2&uid=7 HTTP/1.0\" 200 2326";
+/// let mut entry: LogEntry = line.parse().unwrap();
+/// QueryRedactor::standard().with_param("uid").apply(&mut entry);
+/// assert_eq!(
+///     entry.request_line.as_deref(),
+///     Some("GET /cb?code=REDACTED&Access_Token=REDACTED&page=2&uid=REDACTED HTTP/1.0"),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryRedactor {
+    names: Vec<String>,
+    #[cfg(feature = "regex")]
+    name_regexes: Vec<regex::Regex>,
+    #[cfg(feature = "regex")]
+    value_regexes: Vec<regex::Regex>,
+}
+
+impl QueryRedactor {
+    /// A redactor with no rules, which redacts nothing until some are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A redactor for parameters commonly carrying credentials or personal data: tokens,
+    /// passwords, secrets, API keys, signatures, OAuth codes, session IDs, and email addresses.
+    pub fn standard() -> Self {
+        SENSITIVE_PARAMS
+            .iter()
+            .fold(Self::new(), |r, p| r.with_param(*p))
+    }
+
+    /// Redact parameters whose name matches `pattern`.
+    pub fn with_param(mut self, pattern: impl Into<String>) -> Self {
+        self.names.push(pattern.into().to_lowercase());
+        self
+    }
+
+    /// Redact parameters whose name matches `re`.
+    #[cfg(feature = "regex")]
+    pub fn with_param_regex(mut self, re: regex::Regex) -> Self {
+        self.name_regexes.push(re);
+        self
+    }
+
+    /// Redact parameters whose percent-decoded value matches `re`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::privacy::QueryRedactor;
+    /// let redactor = QueryRedactor::new()
+    ///     .with_value_regex(regex::Regex::new(r"[^@\s]+@[^@\s]+\.\w+").unwrap());
+    /// assert_eq!(redactor.redact_query("q=frank%40example.com&n=1"), "q=REDACTED&n=1");
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn with_value_regex(mut self, re: regex::Regex) -> Self {
+        self.value_regexes.push(re);
+        self
+    }
+
+    /// Whether the parameter `name=value` is to be redacted. `value` is as logged, i.e.
+    /// percent-encoded.
+    pub fn is_sensitive(&self, name: &str, value: &str) -> bool {
+        let lower = name.to_lowercase();
+        if self.names.iter().any(|p| pattern::matches(p, &lower)) {
+            return true;
+        }
+
+        #[cfg(feature = "regex")]
+        {
+            if self.name_regexes.iter().any(|re| re.is_match(name)) {
+                return true;
+            }
+            if !self.value_regexes.is_empty() {
+                let decoded = percent_decode(value);
+                return self.value_regexes.iter().any(|re| re.is_match(&decoded));
+            }
+        }
+        #[cfg(not(feature = "regex"))]
+        let _ = value;
+
+        false
+    }
+
+    /// Redact the sensitive parameters of `query`, which is without its leading `?`.
+    pub fn redact_query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|param| match param.split_once('=') {
+                Some((k, v)) if self.is_sensitive(k, v) => format!("{}={}", k, REDACTED),
+                _ => param.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Redact the sensitive parameters of `entry`'s request target in place.
+    pub fn apply(&self, entry: &mut LogEntry) {
+        rewrite_query(entry, |q| Some(self.redact_query(q)));
+    }
+}
+
+/// Decode `%XX` escapes and `+` in a query value, replacing invalid UTF-8.
+pub(crate) fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(h), Some(l)) => {
+                    out.push((h * 16 + l) as u8);
+                    i += 3;
+                    continue;
+                }
+                _ => out.push(b'%'),
+            },
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Zero the last octet of an IPv4 address or the last 80 bits of an IPv6 address.
+pub fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Replace each address written out in `s` with `f` of it.
+///
+/// Only literal addresses are found; percent-encoded ones, and addresses which run into
+/// neighbouring hex digits or dots, are left as they are.
+pub(crate) fn replace_ips(s: &str, f: impl Fn(IpAddr) -> IpAddr) -> String {
+    let is_addr_char = |c: char| c.is_ascii_hexdigit() || c == '.' || c == ':';
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(is_addr_char) {
+        let len = rest[start..]
+            .find(|c| !is_addr_char(c))
+            .unwrap_or(rest.len() - start);
+        let run = &rest[start..start + len];
+        // A trailing `.` or `:` is punctuation, not part of the address.
+        let candidate = run.trim_end_matches(['.', ':']);
+        out.push_str(&rest[..start]);
+        match candidate.parse() {
+            Ok(ip) => {
+                out.push_str(&f(ip).to_string());
+                out.push_str(&run[candidate.len()..]);
+            }
+            Err(_) => out.push_str(run),
+        }
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replace the query string of `entry`'s request target with the result of `f`, or drop the
+/// query string entirely if `f` returns `None`.
+pub(crate) fn rewrite_query(entry: &mut LogEntry, f: impl FnOnce(&str) -> Option<String>) {
+    let rl = match entry.request_line.as_deref() {
+        Some(rl) => rl,
+        None => return,
+    };
+
+    let mut parts = rl.splitn(3, ' ');
+    let (method, target, rest) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), rest) => (m, t, rest),
+        _ => return,
+    };
+
+    let (path, query) = match target.split_once('?') {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut new = format!("{} {}", method, path);
+    if let Some(q) = f(query) {
+        new.push('?');
+        new.push_str(&q);
+    }
+
+    if let Some(rest) = rest {
+        new.push(' ');
+        new.push_str(rest);
+    }
+
+    entry.request_line = Some(new);
+}
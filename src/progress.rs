@@ -0,0 +1,88 @@
+//! Reporting how far a long read has got.
+//!
+//! Scanning a large trace can take minutes with nothing to show for it until the end.
+//! [`LogReader::with_progress`](crate::reader::LogReader::with_progress) and
+//! [`Pipeline::with_progress`](crate::pipeline::Pipeline::with_progress) call back with a
+//! [`Progress`] at a steady interval while they read, to drive a progress bar, log a status line,
+//! or set metrics gauges.
+
+use std::time::{Duration, Instant};
+
+/// How much of its input a reader has got through.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    /// Bytes read, of decompressed data for compressed input.
+    pub bytes: u64,
+    /// Lines read, including blank ones.
+    pub lines: u64,
+    /// Entries parsed.
+    pub entries: u64,
+    /// Lines which couldn't be parsed.
+    pub parse_errors: u64,
+    /// How long since reading started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// The average number of bytes read per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// The average number of lines read per second.
+    pub fn lines_per_sec(&self) -> f64 {
+        self.lines as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Calls back with progress once per interval, and once at the end.
+pub(crate) struct Tracker {
+    callback: Box<dyn FnMut(&Progress) + Send>,
+    interval: Duration,
+    start: Instant,
+    next: Instant,
+    finished: bool,
+}
+
+impl Tracker {
+    pub fn new(interval: Duration, callback: impl FnMut(&Progress) + Send + 'static) -> Self {
+        let start = Instant::now();
+        Tracker {
+            callback: Box::new(callback),
+            interval,
+            start,
+            next: start + interval,
+            finished: false,
+        }
+    }
+
+    /// How long until the next report is due.
+    pub fn until_next(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Report `progress`, with the time elapsed filled in, if a report is due.
+    pub fn tick(&mut self, progress: Progress) {
+        let now = Instant::now();
+        if now >= self.next {
+            self.next = now + self.interval;
+            self.report(progress, now);
+        }
+    }
+
+    /// Report `progress` as the last, if it hasn't been already.
+    pub fn finish(&mut self, progress: Progress) {
+        if !self.finished {
+            self.finished = true;
+            self.report(progress, Instant::now());
+        }
+    }
+
+    fn report(&mut self, progress: Progress, now: Instant) {
+        let elapsed = now - self.start;
+        (self.callback)(&Progress {
+            elapsed,
+            ..progress
+        });
+    }
+}
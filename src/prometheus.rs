@@ -0,0 +1,287 @@
+//! Exporting request metrics from a live log to Prometheus.
+//!
+//! Servers which only write an access log can still be monitored: [`Metrics`] counts the
+//! entries of a log as it's followed, and [`MetricsServer`] serves the counts at `/metrics` in
+//! Prometheus's text format, like a small `mtail` or `grok_exporter` which needs no patterns.
+//!
+//! | metric                                | type    | labels            |
+//! |---------------------------------------|---------|-------------------|
+//! | `http_requests_total`                 | counter | `method`, `class` |
+//! | `http_response_bytes_total`           | counter | `method`, `class` |
+//! | `access_log_parse_errors_total`       | counter |                   |
+//! | `access_log_last_timestamp_seconds`   | gauge   |                   |
+//!
+//! `class` is the status class, such as `5xx`, or `unknown` without a status. Methods other than
+//! the standard ones are counted as `other`, so a scanner sending made-up methods can't make the
+//! number of series grow without bound. Request and error rates are left to PromQL:
+//!
+//! ```text
+//! sum(rate(http_requests_total[1m]))
+//! sum(rate(http_requests_total{class="5xx"}[1m])) / sum(rate(http_requests_total[1m]))
+//! ```
+//!
+//! The server speaks just enough HTTP/1.1 for a scraper, one connection at a time.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{follow::Follow, reader::ReadError, sink::Sink, LogEntry};
+
+/// The methods counted under their own name.
+const METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// How long to wait for a scraper to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Counters {
+    /// Requests and response bytes by method and status class.
+    requests: BTreeMap<(&'static str, &'static str), (u64, u64)>,
+    parse_errors: u64,
+    last_timestamp: Option<f64>,
+}
+
+/// Counts of the entries in a log, shared by every clone.
+///
+/// # Example
+/// ```
+/// use common_log_format::{prometheus::Metrics, sink::Sink, LogEntry};
+///
+/// let mut metrics = Metrics::new();
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 100",
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 200 50",
+///     "10.0.0.2 - - [2024-05-01T13:00:02Z] \"BREW /pot HTTP/1.1\" 503 0",
+/// ] {
+///     metrics.send(&line.parse::<LogEntry>().unwrap()).unwrap();
+/// }
+///
+/// let text = metrics.render();
+/// assert!(text.contains("http_requests_total{method=\"GET\",class=\"2xx\"} 2\n"));
+/// assert!(text.contains("http_response_bytes_total{method=\"GET\",class=\"2xx\"} 150\n"));
+/// assert!(text.contains("http_requests_total{method=\"other\",class=\"5xx\"} 1\n"));
+/// assert!(text.contains("access_log_last_timestamp_seconds 1714568402\n"));
+/// ```
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `entry`.
+    pub fn observe(&self, entry: &LogEntry) {
+        let method = entry
+            .method()
+            .and_then(|m| METHODS.iter().find(|known| **known == m))
+            .copied()
+            .unwrap_or("other");
+        let class = match entry.status_code.map(|s| s.as_u16() / 100) {
+            Some(1) => "1xx",
+            Some(2) => "2xx",
+            Some(3) => "3xx",
+            Some(4) => "4xx",
+            Some(5) => "5xx",
+            _ => "unknown",
+        };
+
+        let mut counters = self.lock();
+        let (requests, bytes) = counters.requests.entry((method, class)).or_default();
+        *requests += 1;
+        *bytes += entry.object_size.unwrap_or(0) as u64;
+        if let Some(t) = entry.time {
+            let t = t.timestamp_millis() as f64 / 1000.0;
+            let last = counters.last_timestamp.get_or_insert(t);
+            *last = last.max(t);
+        }
+    }
+
+    /// Count a line which couldn't be parsed.
+    pub fn observe_error(&self) {
+        self.lock().parse_errors += 1;
+    }
+
+    /// Count the entries appended to a log file as they're written, until it can't be read.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use common_log_format::{follow::Follow, prometheus::{Metrics, MetricsServer}};
+    ///
+    /// let metrics = Metrics::new();
+    /// MetricsServer::bind("0.0.0.0:9100", metrics.clone()).unwrap().spawn().unwrap();
+    /// metrics.follow(Follow::new("/var/log/nginx/access.log").unwrap()).unwrap();
+    /// ```
+    pub fn follow(&self, follow: Follow) -> io::Result<()> {
+        for entry in follow {
+            match entry {
+                Ok(entry) => self.observe(&entry),
+                Err(ReadError::Parse { .. }) => self.observe_error(),
+                Err(ReadError::Io(e)) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The metrics, in Prometheus's text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.lock();
+        let mut out = String::new();
+        out.push_str("# HELP http_requests_total Requests logged, by method and status class.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, class), (requests, _)) in &counters.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",class=\"{}\"}} {}",
+                method, class, requests
+            );
+        }
+        out.push_str(
+            "# HELP http_response_bytes_total Response bytes logged, by method and status class.\n",
+        );
+        out.push_str("# TYPE http_response_bytes_total counter\n");
+        for ((method, class), (_, bytes)) in &counters.requests {
+            let _ = writeln!(
+                out,
+                "http_response_bytes_total{{method=\"{}\",class=\"{}\"}} {}",
+                method, class, bytes
+            );
+        }
+        out.push_str("# HELP access_log_parse_errors_total Lines which failed to parse.\n");
+        out.push_str("# TYPE access_log_parse_errors_total counter\n");
+        let _ = writeln!(
+            out,
+            "access_log_parse_errors_total {}",
+            counters.parse_errors
+        );
+        if let Some(t) = counters.last_timestamp {
+            out.push_str(
+                "# HELP access_log_last_timestamp_seconds The time of the latest entry logged.\n",
+            );
+            out.push_str("# TYPE access_log_last_timestamp_seconds gauge\n");
+            let _ = writeln!(out, "access_log_last_timestamp_seconds {}", t);
+        }
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Sink for Metrics {
+    type Error = Infallible;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        self.observe(entry);
+        Ok(())
+    }
+}
+
+/// Serves [`Metrics`] at `/metrics` over HTTP.
+///
+/// # Example
+/// ```
+/// use std::{io::{Read, Write}, net::TcpStream};
+/// use common_log_format::prometheus::{Metrics, MetricsServer};
+///
+/// let metrics = Metrics::new();
+/// let server = MetricsServer::bind("127.0.0.1:0", metrics.clone()).unwrap();
+/// let addr = server.local_addr().unwrap();
+/// server.spawn().unwrap();
+/// metrics.observe(&"10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 500 10".parse().unwrap());
+///
+/// let mut stream = TcpStream::connect(addr).unwrap();
+/// stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+/// let mut response = String::new();
+/// stream.read_to_string(&mut response).unwrap();
+/// assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+/// assert!(response.contains("http_requests_total{method=\"GET\",class=\"5xx\"} 1\n"));
+/// ```
+pub struct MetricsServer {
+    listener: TcpListener,
+    metrics: Metrics,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: impl ToSocketAddrs, metrics: Metrics) -> io::Result<Self> {
+        Ok(MetricsServer {
+            listener: TcpListener::bind(addr)?,
+            metrics,
+        })
+    }
+
+    /// The address the server is bound to, which is how to find the port when binding to port
+    /// 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answer requests until accepting connections fails. Errors on a connection close only
+    /// that connection.
+    pub fn run(self) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = self.respond(stream);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// [`MetricsServer::run`] on a thread of its own.
+    pub fn spawn(self) -> io::Result<JoinHandle<io::Result<()>>> {
+        thread::Builder::new()
+            .name("clf-metrics".to_owned())
+            .spawn(move || self.run())
+    }
+
+    /// Read a request from `stream`, ignoring its headers, and answer it.
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut head = BufReader::new(&stream).take(8192);
+        let mut request_line = String::new();
+        head.read_line(&mut request_line)?;
+        let mut header = String::new();
+        while head.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) if target.split('?').next() == Some("/metrics") => {
+                ("200 OK", self.metrics.render())
+            }
+            (Some("GET"), Some(_)) => ("404 Not Found", "not found\n".to_owned()),
+            _ => ("405 Method Not Allowed", "method not allowed\n".to_owned()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\n\
+             Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
@@ -0,0 +1,140 @@
+//! Lines prefixed with a PROXY protocol header.
+//!
+//! When a TCP proxy such as HAProxy sits in front of the web server, the `host` field of each
+//! entry is the proxy's address. Some setups log the [PROXY protocol] (version 1) header the
+//! proxy sent in front of each line, which carries the real client address:
+//!
+//! ```text
+//! PROXY TCP4 203.0.113.7 10.0.0.2 51234 443 10.0.0.1 - - [...] "GET / HTTP/1.1" 200 512
+//! ```
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{net::IpAddr, str::FromStr};
+
+use crate::{peel_ip, LogEntry, LogEntryParseError};
+
+/// The addresses from a `PROXY TCP4` or `PROXY TCP6` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProxyHeader {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub source_port: u16,
+    pub destination_port: u16,
+}
+
+/// Take a PROXY protocol version 1 header from the start of `line`.
+///
+/// Return None (and the unchanged line) if there is no header, and None (and the remainder) for
+/// a `PROXY UNKNOWN` header, which proxies send when they don't know the client address. The
+/// sender may follow `UNKNOWN` with addresses and ports, which are skipped with it.
+///
+/// # Example
+/// ```
+/// use common_log_format::proxy::peel_proxy_header;
+/// let line = "PROXY UNKNOWN 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512";
+/// let (header, rest) = peel_proxy_header(line).unwrap();
+/// assert_eq!(header, None);
+/// assert!(rest.starts_with("10.0.0.1 - -"));
+///
+/// let line = "PROXY UNKNOWN ::1 ::1 51234 443 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512";
+/// let (header, rest) = peel_proxy_header(line).unwrap();
+/// assert_eq!(header, None);
+/// assert!(rest.starts_with("10.0.0.1 - -"));
+/// ```
+pub fn peel_proxy_header(line: &str) -> Result<(Option<ProxyHeader>, &str), LogEntryParseError> {
+    let rest = match line.strip_prefix("PROXY ") {
+        Some(r) => r,
+        None => return Ok((None, line)),
+    };
+
+    let mut fields = rest.splitn(6, ' ');
+    match fields.next() {
+        Some("TCP4") | Some("TCP6") => (),
+        Some("UNKNOWN") => return Ok((None, skip_unknown(&rest["UNKNOWN".len()..]))),
+        _ => return Err(LogEntryParseError::FieldNotFound),
+    }
+
+    let mut next = || fields.next().ok_or(LogEntryParseError::FieldNotFound);
+    let (source, _) = peel_ip(next()?)?;
+    let (destination, _) = peel_ip(next()?)?;
+    let source_port = next()?.parse().map_err(LogEntryParseError::PortParse)?;
+    let destination_port = next()?.parse().map_err(LogEntryParseError::PortParse)?;
+    let remainder = next()?;
+
+    match (source, destination) {
+        (Some(source), Some(destination)) => Ok((
+            Some(ProxyHeader {
+                source,
+                destination,
+                source_port,
+                destination_port,
+            }),
+            remainder.trim_start(),
+        )),
+        _ => Err(LogEntryParseError::FieldNotFound),
+    }
+}
+
+/// Skip the rest of a `PROXY UNKNOWN` header. The spec has receivers ignore everything up to the
+/// header's CRLF, which is usually gone by the time it's logged, so skip whatever addresses and
+/// ports follow `UNKNOWN` instead.
+fn skip_unknown(rest: &str) -> &str {
+    if let Some((_, after)) = rest.split_once("\r\n") {
+        return after.trim_start();
+    }
+    let rest = rest.trim_start();
+    let mut fields = rest.splitn(5, ' ');
+    let addresses = (0..2).all(|_| fields.next().is_some_and(|f| f.parse::<IpAddr>().is_ok()));
+    let ports =
+        addresses && (0..2).all(|_| fields.next().is_some_and(|f| f.parse::<u16>().is_ok()));
+    match fields.next() {
+        Some(remainder) if ports => remainder.trim_start(),
+        _ => rest,
+    }
+}
+
+/// A [`LogEntry`], optionally preceded by a PROXY protocol header.
+///
+/// # Example
+/// ```
+/// use common_log_format::proxy::ProxiedLogEntry;
+/// let line = "PROXY TCP6 ::ffff:203.0.113.7 ::1 51234 443 10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let e: ProxiedLogEntry = line.parse().unwrap();
+/// assert_eq!(e.entry.host, Some("10.0.0.1".parse().unwrap()));
+/// // IPv4-mapped IPv6 addresses are reported as plain IPv4.
+/// assert_eq!(e.client_ip(), Some("203.0.113.7".parse().unwrap()));
+///
+/// // Lines without a header parse as usual.
+/// let line = "10.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let e: ProxiedLogEntry = line.parse().unwrap();
+/// assert_eq!(e.client_ip(), Some("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProxiedLogEntry {
+    pub proxy: Option<ProxyHeader>,
+    pub entry: LogEntry,
+}
+
+impl ProxiedLogEntry {
+    /// The address of the original client: the PROXY header's source address if there is one,
+    /// otherwise the entry's `host`.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.proxy
+            .map(|p| p.source)
+            .or(self.entry.host)
+            .map(|ip| ip.to_canonical())
+    }
+}
+
+impl FromStr for ProxiedLogEntry {
+    type Err = LogEntryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (proxy, remaining) = peel_proxy_header(s)?;
+        Ok(ProxiedLogEntry {
+            proxy,
+            entry: remaining.parse()?,
+        })
+    }
+}
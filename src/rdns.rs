@@ -0,0 +1,155 @@
+//! Resolving client addresses to hostnames.
+//!
+//! Hostnames make reports readable and identify crawlers, which mostly resolve to their
+//! operators' domains (`crawl-66-249-66-1.googlebot.com`). Logs repeat the same few addresses
+//! many times, so a [`ReverseDns`] keeps answers in an LRU cache, including the absence of one,
+//! and only asks DNS about each address once. Lookups run on tokio, at most a fixed number at a
+//! time so that a large batch does not flood the DNS server.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use hickory_resolver::{ResolveError, TokioResolver};
+use lru::LruCache;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::LogEntry;
+
+/// An entry with the hostname its `host` resolves to, if any.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ResolvedEntry {
+    pub entry: LogEntry,
+    pub hostname: Option<String>,
+}
+
+/// A caching reverse DNS resolver.
+///
+/// Cheap to clone; clones share the cache and the concurrency limit.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{rdns::ReverseDns, LogEntry};
+/// # async fn resolve(entries: Vec<LogEntry>) -> Result<(), Box<dyn std::error::Error>> {
+/// let rdns = ReverseDns::new()?.with_concurrency(16);
+/// for resolved in rdns.enrich(entries).await {
+///     println!("{:?} {:?}", resolved.entry.host, resolved.hostname);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ReverseDns {
+    resolver: TokioResolver,
+    cache: Arc<Mutex<LruCache<IpAddr, Option<String>>>>,
+    limit: Arc<Semaphore>,
+}
+
+impl ReverseDns {
+    /// A resolver using the system's DNS configuration, caching 10,000 addresses and making up to
+    /// 8 lookups at a time.
+    pub fn new() -> Result<Self, ResolveError> {
+        Ok(Self::with_resolver(TokioResolver::builder_tokio()?.build()))
+    }
+
+    pub fn with_resolver(resolver: TokioResolver) -> Self {
+        ReverseDns {
+            resolver,
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(10_000).unwrap(),
+            ))),
+            limit: Arc::new(Semaphore::new(8)),
+        }
+    }
+
+    /// Keep the answers for up to `capacity` addresses, dropping those used least recently.
+    pub fn with_cache_size(mut self, capacity: NonZeroUsize) -> Self {
+        self.cache = Arc::new(Mutex::new(LruCache::new(capacity)));
+        self
+    }
+
+    /// Make at most `concurrency` lookups at a time.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.limit = Arc::new(Semaphore::new(concurrency.max(1)));
+        self
+    }
+
+    /// The hostname `ip` resolves to, without the trailing dot.
+    ///
+    /// Addresses without a PTR record give `None`, and that answer is cached like any other.
+    /// Failed lookups, such as timeouts, also give `None` but are not cached, so they are retried
+    /// next time.
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let ip = ip.to_canonical();
+        if let Some(cached) = self.cache.lock().unwrap().get(&ip) {
+            return cached.clone();
+        }
+
+        let _permit = self
+            .limit
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        let hostname = match self.resolver.reverse_lookup(ip).await {
+            Ok(names) => names
+                .iter()
+                .next()
+                .map(|n| n.to_utf8().trim_end_matches('.').to_owned()),
+            Err(e) if e.is_no_records_found() => None,
+            Err(_) => return None,
+        };
+        self.cache.lock().unwrap().put(ip, hostname.clone());
+        hostname
+    }
+
+    /// The hostnames of each of `ips`, looked up concurrently.
+    pub async fn lookup_all(
+        &self,
+        ips: impl IntoIterator<Item = IpAddr>,
+    ) -> HashMap<IpAddr, Option<String>> {
+        let mut names = HashMap::new();
+        let mut lookups = JoinSet::new();
+        for ip in ips {
+            if names.contains_key(&ip) {
+                continue;
+            }
+            names.insert(ip, None);
+            let rdns = self.clone();
+            lookups.spawn(async move { (ip, rdns.lookup(ip).await) });
+        }
+        while let Some(done) = lookups.join_next().await {
+            let (ip, hostname) = done.expect("lookups do not panic");
+            names.insert(ip, hostname);
+        }
+        names
+    }
+
+    /// `entries` with the hostnames of their hosts, in the same order.
+    pub async fn enrich(&self, entries: Vec<LogEntry>) -> Vec<ResolvedEntry> {
+        let names = self.lookup_all(entries.iter().filter_map(|e| e.host)).await;
+        entries
+            .into_iter()
+            .map(|entry| ResolvedEntry {
+                hostname: entry.host.and_then(|h| names[&h].clone()),
+                entry,
+            })
+            .collect()
+    }
+
+    /// The number of addresses with cached answers.
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+}
+
+impl std::fmt::Debug for ReverseDns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReverseDns")
+            .field("cached", &self.cached())
+            .field("available_permits", &self.limit.available_permits())
+            .finish()
+    }
+}
@@ -0,0 +1,489 @@
+//! Reading entries from files and other byte streams.
+//!
+//! [`LogReader`] parses entries line by line from any [`BufRead`]. [`open_log`] opens a file,
+//! transparently decompressing it if it is compressed, which rotated logs usually are.
+//! Decompression for each format is behind a feature:
+//!
+//! | format | extension | feature |
+//! |--------|-----------|---------|
+//! | gzip   | `.gz`     | `gzip`  |
+//! | zstd   | `.zst`    | `zstd`  |
+//! | bzip2  | `.bz2`    | `bzip2` |
+//! | xz     | `.xz`     | `xz`    |
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    progress::{Progress, Tracker},
+    LogEntry, LogEntryParseError, ParseOptions,
+};
+
+/// An error reading a [`LogEntry`] from a stream.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// The line with (1-based) number `line` could not be parsed.
+    Parse {
+        line: u64,
+        error: LogEntryParseError,
+    },
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(_) => write!(f, "error reading log"),
+            Self::Parse { line, .. } => write!(f, "error parsing log entry on line {}", line),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(ref e) => Some(e),
+            Self::Parse { ref error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A stretch of a stream which held binary data rather than log lines, and was skipped.
+///
+/// Disk errors and writes cut short by a crash leave runs of NUL bytes or other binary data in
+/// log files, sometimes in front of a line which was written intact afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CorruptRegion {
+    /// The offset of the first byte skipped, as counted by [`LogReader::offset`].
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// What a [`LogReader`] found next in its stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadEvent {
+    Entry(LogEntry),
+    Corrupt(CorruptRegion),
+}
+
+/// An iterator over the entries in a stream of lines.
+///
+/// Blank lines are skipped. Lines do not need to be valid UTF-8; see [`LogEntry::from_bytes`].
+///
+/// Binary data at the start of a line, recognized by control characters and other non-text bytes
+/// making up much of it, is skipped too, up to the entry following it on the same line if there
+/// is one. To find out where it was, read with [`LogReader::events`] instead.
+///
+/// # Example
+/// ```
+/// use common_log_format::reader::LogReader;
+/// let log = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /a HTTP/1.0\" 200 1\n\
+///            \n\
+///            127.0.0.1 - - [1996-12-19T16:39:58-08:00] \"GET /b HTTP/1.0\" 200 2\n";
+/// let entries: Vec<_> = LogReader::new(log.as_bytes()).collect::<Result<_, _>>().unwrap();
+/// assert_eq!(entries.len(), 2);
+/// ```
+pub struct LogReader<R> {
+    inner: R,
+    opts: ParseOptions,
+    buf: Vec<u8>,
+    line: u64,
+    offset: u64,
+    /// The corrupt region being read, which ends at `offset`.
+    corrupt: Option<CorruptRegion>,
+    /// The entry read just after a corrupt region, to return after it.
+    queued: Option<Result<LogEntry, ReadError>>,
+    entries: u64,
+    parse_errors: u64,
+    progress: Option<Tracker>,
+}
+
+impl<R: BufRead> LogReader<R> {
+    pub fn new(inner: R) -> Self {
+        LogReader {
+            inner,
+            opts: ParseOptions::default(),
+            buf: Vec::new(),
+            line: 0,
+            offset: 0,
+            corrupt: None,
+            queued: None,
+            entries: 0,
+            parse_errors: 0,
+            progress: None,
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Call `callback` with how far reading has got every `interval`, while reading entries, and
+    /// once more at the end of the stream.
+    ///
+    /// # Example
+    /// ```
+    /// use std::{sync::{Arc, Mutex}, time::Duration};
+    /// use common_log_format::{progress::Progress, reader::LogReader};
+    ///
+    /// let log = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /a HTTP/1.0\" 200 1\n\
+    ///            garbage\n";
+    /// let last = Arc::new(Mutex::new(Progress::default()));
+    /// let reader = LogReader::new(log.as_bytes()).with_progress(Duration::from_secs(1), {
+    ///     let last = last.clone();
+    ///     move |p| *last.lock().unwrap() = *p
+    /// });
+    /// assert_eq!(reader.count(), 2);
+    ///
+    /// let last = *last.lock().unwrap();
+    /// assert_eq!((last.bytes, last.lines, last.entries, last.parse_errors), (74, 2, 1, 1));
+    /// ```
+    pub fn with_progress(
+        mut self,
+        interval: Duration,
+        callback: impl FnMut(&Progress) + Send + 'static,
+    ) -> Self {
+        self.progress = Some(Tracker::new(interval, callback));
+        self
+    }
+
+    /// The number of lines read so far.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// The number of bytes read so far (of decompressed data, for compressed input).
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read the next non-blank line, without its line terminator.
+    ///
+    /// Returns `None` at the end of the stream.
+    pub fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            self.buf.clear();
+            let n = self.inner.read_until(b'\n', &mut self.buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+
+            self.line += 1;
+            self.offset += n as u64;
+            let line = trim_line_end(&self.buf);
+            if !line.is_empty() {
+                let len = line.len();
+                return Ok(Some(&self.buf[..len]));
+            }
+        }
+    }
+
+    /// Read the next entry or corrupt region.
+    ///
+    /// Adjacent corrupt lines, and blank lines between them, are reported as one region.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::reader::{CorruptRegion, LogReader, ReadEvent};
+    /// let log = b"127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /a HTTP/1.0\" 200 1\n\
+    ///             \0\0\0\0\x01\xff\n\0\0\
+    ///             127.0.0.1 - - [1996-12-19T16:39:58-08:00] \"GET /b HTTP/1.0\" 200 2\n";
+    /// let events: Vec<ReadEvent> = LogReader::new(&log[..]).events().collect::<Result<_, _>>().unwrap();
+    /// assert_eq!(events.len(), 3);
+    /// assert_eq!(events[1], ReadEvent::Corrupt(CorruptRegion { offset: 66, length: 9 }));
+    /// assert!(matches!(&events[2], ReadEvent::Entry(e) if e.object_size == Some(2)));
+    ///
+    /// // A control character inside an entry doesn't make it binary data.
+    /// let log = b"127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET /\x1b[2J HTTP/1.0\" 200 1\n";
+    /// let events: Vec<ReadEvent> = LogReader::new(&log[..]).events().collect::<Result<_, _>>().unwrap();
+    /// assert!(matches!(&events[..], [ReadEvent::Entry(e)] if e.path() == Some("/\x1b[2J")));
+    /// ```
+    pub fn next_event(&mut self) -> Option<Result<ReadEvent, ReadError>> {
+        if let Some(queued) = self.queued.take() {
+            return Some(queued.map(ReadEvent::Entry));
+        }
+
+        let opts = self.opts;
+        loop {
+            self.buf.clear();
+            let n = match self.inner.read_until(b'\n', &mut self.buf) {
+                Ok(n) => n as u64,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if n == 0 {
+                let progress = self.progress_so_far();
+                if let Some(tracker) = &mut self.progress {
+                    tracker.finish(progress);
+                }
+                return self.corrupt.take().map(|r| Ok(ReadEvent::Corrupt(r)));
+            }
+
+            let start = self.offset;
+            self.line += 1;
+            self.offset += n;
+            let progress = self.progress_so_far();
+            if let Some(tracker) = &mut self.progress {
+                tracker.tick(progress);
+            }
+            let line = trim_line_end(&self.buf);
+            if line.is_empty() {
+                if let Some(r) = &mut self.corrupt {
+                    r.length += n;
+                }
+                continue;
+            }
+
+            let (garbage, entry) = parse_salvaged(line, &opts);
+            let corrupt_len = if entry.is_some() { garbage as u64 } else { n };
+            if corrupt_len > 0 {
+                let r = self.corrupt.get_or_insert(CorruptRegion {
+                    offset: start,
+                    length: 0,
+                });
+                r.length = start + corrupt_len - r.offset;
+            }
+
+            let entry = match entry {
+                Some(Ok(e)) => {
+                    self.entries += 1;
+                    Ok(e)
+                }
+                Some(Err(error)) => {
+                    self.parse_errors += 1;
+                    Err(ReadError::Parse {
+                        line: self.line,
+                        error,
+                    })
+                }
+                None => continue,
+            };
+            return match self.corrupt.take() {
+                Some(r) => {
+                    self.queued = Some(entry);
+                    Some(Ok(ReadEvent::Corrupt(r)))
+                }
+                None => Some(entry.map(ReadEvent::Entry)),
+            };
+        }
+    }
+
+    fn progress_so_far(&self) -> Progress {
+        Progress {
+            bytes: self.offset,
+            lines: self.line,
+            entries: self.entries,
+            parse_errors: self.parse_errors,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// An iterator over the entries and corrupt regions in the stream.
+    pub fn events(&mut self) -> impl Iterator<Item = Result<ReadEvent, ReadError>> + '_ {
+        std::iter::from_fn(move || self.next_event())
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_event()? {
+                Ok(ReadEvent::Entry(e)) => return Some(Ok(e)),
+                Ok(ReadEvent::Corrupt(_)) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Parse `line`, skipping any binary data in front of the entry.
+///
+/// Binary data starts the line with a byte which isn't text (a control character other than tab,
+/// or a byte outside ASCII), and runs to the last such byte which keeps at least a quarter of it
+/// non-text. A line which doesn't start so, or which has no entry after its binary data and
+/// is less than a quarter non-text overall, is parsed as it is, so that an entry with a stray control
+/// character in it is still an entry or a parse error.
+///
+/// Return the number of bytes skipped, and the entry, or `None` if the line is binary data with
+/// no entry after it.
+pub(crate) fn parse_salvaged(
+    line: &[u8],
+    opts: &ParseOptions,
+) -> (usize, Option<Result<LogEntry, LogEntryParseError>>) {
+    let non_text = |b: u8| (b.is_ascii_control() && b != b'\t') || b >= 0x7f;
+    if !line.first().is_some_and(|b| non_text(*b)) {
+        return (0, Some(LogEntry::from_bytes_with(line, opts)));
+    }
+
+    let (mut garbage, mut count) = (0, 0);
+    for (i, b) in line.iter().enumerate() {
+        if non_text(*b) {
+            count += 1;
+            if count * 4 > i {
+                garbage = i + 1;
+            }
+        }
+    }
+
+    let rest = line[garbage..].trim_ascii_start();
+    match LogEntry::from_bytes_with(rest, opts) {
+        Ok(e) => (line.len() - rest.len(), Some(Ok(e))),
+        Err(_) if count * 4 > line.len() => (line.len(), None),
+        Err(_) => (0, Some(LogEntry::from_bytes_with(line, opts))),
+    }
+}
+
+/// Identifies a file independently of its path, to notice when a path is pointed at a new file.
+///
+/// This is the device and inode number on Unix. Elsewhere there is no identity, and every file
+/// compares equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    pub fn of(metadata: &std::fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &std::fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+pub(crate) fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// A compression format recognized by [`open`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Guess the compression format from a file extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            Some("bz2") => Self::Bzip2,
+            Some("xz") => Self::Xz,
+            _ => Self::None,
+        }
+    }
+
+    /// Detect the compression format from the first few bytes of a file.
+    pub fn from_magic(header: &[u8]) -> Self {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if header.starts_with(b"BZh") {
+            Self::Bzip2
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Open the file at `path` for reading, decompressing it if necessary.
+///
+/// The format is detected from the file's contents, falling back to its extension.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut file = BufReader::new(File::open(&path)?);
+    let compression = match Compression::from_magic(file.fill_buf()?) {
+        Compression::None => Compression::from_path(&path),
+        c => c,
+    };
+    decompress(file, compression)
+}
+
+/// Wrap `inner` to decompress it as `compression`.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] if the feature for `compression` is not enabled.
+pub fn decompress<R>(inner: R, compression: Compression) -> io::Result<Box<dyn BufRead + Send>>
+where
+    R: BufRead + Send + 'static,
+{
+    match compression {
+        Compression::None => Ok(Box::new(inner)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(inner),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(inner)?,
+        ))),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Ok(Box::new(BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(inner),
+        ))),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Ok(Box::new(BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(inner),
+        ))),
+        #[allow(unreachable_patterns)]
+        c => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "reading {:?}-compressed input requires the matching feature",
+                c
+            ),
+        )),
+    }
+}
+
+/// Open the log file at `path`, decompressing it if necessary, and iterate over its entries.
+///
+/// # Example
+/// ```no_run
+/// for entry in common_log_format::reader::open_log("access.log.2.gz").unwrap() {
+///     println!("{:?}", entry.unwrap());
+/// }
+/// ```
+pub fn open_log(path: impl AsRef<Path>) -> io::Result<LogReader<Box<dyn BufRead + Send>>> {
+    Ok(LogReader::new(open(path)?))
+}
@@ -0,0 +1,177 @@
+//! Sending entries to Redis, and sharing rate limits between processes through it.
+//!
+//! [`RedisSink`] pushes entries, serialized as JSON, onto a list or a stream. [`RedisRateLimiter`]
+//! keeps per-key request counts in Redis, so that several ingestion processes enforce one quota
+//! between them.
+
+use chrono::{DateTime, Utc};
+use redis::{Connection, RedisResult};
+
+use crate::{sink::Sink, window::bucket_start, LogEntry};
+
+/// Where a [`RedisSink`] puts entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisTarget {
+    /// Append to the list at this key, with `RPUSH`.
+    List(String),
+    /// Add to the stream at this key, with `XADD`, as the field `entry`. If `max_len` is set,
+    /// the stream is trimmed to about that length.
+    Stream { key: String, max_len: Option<usize> },
+}
+
+/// A [`Sink`] which sends entries to Redis as JSON.
+///
+/// Entries are buffered and sent in batches (of 100 by default), and any left are sent when the
+/// sink is dropped. Call [`Sink::flush`] to send them sooner, or to see errors from the last
+/// batch.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{redis::{RedisSink, RedisTarget}, sink::Sink, LogEntry};
+/// let mut sink = RedisSink::open(
+///     "redis://127.0.0.1/",
+///     RedisTarget::Stream { key: "access".to_owned(), max_len: Some(100_000) },
+/// )
+/// .unwrap();
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// sink.send(&entry).unwrap();
+/// sink.flush().unwrap();
+/// ```
+pub struct RedisSink {
+    conn: Connection,
+    target: RedisTarget,
+    batch_size: usize,
+    batch: Vec<String>,
+}
+
+impl RedisSink {
+    /// Connect to the Redis server at `url`, such as `redis://127.0.0.1/`.
+    pub fn open(url: &str, target: RedisTarget) -> RedisResult<Self> {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        Ok(Self::new(conn, target))
+    }
+
+    pub fn new(conn: Connection, target: RedisTarget) -> Self {
+        RedisSink {
+            conn,
+            target,
+            batch_size: 100,
+            batch: Vec::new(),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl Sink for RedisSink {
+    type Error = redis::RedisError;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        self.batch
+            .push(serde_json::to_string(entry).expect("entries serialize to JSON"));
+        if self.batch.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        match &self.target {
+            RedisTarget::List(key) => redis::cmd("RPUSH")
+                .arg(key)
+                .arg(&self.batch)
+                .query::<()>(&mut self.conn)?,
+            RedisTarget::Stream { key, max_len } => {
+                let mut pipe = redis::pipe();
+                for json in &self.batch {
+                    let cmd = pipe.cmd("XADD").arg(key);
+                    if let Some(max_len) = max_len {
+                        cmd.arg("MAXLEN").arg("~").arg(*max_len);
+                    }
+                    cmd.arg("*").arg("entry").arg(json).ignore();
+                }
+                pipe.query::<()>(&mut self.conn)?
+            }
+        }
+
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+impl Drop for RedisSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A fixed-window rate limit whose counts are kept in Redis.
+///
+/// Each key may be seen `limit` times per `window` of log time; windows are aligned to the Unix
+/// epoch. Counts are stored under `{prefix}:{key}:{window start}` and expire once the window is
+/// over, so processes sharing a server and prefix share the limit.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::{redis::RedisRateLimiter, LogEntry};
+/// let conn = redis::Client::open("redis://127.0.0.1/").unwrap().get_connection().unwrap();
+/// let mut limiter = RedisRateLimiter::new(conn, "clf:rate", chrono::Duration::minutes(1), 600);
+///
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// let host = entry.host.unwrap().to_string();
+/// if !limiter.check(&host, entry.time.unwrap()).unwrap() {
+///     println!("{} is over its quota", host);
+/// }
+/// ```
+pub struct RedisRateLimiter {
+    conn: Connection,
+    prefix: String,
+    window: chrono::Duration,
+    limit: u64,
+}
+
+impl RedisRateLimiter {
+    pub fn new(
+        conn: Connection,
+        prefix: impl Into<String>,
+        window: chrono::Duration,
+        limit: u64,
+    ) -> Self {
+        RedisRateLimiter {
+            conn,
+            prefix: prefix.into(),
+            window,
+            limit,
+        }
+    }
+
+    /// Count one use by `key` at `at`, and return whether it is within the limit.
+    pub fn check(&mut self, key: &str, at: DateTime<Utc>) -> RedisResult<bool> {
+        Ok(self.hit(key, at)? <= self.limit)
+    }
+
+    /// Count one use by `key` at `at`, and return the number of uses in its window so far.
+    pub fn hit(&mut self, key: &str, at: DateTime<Utc>) -> RedisResult<u64> {
+        let start = bucket_start(at, self.window);
+        let redis_key = format!("{}:{}:{}", self.prefix, key, start.timestamp());
+        let ttl = self.window.num_seconds().max(1) + 1;
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .incr(&redis_key, 1)
+            .expire(&redis_key, ttl)
+            .ignore()
+            .query(&mut self.conn)?;
+        Ok(count)
+    }
+}
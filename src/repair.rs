@@ -0,0 +1,343 @@
+//! Salvaging entries from lines which were cut short or damaged.
+//!
+//! A server which crashes, or a log rotated while it was being written, leaves lines which
+//! stop partway: the final line of a file without its newline, or with its last few fields
+//! missing, sometimes behind a run of NUL bytes where the file was extended but never written.
+//! These lines fail to parse, but most of what's in them is still good. [`repair_line`] keeps
+//! the fields which were written in full and leaves the rest empty, and says what it guessed, so
+//! an analysis can decide whether to trust them. [`Repairer`] does the same for a whole log, and
+//! keeps a [`RepairReport`].
+//!
+//! Only damage at the ends of a line is repaired: a field which is wrong in the middle of a line
+//! is corruption rather than truncation, and the line is rejected as usual.
+
+use std::io::BufRead;
+
+use crate::{
+    bytes,
+    reader::{trim_line_end, ReadError},
+    LogEntry, LogEntryParseError, ParseOptions,
+};
+
+/// The fields of an entry, by name, in the order they're written.
+const FIELDS: [&str; 7] = [
+    "host",
+    "ident",
+    "authuser",
+    "time",
+    "request_line",
+    "status_code",
+    "object_size",
+];
+
+/// Something [`repair_line`] assumed to make a line parse.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Guess {
+    /// `len` bytes of binary data in front of the entry were dropped.
+    LeadingGarbage { len: usize },
+    /// The line ended before `field`, which was left empty.
+    Missing { field: &'static str },
+    /// `field` was cut off partway. A request line is kept as far as it goes; other fields are
+    /// left empty.
+    Truncated { field: &'static str },
+    /// The line had no newline, so the object size, written last, may have lost digits.
+    Unterminated,
+}
+
+/// An entry salvaged from a damaged line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repaired {
+    pub entry: LogEntry,
+    /// What was assumed, or nothing if the line was intact.
+    pub guesses: Vec<Guess>,
+}
+
+/// Parse `line`, salvaging what it can if the line was cut short or has binary data in front.
+/// `terminated` is whether the line ended with a newline, which only the last line of a log
+/// being written might not.
+///
+/// Fails if a field written in full doesn't parse, or if nothing could be salvaged.
+///
+/// # Example
+/// ```
+/// use common_log_format::repair::{repair_line, Guess};
+/// let line = b"\0\0\0203.0.113.9 - - [2024-05-01T13:00:00Z] \"GET /downloads/big.i";
+/// let repaired = repair_line(line, false).unwrap();
+/// assert_eq!(repaired.entry.request_line.as_deref(), Some("GET /downloads/big.i"));
+/// assert_eq!(repaired.entry.status_code, None);
+/// assert_eq!(repaired.guesses, [
+///     Guess::LeadingGarbage { len: 3 },
+///     Guess::Truncated { field: "request_line" },
+///     Guess::Missing { field: "status_code" },
+///     Guess::Missing { field: "object_size" },
+/// ]);
+///
+/// let intact = repair_line(b"203.0.113.9 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 5", true);
+/// assert!(intact.unwrap().guesses.is_empty());
+/// assert!(repair_line(b"203.0.113.9 - - [yesterday] \"GET / HTTP/1.1\" 200 5", true).is_err());
+/// ```
+pub fn repair_line(line: &[u8], terminated: bool) -> Result<Repaired, LogEntryParseError> {
+    repair_line_with(line, terminated, &ParseOptions::default())
+}
+
+/// [`repair_line`], with `opts`.
+pub fn repair_line_with(
+    line: &[u8],
+    terminated: bool,
+    opts: &ParseOptions,
+) -> Result<Repaired, LogEntryParseError> {
+    let mut guesses = Vec::new();
+    let is_binary = |b: &u8| (b.is_ascii_control() && *b != b'\t') || *b == 0x7f;
+    let mut rem = match line.iter().rposition(is_binary) {
+        Some(i) => {
+            let rest = line[i + 1..].trim_ascii_start();
+            guesses.push(Guess::LeadingGarbage {
+                len: line.len() - rest.len(),
+            });
+            rest
+        }
+        None => line,
+    };
+
+    let lossy = |b: &[u8]| String::from_utf8_lossy(b).into_owned();
+    let mut entry = LogEntry {
+        host: None,
+        ident: None,
+        authuser: None,
+        time: None,
+        request_line: None,
+        status_code: None,
+        object_size: None,
+    };
+    for (i, &field) in FIELDS.iter().enumerate() {
+        if rem.is_empty() {
+            guesses.push(Guess::Missing { field });
+            continue;
+        }
+        let truncated = Guess::Truncated { field };
+        rem = match i {
+            0 => {
+                let (host, r) = bytes::peel_ip(rem)?;
+                entry.host = host;
+                r
+            }
+            1 => {
+                let (ident, r) = bytes::peel_string(rem)?;
+                entry.ident = ident.map(lossy);
+                r
+            }
+            2 => {
+                let (authuser, r) = bytes::peel_string(rem)?;
+                entry.authuser = authuser.map(lossy);
+                r
+            }
+            3 => match bytes::peel_timestamp(rem) {
+                Ok((time, r)) => {
+                    entry.time = time;
+                    r
+                }
+                Err(_) if rem[0] == b'[' && !rem.contains(&b']') => {
+                    guesses.push(truncated);
+                    &[]
+                }
+                Err(e) => return Err(e),
+            },
+            4 => match bytes::peel_quoted_string(rem) {
+                Ok((request_line, r)) => {
+                    entry.request_line = request_line.map(lossy);
+                    r
+                }
+                Err(_) if rem[0] == b'"' => {
+                    entry.request_line = Some(lossy(&rem[1..]));
+                    guesses.push(truncated);
+                    &[]
+                }
+                Err(e) => return Err(e),
+            },
+            5 => match bytes::peel_status_code(rem) {
+                Ok((status_code, r)) => {
+                    entry.status_code = status_code;
+                    r
+                }
+                Err(_) if !rem.contains(&b' ') => {
+                    guesses.push(truncated);
+                    &[]
+                }
+                Err(e) => return Err(e),
+            },
+            _ => {
+                let (object_size, r) = if opts.size_thousands_separators {
+                    bytes::peel_grouped_usize(rem)?
+                } else {
+                    bytes::peel_usize(rem)?
+                };
+                entry.object_size = object_size;
+                if !terminated && object_size.is_some() && r.is_empty() {
+                    guesses.push(Guess::Unterminated);
+                }
+                r
+            }
+        };
+    }
+
+    let salvaged = entry.host.is_some()
+        || entry.time.is_some()
+        || entry.request_line.is_some()
+        || entry.status_code.is_some();
+    if !salvaged {
+        return Err(LogEntryParseError::FieldNotFound);
+    }
+    Ok(Repaired { entry, guesses })
+}
+
+/// A line which needed repairs, and what was guessed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RepairedLine {
+    /// The line number, counting from 1.
+    pub line: u64,
+    pub guesses: Vec<Guess>,
+}
+
+/// What a [`Repairer`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct RepairReport {
+    /// Lines read, not counting blank ones.
+    pub lines: u64,
+    /// Lines which parsed without repair.
+    pub intact: u64,
+    pub repaired: u64,
+    /// Lines which couldn't be repaired.
+    pub rejected: u64,
+    /// How many times each field was guessed missing or truncated, as `(field, count)` in the
+    /// order fields are written.
+    pub guessed_fields: Vec<(&'static str, u64)>,
+    /// Bytes of binary data dropped from in front of entries.
+    pub garbage_bytes: u64,
+    /// The first repaired lines, in order.
+    pub repairs: Vec<RepairedLine>,
+}
+
+/// Reads entries from a log, repairing damaged lines with [`repair_line`].
+///
+/// Only the first 100 repaired lines are listed in the report by default, though all are counted.
+///
+/// # Example
+/// ```
+/// use common_log_format::repair::Repairer;
+/// let log = b"203.0.113.9 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 512\n\
+///             203.0.113.9 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 2\n\
+///             203.0.113.9 - - [2024-05-01T13:00:02Z] \"GET /b HTTP/1.1\" 200 10";
+/// let mut repairer = Repairer::new(&log[..]);
+/// let entries: Vec<_> = repairer.by_ref().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(entries.len(), 3);
+///
+/// let report = repairer.report();
+/// assert_eq!((report.intact, report.repaired), (1, 2));
+/// assert_eq!(report.guessed_fields, [("status_code", 1), ("object_size", 2)]);
+/// assert_eq!(report.repairs[1].line, 3);
+/// ```
+pub struct Repairer<R> {
+    reader: R,
+    opts: ParseOptions,
+    max_repairs: usize,
+    buf: Vec<u8>,
+    line: u64,
+    report: RepairReport,
+}
+
+impl<R: BufRead> Repairer<R> {
+    pub fn new(reader: R) -> Self {
+        Repairer {
+            reader,
+            opts: ParseOptions::default(),
+            max_repairs: 100,
+            buf: Vec::new(),
+            line: 0,
+            report: RepairReport::default(),
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// List the first `max_repairs` repaired lines in the report.
+    pub fn with_max_repairs(mut self, max_repairs: usize) -> Self {
+        self.max_repairs = max_repairs;
+        self
+    }
+
+    pub fn report(&self) -> &RepairReport {
+        &self.report
+    }
+
+    fn record(&mut self, guesses: &[Guess]) {
+        if guesses.is_empty() {
+            self.report.intact += 1;
+            return;
+        }
+        self.report.repaired += 1;
+        for guess in guesses {
+            let field = match guess {
+                Guess::Missing { field } | Guess::Truncated { field } => *field,
+                Guess::LeadingGarbage { len } => {
+                    self.report.garbage_bytes += *len as u64;
+                    continue;
+                }
+                Guess::Unterminated => "object_size",
+            };
+            let counts = &mut self.report.guessed_fields;
+            match counts.iter_mut().find(|(f, _)| *f == field) {
+                Some((_, n)) => *n += 1,
+                None => {
+                    counts.push((field, 1));
+                    counts.sort_by_key(|(f, _)| FIELDS.iter().position(|x| x == f));
+                }
+            }
+        }
+        if self.report.repairs.len() < self.max_repairs {
+            self.report.repairs.push(RepairedLine {
+                line: self.line,
+                guesses: guesses.to_vec(),
+            });
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Repairer<R> {
+    type Item = Result<Repaired, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line += 1;
+            let terminated = self.buf.ends_with(b"\n");
+            let line = trim_line_end(&self.buf);
+            if line.is_empty() {
+                continue;
+            }
+
+            self.report.lines += 1;
+            return Some(match repair_line_with(line, terminated, &self.opts) {
+                Ok(repaired) => {
+                    self.record(&repaired.guesses);
+                    Ok(repaired)
+                }
+                Err(error) => {
+                    self.report.rejected += 1;
+                    Err(ReadError::Parse {
+                        line: self.line,
+                        error,
+                    })
+                }
+            });
+        }
+    }
+}
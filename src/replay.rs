@@ -0,0 +1,276 @@
+//! Replaying recorded traffic against a server.
+//!
+//! An archived access log is a record of real load: which paths were requested, how often, and
+//! in what bursts. A [`Replayer`] sends the same requests again, to a test server, at the same
+//! pace (or faster), and reports where the server now answers differently than it did then. That
+//! turns a log into a benchmark which can be rerun after every change.
+//!
+//! Requests are plain HTTP/1.1 over TCP, one connection each, with no body. Only `GET` and `HEAD`
+//! requests are replayed unless others are allowed, since a log doesn't record request bodies and
+//! replaying writes is rarely what's wanted.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+
+use crate::LogEntry;
+
+/// The URL given to [`Replayer::new`] isn't an `http://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUrl(pub String);
+
+impl Display for InvalidUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid base URL {:?}: expected http://host[:port][/path]",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidUrl {}
+
+/// The outcome of a replay.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Requests which got a response.
+    pub sent: u64,
+    /// Responses with the status code the log recorded.
+    pub matched: u64,
+    /// Responses with a different status code, counted by the logged and the new status.
+    pub mismatches: BTreeMap<(u16, u16), u64>,
+    /// Requests which failed to connect, timed out, or got an invalid response.
+    pub errors: u64,
+    /// Entries not replayed: without a request line, or with a method which isn't allowed.
+    pub skipped: u64,
+    /// How far behind schedule the latest request was sent. If this is large, the replay ran
+    /// slower than the original traffic, and needs more concurrency.
+    pub max_lag: Duration,
+}
+
+impl ReplayReport {
+    /// The number of responses whose status differed from the logged one.
+    pub fn mismatched(&self) -> u64 {
+        self.mismatches.values().sum()
+    }
+}
+
+/// Re-issues the requests in a log against a base URL.
+///
+/// Requests are sent at the times they were logged, relative to the first, divided by the speed
+/// up factor (1 by default). Entries without a time are sent straight after the one before.
+/// Requests are sent from a pool of threads (16 by default), so slow responses don't hold up the
+/// schedule unless every thread is waiting on one.
+///
+/// # Example
+/// ```
+/// use std::{io::{BufRead, BufReader, Write}, net::TcpListener};
+/// use common_log_format::{replay::Replayer, LogEntry};
+///
+/// // A server which only has `/a` now.
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let base = format!("http://{}", listener.local_addr().unwrap());
+/// std::thread::spawn(move || {
+///     for stream in listener.incoming() {
+///         let mut stream = stream.unwrap();
+///         let head: Vec<String> = BufReader::new(&stream)
+///             .lines()
+///             .map(Result::unwrap)
+///             .take_while(|l| !l.is_empty())
+///             .collect();
+///         let status = if head[0].starts_with("GET /a ") { "200 OK" } else { "404 Not Found" };
+///         write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+///     }
+/// });
+///
+/// let log = [
+///     "10.0.0.1 - - [2024-05-01T13:00:00.00Z] \"GET /a HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:00.05Z] \"GET /b HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:00.10Z] \"POST /a HTTP/1.1\" 201 10",
+/// ];
+/// let entries = log.iter().map(|l| l.parse::<LogEntry>().unwrap());
+/// let report = Replayer::new(&base).unwrap().run(entries);
+/// assert_eq!((report.sent, report.matched, report.skipped), (2, 1, 1));
+/// assert_eq!(report.mismatches.get(&(200, 404)), Some(&1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    /// The `host:port` to connect to, and the value of the `Host` header.
+    authority: String,
+    /// Prepended to every request target.
+    base_path: String,
+    speedup: f64,
+    concurrency: usize,
+    timeout: Duration,
+    all_methods: bool,
+}
+
+impl Replayer {
+    /// Replay against `base_url`, such as `http://staging.example.com:8080` or
+    /// `http://localhost/app`, whose path is prepended to every request's.
+    pub fn new(base_url: &str) -> Result<Self, InvalidUrl> {
+        let invalid = || InvalidUrl(base_url.to_owned());
+        let rest = base_url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Replayer {
+            authority: authority.to_owned(),
+            base_path: path.trim_end_matches('/').to_owned(),
+            speedup: 1.,
+            concurrency: 16,
+            timeout: Duration::from_secs(10),
+            all_methods: false,
+        })
+    }
+
+    /// Send requests `speedup` times faster than they were logged. `f64::INFINITY` sends them as
+    /// fast as possible.
+    pub fn with_speedup(mut self, speedup: f64) -> Self {
+        self.speedup = speedup;
+        self
+    }
+
+    /// Have up to `concurrency` requests in flight at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Give up on connecting, sending, or reading a response after `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Replay requests with every method, not only `GET` and `HEAD`. They are sent without a
+    /// body.
+    pub fn with_all_methods(mut self, all_methods: bool) -> Self {
+        self.all_methods = all_methods;
+        self
+    }
+
+    /// Replay `entries`, returning once every request has been answered or has failed.
+    pub fn run(&self, entries: impl IntoIterator<Item = LogEntry>) -> ReplayReport {
+        let (tx, rx) = mpsc::sync_channel::<(String, String, Option<StatusCode>)>(0);
+        let rx = Arc::new(Mutex::new(rx));
+        let report = Arc::new(Mutex::new(ReplayReport::default()));
+        let workers: Vec<_> = (0..self.concurrency)
+            .map(|_| {
+                let rx = Arc::clone(&rx);
+                let report = Arc::clone(&report);
+                let replayer = self.clone();
+                std::thread::spawn(move || loop {
+                    let job = rx.lock().unwrap().recv();
+                    let (method, target, expected) = match job {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    let result = replayer.send(&method, &target);
+                    let mut report = report.lock().unwrap();
+                    match (result, expected) {
+                        (Ok(actual), Some(expected)) if actual != expected => {
+                            report.sent += 1;
+                            *report
+                                .mismatches
+                                .entry((expected.as_u16(), actual.as_u16()))
+                                .or_default() += 1;
+                        }
+                        (Ok(_), expected) => {
+                            report.sent += 1;
+                            report.matched += expected.is_some() as u64;
+                        }
+                        (Err(_), _) => report.errors += 1,
+                    }
+                })
+            })
+            .collect();
+
+        let start = Instant::now();
+        let mut first: Option<DateTime<Utc>> = None;
+        let mut due = start;
+        for entry in entries {
+            let (method, target) = match entry.method().zip(entry.target()) {
+                Some((m, t)) if self.all_methods || matches!(m, "GET" | "HEAD") => {
+                    (m.to_owned(), t.to_owned())
+                }
+                _ => {
+                    report.lock().unwrap().skipped += 1;
+                    continue;
+                }
+            };
+
+            if let Some(t) = entry.time {
+                let since_first = (t - *first.get_or_insert(t)).to_std().unwrap_or_default();
+                if self.speedup.is_finite() {
+                    due = due.max(start + since_first.div_f64(self.speedup));
+                }
+            }
+            let now = Instant::now();
+            match due.checked_duration_since(now) {
+                Some(wait) => std::thread::sleep(wait),
+                None => {
+                    let mut report = report.lock().unwrap();
+                    report.max_lag = report.max_lag.max(now - due);
+                }
+            }
+
+            if tx.send((method, target, entry.status_code)).is_err() {
+                break;
+            }
+        }
+
+        drop(tx);
+        for w in workers {
+            let _ = w.join();
+        }
+        let report = report.lock().unwrap().clone();
+        report
+    }
+
+    /// Send one request and read the status code of the response.
+    fn send(&self, method: &str, target: &str) -> io::Result<StatusCode> {
+        let addr = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        // An absolute-form target (sent to a proxy) is replayed by its path.
+        let target = match target.find("://") {
+            Some(i) => target[i + 3..]
+                .find('/')
+                .map_or("/", |j| &target[i + 3 + j..]),
+            None => target,
+        };
+        write!(
+            stream,
+            "{} {}{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: common-log-format-replay\r\nConnection: close\r\n\r\n",
+            method, self.base_path, target, self.authority
+        )?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|code| StatusCode::from_bytes(code.as_bytes()).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid status line"))
+    }
+}
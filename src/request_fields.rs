@@ -0,0 +1,115 @@
+//! Recognizing the fields of a request among those an application's instrumentation logs.
+//!
+//! Logging libraries record a request as named values, which instrumentation names in a few
+//! common ways: after the CLF field (`status`, `bytes`), after the HTTP header (`referer`,
+//! `user_agent`), or after the OpenTelemetry semantic conventions (`http.response.status_code`,
+//! `http.response.body.size`). [`Fields`] collects the values under any of these names.
+
+use std::net::{IpAddr, SocketAddr};
+
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+
+use crate::{combined::CombinedLogEntry, LogEntry};
+
+/// A request's fields, recorded by name as an application's instrumentation logs them.
+#[derive(Debug, Default)]
+pub(crate) struct Fields {
+    pub time: Option<DateTime<Utc>>,
+    client: Option<IpAddr>,
+    ident: Option<String>,
+    user: Option<String>,
+    request_line: Option<String>,
+    method: Option<String>,
+    target: Option<String>,
+    version: Option<String>,
+    status: Option<u16>,
+    size: Option<usize>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Fields {
+    /// Whether the fields describe a request: whether they have a request line, method, or target.
+    pub fn is_request(&self) -> bool {
+        self.request_line.is_some() || self.method.is_some() || self.target.is_some()
+    }
+
+    /// Record the field `name` as `value`, if it's one of the names instrumentation commonly
+    /// gives a request's fields. Values which don't parse as the field's type are ignored.
+    pub fn set(&mut self, name: &str, value: String) {
+        match name {
+            "time" | "timestamp" => {
+                if let Ok(time) = DateTime::parse_from_rfc3339(&value) {
+                    self.time = Some(time.with_timezone(&Utc));
+                }
+            }
+            "client_addr" | "remote_addr" | "peer_addr" | "client.address" | "http.client_ip" => {
+                let ip = value
+                    .parse::<IpAddr>()
+                    .or_else(|_| value.parse::<SocketAddr>().map(|a| a.ip()));
+                if let Ok(ip) = ip {
+                    self.client = Some(ip.to_canonical());
+                }
+            }
+            "ident" => self.ident = Some(value),
+            "user" | "authuser" | "enduser.id" => self.user = Some(value),
+            "request" | "request_line" => self.request_line = Some(value),
+            "method" | "http.method" | "http.request.method" => self.method = Some(value),
+            "uri" | "url" | "target" | "path" | "http.target" | "http.uri" | "url.path" => {
+                self.target = Some(value)
+            }
+            "version" | "http.version" | "http.flavor" | "network.protocol.version" => {
+                self.version = Some(value)
+            }
+            "status" | "status_code" | "http.status_code" | "http.response.status_code" => {
+                let digits = value.split(|c: char| !c.is_ascii_digit()).next();
+                if let Some(status) = digits.and_then(|d| d.parse().ok()) {
+                    self.status = Some(status);
+                }
+            }
+            "bytes" | "size" | "http.response.body.size" | "http.response_content_length" => {
+                if let Ok(size) = value.parse() {
+                    self.size = Some(size);
+                }
+            }
+            "referer" | "http.referer" | "http.request.header.referer" => {
+                self.referer = Some(value)
+            }
+            "user_agent" | "http.user_agent" | "user_agent.original" => {
+                self.user_agent = Some(value)
+            }
+            _ => (),
+        }
+    }
+
+    /// The entry the fields describe. Its request line is the one recorded, or else one made of
+    /// the method, target, and version, with `-` for those missing and `HTTP/1.1` by default.
+    pub fn into_entry(self) -> CombinedLogEntry {
+        let request_line = self.request_line.unwrap_or_else(|| {
+            let version = self.version.map(|v| match v.starts_with("HTTP/") {
+                true => v,
+                false => format!("HTTP/{}", v),
+            });
+            format!(
+                "{} {} {}",
+                self.method.as_deref().unwrap_or("-"),
+                self.target.as_deref().unwrap_or("-"),
+                version.as_deref().unwrap_or("HTTP/1.1")
+            )
+        });
+        CombinedLogEntry {
+            entry: LogEntry {
+                host: self.client,
+                ident: self.ident,
+                authuser: self.user,
+                time: self.time,
+                request_line: Some(request_line),
+                status_code: self.status.and_then(|s| StatusCode::from_u16(s).ok()),
+                object_size: self.size,
+            },
+            referer: self.referer,
+            user_agent: self.user_agent,
+        }
+    }
+}
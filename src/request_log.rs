@@ -0,0 +1,77 @@
+//! What the access logging middleware for each web framework has in common.
+//!
+//! The middleware builds a [`Record`] of each request as it arrives, fills in its status and
+//! response size as the response goes out, and drops it once the response body has been sent,
+//! which writes its line.
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    combined::CombinedLogEntry,
+    duration::{DurationUnit, LoggedDuration},
+};
+
+/// Where lines are written, shared by every clone of a middleware.
+pub(crate) type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A request being answered, which is logged when it's dropped.
+pub(crate) struct Record {
+    writer: Writer,
+    duration: Option<DurationUnit>,
+    pub entry: CombinedLogEntry,
+    start: Instant,
+}
+
+impl Record {
+    /// Start timing a request, to be written to `writer` as `entry`, with how long it took in
+    /// `duration`, if that's set.
+    pub fn new(writer: &Writer, duration: Option<DurationUnit>, entry: CombinedLogEntry) -> Self {
+        Record {
+            writer: writer.clone(),
+            duration,
+            entry,
+            start: Instant::now(),
+        }
+    }
+
+    /// Count `len` more bytes of response body.
+    pub fn add_size(&mut self, len: usize) {
+        *self.entry.entry.object_size.get_or_insert(0) += len;
+    }
+}
+
+impl Drop for Record {
+    fn drop(&mut self) {
+        let line = match self.duration {
+            Some(unit) => {
+                let duration = LoggedDuration {
+                    duration: self.start.elapsed(),
+                    unit,
+                };
+                format!("{} {}\n", self.entry, duration)
+            }
+            None => format!("{}\n", self.entry),
+        };
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// The user name in an `Authorization` header value, if it's HTTP Basic authentication.
+pub(crate) fn basic_auth_user(authorization: &str) -> Option<String> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, _password) = decoded.split_once(':')?;
+    (!user.is_empty()).then(|| user.to_owned())
+}
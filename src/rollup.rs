@@ -0,0 +1,177 @@
+//! Per-interval request counts, error rates, and byte totals.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+
+use crate::LogEntry;
+
+/// The entries in one interval of a [`rollup`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Bucket {
+    /// The start of the interval, in the rollup's time zone.
+    pub start: DateTime<FixedOffset>,
+    pub requests: u64,
+    /// Entries with 4xx statuses.
+    pub client_errors: u64,
+    /// Entries with 5xx statuses.
+    pub server_errors: u64,
+    pub bytes: u64,
+}
+
+impl Bucket {
+    fn new(start: DateTime<FixedOffset>) -> Self {
+        Bucket {
+            start,
+            requests: 0,
+            client_errors: 0,
+            server_errors: 0,
+            bytes: 0,
+        }
+    }
+
+    /// The fraction of requests which failed with a 5xx status.
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.
+        } else {
+            self.server_errors as f64 / self.requests as f64
+        }
+    }
+}
+
+/// How to divide time into buckets for a rollup.
+///
+/// Buckets are `width` long, and are aligned to midnight in the time zone (UTC by default), plus
+/// `origin`. So with a width of a day, a time zone of `+05:30` and an origin of six hours, each
+/// bucket runs from 06:00 to 06:00 in India. Time zones are fixed offsets, so buckets do not follow
+/// daylight saving changes.
+///
+/// # Example
+/// ```
+/// use chrono::{Duration, FixedOffset};
+/// use common_log_format::{rollup::Rollup, LogEntry};
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T00:10:00Z] \"GET / HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T00:40:00Z] \"GET / HTTP/1.1\" 503 0",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let buckets = Rollup::new(Duration::hours(1))
+///     .with_timezone(FixedOffset::east_opt(5 * 3600 + 1800).unwrap())
+///     .run(&entries);
+/// // India is half an hour off from UTC, so the entries fall into two hourly buckets.
+/// assert_eq!(buckets.len(), 2);
+/// assert_eq!(buckets[0].start.to_rfc3339(), "2024-05-01T05:00:00+05:30");
+/// assert_eq!(buckets[1].error_rate(), 1.);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rollup {
+    pub width: chrono::Duration,
+    pub timezone: FixedOffset,
+    pub origin: chrono::Duration,
+    /// Whether to include empty buckets between the first and last entries.
+    pub fill_gaps: bool,
+}
+
+impl Rollup {
+    pub fn new(width: chrono::Duration) -> Self {
+        assert!(width.num_seconds() > 0, "buckets must be at least a second");
+        Rollup {
+            width,
+            timezone: FixedOffset::east_opt(0).unwrap(),
+            origin: chrono::Duration::zero(),
+            fill_gaps: true,
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_origin(mut self, origin: chrono::Duration) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_fill_gaps(mut self, fill_gaps: bool) -> Self {
+        self.fill_gaps = fill_gaps;
+        self
+    }
+
+    /// The start of the bucket containing `t`.
+    pub fn bucket_start(&self, t: DateTime<Utc>) -> DateTime<FixedOffset> {
+        let w = self.width.num_seconds();
+        let local = t.timestamp() + self.timezone.local_minus_utc() as i64;
+        let into = (local - self.origin.num_seconds()).rem_euclid(w);
+        self.timezone
+            .timestamp_opt(t.timestamp() - into, 0)
+            .unwrap()
+    }
+
+    /// Roll `entries` up into buckets, in time order. Entries without times are skipped.
+    pub fn run<'a>(&self, entries: impl IntoIterator<Item = &'a LogEntry>) -> Vec<Bucket> {
+        let mut buckets: BTreeMap<DateTime<FixedOffset>, Bucket> = BTreeMap::new();
+        for e in entries {
+            let t = match e.time {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let start = self.bucket_start(t);
+            let b = buckets.entry(start).or_insert_with(|| Bucket::new(start));
+            b.requests += 1;
+            b.bytes += e.object_size.unwrap_or(0) as u64;
+            match e.status_code {
+                Some(s) if s.is_client_error() => b.client_errors += 1,
+                Some(s) if s.is_server_error() => b.server_errors += 1,
+                _ => (),
+            }
+        }
+
+        if !self.fill_gaps || buckets.is_empty() {
+            return buckets.into_values().collect();
+        }
+
+        let first = *buckets.keys().next().unwrap();
+        let last = *buckets.keys().next_back().unwrap();
+        let mut filled = Vec::new();
+        let mut start = first;
+        while start <= last {
+            filled.push(buckets.remove(&start).unwrap_or_else(|| Bucket::new(start)));
+            start += self.width;
+        }
+        filled
+    }
+}
+
+/// Roll `entries` up into `width`-long buckets aligned to UTC midnight, with empty buckets
+/// filling any gaps. See [`Rollup`] for other alignments.
+///
+/// # Example
+/// ```
+/// use common_log_format::{rollup::rollup, LogEntry};
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET / HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:50Z] \"GET / HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:02:30Z] \"GET / HTTP/1.1\" 200 10",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let per_minute: Vec<u64> = rollup(&entries, chrono::Duration::minutes(1))
+///     .iter()
+///     .map(|b| b.requests)
+///     .collect();
+/// assert_eq!(per_minute, [2, 0, 1]);
+/// ```
+pub fn rollup<'a>(
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    width: chrono::Duration,
+) -> Vec<Bucket> {
+    Rollup::new(width).run(entries)
+}
@@ -0,0 +1,176 @@
+//! Reading a set of rotated log files as one stream.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    merge::{merge_by_time, MergeByTime},
+    pattern,
+    reader::{self, LogReader, ReadError},
+    LogEntry, ParseOptions,
+};
+
+const COMPRESSED_EXTENSIONS: [&str; 4] = [".gz", ".zst", ".bz2", ".xz"];
+
+/// Files this crate keeps next to a log, which match the log's pattern but aren't part of it:
+/// [`crate::index::LogIndex`], [`crate::checkpoint`], and `seal` files.
+const SIDECAR_EXTENSIONS: [&str; 3] = [".idx", ".checkpoint", ".seal"];
+
+type FileEntries = Box<dyn Iterator<Item = Result<LogEntry, ReadError>> + Send>;
+
+/// The entries of a rotated set of log files, merged into one stream in time order.
+///
+/// Files are found by matching a pattern such as `/var/log/nginx/access.log*`, where `*` may only
+/// appear in the file name. Index, checkpoint, and seal files kept next to the log, such as
+/// `access.log.idx`, are left out. If every file is either the live log or has a numeric
+/// rotation suffix (`access.log`, `access.log.1`, `access.log.2.gz`, ...), higher suffixes are
+/// taken to be older, as logrotate numbers them. Otherwise, such as with date suffixes, files are
+/// ordered by modification time. Compressed files are decompressed as by [`reader::open`].
+///
+/// All the files are read at once, and their entries merged as by [`merge_by_time`], so entries
+/// around a rotation which were logged out of order across files come out in order. Entries
+/// with the same time come from the older file first.
+///
+/// # Example
+/// ```no_run
+/// use common_log_format::rotated::RotatedLogReader;
+/// let reader = RotatedLogReader::new("/var/log/nginx/access.log*").unwrap();
+/// for entry in reader {
+///     println!("{:?}", entry.unwrap());
+/// }
+/// ```
+pub struct RotatedLogReader {
+    paths: Vec<PathBuf>,
+    /// The files being read, once reading has started.
+    merged: Option<MergeByTime<FileEntries>>,
+    opts: ParseOptions,
+}
+
+impl RotatedLogReader {
+    /// Read the files matching `pattern`.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{index::LogIndex, rotated::RotatedLogReader};
+    /// let dir = std::env::temp_dir().join(format!("clf-rotated-doctest-{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("access.log.1"), "\
+    /// 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10
+    /// 10.0.0.1 - - [2024-05-01T13:00:02Z] \"GET /c HTTP/1.1\" 200 10
+    /// ").unwrap();
+    /// // Logged on completion, after the rotation, but started before the last entry above.
+    /// std::fs::write(dir.join("access.log"), "\
+    /// 10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 200 10
+    /// 10.0.0.1 - - [2024-05-01T13:00:03Z] \"GET /d HTTP/1.1\" 200 10
+    /// ").unwrap();
+    /// // An index of the live log isn't part of the set.
+    /// LogIndex::build(dir.join("access.log"), 1024).unwrap().save(dir.join("access.log.idx")).unwrap();
+    ///
+    /// let reader = RotatedLogReader::new(dir.join("access.log*")).unwrap();
+    /// assert_eq!(reader.paths(), [dir.join("access.log.1"), dir.join("access.log")]);
+    /// let paths: Vec<_> = reader.map(|e| e.unwrap().path().unwrap().to_owned()).collect();
+    /// assert_eq!(paths, ["/a", "/b", "/c", "/d"]);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn new(pattern: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_paths(discover(pattern.as_ref())?))
+    }
+
+    /// Read `paths`, which are oldest first.
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        RotatedLogReader {
+            paths,
+            merged: None,
+            opts: ParseOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, opts: ParseOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// The files which make up the set, oldest first.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Open every file, or, for one which can't be opened, the error instead of its entries.
+    fn open(&self) -> MergeByTime<FileEntries> {
+        let opts = self.opts;
+        merge_by_time(self.paths.iter().map(|path| -> FileEntries {
+            match reader::open(path) {
+                Ok(r) => Box::new(LogReader::new(r).with_options(opts)),
+                Err(e) => Box::new(std::iter::once(Err(e.into()))),
+            }
+        }))
+    }
+}
+
+impl Iterator for RotatedLogReader {
+    type Item = Result<LogEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.merged.is_none() {
+            self.merged = Some(self.open());
+        }
+        self.merged.as_mut()?.next()
+    }
+}
+
+/// Find the files matching `pattern`, oldest first.
+pub fn discover(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let file_pattern = pattern
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file pattern"))?;
+    let dir = match pattern.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+
+    let mut found = vec![];
+    for dirent in fs::read_dir(dir)? {
+        let dirent = dirent?;
+        let name = dirent.file_name();
+        let name = match name.to_str() {
+            Some(n) if pattern::matches(file_pattern, n) && !is_sidecar(n) => n.to_owned(),
+            _ => continue,
+        };
+        if !dirent.file_type()?.is_file() {
+            continue;
+        }
+
+        let modified = dirent.metadata()?.modified()?;
+        found.push((
+            dirent.path(),
+            rotation_number(file_pattern, &name),
+            modified,
+        ));
+    }
+
+    if found.iter().filter(|(_, n, _)| n.is_none()).count() <= 1 {
+        // Sorts the live log (no suffix) last.
+        found.sort_by_key(|(_, n, _)| std::cmp::Reverse(n.map_or(-1, |n| n as i64)));
+    } else {
+        found.sort_by_key(|(_, _, m)| *m);
+    }
+
+    Ok(found.into_iter().map(|(p, _, _)| p).collect())
+}
+
+fn is_sidecar(name: &str) -> bool {
+    SIDECAR_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// The logrotate-style number suffix of `name`, ignoring any compression extension.
+fn rotation_number(file_pattern: &str, name: &str) -> Option<u32> {
+    let base = file_pattern.split('*').next().unwrap_or("");
+    let name = COMPRESSED_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name);
+    name.strip_prefix(base)?.strip_prefix('.')?.parse().ok()
+}
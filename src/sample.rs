@@ -0,0 +1,237 @@
+//! Downsampling streams of entries, reproducibly.
+//!
+//! Every sampler takes a seed, and the same seed picks the same entries from the same input, so
+//! a sample can be regenerated rather than stored. Choices are made by hashing, so they are
+//! reproducible across runs of one build, but may change with the Rust version.
+//!
+//! | sampler            | keeps                                                            |
+//! |--------------------|------------------------------------------------------------------|
+//! | [`sample_ratio`]   | each entry with the given probability                            |
+//! | [`sample_keys`]    | every entry of each key (e.g. client) with the given probability |
+//! | [`sample_per_key`] | up to `n` entries of every key, chosen uniformly                 |
+
+use std::{
+    collections::{hash_map::DefaultHasher, BinaryHeap, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::LogEntry;
+
+/// A number in `0..1` chosen by `seed` and `value`.
+fn uniform(seed: u64, value: impl Hash) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    value.hash(&mut hasher);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Keep each entry of `entries` with probability `ratio`, independently.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sample::sample_ratio, LogEntry};
+/// let line = "127.0.0.1 - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 2326";
+/// let entries = vec![line.parse::<LogEntry>().unwrap(); 10_000];
+///
+/// let n = sample_ratio(entries.iter().cloned(), 0.01).with_seed(7).count();
+/// assert!((50..150).contains(&n));
+/// assert_eq!(sample_ratio(entries.into_iter(), 0.01).with_seed(7).count(), n);
+/// ```
+pub fn sample_ratio<I>(entries: I, ratio: f64) -> SampleRatio<I::IntoIter>
+where
+    I: IntoIterator<Item = LogEntry>,
+{
+    SampleRatio {
+        entries: entries.into_iter(),
+        ratio,
+        seed: 0,
+        index: 0,
+    }
+}
+
+/// The iterator returned by [`sample_ratio`].
+#[derive(Debug, Clone)]
+pub struct SampleRatio<I> {
+    entries: I,
+    ratio: f64,
+    seed: u64,
+    index: u64,
+}
+
+impl<I> SampleRatio<I> {
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl<I: Iterator<Item = LogEntry>> Iterator for SampleRatio<I> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.entries.next()?;
+            self.index += 1;
+            if uniform(self.seed, self.index) < self.ratio {
+                return Some(entry);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.entries.size_hint().1)
+    }
+}
+
+/// Keep every entry of each key with probability `ratio`, so that the entries kept for a key are
+/// all of them.
+///
+/// Sampling clients (`|e| e.host`) rather than entries keeps their sessions whole. Whether a key
+/// is kept depends only on the key and the seed, so separate logs sampled with the same seed keep
+/// the same clients.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sample::sample_keys, LogEntry};
+/// let entries: Vec<LogEntry> = (0..1000)
+///     .map(|i| format!("10.0.{}.{} - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 1", i % 100 / 10, i % 10))
+///     .map(|l| l.parse().unwrap())
+///     .collect();
+///
+/// let sampled: Vec<LogEntry> = sample_keys(entries, 0.2, |e| e.host).with_seed(1).collect();
+/// let hosts: std::collections::HashSet<_> = sampled.iter().map(|e| e.host).collect();
+/// // Each of the 100 hosts made 10 requests, and those of the hosts kept are all kept.
+/// assert_eq!(sampled.len(), hosts.len() * 10);
+/// ```
+pub fn sample_keys<I, F, K>(entries: I, ratio: f64, key: F) -> SampleKeys<I::IntoIter, F>
+where
+    I: IntoIterator<Item = LogEntry>,
+    F: FnMut(&LogEntry) -> K,
+    K: Hash,
+{
+    SampleKeys {
+        entries: entries.into_iter(),
+        ratio,
+        key,
+        seed: 0,
+    }
+}
+
+/// The iterator returned by [`sample_keys`].
+#[derive(Debug, Clone)]
+pub struct SampleKeys<I, F> {
+    entries: I,
+    ratio: f64,
+    key: F,
+    seed: u64,
+}
+
+impl<I, F> SampleKeys<I, F> {
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl<I, F, K> Iterator for SampleKeys<I, F>
+where
+    I: Iterator<Item = LogEntry>,
+    F: FnMut(&LogEntry) -> K,
+    K: Hash,
+{
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.entries.next()?;
+            if uniform(self.seed, (self.key)(&entry)) < self.ratio {
+                return Some(entry);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.entries.size_hint().1)
+    }
+}
+
+/// Keep up to `n` entries of each key, chosen uniformly at random with `seed`, in their original
+/// order.
+///
+/// Keys with at most `n` entries are kept whole, so rare clients aren't lost among busy ones.
+/// Only the entries which may be kept are held in memory, but none are returned until `entries`
+/// ends.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sample::sample_per_key, LogEntry};
+/// let line = |host: &str, size: u32| -> LogEntry {
+///     format!("{} - - [1996-12-19T16:39:57-08:00] \"GET / HTTP/1.0\" 200 {}", host, size).parse().unwrap()
+/// };
+/// let entries = (0..100)
+///     .map(|i| line("10.0.0.1", i))
+///     .chain([line("10.0.0.2", 0)]);
+///
+/// let sampled = sample_per_key(entries, |e| e.host, 5, 42);
+/// assert_eq!(sampled.len(), 6);
+/// assert!(sampled.windows(2).take(4).all(|w| w[0].object_size < w[1].object_size));
+/// ```
+pub fn sample_per_key<I, F, K>(entries: I, mut key: F, n: usize, seed: u64) -> Vec<LogEntry>
+where
+    I: IntoIterator<Item = LogEntry>,
+    F: FnMut(&LogEntry) -> K,
+    K: Hash + Eq,
+{
+    // For each key, the `n` entries with the lowest priorities: a uniform sample.
+    let mut kept: HashMap<K, BinaryHeap<Prioritized>> = HashMap::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let priority = uniform(seed, index as u64);
+        let heap = kept.entry(key(&entry)).or_default();
+        if heap.len() < n {
+            heap.push(Prioritized {
+                priority,
+                index,
+                entry,
+            });
+        } else if heap.peek().is_some_and(|p| priority < p.priority) {
+            heap.pop();
+            heap.push(Prioritized {
+                priority,
+                index,
+                entry,
+            });
+        }
+    }
+
+    let mut sample: Vec<Prioritized> = kept.into_values().flatten().collect();
+    sample.sort_unstable_by_key(|p| p.index);
+    sample.into_iter().map(|p| p.entry).collect()
+}
+
+struct Prioritized {
+    priority: f64,
+    index: usize,
+    entry: LogEntry,
+}
+
+impl PartialEq for Prioritized {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Prioritized {}
+
+impl PartialOrd for Prioritized {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Prioritized {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then(self.index.cmp(&other.index))
+    }
+}
@@ -0,0 +1,209 @@
+//! Spotting hosts which probe for paths rather than browse.
+//!
+//! Vulnerability scanners and content discovery tools request long lists of paths which mostly do
+//! not exist: each path once, and mostly answered with 404. A [`ScannerDetector`] scores each host
+//! on just that, regardless of how fast it sends requests, so a slow scanner is found and a busy
+//! but well-behaved client is not. Floods of requests are a separate problem; see the `redis`
+//! module's rate limiter or [`crate::topk`] for those.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+
+use crate::LogEntry;
+
+/// A host whose requests look like probing.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScannerReport {
+    pub host: IpAddr,
+    pub requests: u64,
+    /// The number of different paths requested, counting each beyond the tracking limit as new.
+    pub distinct_paths: u64,
+    /// The fraction of requests answered with 404 Not Found or 410 Gone.
+    pub not_found_ratio: f64,
+    /// The entropy of the host's requests over the paths it requested, scaled to `0..=1`: 1 when
+    /// every request is for a different path, and 0 when they are all for the same one.
+    pub path_entropy: f64,
+    /// `not_found_ratio * path_entropy`.
+    pub score: f64,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct HostPaths {
+    requests: u64,
+    not_found: u64,
+    paths: HashMap<String, u64>,
+    /// Requests for paths not tracked because `paths` was full.
+    untracked: u64,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+impl HostPaths {
+    /// Shannon entropy of the path distribution divided by its maximum, `log2(requests)`.
+    /// Untracked requests are assumed to each be for a different path.
+    fn entropy(&self) -> f64 {
+        if self.requests < 2 {
+            return 0.;
+        }
+        let n = self.requests as f64;
+        let h: f64 = self
+            .paths
+            .values()
+            .map(|&c| c as f64 / n)
+            .map(|p| -p * p.log2())
+            .sum::<f64>()
+            + self.untracked as f64 * (n.log2() / n);
+        (h / n.log2()).clamp(0., 1.)
+    }
+}
+
+/// Scores hosts by how random their requested paths are and how many of them were not found.
+///
+/// A host is reported once it has made at least `min_requests` requests and its
+/// [`ScannerReport::score`] reaches the threshold. To bound memory, at most `max_paths` paths are
+/// kept per host.
+///
+/// # Example
+/// ```
+/// use common_log_format::{scanner::ScannerDetector, LogEntry};
+/// let mut detector = ScannerDetector::new();
+/// for p in ["/.env", "/wp-login.php", "/admin/config.php", "/.git/HEAD", "/phpmyadmin/",
+///           "/backup.zip", "/server-status", "/cgi-bin/test.cgi", "/xmlrpc.php", "/debug"] {
+///     let line = format!("203.0.113.9 - - [2024-05-01T13:00:00Z] \"GET {} HTTP/1.1\" 404 0", p);
+///     detector.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+/// for _ in 0..50 {
+///     let line = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /index.html HTTP/1.1\" 200 512";
+///     detector.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+///
+/// let reports = detector.reports();
+/// assert_eq!(reports.len(), 1);
+/// assert_eq!(reports[0].host.to_string(), "203.0.113.9");
+/// assert_eq!(reports[0].distinct_paths, 10);
+/// assert!(reports[0].score > 0.99);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScannerDetector {
+    min_requests: u64,
+    threshold: f64,
+    max_paths: usize,
+    hosts: HashMap<IpAddr, HostPaths>,
+}
+
+impl Default for ScannerDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScannerDetector {
+    /// A detector reporting hosts with at least 10 requests and a score of at least 0.5.
+    pub fn new() -> Self {
+        ScannerDetector {
+            min_requests: 10,
+            threshold: 0.5,
+            max_paths: 1024,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Don't report hosts with fewer than `min_requests` requests, whose scores mean little.
+    pub fn with_min_requests(mut self, min_requests: u64) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    /// Report hosts whose score is at least `threshold`, between 0 and 1.
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Keep at most `max_paths` distinct paths per host.
+    pub fn with_max_paths(mut self, max_paths: usize) -> Self {
+        self.max_paths = max_paths;
+        self
+    }
+
+    /// Count `entry` against its host. Entries without a host are ignored.
+    pub fn observe(&mut self, entry: &LogEntry) {
+        let host = match entry.host {
+            Some(h) => h,
+            None => return,
+        };
+        let h = self.hosts.entry(host).or_default();
+        h.requests += 1;
+        if matches!(entry.status_code.map(|s| s.as_u16()), Some(404 | 410)) {
+            h.not_found += 1;
+        }
+        if let Some(t) = entry.time {
+            h.first_seen = Some(h.first_seen.map_or(t, |f| f.min(t)));
+            h.last_seen = Some(h.last_seen.map_or(t, |l| l.max(t)));
+        }
+
+        let path = entry.path().unwrap_or("-");
+        if let Some(c) = h.paths.get_mut(path) {
+            *c += 1;
+        } else if h.paths.len() < self.max_paths {
+            h.paths.insert(path.to_owned(), 1);
+        } else {
+            h.untracked += 1;
+        }
+    }
+
+    /// The report for `host`, whether or not it would be reported as a scanner.
+    pub fn score(&self, host: IpAddr) -> Option<ScannerReport> {
+        let h = self.hosts.get(&host)?;
+        let not_found_ratio = h.not_found as f64 / h.requests as f64;
+        let path_entropy = h.entropy();
+        Some(ScannerReport {
+            host,
+            requests: h.requests,
+            distinct_paths: h.paths.len() as u64 + h.untracked,
+            not_found_ratio,
+            path_entropy,
+            score: not_found_ratio * path_entropy,
+            first_seen: h.first_seen,
+            last_seen: h.last_seen,
+        })
+    }
+
+    /// The hosts which look like scanners, highest score first.
+    pub fn reports(&self) -> Vec<ScannerReport> {
+        let mut reports: Vec<ScannerReport> = self
+            .hosts
+            .iter()
+            .filter(|(_, h)| h.requests >= self.min_requests)
+            .filter_map(|(host, _)| self.score(*host))
+            .filter(|r| r.score >= self.threshold)
+            .collect();
+        reports.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.host.cmp(&b.host)));
+        reports
+    }
+
+    /// Forget `host`, e.g. once it has been blocked.
+    pub fn remove(&mut self, host: IpAddr) {
+        self.hosts.remove(&host);
+    }
+
+    /// The number of hosts being tracked.
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+impl<'a> Extend<&'a LogEntry> for ScannerDetector {
+    fn extend<T: IntoIterator<Item = &'a LogEntry>>(&mut self, iter: T) {
+        for e in iter {
+            self.observe(e);
+        }
+    }
+}
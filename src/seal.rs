@@ -0,0 +1,369 @@
+//! Sealing log files against tampering, with rotating keys.
+//!
+//! A [`Sealer`] is fed a log's lines as they are written, and every so many lines produces a
+//! [`SealRecord`]: a checkpoint saying how far the log had got, with an HMAC-SHA256 over the lines
+//! since the previous record. Each MAC also covers the previous record's, so lines can't be
+//! changed, removed, or reordered, nor records dropped from the middle, without the MACs failing
+//! to verify. The records are kept beside the log, one per line, conventionally in `FILE.seal`.
+//!
+//! Sealing keys should be replaced from time to time, but an archive sealed years ago must still
+//! verify. So each record names the key which sealed it by an ID, and [`Sealer::rotate`] switches
+//! to a new key between records. A [`Keyring`] holds every key which may have been used, and
+//! [`Keyring::verify`] looks each record's key up by its ID.
+//!
+//! Lines after the last record aren't sealed yet; [`Keyring::verify`] reports how much of the log
+//! was.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A checkpoint in a sealed log: the first `line` lines, ending at byte `offset`, were sealed by
+/// the key named `key_id` with MAC `mac`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealRecord {
+    pub key_id: String,
+    pub line: u64,
+    pub offset: u64,
+    pub mac: [u8; 32],
+}
+
+impl Display for SealRecord {
+    /// The record as written to a seal file, e.g.
+    /// `key=2024-05 line=1000 offset=95310 mac=3fa0…`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key={} line={} offset={} mac=",
+            self.key_id, self.line, self.offset
+        )?;
+        self.mac.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// Read the records in a seal file, written one per line in the form [`SealRecord`] displays.
+pub fn load_seals(path: impl AsRef<Path>) -> io::Result<Vec<SealRecord>> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid seal record: {}", what),
+        )
+    };
+    let num = |v: &str| v.parse::<u64>().map_err(|_| invalid("bad number"));
+
+    let mut records = Vec::new();
+    for l in fs::read_to_string(path)?.lines() {
+        let (mut key_id, mut line, mut offset, mut mac) = (None, None, None, None);
+        for field in l.split(' ') {
+            match field.split_once('=') {
+                Some(("key", v)) => key_id = Some(v.to_owned()),
+                Some(("line", v)) => line = Some(num(v)?),
+                Some(("offset", v)) => offset = Some(num(v)?),
+                Some(("mac", v)) => mac = Some(parse_mac(v).ok_or_else(|| invalid("bad mac"))?),
+                _ => return Err(invalid(l)),
+            }
+        }
+        records.push(SealRecord {
+            key_id: key_id.ok_or_else(|| invalid("missing key"))?,
+            line: line.ok_or_else(|| invalid("missing line"))?,
+            offset: offset.ok_or_else(|| invalid("missing offset"))?,
+            mac: mac.ok_or_else(|| invalid("missing mac"))?,
+        });
+    }
+    Ok(records)
+}
+
+fn parse_mac(hex: &str) -> Option<[u8; 32]> {
+    let mut mac = [0; 32];
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    for (b, pair) in mac.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *b = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(mac)
+}
+
+/// Start the MAC of a segment following the record with MAC `prev`.
+fn start_segment(key: &[u8], prev: &[u8; 32]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(prev);
+    mac
+}
+
+/// Finish the MAC of a segment, binding it to where it ends and the key which sealed it.
+fn end_segment(mut mac: HmacSha256, key_id: &str, line: u64, offset: u64) -> HmacSha256 {
+    mac.update(key_id.as_bytes());
+    mac.update(&[0]);
+    mac.update(&line.to_be_bytes());
+    mac.update(&offset.to_be_bytes());
+    mac
+}
+
+/// Produces [`SealRecord`]s for a log as its lines are written.
+///
+/// # Example
+/// ```
+/// use common_log_format::seal::{Keyring, Sealer};
+/// let log = b"10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10\n\
+///             10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 200 10\n\
+///             10.0.0.1 - - [2024-05-01T13:00:02Z] \"GET /c HTTP/1.1\" 200 10\n";
+/// let lines: Vec<&[u8]> = log.split_inclusive(|&b| b == b'\n').collect();
+///
+/// let mut sealer = Sealer::new("2024-04", b"april key").with_interval(2);
+/// let mut records: Vec<_> = lines[..2].iter().filter_map(|l| sealer.update(l)).collect();
+/// // The key is rotated, and the last line sealed with the new one.
+/// records.extend(sealer.rotate("2024-05", b"may key"));
+/// records.extend(sealer.update(lines[2]));
+/// records.extend(sealer.finish());
+/// assert_eq!(records.iter().map(|r| r.key_id.as_str()).collect::<Vec<_>>(), ["2024-04", "2024-05"]);
+///
+/// let keyring = Keyring::new().with_key("2024-04", b"april key").with_key("2024-05", b"may key");
+/// assert_eq!(keyring.verify(&log[..], &records).unwrap(), 3);
+///
+/// // Without the retired key, the older part of the archive can't be verified.
+/// let current = Keyring::new().with_key("2024-05", b"may key");
+/// assert!(current.verify(&log[..], &records).is_err());
+///
+/// let mut tampered = log.to_vec();
+/// tampered[60] = b'5';
+/// assert!(keyring.verify(&tampered[..], &records).is_err());
+/// ```
+pub struct Sealer {
+    key_id: String,
+    key: Vec<u8>,
+    /// The MAC of the open segment, started from `prev`.
+    mac: HmacSha256,
+    /// The MAC of the last record.
+    prev: [u8; 32],
+    interval: u64,
+    /// Lines since the last record.
+    pending: u64,
+    line: u64,
+    offset: u64,
+}
+
+impl fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sealer")
+            .field("key_id", &self.key_id)
+            .field("interval", &self.interval)
+            .field("line", &self.line)
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sealer {
+    /// Seal a log from its start with `key`, recorded as `key_id`, writing a record every 1,000
+    /// lines.
+    ///
+    /// # Panics
+    /// If `key_id` is empty or contains whitespace or `=`, which seal files can't hold.
+    pub fn new(key_id: impl Into<String>, key: impl AsRef<[u8]>) -> Self {
+        let key_id = check_key_id(key_id.into());
+        let key = key.as_ref().to_vec();
+        Sealer {
+            mac: start_segment(&key, &[0; 32]),
+            key_id,
+            key,
+            prev: [0; 32],
+            interval: 1000,
+            pending: 0,
+            line: 0,
+            offset: 0,
+        }
+    }
+
+    /// Write a record every `lines` lines.
+    pub fn with_interval(mut self, lines: u64) -> Self {
+        self.interval = lines.max(1);
+        self
+    }
+
+    /// Add the next line of the log, with its line ending, returning a record if one is due.
+    pub fn update(&mut self, line: &[u8]) -> Option<SealRecord> {
+        self.mac.update(line);
+        self.pending += 1;
+        self.line += 1;
+        self.offset += line.len() as u64;
+        (self.pending >= self.interval).then(|| self.seal())
+    }
+
+    /// Seal the lines since the last record with the current key, if there are any, and use
+    /// `key`, recorded as `key_id`, from now on.
+    ///
+    /// # Panics
+    /// As for [`Sealer::new`].
+    pub fn rotate(
+        &mut self,
+        key_id: impl Into<String>,
+        key: impl AsRef<[u8]>,
+    ) -> Option<SealRecord> {
+        let record = (self.pending > 0).then(|| self.seal());
+        self.key_id = check_key_id(key_id.into());
+        self.key = key.as_ref().to_vec();
+        self.mac = start_segment(&self.key, &self.prev);
+        record
+    }
+
+    /// Seal the lines since the last record, if there are any.
+    pub fn finish(mut self) -> Option<SealRecord> {
+        (self.pending > 0).then(|| self.seal())
+    }
+
+    fn seal(&mut self) -> SealRecord {
+        let mac = std::mem::replace(&mut self.mac, start_segment(&self.key, &[0; 32]));
+        let mac: [u8; 32] = end_segment(mac, &self.key_id, self.line, self.offset)
+            .finalize()
+            .into_bytes()
+            .into();
+        self.mac = start_segment(&self.key, &mac);
+        self.prev = mac;
+        self.pending = 0;
+        SealRecord {
+            key_id: self.key_id.clone(),
+            line: self.line,
+            offset: self.offset,
+            mac,
+        }
+    }
+}
+
+fn check_key_id(key_id: String) -> String {
+    assert!(
+        !key_id.is_empty() && !key_id.contains(|c: char| c.is_whitespace() || c == '='),
+        "invalid sealing key ID {:?}",
+        key_id
+    );
+    key_id
+}
+
+/// The keys which may have sealed a log, by ID.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept records sealed by `key` under `key_id`.
+    pub fn with_key(mut self, key_id: impl Into<String>, key: impl AsRef<[u8]>) -> Self {
+        self.keys.insert(key_id.into(), key.as_ref().to_vec());
+        self
+    }
+
+    /// Check the log read from `log` against its seal `records`, in order, returning the number
+    /// of lines sealed. Anything after the last record isn't checked.
+    pub fn verify<'a>(
+        &self,
+        mut log: impl Read,
+        records: impl IntoIterator<Item = &'a SealRecord>,
+    ) -> Result<u64, VerifyError> {
+        let (mut prev, mut line, mut offset) = ([0; 32], 0, 0);
+        let mut buf = vec![0; 64 * 1024];
+        for record in records {
+            let key = self
+                .keys
+                .get(&record.key_id)
+                .ok_or_else(|| VerifyError::UnknownKey {
+                    key_id: record.key_id.clone(),
+                    line: record.line,
+                })?;
+            if record.offset < offset || record.line < line {
+                return Err(VerifyError::Mismatch { line: record.line });
+            }
+
+            let mut mac = start_segment(key, &prev);
+            let mut remaining = record.offset - offset;
+            while remaining > 0 {
+                let want = remaining.min(buf.len() as u64) as usize;
+                let n = log.read(&mut buf[..want])?;
+                if n == 0 {
+                    return Err(VerifyError::Truncated { line: record.line });
+                }
+                mac.update(&buf[..n]);
+                line += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+                remaining -= n as u64;
+            }
+            if line != record.line {
+                return Err(VerifyError::Mismatch { line: record.line });
+            }
+            end_segment(mac, &record.key_id, record.line, record.offset)
+                .verify_slice(&record.mac)
+                .map_err(|_| VerifyError::Mismatch { line: record.line })?;
+
+            (prev, offset) = (record.mac, record.offset);
+        }
+        Ok(line)
+    }
+}
+
+/// Why a sealed log failed to verify.
+#[derive(Debug)]
+pub enum VerifyError {
+    Io(io::Error),
+    /// The record ending at (1-based) line `line` was sealed by a key not in the keyring.
+    UnknownKey {
+        key_id: String,
+        line: u64,
+    },
+    /// The lines up to `line` don't match the record ending there: they, or an earlier record,
+    /// were changed.
+    Mismatch {
+        line: u64,
+    },
+    /// The log ends before the record ending at `line`.
+    Truncated {
+        line: u64,
+    },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(_) => write!(f, "error reading log"),
+            Self::UnknownKey { key_id, line } => {
+                write!(f, "unknown sealing key {:?} at line {}", key_id, line)
+            }
+            Self::Mismatch { line } => write!(f, "seal does not match log at line {}", line),
+            Self::Truncated { line } => write!(f, "log truncated before line {}", line),
+        }
+    }
+}
+
+impl Error for VerifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VerifyError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
@@ -0,0 +1,256 @@
+//! Spotting attacks in request lines.
+//!
+//! Most of the hostile traffic a public server sees is automated, and looks it: requests climbing
+//! out of the document root with `../`, query strings carrying SQL, probes for `/.env` and
+//! `/wp-login.php` on servers which have never run PHP, and methods no browser sends. A
+//! [`Detector`] recognizes these by their text and returns what it found as [`Finding`]s, to
+//! filter on, count, or feed to a blocklist.
+//!
+//! Matching is done on the request target after percent-decoding it twice (to see through
+//! double encoding) and lowercasing it, with runs of whitespace and SQL comments collapsed to
+//! single spaces. It flags what requests attempt, not whether they succeeded; pair it with the
+//! status code to tell the two apart.
+
+use std::{collections::HashSet, fmt::Display};
+
+use crate::{privacy::percent_decode, LogEntry};
+
+/// The methods a [`Detector`] allows by default.
+const METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+/// Decoded path fragments which climb out of the document root or name system files.
+const TRAVERSAL: &[&str] = &[
+    "../",
+    "..\\",
+    "/..",
+    "\\..",
+    "/etc/passwd",
+    "/etc/shadow",
+    "/proc/self/",
+    "win.ini",
+    "boot.ini",
+    "c:\\windows",
+];
+
+/// Decoded, normalized fragments of SQL injection probes.
+const SQL_INJECTION: &[&str] = &[
+    "union select",
+    "union all select",
+    "' or '1'='1",
+    "' or 1=1",
+    "\" or \"1\"=\"1",
+    "\" or 1=1",
+    " or 1=1",
+    "' and 1=1",
+    "' and 1=2",
+    "'--",
+    "';",
+    "sleep(",
+    "pg_sleep(",
+    "benchmark(",
+    "waitfor delay",
+    "information_schema",
+    "xp_cmdshell",
+    "@@version",
+    "extractvalue(",
+    "updatexml(",
+    "load_file(",
+    "into outfile",
+];
+
+/// Paths, lowercased, which scanners probe for and sites rarely serve. Each matches the start of
+/// a path, or of any segment of it if it starts with `/`.
+const PROBE_PATHS: &[&str] = &[
+    "/.env",
+    "/.git/",
+    "/.svn/",
+    "/.hg/",
+    "/.ds_store",
+    "/.htaccess",
+    "/.htpasswd",
+    "/.aws/",
+    "/.ssh/",
+    "/wp-login.php",
+    "/xmlrpc.php",
+    "/wp-config.php",
+    "/phpmyadmin",
+    "/phpinfo.php",
+    "/vendor/phpunit/",
+    "/cgi-bin/",
+    "/server-status",
+    "/actuator/",
+    "/manager/html",
+    "/solr/admin",
+    "/boaform/",
+    "/hnap1",
+    "/owa/auth/",
+    "/web.config",
+];
+
+/// User agent fragments, lowercased, of scanning tools which announce themselves.
+const SCANNER_AGENTS: &[&str] = &[
+    "sqlmap",
+    "nikto",
+    "nmap",
+    "masscan",
+    "zgrab",
+    "nuclei",
+    "wpscan",
+    "dirbuster",
+    "gobuster",
+    "ffuf",
+    "acunetix",
+    "nessus",
+    "openvas",
+    "w3af",
+];
+
+/// A kind of attack a request looks like.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Finding {
+    /// Climbing out of the document root, or asking for system files.
+    PathTraversal,
+    /// SQL in the target.
+    SqlInjection,
+    /// Probing for files and admin pages commonly left exposed, a proxy, or announcing a scanning
+    /// tool.
+    ScannerProbe,
+    /// A method which isn't allowed, or a request line which isn't HTTP at all.
+    UnusualMethod,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Finding::PathTraversal => "path_traversal",
+            Finding::SqlInjection => "sql_injection",
+            Finding::ScannerProbe => "scanner_probe",
+            Finding::UnusualMethod => "unusual_method",
+        })
+    }
+}
+
+/// Checks request lines for the signs of attacks listed in [`Finding`].
+///
+/// # Example
+/// ```
+/// use common_log_format::{security::{Detector, Finding}, LogEntry};
+/// let detector = Detector::default();
+/// let findings = |request: &str| {
+///     let line = format!("203.0.113.9 - - [2024-05-01T13:00:00Z] \"{}\" 404 0", request);
+///     detector.detect(&line.parse::<LogEntry>().unwrap())
+/// };
+///
+/// assert_eq!(findings("GET /static/%252e%252e/%252e%252e/etc/passwd HTTP/1.1"), [Finding::PathTraversal]);
+/// assert_eq!(findings("GET /item?id=1%27%20UNION/**/SELECT%20password HTTP/1.1"), [Finding::SqlInjection]);
+/// assert_eq!(findings("GET /.git/config HTTP/1.1"), [Finding::ScannerProbe]);
+/// assert_eq!(findings("PROPFIND / HTTP/1.1"), [Finding::UnusualMethod]);
+/// assert_eq!(findings("GET /search?q=union+station HTTP/1.1"), []);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detector {
+    methods: HashSet<String>,
+    probe_paths: Vec<String>,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Detector {
+            methods: METHODS.iter().map(|m| m.to_string()).collect(),
+            probe_paths: PROBE_PATHS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+impl Detector {
+    /// Allow `method`, as well as the default `GET`, `HEAD`, `POST`, `PUT`, `DELETE`, `OPTIONS`,
+    /// and `PATCH`: `PROPFIND` for a WebDAV server, say.
+    pub fn with_allowed_method(mut self, method: &str) -> Self {
+        self.methods.insert(method.to_owned());
+        self
+    }
+
+    /// Also count requests for paths starting with `prefix` (or with a segment starting with it,
+    /// if it starts with `/`) as probes. Matching ignores case.
+    pub fn with_probe_path(mut self, prefix: &str) -> Self {
+        self.probe_paths.push(prefix.to_lowercase());
+        self
+    }
+
+    /// What `entry`'s request line looks like, in the order of [`Finding`]. Entries without a
+    /// request line have none.
+    pub fn detect(&self, entry: &LogEntry) -> Vec<Finding> {
+        entry
+            .request_line
+            .as_deref()
+            .map_or_else(Vec::new, |r| self.detect_request_line(r))
+    }
+
+    /// [`Detector::detect`], also counting a scanning tool's `user_agent` as a probe.
+    pub fn detect_with_user_agent(
+        &self,
+        entry: &LogEntry,
+        user_agent: Option<&str>,
+    ) -> Vec<Finding> {
+        let mut findings = self.detect(entry);
+        let ua = user_agent.unwrap_or("").to_lowercase();
+        if SCANNER_AGENTS.iter().any(|s| ua.contains(s))
+            && !findings.contains(&Finding::ScannerProbe)
+        {
+            findings.push(Finding::ScannerProbe);
+            findings.sort();
+        }
+        findings
+    }
+
+    /// What a request line looks like, in the order of [`Finding`].
+    pub fn detect_request_line(&self, request_line: &str) -> Vec<Finding> {
+        let mut parts = request_line.split(' ');
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let is_http = parts.next().is_some_and(|v| v.starts_with("HTTP/"));
+
+        let decoded = normalize(&percent_decode(&percent_decode(target)));
+        let path = decoded.split('?').next().unwrap_or("");
+        let mut findings = Vec::new();
+        if TRAVERSAL.iter().any(|t| decoded.contains(t)) {
+            findings.push(Finding::PathTraversal);
+        }
+        if SQL_INJECTION.iter().any(|s| decoded.contains(s)) {
+            findings.push(Finding::SqlInjection);
+        }
+        let is_proxy = method == "CONNECT" || (!target.starts_with('/') && target.contains("://"));
+        if is_proxy || self.probe_paths.iter().any(|p| is_probe(path, p)) {
+            findings.push(Finding::ScannerProbe);
+        }
+        if !is_http || !self.methods.contains(method) {
+            findings.push(Finding::UnusualMethod);
+        }
+        findings
+    }
+}
+
+/// Lowercase `s`, and collapse SQL comments and runs of whitespace to single spaces.
+fn normalize(s: &str) -> String {
+    let s = s.to_lowercase().replace("/**/", " ");
+    let mut out = String::with_capacity(s.len());
+    for word in s.split_whitespace() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+/// Whether `path` starts with `prefix`, or has a segment which does if `prefix` starts with `/`.
+fn is_probe(path: &str, prefix: &str) -> bool {
+    match prefix.strip_prefix('/') {
+        Some(rest) => path
+            .match_indices('/')
+            .any(|(i, _)| path[i + 1..].starts_with(rest)),
+        None => path.starts_with(prefix),
+    }
+}
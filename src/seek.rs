@@ -0,0 +1,120 @@
+//! Finding a point in time in a large log file without reading all of it.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    reader::{trim_line_end, LogReader, ReadError},
+    LogEntry, LogEntryRef,
+};
+
+/// Move `r` to the start of the first line with a time at or after `target`, and return its
+/// offset.
+///
+/// The log must be in time order: this binary searches it, reading one line at each step. Lines
+/// which cannot be parsed or have no time are skipped over when searching, and included when
+/// reading on from the offset found. Servers log entries when requests complete, so a log is
+/// usually only roughly in order; to be sure of getting every entry from `target` on, search for
+/// a little (such as a minute) before it.
+///
+/// # Example
+/// ```
+/// use std::io::{BufRead, Cursor};
+/// let log = "\
+/// 10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10
+/// 10.0.0.1 - - [2024-05-01T13:05:00Z] \"GET /b HTTP/1.1\" 200 10
+/// 10.0.0.1 - - [2024-05-01T13:10:00Z] \"GET /c HTTP/1.1\" 200 10
+/// ";
+/// let mut r = Cursor::new(log);
+/// let target = "2024-05-01T13:03:00Z".parse().unwrap();
+/// let offset = common_log_format::seek::seek_to_time(&mut r, target).unwrap();
+/// assert_eq!(offset, 61);
+///
+/// let mut line = String::new();
+/// r.read_line(&mut line).unwrap();
+/// assert!(line.contains("/b"));
+/// ```
+pub fn seek_to_time<R: BufRead + Seek>(r: &mut R, target: DateTime<Utc>) -> io::Result<u64> {
+    let (mut lo, mut hi) = (0, r.seek(SeekFrom::End(0))?);
+    let mut buf = Vec::new();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match first_time_after(r, mid, &mut buf)? {
+            Some(t) if t < target => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+
+    let start = line_start_after(r, lo, &mut buf)?;
+    r.seek(SeekFrom::Start(start))
+}
+
+/// Read the entries of the file at `path` with times in `[start, end)`, as found by
+/// [`seek_to_time`].
+///
+/// Reading stops at the first entry at or after `end`. Entries without times, and lines which
+/// cannot be parsed, are returned along the way.
+///
+/// # Example
+/// ```no_run
+/// let start = "2024-05-01T13:00:00Z".parse().unwrap();
+/// let end = "2024-05-01T14:00:00Z".parse().unwrap();
+/// for entry in common_log_format::seek::time_range("access.log", start, end).unwrap() {
+///     println!("{:?}", entry.unwrap());
+/// }
+/// ```
+pub fn time_range(
+    path: impl AsRef<Path>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> io::Result<impl Iterator<Item = Result<LogEntry, ReadError>>> {
+    let mut r = BufReader::new(File::open(path)?);
+    seek_to_time(&mut r, start)?;
+    Ok(LogReader::new(r).take_while(move |e| match e {
+        Ok(e) => e.time.is_none_or(|t| t < end),
+        Err(_) => true,
+    }))
+}
+
+/// The offset of the first line which starts at or after `offset`.
+fn line_start_after<R: BufRead + Seek>(
+    r: &mut R,
+    offset: u64,
+    buf: &mut Vec<u8>,
+) -> io::Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+
+    // If `offset - 1` is a newline, this reads just that.
+    r.seek(SeekFrom::Start(offset - 1))?;
+    buf.clear();
+    let n = r.read_until(b'\n', buf)?;
+    Ok(offset - 1 + n as u64)
+}
+
+/// The time of the first line with one which starts at or after `offset`, or `None` if there is
+/// no such line.
+fn first_time_after<R: BufRead + Seek>(
+    r: &mut R,
+    offset: u64,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<DateTime<Utc>>> {
+    let start = line_start_after(r, offset, buf)?;
+    r.seek(SeekFrom::Start(start))?;
+    loop {
+        buf.clear();
+        if r.read_until(b'\n', buf)? == 0 {
+            return Ok(None);
+        }
+
+        if let Ok(LogEntryRef { time: Some(t), .. }) = LogEntryRef::from_bytes(trim_line_end(buf)) {
+            return Ok(Some(t));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Grouping each client's requests into sessions separated by idle time.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    classify::{AssetClassifier, RequestKind},
+    LogEntry,
+};
+
+/// One client's requests, with no gap between consecutive ones longer than the idle gap.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub host: IpAddr,
+    /// The times of the first and last requests.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub requests: u64,
+    /// Requests for pages, as opposed to static assets such as stylesheets, scripts, and images.
+    pub pages: u64,
+    pub bytes: u64,
+}
+
+impl Session {
+    fn new(host: IpAddr, at: DateTime<Utc>) -> Self {
+        Session {
+            host,
+            start: at,
+            end: at,
+            requests: 0,
+            pages: 0,
+            bytes: 0,
+        }
+    }
+
+    fn add(&mut self, entry: &LogEntry, at: DateTime<Utc>, classifier: &AssetClassifier) {
+        self.start = self.start.min(at);
+        self.end = self.end.max(at);
+        self.requests += 1;
+        if classifier.classify(entry) == Some(RequestKind::Dynamic) {
+            self.pages += 1;
+        }
+        self.bytes += entry.object_size.unwrap_or(0) as u64;
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        self.end - self.start
+    }
+}
+
+/// Split `entries` into per-host sessions, ending a session when a host is idle for longer than
+/// `idle_gap`. Entries without a host or time are skipped, and pages are told from assets by the
+/// default [`AssetClassifier`].
+///
+/// Sessions are returned in order of their start times.
+///
+/// # Example
+/// ```
+/// use common_log_format::{session::sessionize, LogEntry};
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 1000",
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /site.css HTTP/1.1\" 200 200",
+///     "10.0.0.2 - - [2024-05-01T13:05:00Z] \"GET / HTTP/1.1\" 200 1000",
+///     "10.0.0.1 - - [2024-05-01T13:10:00Z] \"GET /about HTTP/1.1\" 200 500",
+///     "10.0.0.1 - - [2024-05-01T14:00:00Z] \"GET / HTTP/1.1\" 200 1000",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let sessions = sessionize(&entries, chrono::Duration::minutes(30));
+/// assert_eq!(sessions.len(), 3);
+/// assert_eq!(sessions[0].host.to_string(), "10.0.0.1");
+/// assert_eq!((sessions[0].requests, sessions[0].pages, sessions[0].bytes), (3, 2, 1700));
+/// assert_eq!(sessions[0].duration(), chrono::Duration::minutes(10));
+/// ```
+pub fn sessionize<'a>(
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    idle_gap: chrono::Duration,
+) -> Vec<Session> {
+    let mut by_host: HashMap<IpAddr, Vec<(DateTime<Utc>, &LogEntry)>> = HashMap::new();
+    for e in entries {
+        if let (Some(h), Some(t)) = (e.host, e.time) {
+            by_host.entry(h).or_default().push((t, e));
+        }
+    }
+
+    let classifier = AssetClassifier::default();
+    let mut sessions = vec![];
+    for (host, mut entries) in by_host {
+        entries.sort_by_key(|(t, _)| *t);
+        let mut current: Option<Session> = None;
+        for (t, e) in entries {
+            match &mut current {
+                Some(s) if t - s.end <= idle_gap => s.add(e, t, &classifier),
+                _ => {
+                    sessions.extend(current.take());
+                    current.insert(Session::new(host, t)).add(e, t, &classifier);
+                }
+            }
+        }
+        sessions.extend(current);
+    }
+    sessions.sort_by_key(|s| (s.start, s.host));
+    sessions
+}
+
+/// Sessionizes a stream of entries as they arrive, as [`sessionize`] does for a batch.
+///
+/// Sessions are handed back once they are over: when their host's next request comes after the
+/// idle gap, or when [`Sessionizer::advance_to`] passes the end of the gap. Entries should arrive
+/// roughly in time order; one which is earlier than its host's open session is added to it.
+///
+/// # Example
+/// ```
+/// use common_log_format::{session::Sessionizer, LogEntry};
+/// let mut sessions = Sessionizer::new(chrono::Duration::minutes(30));
+/// let e: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// assert!(sessions.push(&e).is_none());
+///
+/// let closed = sessions.advance_to("2024-05-01T13:31:00Z".parse().unwrap());
+/// assert_eq!(closed.len(), 1);
+/// assert_eq!(sessions.open(), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sessionizer {
+    idle_gap: chrono::Duration,
+    classifier: AssetClassifier,
+    open: HashMap<IpAddr, Session>,
+}
+
+impl Sessionizer {
+    pub fn new(idle_gap: chrono::Duration) -> Self {
+        Sessionizer {
+            idle_gap,
+            classifier: AssetClassifier::default(),
+            open: HashMap::new(),
+        }
+    }
+
+    /// Tell pages from assets with `classifier` rather than the default one.
+    pub fn with_classifier(mut self, classifier: AssetClassifier) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// Add `entry` to its host's session, returning the host's previous session if this entry
+    /// starts a new one. Entries without a host or time are ignored.
+    pub fn push(&mut self, entry: &LogEntry) -> Option<Session> {
+        let (host, t) = (entry.host?, entry.time?);
+        let session = self
+            .open
+            .entry(host)
+            .or_insert_with(|| Session::new(host, t));
+        if t - session.end <= self.idle_gap {
+            session.add(entry, t, &self.classifier);
+            return None;
+        }
+
+        let mut next = Session::new(host, t);
+        next.add(entry, t, &self.classifier);
+        Some(std::mem::replace(session, next))
+    }
+
+    /// Close and return the sessions idle for longer than the gap at `now`, oldest first.
+    pub fn advance_to(&mut self, now: DateTime<Utc>) -> Vec<Session> {
+        let idle: Vec<IpAddr> = self
+            .open
+            .values()
+            .filter(|s| now - s.end > self.idle_gap)
+            .map(|s| s.host)
+            .collect();
+        let mut closed: Vec<Session> = idle
+            .into_iter()
+            .filter_map(|h| self.open.remove(&h))
+            .collect();
+        closed.sort_by_key(|s| (s.start, s.host));
+        closed
+    }
+
+    /// Close and return every open session, oldest first.
+    pub fn finish(self) -> Vec<Session> {
+        let mut closed: Vec<Session> = self.open.into_values().collect();
+        closed.sort_by_key(|s| (s.start, s.host));
+        closed
+    }
+
+    /// The number of open sessions.
+    pub fn open(&self) -> usize {
+        self.open.len()
+    }
+}
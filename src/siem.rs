@@ -0,0 +1,198 @@
+//! Formatting entries as CEF and LEEF events, for SIEMs.
+//!
+//! ArcSight and most SIEMs which followed it ingest [CEF] (Common Event Format); QRadar ingests
+//! [LEEF] (Log Event Extended Format). Both are a header naming the device which logged the
+//! event and the kind of event, followed by `key=value` attributes. [`Device::to_cef`] and
+//! [`Device::to_leef`] write an entry as either, so an access log can be forwarded, by syslog
+//! for example, without a separate transformation step.
+//!
+//! The event ID (CEF's signature ID) is the status code, and the attributes are:
+//!
+//! | CEF                         | LEEF                      | from                         |
+//! |-----------------------------|---------------------------|------------------------------|
+//! | `rt`, in epoch milliseconds | `devTime`                 | [`LogEntry::time`]           |
+//! | `src`                       | `src`                     | [`LogEntry::host`]           |
+//! | `suser`                     | `usrName`                 | [`LogEntry::authuser`]       |
+//! | `requestMethod`             | `method`                  | [`LogEntry::method`]         |
+//! | `request`                   | `url`                     | [`LogEntry::target`]         |
+//! | `app`                       | `proto`                   | the request line's protocol  |
+//! | `cn1`, labelled `status`    | `status`                  | [`LogEntry::status_code`]    |
+//! | `out`                       | `dstBytes`                | [`LogEntry::object_size`]    |
+//!
+//! Severity, on CEF's scale of 0 to 10 and in LEEF's `sev`, is 8 for a 5xx status, 5 for 4xx,
+//! and 1 otherwise.
+//!
+//! [CEF]: https://www.microfocus.com/documentation/arcsight/arcsight-smartconnectors/pdfdoc/common-event-format-v25/common-event-format-v25.pdf
+//! [LEEF]: https://www.ibm.com/docs/en/dsm?topic=leef-overview
+
+use std::fmt::Write as _;
+
+use crate::LogEntry;
+
+/// The device, or application, events are reported as coming from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub vendor: String,
+    pub product: String,
+    pub version: String,
+}
+
+impl Device {
+    pub fn new(vendor: &str, product: &str, version: &str) -> Self {
+        Device {
+            vendor: vendor.to_owned(),
+            product: product.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    /// `entry` as a CEF event.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{siem::Device, LogEntry};
+    /// let entry: LogEntry = "10.0.0.1 - frank [2024-05-01T13:00:00Z] \"GET /a?b=c HTTP/1.1\" 404 10".parse().unwrap();
+    /// let device = Device::new("Apache", "httpd", "2.4");
+    /// assert_eq!(
+    ///     device.to_cef(&entry),
+    ///     "CEF:0|Apache|httpd|2.4|404|GET /a?b=c HTTP/1.1|5|rt=1714568400000 src=10.0.0.1 \
+    ///      suser=frank requestMethod=GET request=/a?b\\=c app=HTTP/1.1 cn1=404 cn1Label=status out=10",
+    /// );
+    /// ```
+    pub fn to_cef(&self, entry: &LogEntry) -> String {
+        let status = entry.status_code.map(|s| s.as_u16());
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "CEF:0|{}|{}|{}|{}|{}|{}|",
+            cef_header(&self.vendor),
+            cef_header(&self.product),
+            cef_header(&self.version),
+            status.map_or("-".to_owned(), |s| s.to_string()),
+            cef_header(entry.request_line.as_deref().unwrap_or("-")),
+            severity(status)
+        );
+
+        let mut extensions = vec![];
+        if let Some(t) = entry.time {
+            extensions.push(("rt", t.timestamp_millis().to_string()));
+        }
+        extensions.extend(attributes(
+            entry,
+            ["src", "suser", "requestMethod", "request", "app"],
+        ));
+        if let Some(status) = status {
+            extensions.push(("cn1", status.to_string()));
+            extensions.push(("cn1Label", "status".to_owned()));
+        }
+        if let Some(size) = entry.object_size {
+            extensions.push(("out", size.to_string()));
+        }
+        for (i, (key, value)) in extensions.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            let _ = write!(out, "{}={}", key, cef_value(value));
+        }
+        out
+    }
+
+    /// `entry` as a LEEF 2.0 event, with attributes separated by tabs (`x09` in the header).
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{siem::Device, LogEntry};
+    /// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+    /// let device = Device::new("Apache", "httpd", "2.4");
+    /// assert_eq!(
+    ///     device.to_leef(&entry),
+    ///     "LEEF:2.0|Apache|httpd|2.4|200|x09|devTime=May 01 2024 13:00:00.000 UTC\tsrc=10.0.0.1\
+    ///      \tmethod=GET\turl=/\tproto=HTTP/1.1\tstatus=200\tdstBytes=10\tsev=1",
+    /// );
+    /// ```
+    pub fn to_leef(&self, entry: &LogEntry) -> String {
+        let status = entry.status_code.map(|s| s.as_u16());
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "LEEF:2.0|{}|{}|{}|{}|x09|",
+            leef_header(&self.vendor),
+            leef_header(&self.product),
+            leef_header(&self.version),
+            status.map_or("-".to_owned(), |s| s.to_string()),
+        );
+
+        let mut attrs = vec![];
+        if let Some(t) = entry.time {
+            attrs.push(("devTime", t.format("%b %d %Y %H:%M:%S%.3f UTC").to_string()));
+        }
+        attrs.extend(attributes(
+            entry,
+            ["src", "usrName", "method", "url", "proto"],
+        ));
+        if let Some(status) = status {
+            attrs.push(("status", status.to_string()));
+        }
+        if let Some(size) = entry.object_size {
+            attrs.push(("dstBytes", size.to_string()));
+        }
+        attrs.push(("sev", severity(status).to_string()));
+        for (i, (key, value)) in attrs.iter().enumerate() {
+            if i > 0 {
+                out.push('\t');
+            }
+            let _ = write!(out, "{}={}", key, leef_value(value));
+        }
+        out
+    }
+}
+
+/// The client address, user, method, target, and protocol of `entry`, under the names in `keys`.
+fn attributes(entry: &LogEntry, keys: [&'static str; 5]) -> Vec<(&'static str, String)> {
+    let protocol = entry
+        .request_line
+        .as_deref()
+        .and_then(|l| l.split(' ').nth(2));
+    let values = [
+        entry.host.map(|h| h.to_string()),
+        entry.authuser.clone(),
+        entry.method().map(str::to_owned),
+        entry.target().map(str::to_owned),
+        protocol.map(str::to_owned),
+    ];
+    keys.into_iter()
+        .zip(values)
+        .filter_map(|(k, v)| Some((k, v?)))
+        .collect()
+}
+
+fn severity(status: Option<u16>) -> u8 {
+    match status {
+        Some(500..) => 8,
+        Some(400..=499) => 5,
+        _ => 1,
+    }
+}
+
+/// `s` escaped for a CEF header field.
+fn cef_header(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// `s` escaped for a CEF extension value.
+fn cef_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// `s` escaped for a LEEF header field.
+fn leef_header(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+/// `s` as a LEEF attribute value, which can't contain the tab delimiter or line breaks.
+fn leef_value(s: &str) -> String {
+    s.replace(['\t', '\r', '\n'], " ")
+}
@@ -0,0 +1,263 @@
+//! Simulating caches over access-log traces.
+//!
+//! How well would a cache of a given size, with a given eviction policy, have served the traffic
+//! in a log? [`simulate`] runs the log's requests through a [`CachePolicy`] and counts the hits,
+//! both by request ([`SimulationReport::hit_ratio`]) and by bytes served
+//! ([`SimulationReport::byte_hit_ratio`]). Objects are identified by request target and sized by
+//! `object_size`.
+//!
+//! Only successful `GET` requests (status 200 with a size) are cacheable, so only those are
+//! simulated; the rest are counted as skipped.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::LogEntry;
+
+/// A cache whose contents are tracked but not stored: a policy deciding what to keep.
+pub trait CachePolicy {
+    /// Record a request for `key`, returning whether it was cached.
+    fn lookup(&mut self, key: &str) -> bool;
+
+    /// Cache `key`, of `size` bytes, after a miss, evicting as the policy sees fit. Objects larger
+    /// than the whole cache are not cached.
+    fn admit(&mut self, key: &str, size: u64);
+
+    /// The bytes cached.
+    fn used(&self) -> u64;
+}
+
+/// A built-in eviction policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Policy {
+    /// Evict the least recently requested object.
+    Lru,
+    /// Evict the least frequently requested object, the least recently requested of those if
+    /// there's a tie.
+    Lfu,
+    /// Evict the object cached longest ago, however often it is requested.
+    Fifo,
+}
+
+impl Policy {
+    pub const ALL: [Policy; 3] = [Policy::Lru, Policy::Lfu, Policy::Fifo];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Policy::Lru => "lru",
+            Policy::Lfu => "lfu",
+            Policy::Fifo => "fifo",
+        }
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name given to [`Policy::from_str`] is not a known policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPolicy(pub String);
+
+impl Display for UnknownPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown cache policy {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPolicy {}
+
+impl FromStr for Policy {
+    type Err = UnknownPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Policy::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| UnknownPolicy(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    size: u64,
+    /// The object's place in the eviction order; the lowest is evicted first.
+    rank: (u64, u64),
+}
+
+/// A simulated cache of a fixed number of bytes, evicting by one of the built-in [`Policy`]s.
+///
+/// # Example
+/// ```
+/// use common_log_format::simulate::{CachePolicy, Policy, SimulatedCache};
+/// let mut lru = SimulatedCache::new(Policy::Lru, 100);
+/// lru.admit("/a", 50);
+/// lru.admit("/b", 50);
+/// assert!(lru.lookup("/a"));
+/// // `/b` is now the least recently used, so it makes room for `/c`.
+/// lru.admit("/c", 50);
+/// assert!(lru.lookup("/a"));
+/// assert!(!lru.lookup("/b"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SimulatedCache {
+    policy: Policy,
+    capacity: u64,
+    used: u64,
+    slots: HashMap<String, Slot>,
+    order: BTreeMap<(u64, u64), String>,
+    /// Incremented on every request, to order them.
+    clock: u64,
+}
+
+impl SimulatedCache {
+    pub fn new(policy: Policy, capacity: u64) -> Self {
+        SimulatedCache {
+            policy,
+            capacity,
+            used: 0,
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The number of objects cached.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    fn set_rank(&mut self, key: &str, rank: (u64, u64)) {
+        if let Some(slot) = self.slots.get_mut(key) {
+            let name = self.order.remove(&slot.rank).expect("ranked");
+            slot.rank = rank;
+            self.order.insert(rank, name);
+        }
+    }
+}
+
+impl CachePolicy for SimulatedCache {
+    fn lookup(&mut self, key: &str) -> bool {
+        self.clock += 1;
+        let rank = match self.slots.get(key) {
+            Some(slot) => slot.rank,
+            None => return false,
+        };
+        match self.policy {
+            Policy::Lru => self.set_rank(key, (0, self.clock)),
+            Policy::Lfu => self.set_rank(key, (rank.0 + 1, self.clock)),
+            Policy::Fifo => (),
+        }
+        true
+    }
+
+    fn admit(&mut self, key: &str, size: u64) {
+        if size > self.capacity || self.slots.contains_key(key) {
+            return;
+        }
+        while self.used + size > self.capacity {
+            let (_, evicted) = self.order.pop_first().expect("cache holds the bytes used");
+            self.used -= self.slots.remove(&evicted).expect("ranked").size;
+        }
+
+        self.clock += 1;
+        let rank = match self.policy {
+            Policy::Lfu => (1, self.clock),
+            Policy::Lru | Policy::Fifo => (0, self.clock),
+        };
+        self.slots.insert(key.to_owned(), Slot { size, rank });
+        self.order.insert(rank, key.to_owned());
+        self.used += size;
+    }
+
+    fn used(&self) -> u64 {
+        self.used
+    }
+}
+
+/// How a cache served a trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SimulationReport {
+    /// Cacheable requests.
+    pub requests: u64,
+    pub hits: u64,
+    /// The bytes served to cacheable requests.
+    pub bytes: u64,
+    /// The bytes served from the cache.
+    pub hit_bytes: u64,
+    /// Requests which weren't cacheable.
+    pub skipped: u64,
+}
+
+impl SimulationReport {
+    /// The fraction of cacheable requests served from the cache.
+    pub fn hit_ratio(&self) -> f64 {
+        self.hits as f64 / self.requests.max(1) as f64
+    }
+
+    /// The fraction of cacheable bytes served from the cache.
+    pub fn byte_hit_ratio(&self) -> f64 {
+        self.hit_bytes as f64 / self.bytes.max(1) as f64
+    }
+}
+
+/// Run the requests in `entries` through `cache`, in order.
+///
+/// # Example
+/// ```
+/// use common_log_format::{simulate::{simulate, Policy, SimulatedCache}, LogEntry};
+/// let entries: Vec<LogEntry> = ["/a", "/b", "/a", "/c", "/a", "/b"]
+///     .iter()
+///     .map(|p| format!("10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET {} HTTP/1.1\" 200 100", p))
+///     .map(|l| l.parse().unwrap())
+///     .collect();
+///
+/// let lru = simulate(&entries, &mut SimulatedCache::new(Policy::Lru, 200));
+/// assert_eq!((lru.requests, lru.hits), (6, 2));
+/// let fifo = simulate(&entries, &mut SimulatedCache::new(Policy::Fifo, 200));
+/// assert_eq!(fifo.hits, 1);
+/// assert_eq!(fifo.byte_hit_ratio(), 100. / 600.);
+/// ```
+pub fn simulate<'a>(
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    cache: &mut dyn CachePolicy,
+) -> SimulationReport {
+    let mut report = SimulationReport::default();
+    for e in entries {
+        let cacheable = e.method() == Some("GET") && e.status_code.is_some_and(|s| s == 200);
+        let (target, size) = match (e.target(), e.object_size) {
+            (Some(t), Some(s)) if cacheable => (t, s as u64),
+            _ => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+
+        report.requests += 1;
+        report.bytes += size;
+        if cache.lookup(target) {
+            report.hits += 1;
+            report.hit_bytes += size;
+        } else {
+            cache.admit(target, size);
+        }
+    }
+    report
+}
@@ -0,0 +1,139 @@
+//! Destinations for entries.
+//!
+//! A [`Sink`] accepts entries one at a time, buffering them if it likes until
+//! [`Sink::flush`]. The integrations behind features (such as `redis`) implement it, so a
+//! pipeline can be written once against the trait and pointed at any of them.
+
+use std::convert::Infallible;
+
+use crate::LogEntry;
+
+/// Somewhere entries can be sent.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sink::Sink, LogEntry};
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// let mut collected: Vec<LogEntry> = Vec::new();
+/// collected.send_all([&entry, &entry]).unwrap();
+/// assert_eq!(collected.len(), 2);
+/// ```
+pub trait Sink {
+    type Error;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error>;
+
+    /// Deliver any buffered entries.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Send every entry in `entries`, then flush.
+    fn send_all<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = &'a LogEntry>,
+    ) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        for e in entries {
+            self.send(e)?;
+        }
+        self.flush()
+    }
+}
+
+impl Sink for Vec<LogEntry> {
+    type Error = Infallible;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        self.push(entry.clone());
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    type Error = S::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        (**self).send(entry)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        (**self).flush()
+    }
+}
+
+/// A subject, topic, or key name with placeholders filled in from each entry.
+///
+/// The placeholders are `{host}`, `{method}`, `{status}`, and `{status_class}` (such as `5xx`).
+/// Fields missing from an entry are replaced with `-`, and other placeholders are left as they
+/// are, except by [`Template::render_derived`].
+///
+/// # Example
+/// ```
+/// use common_log_format::{sink::Template, LogEntry};
+/// let t = Template::new("access.{status_class}.{method}");
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 503 0".parse().unwrap();
+/// assert_eq!(t.render(&entry), "access.5xx.GET");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(String);
+
+impl Template {
+    pub fn new(template: impl Into<String>) -> Self {
+        Template(template.into())
+    }
+
+    pub fn render(&self, entry: &LogEntry) -> String {
+        self.render_with(entry, |_| None)
+    }
+
+    /// Render `entry`, in which any other placeholder names a derived field. Fields missing from
+    /// the entry are replaced with `-`.
+    #[cfg(feature = "analytics")]
+    pub fn render_derived(&self, entry: &crate::derived::DerivedEntry) -> String {
+        self.render_with(&entry.entry, |name| {
+            Some(entry.get(name).map_or("-".to_owned(), |v| v.to_string()))
+        })
+    }
+
+    fn render_with(&self, entry: &LogEntry, other: impl Fn(&str) -> Option<String>) -> String {
+        let mut out = String::with_capacity(self.0.len());
+        let mut rest = self.0.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let after = &rest[open..];
+            let close = match after.find('}') {
+                Some(c) => c,
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+
+            let status = entry.status_code.map(|s| s.as_u16());
+            match &after[1..close] {
+                "host" => push_or_dash(&mut out, entry.host),
+                "method" => push_or_dash(&mut out, entry.method()),
+                "status" => push_or_dash(&mut out, status),
+                "status_class" => push_or_dash(&mut out, status.map(|s| format!("{}xx", s / 100))),
+                name => match other(name) {
+                    Some(v) => out.push_str(&v),
+                    None => out.push_str(&after[..=close]),
+                },
+            }
+            rest = &after[close + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+fn push_or_dash(out: &mut String, v: Option<impl std::fmt::Display>) {
+    use std::fmt::Write;
+    match v {
+        Some(v) => write!(out, "{}", v).unwrap(),
+        None => out.push('-'),
+    }
+}
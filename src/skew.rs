@@ -0,0 +1,369 @@
+//! Estimating clock skew and shipping lag between the servers behind a merged log.
+//!
+//! [`crate::merge::merge_by_time`] trusts each server's timestamps. If one server's clock is a
+//! few seconds off, its requests land in the wrong place in the merged timeline, and whatever is
+//! computed from it (sessions, request chains, latencies across tiers) is quietly wrong.
+//!
+//! Servers behind one load balancer see the same clients at the same moments: a page load is
+//! spread over several of them within a second or two. A [`SkewEstimator`] records when each
+//! client was seen by each source, and finds the offset which best lines one source's record up
+//! with a reference source's. Where requests carry an ID which every tier logs, matching the IDs
+//! is more exact still; where each server sees different clients, as behind a load balancer which
+//! hashes by address, the rise and fall of each server's request rate can be lined up instead. If
+//! the time each entry was received is known too, it also reports how long entries took to be
+//! shipped from each source.
+//!
+//! With the skews known, [`SkewReport::adjust`] moves entries onto the reference's clock, and
+//! [`crate::merge::merge_by_time_shifted`] merges logs with each moved by its source's skew.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::LogEntry;
+
+/// How one source lines up with the reference.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SourceSkew<S> {
+    pub source: S,
+    pub entries: u64,
+    /// How far ahead of the reference's clock this source's clock is, to the second, or `None`
+    /// if it saw none of the clients the reference saw. Zero for the reference itself.
+    pub skew: Option<chrono::Duration>,
+    /// The fraction of the (client, second) pairs seen by the smaller of this source and the
+    /// reference which match up once the skew is corrected for, or with [`Method::RateCurve`],
+    /// the correlation of their request rates. Near 0 means the skew is a guess.
+    pub agreement: f64,
+    /// The median time between an entry being logged and being received, including any skew
+    /// between the source's clock and the receiver's.
+    pub median_lag: Option<chrono::Duration>,
+    pub max_lag: Option<chrono::Duration>,
+}
+
+/// The skew of each source relative to one of them.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SkewReport<S> {
+    pub reference: S,
+    /// Every source, including the reference, in order.
+    pub sources: Vec<SourceSkew<S>>,
+}
+
+impl<S: PartialEq> SkewReport<S> {
+    pub fn get(&self, source: &S) -> Option<&SourceSkew<S>> {
+        self.sources.iter().find(|s| s.source == *source)
+    }
+
+    /// The skew of `source`, or zero if it's unknown.
+    pub fn offset(&self, source: &S) -> chrono::Duration {
+        self.get(source)
+            .and_then(|s| s.skew)
+            .unwrap_or_else(chrono::Duration::zero)
+    }
+
+    /// Move `entry`, logged by `source`, onto the reference's clock.
+    pub fn adjust(&self, source: &S, entry: &mut LogEntry) {
+        let skew = self.get(source).and_then(|s| s.skew);
+        if let (Some(skew), Some(time)) = (skew, entry.time) {
+            entry.time = Some(time - skew);
+        }
+    }
+}
+
+/// How a [`SkewEstimator`] lines sources up.
+///
+/// # Example
+/// ```
+/// use common_log_format::{skew::{Method, SkewEstimator}, LogEntry};
+/// let entry = |host: &str, sec: u32| -> LogEntry {
+///     format!("{} - - [2024-05-01T13:00:{:02}Z] \"GET / HTTP/1.1\" 200 0", host, sec)
+///         .parse()
+///         .unwrap()
+/// };
+/// // Each server sees its own clients, but the same bursts of traffic; web2's clock is 4
+/// // seconds slow.
+/// let mut estimator = SkewEstimator::new().with_method(Method::RateCurve);
+/// for (sec, requests) in [(10, 1), (11, 6), (12, 2), (20, 9), (21, 1), (30, 4), (31, 8)] {
+///     for _ in 0..requests {
+///         estimator.observe("web1", &entry("10.0.0.1", sec));
+///         estimator.observe("web2", &entry("10.0.1.1", sec - 4));
+///     }
+/// }
+/// let report = estimator.with_reference("web1").report().unwrap();
+/// assert_eq!(report.offset(&"web2"), chrono::Duration::seconds(-4));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Method {
+    /// Match the clients, and request IDs, each source saw in each second.
+    #[default]
+    Matching,
+    /// Correlate the number of requests each source logged in each second. This needs the
+    /// request rate to vary, but not the sources to see the same clients.
+    RateCurve,
+}
+
+/// Something seen by more than one source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Client(IpAddr),
+    /// The hash of a request ID.
+    Request(u64),
+}
+
+#[derive(Debug, Default, Clone)]
+struct Source {
+    entries: u64,
+    /// The clients and requests seen, by the second they were seen in.
+    seen: HashSet<(Key, i64)>,
+    /// Entries by the second they were logged in.
+    rate: BTreeMap<i64, u64>,
+    /// Milliseconds between being logged and received.
+    lags: Vec<i64>,
+}
+
+/// Estimates how far the clock of each of several sources is from one of them.
+///
+/// The skew of a source is the offset, up to the maximum (5 minutes by default), at which the
+/// most clients seen by the reference in one second are seen by the source in the offset second.
+/// Ties go to the smallest offset. The reference is the source with the most such pairs (the first
+/// of them, in order), unless one is chosen. With [`Method::RateCurve`], the skew is instead the
+/// offset at which the source's requests per second correlate best with the reference's.
+///
+/// # Example
+/// ```
+/// use common_log_format::{skew::SkewEstimator, LogEntry};
+/// let entry = |host: u8, sec: u32| -> LogEntry {
+///     format!("10.0.0.{} - - [2024-05-01T13:00:{:02}Z] \"GET / HTTP/1.1\" 200 0", host, sec)
+///         .parse()
+///         .unwrap()
+/// };
+/// let mut estimator = SkewEstimator::new();
+/// for (host, sec) in [(1, 0), (2, 3), (3, 4), (4, 9), (5, 15), (6, 20)] {
+///     estimator.observe("web1", &entry(host, sec));
+///     // web2's clock is 2 seconds fast.
+///     estimator.observe("web2", &entry(host, sec + 2));
+/// }
+///
+/// let report = estimator.report().unwrap();
+/// let web2 = report.get(&"web2").unwrap();
+/// assert_eq!(web2.skew, Some(chrono::Duration::seconds(2)));
+/// assert_eq!(web2.agreement, 1.);
+///
+/// let mut e = entry(1, 2);
+/// report.adjust(&"web2", &mut e);
+/// assert_eq!(e.time, entry(1, 0).time);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SkewEstimator<S> {
+    max_offset: i64,
+    method: Method,
+    reference: Option<S>,
+    sources: BTreeMap<S, Source>,
+}
+
+impl<S: Ord + Clone> Default for SkewEstimator<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Ord + Clone> SkewEstimator<S> {
+    pub fn new() -> Self {
+        SkewEstimator {
+            max_offset: 300,
+            method: Method::Matching,
+            reference: None,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Consider skews of up to `max_offset` either way.
+    pub fn with_max_offset(mut self, max_offset: Duration) -> Self {
+        self.max_offset = max_offset.as_secs() as i64;
+        self
+    }
+
+    /// Measure every source against `reference`, such as the server with the best-kept clock.
+    pub fn with_reference(mut self, reference: S) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Line sources up by `method`, rather than [`Method::Matching`].
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Record `entry`, logged by `source`. Entries without a host or time are only counted.
+    pub fn observe(&mut self, source: S, entry: &LogEntry) {
+        let s = self.sources.entry(source).or_default();
+        s.entries += 1;
+        if let Some(time) = entry.time {
+            *s.rate.entry(time.timestamp()).or_default() += 1;
+            if let Some(host) = entry.host {
+                s.seen
+                    .insert((Key::Client(host.to_canonical()), time.timestamp()));
+            }
+        }
+    }
+
+    /// Record `entry`, logged by `source` with the ID of its request, such as the
+    /// `X-Request-ID` header, for matching with the other sources which logged the same ID.
+    pub fn observe_with_id(&mut self, source: S, entry: &LogEntry, request_id: &str) {
+        if let Some(time) = entry.time {
+            let mut hasher = DefaultHasher::new();
+            request_id.hash(&mut hasher);
+            self.sources
+                .entry(source.clone())
+                .or_default()
+                .seen
+                .insert((Key::Request(hasher.finish()), time.timestamp()));
+        }
+        self.observe(source, entry);
+    }
+
+    /// Record `entry`, logged by `source` and received at `received`.
+    pub fn observe_received(&mut self, source: S, entry: &LogEntry, received: DateTime<Utc>) {
+        if let Some(time) = entry.time {
+            let lag = (received - time).num_milliseconds();
+            self.sources
+                .entry(source.clone())
+                .or_default()
+                .lags
+                .push(lag);
+        }
+        self.observe(source, entry);
+    }
+
+    /// The skew of every source, or `None` if nothing has been observed.
+    pub fn report(&self) -> Option<SkewReport<S>> {
+        let reference = match &self.reference {
+            Some(r) => r.clone(),
+            None => self
+                .sources
+                .iter()
+                .rev()
+                .max_by_key(|(_, s)| s.seen.len())
+                .map(|(k, _)| k.clone())?,
+        };
+        let empty = Source::default();
+        let reference_source = self.sources.get(&reference).unwrap_or(&empty);
+
+        let sources = self
+            .sources
+            .iter()
+            .map(|(name, s)| {
+                let (skew, agreement) = if *name == reference {
+                    (Some(0), 1.)
+                } else {
+                    match self.method {
+                        Method::Matching => self.best_offset(&reference_source.seen, &s.seen),
+                        Method::RateCurve => self.best_correlation(&reference_source.rate, &s.rate),
+                    }
+                };
+                let mut lags = s.lags.clone();
+                lags.sort_unstable();
+                SourceSkew {
+                    source: name.clone(),
+                    entries: s.entries,
+                    skew: skew.map(chrono::Duration::seconds),
+                    agreement,
+                    median_lag: lags
+                        .get(lags.len() / 2)
+                        .map(|&l| chrono::Duration::milliseconds(l)),
+                    max_lag: lags.last().map(|&l| chrono::Duration::milliseconds(l)),
+                }
+            })
+            .collect();
+
+        Some(SkewReport { reference, sources })
+    }
+
+    /// The offset of `other` from `reference` with the most matching pairs, and the fraction
+    /// matched.
+    fn best_offset(
+        &self,
+        reference: &HashSet<(Key, i64)>,
+        other: &HashSet<(Key, i64)>,
+    ) -> (Option<i64>, f64) {
+        let (small, large, sign) = if reference.len() <= other.len() {
+            (reference, other, 1)
+        } else {
+            (other, reference, -1)
+        };
+        if small.is_empty() {
+            return (None, 0.);
+        }
+
+        let mut best = (0, 0);
+        for k in (0..=self.max_offset).flat_map(|k| [k, -k]) {
+            let matches = small
+                .iter()
+                .filter(|(host, t)| large.contains(&(*host, t + k)))
+                .count();
+            if matches > best.1 {
+                best = (k, matches);
+            }
+        }
+
+        match best {
+            (_, 0) => (None, 0.),
+            (k, matches) => (Some(sign * k), matches as f64 / small.len() as f64),
+        }
+    }
+    /// The offset of `other`'s rate from `reference`'s with the highest correlation, and the
+    /// correlation.
+    fn best_correlation(
+        &self,
+        reference: &BTreeMap<i64, u64>,
+        other: &BTreeMap<i64, u64>,
+    ) -> (Option<i64>, f64) {
+        let (start, end) = match (reference.keys().next(), reference.keys().next_back()) {
+            (Some(&start), Some(&end)) if !other.is_empty() => (start, end),
+            _ => return (None, 0.),
+        };
+        let per_second = |counts: &BTreeMap<i64, u64>, from: i64, to: i64| -> Vec<f64> {
+            (from..=to)
+                .map(|t| counts.get(&t).copied().unwrap_or(0) as f64)
+                .collect()
+        };
+        let x = per_second(reference, start, end);
+        let y = per_second(other, start - self.max_offset, end + self.max_offset);
+
+        let mut best: Option<(i64, f64)> = None;
+        for k in (0..=self.max_offset).flat_map(|k| [k, -k]) {
+            let from = (k + self.max_offset) as usize;
+            let c = match correlation(&x, &y[from..from + x.len()]) {
+                Some(c) => c,
+                None => continue,
+            };
+            if best.is_none_or(|(_, b)| c > b) {
+                best = Some((k, c));
+            }
+        }
+
+        match best {
+            Some((k, c)) if c > 0. => (Some(k), c),
+            _ => (None, 0.),
+        }
+    }
+}
+
+/// The Pearson correlation of `x` and `y`, or `None` if either is constant.
+fn correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    let (mean_x, mean_y) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (mut cov, mut var_x, mut var_y) = (0., 0., 0.);
+    for (a, b) in x.iter().zip(y) {
+        let (dx, dy) = (a - mean_x, b - mean_y);
+        cov += dx * dy;
+        var_x += dx * dx;
+        var_y += dy * dy;
+    }
+    (var_x > 0. && var_y > 0.).then(|| cov / (var_x * var_y).sqrt())
+}
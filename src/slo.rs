@@ -0,0 +1,465 @@
+//! Per-path service-level objectives.
+//!
+//! An [`Slo`] pairs a request path pattern with an availability target and, optionally, a
+//! latency target. Common Log Format does not record response times, so latency targets need logs
+//! with durations appended, such as nginx's `$request_time`: [`SloEvaluator::observe_canonical`]
+//! takes each entry's latency from its `duration`, as read by `duration::parse_timed`. Otherwise
+//! [`SloEvaluator::observe`] takes latencies from the caller.
+//!
+//! [`SloEvaluator`] reports compliance per period, after the fact. [`BurnAlerter`] watches for
+//! an objective's error budget burning too fast on any one endpoint, as it happens.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{pattern, window::bucket_start, LogEntry};
+
+/// A service-level objective for the requests whose path matches `pattern`.
+///
+/// `pattern` is matched against [`LogEntry::path`]; `*` matches any run of characters, so
+/// `/api/*` covers everything under `/api/`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Slo {
+    pub pattern: String,
+    /// Fraction of requests which must not fail with a 5xx status, e.g. `0.999`.
+    pub availability_target: f64,
+    pub latency: Option<LatencyTarget>,
+    /// Relative importance of this objective in [`PeriodReport::weighted_availability`].
+    pub weight: f64,
+}
+
+/// Fraction of requests (`target`) which must complete within `threshold`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LatencyTarget {
+    pub threshold: Duration,
+    pub target: f64,
+}
+
+impl Slo {
+    /// An availability-only objective with weight 1.
+    pub fn new(pattern: impl Into<String>, availability_target: f64) -> Self {
+        Slo {
+            pattern: pattern.into(),
+            availability_target,
+            latency: None,
+            weight: 1.,
+        }
+    }
+
+    pub fn with_latency(mut self, threshold: Duration, target: f64) -> Self {
+        self.latency = Some(LatencyTarget { threshold, target });
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Whether `entry` falls under this objective.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        entry
+            .path()
+            .is_some_and(|p| pattern::matches(&self.pattern, p))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    requests: u64,
+    failed: u64,
+    timed: u64,
+    slow: u64,
+}
+
+/// Evaluate a set of [`Slo`]s over a stream of entries, bucketed into fixed periods.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use common_log_format::{
+///     duration::{parse_timed, DurationUnit},
+///     slo::{Slo, SloEvaluator},
+///     ParseOptions,
+/// };
+/// let mut eval = SloEvaluator::new(
+///     vec![Slo::new("/api/*", 0.99).with_latency(Duration::from_millis(100), 0.9)],
+///     chrono::Duration::hours(1),
+/// );
+/// // nginx's combined format with `$request_time` appended.
+/// let log = "\
+/// 10.0.0.1 - - [2024-05-01T13:05:00Z] \"GET /api/x HTTP/1.1\" 200 10 \"-\" \"curl/8.5.0\" 0.020
+/// 10.0.0.1 - - [2024-05-01T13:06:00Z] \"GET /api/y HTTP/1.1\" 503 10 \"-\" \"curl/8.5.0\" 0.300
+/// ";
+/// for line in log.lines() {
+///     let entry = parse_timed(line, DurationUnit::NGINX, &ParseOptions::default()).unwrap();
+///     eval.observe_canonical(&entry);
+/// }
+///
+/// let reports = eval.reports();
+/// let obj = &reports[0].objectives[0];
+/// assert_eq!(obj.requests, 2);
+/// assert_eq!(obj.availability, 0.5);
+/// assert_eq!(obj.latency_compliance, Some(0.5));
+/// // Half the requests failed against an allowance of 1%.
+/// assert!((obj.availability_budget_consumed - 50.).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SloEvaluator {
+    slos: Vec<Slo>,
+    period: chrono::Duration,
+    counts: BTreeMap<DateTime<Utc>, Vec<Counts>>,
+}
+
+impl SloEvaluator {
+    pub fn new(slos: Vec<Slo>, period: chrono::Duration) -> Self {
+        SloEvaluator {
+            slos,
+            period,
+            counts: Default::default(),
+        }
+    }
+
+    pub fn slos(&self) -> &[Slo] {
+        &self.slos
+    }
+
+    /// Account for `entry`, taking its latency from its logged `duration`, if it has one.
+    #[cfg(feature = "formats")]
+    pub fn observe_canonical(&mut self, entry: &crate::canonical::CanonicalEntry) {
+        self.observe(&entry.entry, entry.duration.map(|d| d.duration));
+    }
+
+    /// Account for `entry`, which took `latency` to serve if known.
+    ///
+    /// Entries without a timestamp, path, or status code are ignored.
+    pub fn observe(&mut self, entry: &LogEntry, latency: Option<Duration>) {
+        let (time, status) = match (entry.time, entry.status_code) {
+            (Some(t), Some(s)) => (t, s),
+            _ => return,
+        };
+
+        let n = self.slos.len();
+        let period = self
+            .counts
+            .entry(bucket_start(time, self.period))
+            .or_insert_with(|| vec![Counts::default(); n]);
+        for (slo, c) in self.slos.iter().zip(period.iter_mut()) {
+            if !slo.matches(entry) {
+                continue;
+            }
+
+            c.requests += 1;
+            if status.is_server_error() {
+                c.failed += 1;
+            }
+
+            if let (Some(target), Some(latency)) = (slo.latency, latency) {
+                c.timed += 1;
+                if latency > target.threshold {
+                    c.slow += 1;
+                }
+            }
+        }
+    }
+
+    /// One report per period which saw at least one entry, in chronological order.
+    pub fn reports(&self) -> Vec<PeriodReport> {
+        self.counts
+            .iter()
+            .map(|(start, counts)| PeriodReport {
+                start: *start,
+                objectives: self
+                    .slos
+                    .iter()
+                    .zip(counts)
+                    .map(|(slo, c)| ObjectiveReport::new(slo, c))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Compliance of every objective over one period.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PeriodReport {
+    pub start: DateTime<Utc>,
+    /// In the same order as the objectives passed to [`SloEvaluator::new`].
+    pub objectives: Vec<ObjectiveReport>,
+}
+
+impl PeriodReport {
+    /// Availability averaged over the objectives which saw requests, weighted by [`Slo::weight`].
+    pub fn weighted_availability(&self) -> Option<f64> {
+        let (sum, weights) = self
+            .objectives
+            .iter()
+            .filter(|o| o.requests > 0)
+            .fold((0., 0.), |(s, w), o| {
+                (s + o.availability * o.weight, w + o.weight)
+            });
+        if weights > 0. {
+            Some(sum / weights)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compliance of one objective over one period.
+///
+/// Compliance values are fractions in `[0, 1]`. Budget consumption is the fraction of the
+/// allowed failures which were used up: `1.0` means the objective was exactly met, and anything
+/// above means it was violated.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ObjectiveReport {
+    pub pattern: String,
+    pub weight: f64,
+    pub requests: u64,
+    pub availability: f64,
+    pub availability_budget_consumed: f64,
+    /// `None` if the objective has no latency target or no latencies were observed.
+    pub latency_compliance: Option<f64>,
+    pub latency_budget_consumed: Option<f64>,
+}
+
+impl ObjectiveReport {
+    fn new(slo: &Slo, c: &Counts) -> Self {
+        let availability = compliance(c.requests, c.failed);
+        let (latency_compliance, latency_budget_consumed) = match slo.latency {
+            Some(t) if c.timed > 0 => {
+                let l = compliance(c.timed, c.slow);
+                (Some(l), Some(budget_consumed(l, t.target)))
+            }
+            _ => (None, None),
+        };
+
+        ObjectiveReport {
+            pattern: slo.pattern.clone(),
+            weight: slo.weight,
+            requests: c.requests,
+            availability,
+            availability_budget_consumed: budget_consumed(availability, slo.availability_target),
+            latency_compliance,
+            latency_budget_consumed,
+        }
+    }
+}
+
+fn compliance(total: u64, bad: u64) -> f64 {
+    if total == 0 {
+        1.
+    } else {
+        (total - bad) as f64 / total as f64
+    }
+}
+
+pub(crate) fn budget_consumed(compliance: f64, target: f64) -> f64 {
+    let allowed = 1. - target;
+    let used = 1. - compliance;
+    if allowed <= 0. {
+        if used > 0. {
+            f64::INFINITY
+        } else {
+            0.
+        }
+    } else {
+        used / allowed
+    }
+}
+
+/// `path` with segments which look like identifiers (numbers, long hex strings, and UUIDs)
+/// replaced by `*`, so that requests for different resources of the same endpoint group together.
+///
+/// # Example
+/// ```
+/// use common_log_format::slo::normalize_path;
+/// assert_eq!(normalize_path("/users/1234/orders"), "/users/*/orders");
+/// assert_eq!(normalize_path("/blob/9f86d081884c7d65/raw"), "/blob/*/raw");
+/// assert_eq!(normalize_path("/sessions/123e4567-e89b-12d3-a456-426614174000"), "/sessions/*");
+/// assert_eq!(normalize_path("/v2/status"), "/v2/status");
+/// ```
+pub fn normalize_path(path: &str) -> String {
+    let is_id = |seg: &str| {
+        let hex = seg.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+        !seg.is_empty()
+            && (seg.bytes().all(|b| b.is_ascii_digit())
+                || (hex && seg.len() >= 8 && seg.bytes().any(|b| b.is_ascii_digit())))
+    };
+
+    path.split('/')
+        .map(|seg| if is_id(seg) { "*" } else { seg })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// An objective's error budget burning too fast on one endpoint.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct BurnAlert {
+    /// The [`Slo::pattern`] of the objective.
+    pub pattern: String,
+    /// The endpoint, as given by [`normalize_path`].
+    pub path: String,
+    pub at: DateTime<Utc>,
+    /// The requests and failures in the window ending at `at`.
+    pub requests: u64,
+    pub failed: u64,
+    /// How many times faster than sustainable the budget is being used: the failure rate over the
+    /// window divided by the rate the objective allows.
+    pub burn_rate: f64,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Endpoint {
+    /// Time and whether it failed, for each request in the window.
+    recent: VecDeque<(DateTime<Utc>, bool)>,
+    failed: u64,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+impl Endpoint {
+    /// Forget the requests at or before `cutoff`.
+    fn expire(&mut self, cutoff: DateTime<Utc>) {
+        while let Some(&(t, f)) = self.recent.front() {
+            if t > cutoff {
+                break;
+            }
+            self.recent.pop_front();
+            self.failed -= f as u64;
+        }
+    }
+}
+
+/// Alert when an objective's availability budget burns too fast on any one endpoint.
+///
+/// Each endpoint ([`normalize_path`] of the requests matching an objective) is tracked
+/// separately, over a sliding window of log time. Once an endpoint alerts, it doesn't alert again
+/// until the cooldown has passed, but other endpoints still can. An endpoint with no requests in
+/// the window, and no alert still cooling down, is forgotten, so memory use follows the number of
+/// endpoints requested recently rather than ever.
+///
+/// # Example
+/// ```
+/// use common_log_format::{slo::{BurnAlerter, Slo}, LogEntry};
+/// let mut alerts = BurnAlerter::new(
+///     vec![Slo::new("/api/*", 0.99)],
+///     chrono::Duration::minutes(5),
+///     10.,
+/// )
+/// .with_min_requests(3)
+/// .with_cooldown(chrono::Duration::minutes(30));
+///
+/// let line = |sec: u32, path: &str| -> LogEntry {
+///     format!("10.0.0.1 - - [2024-05-01T13:00:{:02}Z] \"GET {} HTTP/1.1\" 503 0", sec, path)
+///         .parse()
+///         .unwrap()
+/// };
+/// let fired: Vec<_> = (0..6)
+///     .map(|s| line(s, &format!("/api/users/{}", s)))
+///     .chain((6..9).map(|s| line(s, "/api/orders")))
+///     .filter_map(|e| alerts.observe(&e))
+///     .map(|a| a.path)
+///     .collect();
+/// // Each failing endpoint alerts once.
+/// assert_eq!(fired, ["/api/users/*", "/api/orders"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BurnAlerter {
+    slos: Vec<Slo>,
+    window: chrono::Duration,
+    threshold: f64,
+    min_requests: u64,
+    cooldown: chrono::Duration,
+    endpoints: HashMap<(usize, String), Endpoint>,
+    /// When idle endpoints were last forgotten.
+    swept: Option<DateTime<Utc>>,
+}
+
+impl BurnAlerter {
+    /// Alert when the burn rate over `window` reaches `threshold`.
+    ///
+    /// By default an endpoint needs 10 requests in the window to alert, and alerts at most once
+    /// an hour.
+    pub fn new(slos: Vec<Slo>, window: chrono::Duration, threshold: f64) -> Self {
+        BurnAlerter {
+            slos,
+            window,
+            threshold,
+            min_requests: 10,
+            cooldown: chrono::Duration::hours(1),
+            endpoints: Default::default(),
+            swept: None,
+        }
+    }
+
+    pub fn with_min_requests(mut self, min_requests: u64) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: chrono::Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Account for `entry`, returning an alert if it pushed its endpoint over the threshold.
+    ///
+    /// Entries without a timestamp, path, or status code are ignored. If an entry matches more
+    /// than one objective, only the first alert is returned.
+    pub fn observe(&mut self, entry: &LogEntry) -> Option<BurnAlert> {
+        let (time, status, path) = match (entry.time, entry.status_code, entry.path()) {
+            (Some(t), Some(s), Some(p)) => (t, s, p),
+            _ => return None,
+        };
+
+        let failed = status.is_server_error();
+        let path = normalize_path(path);
+        let mut alert = None;
+        for (i, slo) in self.slos.iter().enumerate() {
+            if !slo.matches(entry) {
+                continue;
+            }
+
+            let ep = self.endpoints.entry((i, path.clone())).or_default();
+            ep.recent.push_back((time, failed));
+            ep.failed += failed as u64;
+            ep.expire(time - self.window);
+
+            let requests = ep.recent.len() as u64;
+            let burn_rate =
+                budget_consumed(compliance(requests, ep.failed), slo.availability_target);
+            let cooled = ep.last_alert.is_none_or(|t| time - t >= self.cooldown);
+            if requests >= self.min_requests && burn_rate >= self.threshold && cooled {
+                ep.last_alert = Some(time);
+                alert.get_or_insert(BurnAlert {
+                    pattern: slo.pattern.clone(),
+                    path: path.clone(),
+                    at: time,
+                    requests,
+                    failed: ep.failed,
+                    burn_rate,
+                });
+            }
+        }
+
+        if self.swept.is_none_or(|t| time - t >= self.window) {
+            self.sweep(time);
+        }
+        alert
+    }
+
+    /// Forget the endpoints with nothing in the window ending at `now`, and no alert which is
+    /// still cooling down.
+    fn sweep(&mut self, now: DateTime<Utc>) {
+        let (window, cooldown) = (self.window, self.cooldown);
+        self.endpoints.retain(|_, ep| {
+            ep.expire(now - window);
+            !ep.recent.is_empty() || ep.last_alert.is_some_and(|t| now - t < cooldown)
+        });
+        self.swept = Some(now);
+    }
+}
@@ -0,0 +1,198 @@
+//! Spotting slow-client attacks in logs with request durations.
+//!
+//! A Slowloris-style attack ties up a server's connections by sending requests as slowly as it
+//! can, so the log fills with requests which took a long time and moved almost no data, often
+//! ending in 408 Request Timeout. One such request is an unlucky mobile client; dozens from one
+//! network within minutes are an attack, even if each comes from a different address. A
+//! [`SlowlorisDetector`] counts them per network and alerts, like [`crate::slo::BurnAlerter`],
+//! as the entry that crosses the threshold is observed.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{filter::Cidr, LogEntry};
+
+/// Many slow, small requests from one network.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SlowlorisAlert {
+    /// The network, with host bits zeroed.
+    pub network: Cidr,
+    pub at: DateTime<Utc>,
+    /// The slow requests in the window ending at `at`.
+    pub slow_requests: u64,
+    /// The number of different addresses they came from.
+    pub hosts: usize,
+    pub mean_duration: Duration,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Network {
+    /// Time, host, and duration of each slow request in the window.
+    recent: VecDeque<(DateTime<Utc>, IpAddr, Duration)>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+/// Alert when a network sends many requests which took long and transferred little.
+///
+/// A request counts as slow if it took at least the minimum duration and its object size is at
+/// most the byte limit (a missing size counts as 0). Addresses are grouped into networks by
+/// prefix, /24 for IPv4 and /64 for IPv6 by default, and slow requests are counted per network
+/// over a sliding window of log time. Once a network alerts, it doesn't alert again until the
+/// cooldown has passed.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use common_log_format::{slowloris::SlowlorisDetector, LogEntry};
+/// let mut detector = SlowlorisDetector::new(chrono::Duration::minutes(5), 5);
+///
+/// let line = |host: u8, sec: u32| -> LogEntry {
+///     format!("198.51.100.{} - - [2024-05-01T13:00:{:02}Z] \"GET / HTTP/1.1\" 408 0", host, sec)
+///         .parse()
+///         .unwrap()
+/// };
+/// let alerts: Vec<_> = (0..10)
+///     .filter_map(|i| detector.observe(&line(i, i as u32), Duration::from_secs(120)))
+///     .collect();
+/// // The fifth slow request alerts, and the cooldown holds back the rest.
+/// assert_eq!(alerts.len(), 1);
+/// assert_eq!(alerts[0].network, "198.51.100.0/24".parse().unwrap());
+/// assert_eq!((alerts[0].slow_requests, alerts[0].hosts), (5, 5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SlowlorisDetector {
+    window: chrono::Duration,
+    threshold: u64,
+    min_duration: Duration,
+    max_bytes: usize,
+    prefix_v4: u8,
+    prefix_v6: u8,
+    cooldown: chrono::Duration,
+    networks: HashMap<Cidr, Network>,
+}
+
+impl SlowlorisDetector {
+    /// Alert when a network sends `threshold` slow requests within `window`.
+    ///
+    /// By default a request is slow if it took at least 30 seconds and transferred at most 1 KiB,
+    /// and a network alerts at most once an hour.
+    pub fn new(window: chrono::Duration, threshold: u64) -> Self {
+        SlowlorisDetector {
+            window,
+            threshold: threshold.max(1),
+            min_duration: Duration::from_secs(30),
+            max_bytes: 1024,
+            prefix_v4: 24,
+            prefix_v6: 64,
+            cooldown: chrono::Duration::hours(1),
+            networks: HashMap::new(),
+        }
+    }
+
+    pub fn with_min_duration(mut self, min_duration: Duration) -> Self {
+        self.min_duration = min_duration;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Group IPv4 addresses by their first `v4` bits and IPv6 addresses by their first `v6` bits.
+    pub fn with_prefix_lengths(mut self, v4: u8, v6: u8) -> Self {
+        self.prefix_v4 = v4.min(32);
+        self.prefix_v6 = v6.min(128);
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: chrono::Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Account for `entry`, which took `duration` to serve, returning an alert if it pushed its
+    /// network over the threshold.
+    ///
+    /// Entries without a host or timestamp are ignored.
+    pub fn observe(&mut self, entry: &LogEntry, duration: Duration) -> Option<SlowlorisAlert> {
+        let (host, time) = match (entry.host, entry.time) {
+            (Some(h), Some(t)) => (h.to_canonical(), t),
+            _ => return None,
+        };
+        if duration < self.min_duration || entry.object_size.unwrap_or(0) > self.max_bytes {
+            return None;
+        }
+
+        let network = self.network(host);
+        let net = self.networks.entry(network).or_default();
+        net.recent.push_back((time, host, duration));
+        while let Some(&(t, _, _)) = net.recent.front() {
+            if t > time - self.window {
+                break;
+            }
+            net.recent.pop_front();
+        }
+
+        let slow_requests = net.recent.len() as u64;
+        let cooled = net.last_alert.is_none_or(|t| time - t >= self.cooldown);
+        if slow_requests < self.threshold || !cooled {
+            return None;
+        }
+
+        net.last_alert = Some(time);
+        let total: Duration = net.recent.iter().map(|(_, _, d)| *d).sum();
+        Some(SlowlorisAlert {
+            network,
+            at: time,
+            slow_requests,
+            hosts: net
+                .recent
+                .iter()
+                .map(|(_, h, _)| h)
+                .collect::<HashSet<_>>()
+                .len(),
+            mean_duration: total / slow_requests as u32,
+        })
+    }
+
+    /// Forget networks with no slow requests in the window ending at `now` and no alert still
+    /// cooling down, to bound memory.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let (window, cooldown) = (self.window, self.cooldown);
+        self.networks.retain(|_, n| {
+            n.recent.back().is_some_and(|&(t, _, _)| t > now - window)
+                || n.last_alert.is_some_and(|t| now - t < cooldown)
+        });
+    }
+
+    fn network(&self, ip: IpAddr) -> Cidr {
+        match ip {
+            IpAddr::V4(v4) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_v4 as u32)
+                    .unwrap_or(0);
+                let net = Ipv4Addr::from(u32::from(v4) & mask);
+                Cidr {
+                    addr: IpAddr::V4(net),
+                    prefix_len: self.prefix_v4,
+                }
+            }
+            IpAddr::V6(v6) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_v6 as u32)
+                    .unwrap_or(0);
+                let net = Ipv6Addr::from(u128::from(v6) & mask);
+                Cidr {
+                    addr: IpAddr::V6(net),
+                    prefix_len: self.prefix_v6,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Sorting log files larger than memory by time.
+//!
+//! Sessions, request chains, and binary search over a file all need entries in time order, but
+//! logs gathered from several servers, or written by requests as they complete, rarely are.
+//! [`sort_by_time`] reads a log in runs which fit in a memory budget, sorts each run and spills it
+//! to a temporary file, then merges the runs into the output. Lines are written exactly as they
+//! were read.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{reader::trim_line_end, LogEntryRef};
+
+/// The most runs merged at once. More are merged in several passes, to bound open files.
+const MAX_FAN_IN: usize = 64;
+
+/// The memory a buffered line takes beyond its text.
+const LINE_OVERHEAD: usize = std::mem::size_of::<(Option<i64>, Vec<u8>)>();
+
+/// Numbers the temporary directories of sorts in this process.
+static SORTS: AtomicU64 = AtomicU64::new(0);
+
+/// What [`sort_by_time`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SortReport {
+    pub lines: u64,
+    /// Lines which didn't parse, or had no time. These come first in the output, in the order
+    /// they were read.
+    pub untimed: u64,
+    /// The sorted runs spilled to disk, or 0 if the log fit in memory.
+    pub runs: u64,
+}
+
+/// Sort the lines of `input` by their timestamps into `output`, holding about `memory_budget`
+/// bytes of lines in memory at a time, and spilling the rest to temporary files.
+///
+/// The sort is stable: lines with the same time stay in the order they were read. Lines which
+/// don't parse, or have no time, come first, as with [`crate::merge::merge_by_time`]. Blank lines
+/// are dropped.
+///
+/// # Example
+/// ```
+/// use common_log_format::sort::sort_by_time;
+/// let log = "\
+/// 10.0.0.1 - - [2024-05-01T13:00:03Z] \"GET /c HTTP/1.1\" 200 10
+/// 10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 200 10
+/// 10.0.0.1 - - [2024-05-01T13:00:04Z] \"GET /d HTTP/1.1\" 200 10
+/// 10.0.0.1 - - [2024-05-01T13:00:02+00:00] \"GET /b HTTP/1.1\" 200 10
+/// ";
+/// let mut sorted = Vec::new();
+/// // A budget of about one line, to spill every line to a run of its own.
+/// let report = sort_by_time(log.as_bytes(), &mut sorted, 100).unwrap();
+/// assert_eq!(report.runs, 4);
+///
+/// let paths: Vec<_> = String::from_utf8(sorted)
+///     .unwrap()
+///     .lines()
+///     .map(|l| l.split(' ').nth(5).unwrap().to_owned())
+///     .collect();
+/// assert_eq!(paths, ["/a", "/b", "/c", "/d"]);
+/// ```
+pub fn sort_by_time(
+    input: impl BufRead,
+    output: impl Write,
+    memory_budget: usize,
+) -> io::Result<SortReport> {
+    Sorter::new(memory_budget).run(input, output)
+}
+
+/// The options of [`sort_by_time`].
+#[derive(Debug, Clone)]
+pub struct Sorter {
+    memory_budget: usize,
+    temp_dir: PathBuf,
+}
+
+impl Sorter {
+    pub fn new(memory_budget: usize) -> Self {
+        Sorter {
+            memory_budget,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Spill runs to a directory made in `temp_dir`, rather than the system's temporary
+    /// directory. The runs together take about as much space as the input.
+    pub fn with_temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = temp_dir.into();
+        self
+    }
+
+    /// Sort `input` into `output`, as [`sort_by_time`] does.
+    pub fn run(&self, mut input: impl BufRead, mut output: impl Write) -> io::Result<SortReport> {
+        let mut report = SortReport::default();
+        let mut spill: Option<SpillDir> = None;
+        let mut runs = Vec::new();
+        let mut lines: Vec<(Option<i64>, Vec<u8>)> = Vec::new();
+        let mut used = 0;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let end = input.read_until(b'\n', &mut buf)? == 0;
+            if !end {
+                let line = trim_line_end(&buf);
+                if line.is_empty() {
+                    continue;
+                }
+                let time = LogEntryRef::from_bytes(line)
+                    .ok()
+                    .and_then(|e| e.time)
+                    .map(|t| t.timestamp_micros());
+                report.lines += 1;
+                report.untimed += time.is_none() as u64;
+                used += line.len() + LINE_OVERHEAD;
+                lines.push((time, line.to_vec()));
+                if used < self.memory_budget {
+                    continue;
+                }
+            }
+            if end && runs.is_empty() {
+                lines.sort_by_key(|(t, _)| *t);
+                for (_, line) in &lines {
+                    output.write_all(line)?;
+                    output.write_all(b"\n")?;
+                }
+                return output.flush().map(|()| report);
+            }
+            if !lines.is_empty() {
+                let dir = match &mut spill {
+                    Some(dir) => dir,
+                    None => spill.insert(SpillDir::create(&self.temp_dir)?),
+                };
+                lines.sort_by_key(|(t, _)| *t);
+                let path = dir.next_run();
+                let mut w = BufWriter::new(File::create(&path)?);
+                for (time, line) in lines.drain(..) {
+                    write_record(&mut w, time, &line)?;
+                }
+                w.flush()?;
+                runs.push(path);
+                report.runs += 1;
+                used = 0;
+            }
+            if end {
+                break;
+            }
+        }
+
+        // Merge adjacent runs, keeping them in input order for stability, until few enough
+        // remain to merge into the output.
+        let dir = spill.as_mut().expect("runs were spilled");
+        while runs.len() > MAX_FAN_IN {
+            let mut merged = Vec::new();
+            for batch in runs.chunks(MAX_FAN_IN) {
+                let path = dir.next_run();
+                let mut w = BufWriter::new(File::create(&path)?);
+                merge(batch, |time, line| write_record(&mut w, time, line))?;
+                w.flush()?;
+                for run in batch {
+                    fs::remove_file(run)?;
+                }
+                merged.push(path);
+            }
+            runs = merged;
+        }
+        merge(&runs, |_, line| {
+            output.write_all(line)?;
+            output.write_all(b"\n")
+        })?;
+        output.flush()?;
+        Ok(report)
+    }
+}
+
+/// A directory of runs, removed when dropped.
+struct SpillDir {
+    path: PathBuf,
+    runs: u64,
+}
+
+impl SpillDir {
+    fn create(parent: &Path) -> io::Result<Self> {
+        let path = parent.join(format!(
+            "clf-sort-{}-{}",
+            std::process::id(),
+            SORTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(SpillDir { path, runs: 0 })
+    }
+
+    fn next_run(&mut self) -> PathBuf {
+        self.runs += 1;
+        self.path.join(format!("run-{:06}", self.runs))
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Write a line of a run: whether it has a time, the time, the line's length, and the line.
+fn write_record(w: &mut impl Write, time: Option<i64>, line: &[u8]) -> io::Result<()> {
+    w.write_all(&[time.is_some() as u8])?;
+    w.write_all(&time.unwrap_or(0).to_le_bytes())?;
+    w.write_all(&(line.len() as u64).to_le_bytes())?;
+    w.write_all(line)
+}
+
+/// Read the next line of a run into `line`, returning its time, or `None` at the end.
+fn read_record(r: &mut impl Read, line: &mut Vec<u8>) -> io::Result<Option<Option<i64>>> {
+    let mut header = [0; 17];
+    match r.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let time = i64::from_le_bytes(header[1..9].try_into().expect("8 bytes"));
+    let len = u64::from_le_bytes(header[9..17].try_into().expect("8 bytes"));
+    line.clear();
+    r.take(len).read_to_end(line)?;
+    if line.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some((header[0] == 1).then_some(time)))
+}
+
+/// Merge the sorted `runs`, calling `f` with each line in order. Ties go to the earlier run.
+fn merge(
+    runs: &[PathBuf],
+    mut f: impl FnMut(Option<i64>, &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut readers = runs
+        .iter()
+        .map(|p| File::open(p).map(BufReader::new))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut lines = vec![Vec::new(); runs.len()];
+    let mut heads = BinaryHeap::new();
+    for (i, r) in readers.iter_mut().enumerate() {
+        if let Some(time) = read_record(r, &mut lines[i])? {
+            heads.push(Reverse((time, i)));
+        }
+    }
+    while let Some(Reverse((time, i))) = heads.pop() {
+        f(time, &lines[i])?;
+        if let Some(time) = read_record(&mut readers[i], &mut lines[i])? {
+            heads.push(Reverse((time, i)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Exporting entries to an [SQLite] database.
+//!
+//! An SQLite file is the easiest queryable artifact to hand someone for a mid-sized log. The
+//! schema is normalized: each distinct host and request line is stored once, and `entries`
+//! refers to them, with indexes on `time` and `status`. The `log` view joins them back into one
+//! row per entry:
+//!
+//! ```sql
+//! SELECT path, count(*) FROM log WHERE status >= 500 GROUP BY path;
+//! ```
+//!
+//! Times are stored as RFC 3339 text in UTC, to the microsecond, which SQLite's date and time
+//! functions understand and which sorts in time order.
+//!
+//! [`export_sqlite`] stores entries through an SQLite library linked into the crate (built from
+//! source by `rusqlite`), so nothing needs to be installed. [`SqliteScript`] instead only emits the
+//! SQL which creates and fills the database, for feeding to the `sqlite3` shell or another client.
+//!
+//! [SQLite]: https://sqlite.org
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    path::Path,
+};
+
+use rusqlite::{params, Connection};
+
+use crate::{sink::Sink, LogEntry};
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS hosts (
+    id INTEGER PRIMARY KEY,
+    addr TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS requests (
+    id INTEGER PRIMARY KEY,
+    line TEXT NOT NULL UNIQUE,
+    method TEXT,
+    target TEXT,
+    path TEXT,
+    protocol TEXT
+);
+CREATE TABLE IF NOT EXISTS entries (
+    id INTEGER PRIMARY KEY,
+    host_id INTEGER REFERENCES hosts (id),
+    ident TEXT,
+    authuser TEXT,
+    time TEXT,
+    request_id INTEGER REFERENCES requests (id),
+    status INTEGER,
+    size INTEGER
+);
+CREATE INDEX IF NOT EXISTS entries_time ON entries (time);
+CREATE INDEX IF NOT EXISTS entries_status ON entries (status);
+CREATE VIEW IF NOT EXISTS log AS
+SELECT e.id, h.addr AS host, e.ident, e.authuser, e.time, r.line AS request_line, r.method,
+    r.target, r.path, r.protocol, e.status, e.size
+FROM entries e
+LEFT JOIN hosts h ON h.id = e.host_id
+LEFT JOIN requests r ON r.id = e.request_id;
+";
+
+/// A [`Sink`] writing the SQL statements which store entries in the schema described in the
+/// [module docs](self), for the `sqlite3` shell or any other SQLite client.
+///
+/// The schema is created if it doesn't exist, so a script can add to an existing database.
+/// Inserts are grouped into transactions of 10,000 entries (by default), since SQLite is much
+/// slower committing each one.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sink::Sink, sqlite::SqliteScript, LogEntry};
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /it's HTTP/1.1\" 200 10".parse().unwrap();
+///
+/// let mut script = SqliteScript::new(Vec::new()).unwrap();
+/// script.send(&entry).unwrap();
+/// let sql = String::from_utf8(script.finish().unwrap()).unwrap();
+/// assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS hosts"));
+/// assert!(sql.contains("VALUES ('GET /it''s HTTP/1.1', 'GET', '/it''s', '/it''s', 'HTTP/1.1')"));
+/// assert!(sql.trim_end().ends_with("COMMIT;"));
+/// ```
+pub struct SqliteScript<W: Write> {
+    out: W,
+    batch_size: u64,
+    /// Entries written in the open transaction, if there is one.
+    in_batch: u64,
+    rows: u64,
+    buf: String,
+}
+
+impl<W: Write> SqliteScript<W> {
+    /// Start a script on `out`, writing the schema.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(SCHEMA.as_bytes())?;
+        Ok(SqliteScript {
+            out,
+            batch_size: 10_000,
+            in_batch: 0,
+            rows: 0,
+            buf: String::new(),
+        })
+    }
+
+    /// Commit every `batch_size` entries.
+    pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The number of entries sent so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Commit the open transaction, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Sink for SqliteScript<W> {
+    type Error = io::Error;
+
+    fn send(&mut self, e: &LogEntry) -> io::Result<()> {
+        let buf = &mut self.buf;
+        buf.clear();
+        if self.in_batch == 0 {
+            buf.push_str("BEGIN;\n");
+        }
+
+        let host = e.host.map(|h| h.to_string());
+        if let Some(host) = &host {
+            let _ = writeln!(
+                buf,
+                "INSERT OR IGNORE INTO hosts (addr) VALUES ({});",
+                quote(host)
+            );
+        }
+        if let Some(line) = &e.request_line {
+            let protocol = line.split(' ').nth(2);
+            let _ = writeln!(
+                buf,
+                "INSERT OR IGNORE INTO requests (line, method, target, path, protocol) VALUES ({}, {}, {}, {}, {});",
+                quote(line),
+                quote_opt(e.method()),
+                quote_opt(e.target()),
+                quote_opt(e.path()),
+                quote_opt(protocol)
+            );
+        }
+        let time = e
+            .time
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Micros, true));
+        let _ = writeln!(
+            buf,
+            "INSERT INTO entries (host_id, ident, authuser, time, request_id, status, size) VALUES ({}, {}, {}, {}, {}, {}, {});",
+            host.map_or("NULL".to_owned(), |h| format!("(SELECT id FROM hosts WHERE addr = {})", quote(&h))),
+            quote_opt(e.ident.as_deref()),
+            quote_opt(e.authuser.as_deref()),
+            quote_opt(time.as_deref()),
+            e.request_line.as_deref().map_or("NULL".to_owned(), |l| format!("(SELECT id FROM requests WHERE line = {})", quote(l))),
+            e.status_code.map_or("NULL".to_owned(), |s| s.as_u16().to_string()),
+            e.object_size.map_or("NULL".to_owned(), |s| s.to_string()),
+        );
+
+        self.in_batch += 1;
+        self.rows += 1;
+        if self.in_batch >= self.batch_size {
+            buf.push_str("COMMIT;\n");
+            self.in_batch = 0;
+        }
+        self.out.write_all(buf.as_bytes())
+    }
+
+    /// Commit the open transaction, and flush the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.in_batch > 0 {
+            self.out.write_all(b"COMMIT;\n")?;
+            self.in_batch = 0;
+        }
+        self.out.flush()
+    }
+}
+
+/// `s` as an SQL string literal.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn quote_opt(s: Option<&str>) -> String {
+    s.map_or("NULL".to_owned(), quote)
+}
+
+/// Store `entries` in the SQLite database at `path`, creating it if it doesn't exist, and return
+/// the number stored.
+///
+/// Entries are inserted in transactions of 10,000, like [`SqliteScript`]'s, and the first error
+/// stops the export; entries in transactions committed before then are kept.
+///
+/// # Example
+/// ```
+/// use common_log_format::{sqlite::export_sqlite, LogEntry};
+/// let entries: Vec<LogEntry> = [
+///     "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET /a HTTP/1.1\" 200 10",
+///     "10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /b HTTP/1.1\" 503 0",
+/// ]
+/// .iter()
+/// .map(|l| l.parse().unwrap())
+/// .collect();
+///
+/// let path = std::env::temp_dir().join(format!("clf-export-{}.db", std::process::id()));
+/// assert_eq!(export_sqlite(&path, &entries).unwrap(), 2);
+///
+/// let db = rusqlite::Connection::open(&path).unwrap();
+/// let path: String = db
+///     .query_row("SELECT path FROM log WHERE status >= 500", [], |row| row.get(0))
+///     .unwrap();
+/// assert_eq!(path, "/b");
+/// # drop(db);
+/// # std::fs::remove_file(std::env::temp_dir().join(format!("clf-export-{}.db", std::process::id()))).unwrap();
+/// ```
+pub fn export_sqlite<'a>(
+    path: impl AsRef<Path>,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+) -> io::Result<u64> {
+    export(path.as_ref(), entries).map_err(io::Error::other)
+}
+
+fn export<'a>(
+    path: &Path,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+) -> rusqlite::Result<u64> {
+    const BATCH_SIZE: usize = 10_000;
+
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let mut entries = entries.into_iter().peekable();
+    let mut stored = 0;
+    while entries.peek().is_some() {
+        let tx = conn.transaction()?;
+        {
+            let mut host = tx.prepare_cached("INSERT OR IGNORE INTO hosts (addr) VALUES (?1)")?;
+            let mut request = tx.prepare_cached(
+                "INSERT OR IGNORE INTO requests (line, method, target, path, protocol) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut entry = tx.prepare_cached(
+                "INSERT INTO entries (host_id, ident, authuser, time, request_id, status, size) \
+                 VALUES ((SELECT id FROM hosts WHERE addr = ?1), ?2, ?3, ?4, \
+                 (SELECT id FROM requests WHERE line = ?5), ?6, ?7)",
+            )?;
+            for e in entries.by_ref().take(BATCH_SIZE) {
+                let addr = e.host.map(|h| h.to_string());
+                if let Some(addr) = &addr {
+                    host.execute([addr])?;
+                }
+                if let Some(line) = &e.request_line {
+                    let protocol = line.split(' ').nth(2);
+                    request.execute(params![line, e.method(), e.target(), e.path(), protocol])?;
+                }
+                let time = e
+                    .time
+                    .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Micros, true));
+                entry.execute(params![
+                    addr,
+                    e.ident,
+                    e.authuser,
+                    time,
+                    e.request_line,
+                    e.status_code.map(|s| s.as_u16()),
+                    e.object_size.map(|s| s as i64),
+                ])?;
+                stored += 1;
+            }
+        }
+        tx.commit()?;
+    }
+    Ok(stored)
+}
@@ -0,0 +1,926 @@
+//! Summary statistics over a stream of entries.
+//!
+//! [`Stats`] keeps a bounded amount of state however many entries it sees (apart from a counter
+//! per time bucket), and produces a serializable [`Summary`]: the busiest hosts and paths, counts by status code, bytes sent, and
+//! requests over time. It implements [`Aggregate`], so it can also summarize each of a series of
+//! [`TumblingWindows`](crate::window::TumblingWindows).
+//!
+//! [`DistinctCount`] estimates the number of distinct hosts (or any other key) in fixed memory,
+//! using a [`HyperLogLog`], and [`HeavyHitters`] finds the most frequent keys using a
+//! [`CountMinSketch`]. [`QuantileSketch`] estimates percentiles and CDFs of object sizes,
+//! request durations, or any other non-negative quantity.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    window::{bucket_start, Aggregate},
+    LogEntry,
+};
+
+/// The most frequent keys in a stream, in bounded space.
+///
+/// This is the Space-Saving algorithm: at most `capacity` keys are counted, and a new key
+/// replaces the least frequent one, inheriting its count. Counts may therefore be overestimated
+/// by at most the smallest count tracked, but any key more frequent than `1 / capacity` of the
+/// stream is always present.
+///
+/// # Example
+/// ```
+/// use common_log_format::stats::TopK;
+/// let mut top = TopK::new(2);
+/// for k in ["a", "b", "a", "c", "a"] {
+///     top.observe(k);
+/// }
+/// assert_eq!(top.top(1), [("a", 3)]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TopK<K> {
+    capacity: usize,
+    counts: ScoreHeap<K, u64>,
+}
+
+impl<K: Hash + Eq + Clone + Ord> TopK<K> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        TopK {
+            capacity,
+            counts: ScoreHeap::new(capacity, fewer),
+        }
+    }
+
+    pub fn observe(&mut self, key: K) {
+        self.observe_n(key, 1)
+    }
+
+    /// Count `key` `n` times.
+    pub fn observe_n(&mut self, key: K, n: u64) {
+        if let Some(c) = self.counts.get(&key) {
+            self.counts.set(&key, c + n);
+        } else if self.counts.len() < self.capacity {
+            self.counts.insert(key, n);
+        } else {
+            let base = self.counts.min().map_or(0, |(_, c)| c);
+            self.counts.replace_min(key, base + n);
+        }
+    }
+
+    /// Up to `n` keys with their counts, most frequent first. Ties are broken by key.
+    pub fn top(&self, n: usize) -> Vec<(K, u64)> {
+        let mut top: Vec<(K, u64)> = self.counts.iter().map(|(k, c)| (k.clone(), c)).collect();
+        top.sort_unstable_by(|(ka, a), (kb, b)| b.cmp(a).then_with(|| ka.cmp(kb)));
+        top.truncate(n);
+        top
+    }
+
+    /// The number of keys being counted.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+/// Whether `a` is counted fewer times than `b`, or as many but with a greater key: the order in
+/// which [`TopK`] and [`HeavyHitters`] evict keys.
+fn fewer<K: Ord>(a: &(K, u64), b: &(K, u64)) -> bool {
+    (a.1, std::cmp::Reverse(&a.0)) < (b.1, std::cmp::Reverse(&b.0))
+}
+
+/// Keys with scores, kept as a binary min-heap under `less` with the position of each key in it,
+/// so the lowest-scoring key is found in constant time and a score changed in logarithmic time.
+#[derive(Debug, Clone)]
+pub(crate) struct ScoreHeap<K, S> {
+    heap: Vec<(K, S)>,
+    positions: HashMap<K, usize>,
+    less: fn(&(K, S), &(K, S)) -> bool,
+}
+
+impl<K: Hash + Eq + Clone, S: Copy> ScoreHeap<K, S> {
+    pub(crate) fn new(capacity: usize, less: fn(&(K, S), &(K, S)) -> bool) -> Self {
+        ScoreHeap {
+            heap: Vec::with_capacity(capacity),
+            positions: HashMap::with_capacity(capacity),
+            less,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<S> {
+        self.positions.get(key).map(|&i| self.heap[i].1)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, S)> {
+        self.heap.iter().map(|(k, s)| (k, *s))
+    }
+
+    /// The lowest-scoring key.
+    pub(crate) fn min(&self) -> Option<(&K, S)> {
+        self.heap.first().map(|(k, s)| (k, *s))
+    }
+
+    /// Add `key`, which must not be present.
+    pub(crate) fn insert(&mut self, key: K, score: S) {
+        self.positions.insert(key.clone(), self.heap.len());
+        self.heap.push((key, score));
+        self.sift_up(self.heap.len() - 1);
+    }
+
+    /// Change the score of `key`, if it is present.
+    pub(crate) fn set(&mut self, key: &K, score: S) {
+        if let Some(&i) = self.positions.get(key) {
+            self.heap[i].1 = score;
+            self.sift_up(i);
+            self.sift_down(self.positions[key]);
+        }
+    }
+
+    /// Replace the lowest-scoring key with `key`, which must not be present.
+    pub(crate) fn replace_min(&mut self, key: K, score: S) {
+        if self.heap.is_empty() {
+            return self.insert(key, score);
+        }
+        self.positions.remove(&self.heap[0].0);
+        self.positions.insert(key.clone(), 0);
+        self.heap[0] = (key, score);
+        self.sift_down(0);
+    }
+
+    /// Apply `f`, which must keep scores in the same order, to every score.
+    pub(crate) fn map_scores(&mut self, f: impl Fn(S) -> S) {
+        for (_, s) in &mut self.heap {
+            *s = f(*s);
+        }
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        for k in [i, j] {
+            *self
+                .positions
+                .get_mut(&self.heap[k].0)
+                .expect("every key has a position") = k;
+        }
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if !(self.less)(&self.heap[i], &self.heap[parent]) {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut least = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.heap.len() && (self.less)(&self.heap[child], &self.heap[least]) {
+                    least = child;
+                }
+            }
+            if least == i {
+                break;
+            }
+            self.swap(i, least);
+            i = least;
+        }
+    }
+}
+
+/// An estimate of the number of distinct items in a stream, in fixed memory.
+///
+/// With precision `p` this keeps `2^p` one-byte registers, and the estimate has a standard error
+/// of about `1.04 / sqrt(2^p)`: the default of 14 uses 16 KiB for an error of 0.8%. Sketches with
+/// the same precision can be merged, e.g. to combine counts from several files.
+///
+/// Items are hashed with the standard library's default hasher, which may change between Rust
+/// releases, so merge only sketches built by the same program.
+///
+/// # Example
+/// ```
+/// use common_log_format::stats::HyperLogLog;
+/// let mut hll = HyperLogLog::default();
+/// for i in 0..100_000u32 {
+///     hll.insert(&(i % 20_000));
+/// }
+/// let estimate = hll.estimate() as f64;
+/// assert!((estimate - 20_000.).abs() / 20_000. < 0.03);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new(14)
+    }
+}
+
+impl HyperLogLog {
+    /// Panics unless `precision` is between 4 and 18.
+    pub fn new(precision: u8) -> Self {
+        assert!(
+            (4..=18).contains(&precision),
+            "precision must be between 4 and 18"
+        );
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let p = self.precision;
+        let index = (hash >> (64 - p)) as usize;
+        // The remaining bits, with a sentinel so that the count of leading zeros is bounded.
+        let rest = (hash << p) | (1 << (p - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// The estimated number of distinct items inserted.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // Small cardinalities are more accurately estimated from the number of empty registers.
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+
+    /// Add the items counted by `other` to this sketch. Panics if the precisions differ.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(
+            self.precision, other.precision,
+            "can only merge sketches with the same precision"
+        );
+        for (r, &o) in self.registers.iter_mut().zip(&other.registers) {
+            *r = (*r).max(o);
+        }
+    }
+}
+
+/// The approximate number of distinct keys among entries, such as unique visitors.
+///
+/// Keys are extracted from each entry by a function; entries it returns `None` for are not
+/// counted.
+///
+/// # Example
+/// ```
+/// use common_log_format::{stats::DistinctCount, LogEntry};
+/// let mut hosts = DistinctCount::hosts();
+/// let mut paths = DistinctCount::new(|e: &LogEntry| e.path().map(str::to_owned));
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET /a HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:00:20Z] \"GET /a HTTP/1.1\" 200 100",
+///     "10.0.0.1 - - [2024-05-01T13:01:05Z] \"GET /b HTTP/1.1\" 200 50",
+/// ] {
+///     let e: LogEntry = line.parse().unwrap();
+///     hosts.observe(&e);
+///     paths.observe(&e);
+/// }
+/// assert_eq!(hosts.estimate(), 2);
+/// assert_eq!(paths.estimate(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DistinctCount<F> {
+    key: F,
+    sketch: HyperLogLog,
+}
+
+impl DistinctCount<fn(&LogEntry) -> Option<IpAddr>> {
+    /// Count distinct client hosts.
+    pub fn hosts() -> Self {
+        DistinctCount::new(|e: &LogEntry| e.host)
+    }
+}
+
+impl<F, K> DistinctCount<F>
+where
+    F: FnMut(&LogEntry) -> Option<K>,
+    K: Hash,
+{
+    pub fn new(key: F) -> Self {
+        DistinctCount {
+            key,
+            sketch: HyperLogLog::default(),
+        }
+    }
+
+    /// Use a sketch of the given precision; see [`HyperLogLog`].
+    pub fn with_precision(mut self, precision: u8) -> Self {
+        self.sketch = HyperLogLog::new(precision);
+        self
+    }
+
+    pub fn observe(&mut self, entry: &LogEntry) {
+        if let Some(k) = (self.key)(entry) {
+            self.sketch.insert(&k);
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        self.sketch.estimate()
+    }
+
+    pub fn sketch(&self) -> &HyperLogLog {
+        &self.sketch
+    }
+}
+
+impl<F, K> Aggregate for DistinctCount<F>
+where
+    F: FnMut(&LogEntry) -> Option<K>,
+    K: Hash,
+{
+    type Output = u64;
+
+    fn observe(&mut self, entry: &LogEntry) {
+        DistinctCount::observe(self, entry)
+    }
+
+    fn finish(self) -> u64 {
+        self.estimate()
+    }
+}
+
+/// Approximate counts of items in a stream, in fixed memory.
+///
+/// Each item is counted in one cell of each of `depth` rows of `width` counters, and its estimate
+/// is the smallest of those cells. Estimates are never too low; with probability `1 - delta` they
+/// are too high by at most `epsilon` times the total count, where [`CountMinSketch::with_error`]
+/// picks the dimensions for a given `epsilon` and `delta`.
+///
+/// # Example
+/// ```
+/// use common_log_format::stats::CountMinSketch;
+/// let mut cms = CountMinSketch::with_error(0.001, 0.01);
+/// for path in ["/a", "/b", "/a"] {
+///     cms.add(path, 1);
+/// }
+/// assert_eq!(cms.estimate("/a"), 2);
+/// assert_eq!(cms.total(), 3);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        assert!(width > 0 && depth > 0, "dimensions must be positive");
+        CountMinSketch {
+            width,
+            depth,
+            counts: vec![0; width * depth],
+            total: 0,
+        }
+    }
+
+    /// A sketch whose estimates are within `epsilon` of the total count, with probability
+    /// `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        assert!(
+            epsilon > 0. && delta > 0. && delta < 1.,
+            "epsilon must be positive and delta between 0 and 1"
+        );
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1. / delta).ln().ceil().max(1.) as usize;
+        Self::new(width, depth)
+    }
+
+    fn cells<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> + '_ {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+        // Derive the rows' hashes from two halves of one hash (Kirsch and Mitzenmacher).
+        let (h1, h2) = (hash as u32 as u64, (hash >> 32) | 1);
+        (0..self.depth).map(move |row| {
+            let h = h1.wrapping_add((row as u64).wrapping_mul(h2));
+            row * self.width + (h % self.width as u64) as usize
+        })
+    }
+
+    /// Count `item` `n` times, and return its new estimate.
+    pub fn add<T: Hash + ?Sized>(&mut self, item: &T, n: u64) -> u64 {
+        self.total += n;
+        let cells: Vec<usize> = self.cells(item).collect();
+        let mut estimate = u64::MAX;
+        for c in cells {
+            self.counts[c] += n;
+            estimate = estimate.min(self.counts[c]);
+        }
+        estimate
+    }
+
+    pub fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u64 {
+        self.cells(item).map(|c| self.counts[c]).min().unwrap_or(0)
+    }
+
+    /// The total of all counts added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Add the counts in `other` to this sketch. Panics if the dimensions differ.
+    pub fn merge(&mut self, other: &CountMinSketch) {
+        assert!(
+            self.width == other.width && self.depth == other.depth,
+            "can only merge sketches with the same dimensions"
+        );
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c += o;
+        }
+        self.total += other.total;
+    }
+}
+
+/// The most frequent keys in a stream, with memory fixed by the number of keys reported.
+///
+/// Every key is counted in a [`CountMinSketch`], and the `k` keys with the highest estimates so
+/// far are kept alongside. Unlike [`TopK`], memory does not grow with the number of candidates
+/// tracked, so a small `k` can be reported accurately from an arbitrarily large stream. The
+/// default sketch uses about 100 KiB, for estimates within 0.1% of the total with 99%
+/// probability.
+///
+/// # Example
+/// ```
+/// use common_log_format::{stats::HeavyHitters, LogEntry};
+/// let mut top = HeavyHitters::new(1);
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET /a HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:00:20Z] \"GET /b HTTP/1.1\" 200 100",
+///     "10.0.0.1 - - [2024-05-01T13:01:05Z] \"GET /a HTTP/1.1\" 200 50",
+/// ] {
+///     let e: LogEntry = line.parse().unwrap();
+///     top.observe(e.path().unwrap().to_owned());
+/// }
+/// assert_eq!(top.top(), [("/a".to_owned(), 2)]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeavyHitters<K> {
+    k: usize,
+    sketch: CountMinSketch,
+    top: ScoreHeap<K, u64>,
+}
+
+impl<K: Hash + Eq + Clone + Ord> HeavyHitters<K> {
+    /// Report the `k` most frequent keys.
+    pub fn new(k: usize) -> Self {
+        Self::with_sketch(k, CountMinSketch::with_error(0.001, 0.01))
+    }
+
+    pub fn with_sketch(k: usize, sketch: CountMinSketch) -> Self {
+        assert!(k > 0, "k must be positive");
+        HeavyHitters {
+            k,
+            sketch,
+            top: ScoreHeap::new(k, fewer),
+        }
+    }
+
+    pub fn observe(&mut self, key: K) {
+        self.observe_n(key, 1)
+    }
+
+    /// Count `key` `n` times.
+    pub fn observe_n(&mut self, key: K, n: u64) {
+        let estimate = self.sketch.add(&key, n);
+        if self.top.get(&key).is_some() {
+            self.top.set(&key, estimate);
+        } else if self.top.len() < self.k {
+            self.top.insert(key, estimate);
+        } else if self.top.min().is_some_and(|(_, min)| estimate > min) {
+            self.top.replace_min(key, estimate);
+        }
+    }
+
+    /// The keys with their estimated counts, most frequent first. Ties are broken by key.
+    pub fn top(&self) -> Vec<(K, u64)> {
+        let mut top: Vec<(K, u64)> = self.top.iter().map(|(k, c)| (k.clone(), c)).collect();
+        top.sort_unstable_by(|(ka, a), (kb, b)| b.cmp(a).then_with(|| ka.cmp(kb)));
+        top
+    }
+
+    pub fn sketch(&self) -> &CountMinSketch {
+        &self.sketch
+    }
+}
+
+/// Streaming quantiles of non-negative values, with bounded relative error.
+///
+/// This is a [DDSketch]: values are counted in logarithmically sized bins, so any quantile is
+/// estimated to within the given relative accuracy (1% by default) of a value actually at that
+/// rank, however skewed the distribution. Memory grows with the logarithm of the range of values,
+/// not their number, and sketches with the same accuracy can be merged.
+///
+/// [DDSketch]: https://arxiv.org/abs/1908.10693
+///
+/// # Example
+/// ```
+/// use common_log_format::{duration::{peel_duration, DurationUnit}, stats::QuantileSketch};
+/// // nginx's $request_time, from the end of each line.
+/// let mut latency = QuantileSketch::default();
+/// for t in ["0.002", "0.010", "0.011", "0.012", "1.500"] {
+///     let (d, _) = peel_duration(t, DurationUnit::NGINX).unwrap();
+///     latency.insert(d.unwrap().duration.as_secs_f64());
+/// }
+/// let median = latency.quantile(0.5).unwrap();
+/// assert!((median - 0.011).abs() <= 0.011 * 0.01);
+///
+/// let cdf = latency.to_cdf_points();
+/// assert_eq!(cdf.last().unwrap(), &(1.5, 1.0));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileSketch {
+    gamma: f64,
+    ln_gamma: f64,
+    /// Counts by bin; bin `i` holds values in `(gamma^(i-1), gamma^i]`.
+    bins: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::new(0.01)
+    }
+}
+
+impl QuantileSketch {
+    /// A sketch whose quantiles are within `relative_accuracy` (between 0 and 1) of the truth.
+    pub fn new(relative_accuracy: f64) -> Self {
+        assert!(
+            relative_accuracy > 0. && relative_accuracy < 1.,
+            "relative accuracy must be between 0 and 1"
+        );
+        let gamma = (1. + relative_accuracy) / (1. - relative_accuracy);
+        QuantileSketch {
+            gamma,
+            ln_gamma: gamma.ln(),
+            bins: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+            sum: 0.,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Panics if `value` is negative or NaN.
+    pub fn insert(&mut self, value: f64) {
+        self.insert_n(value, 1)
+    }
+
+    /// Count `value` `n` times. Panics if `value` is negative or NaN.
+    pub fn insert_n(&mut self, value: f64, n: u64) {
+        assert!(value >= 0., "values must be non-negative");
+        if n == 0 {
+            return;
+        }
+        if value == 0. {
+            self.zeros += n;
+        } else {
+            let bin = (value.ln() / self.ln_gamma).ceil() as i32;
+            *self.bins.entry(bin).or_default() += n;
+        }
+        self.count += n;
+        self.sum += value * n as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// The estimated `q`-quantile, for `q` from 0 to 1, or `None` if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        assert!((0. ..=1.).contains(&q), "q must be between 0 and 1");
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (q * (self.count - 1) as f64).floor() as u64;
+        if rank == 0 {
+            return Some(self.min);
+        } else if rank == self.count - 1 {
+            return Some(self.max);
+        } else if rank < self.zeros {
+            return Some(0.);
+        }
+        let mut seen = self.zeros;
+        for (&bin, &n) in &self.bins {
+            seen += n;
+            if seen > rank {
+                // The point in the bin with equal relative error to either end.
+                let v = 2. * self.gamma.powi(bin) / (self.gamma + 1.);
+                return Some(v.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// The estimated cumulative distribution, as `(value, fraction of values <= value)` at the
+    /// top of each non-empty bin, for plotting or fitting. The last point is the maximum.
+    pub fn to_cdf_points(&self) -> Vec<(f64, f64)> {
+        let total = self.count as f64;
+        let mut points = Vec::with_capacity(self.bins.len() + 1);
+        let mut seen = self.zeros;
+        if self.zeros > 0 {
+            points.push((0., seen as f64 / total));
+        }
+        for (&bin, &n) in &self.bins {
+            seen += n;
+            let top = self.gamma.powi(bin).min(self.max);
+            points.push((top, seen as f64 / total));
+        }
+        points
+    }
+
+    /// Add the values counted by `other` to this sketch. Panics if the accuracies differ.
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        assert_eq!(
+            self.gamma, other.gamma,
+            "can only merge sketches with the same accuracy"
+        );
+        for (&bin, &n) in &other.bins {
+            *self.bins.entry(bin).or_default() += n;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// The distribution of a numeric field over entries, such as object sizes.
+///
+/// Values are extracted from each entry by a function; entries it returns `None` for are not
+/// counted.
+///
+/// # Example
+/// ```
+/// use common_log_format::{stats::Quantiles, LogEntry};
+/// let mut sizes = Quantiles::object_sizes();
+/// for size in [100, 200, 300, 400, 10_000] {
+///     let line = format!("10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET / HTTP/1.1\" 200 {}", size);
+///     sizes.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+/// let p50 = sizes.sketch().quantile(0.5).unwrap();
+/// assert!((p50 - 300.).abs() <= 3.);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Quantiles<F> {
+    value: F,
+    sketch: QuantileSketch,
+}
+
+impl Quantiles<fn(&LogEntry) -> Option<f64>> {
+    /// The distribution of object sizes, in bytes.
+    pub fn object_sizes() -> Self {
+        Quantiles::new(|e: &LogEntry| e.object_size.map(|s| s as f64))
+    }
+}
+
+impl<F: FnMut(&LogEntry) -> Option<f64>> Quantiles<F> {
+    pub fn new(value: F) -> Self {
+        Quantiles {
+            value,
+            sketch: QuantileSketch::default(),
+        }
+    }
+
+    pub fn with_sketch(mut self, sketch: QuantileSketch) -> Self {
+        self.sketch = sketch;
+        self
+    }
+
+    pub fn observe(&mut self, entry: &LogEntry) {
+        if let Some(v) = (self.value)(entry) {
+            self.sketch.insert(v);
+        }
+    }
+
+    pub fn sketch(&self) -> &QuantileSketch {
+        &self.sketch
+    }
+}
+
+impl<F: FnMut(&LogEntry) -> Option<f64>> Aggregate for Quantiles<F> {
+    type Output = QuantileSketch;
+
+    fn observe(&mut self, entry: &LogEntry) {
+        Quantiles::observe(self, entry)
+    }
+
+    fn finish(self) -> QuantileSketch {
+        self.sketch
+    }
+}
+
+/// A serializable summary of a set of entries, produced by [`Stats`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Summary {
+    pub entries: u64,
+    /// The earliest and latest times seen.
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// The total of the entries' object sizes.
+    pub bytes: u64,
+    /// The number of entries with each status code.
+    pub statuses: BTreeMap<u16, u64>,
+    pub top_hosts: Vec<(IpAddr, u64)>,
+    pub top_paths: Vec<(String, u64)>,
+    /// The number of entries in each time bucket which saw any, by bucket start.
+    pub requests_per_bucket: BTreeMap<DateTime<Utc>, u64>,
+}
+
+/// Streaming statistics over entries.
+///
+/// # Example
+/// ```
+/// use common_log_format::{stats::Stats, LogEntry};
+/// let mut stats = Stats::new().with_bucket_width(chrono::Duration::minutes(1));
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:00:10Z] \"GET /a HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:00:20Z] \"GET /a HTTP/1.1\" 404 0",
+///     "10.0.0.1 - - [2024-05-01T13:01:05Z] \"GET /b?x=1 HTTP/1.1\" 200 50",
+/// ] {
+///     stats.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+///
+/// let summary = stats.summary();
+/// assert_eq!(summary.entries, 3);
+/// assert_eq!(summary.bytes, 150);
+/// assert_eq!(summary.statuses[&200], 2);
+/// assert_eq!(summary.top_hosts[0], ("10.0.0.1".parse().unwrap(), 2));
+/// assert_eq!(summary.top_paths[0], ("/a".to_owned(), 2));
+/// assert_eq!(summary.requests_per_bucket.values().collect::<Vec<_>>(), [&2, &1]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Stats {
+    entries: u64,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    bytes: u64,
+    statuses: BTreeMap<u16, u64>,
+    hosts: TopK<IpAddr>,
+    paths: TopK<String>,
+    top_n: usize,
+    bucket_width: chrono::Duration,
+    buckets: BTreeMap<DateTime<Utc>, u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    /// Report the top 10 hosts and paths, out of 1000 tracked, and requests per minute.
+    pub fn new() -> Self {
+        Stats {
+            entries: 0,
+            first: None,
+            last: None,
+            bytes: 0,
+            statuses: BTreeMap::new(),
+            hosts: TopK::new(1000),
+            paths: TopK::new(1000),
+            top_n: 10,
+            bucket_width: chrono::Duration::minutes(1),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Report the top `n` hosts and paths, out of `capacity` tracked of each.
+    pub fn with_top(mut self, n: usize, capacity: usize) -> Self {
+        self.top_n = n;
+        self.hosts = TopK::new(capacity);
+        self.paths = TopK::new(capacity);
+        self
+    }
+
+    pub fn with_bucket_width(mut self, width: chrono::Duration) -> Self {
+        self.bucket_width = width;
+        self
+    }
+
+    pub fn observe(&mut self, entry: &LogEntry) {
+        self.entries += 1;
+        if let Some(t) = entry.time {
+            self.first = Some(self.first.map_or(t, |f| f.min(t)));
+            self.last = Some(self.last.map_or(t, |l| l.max(t)));
+            *self
+                .buckets
+                .entry(bucket_start(t, self.bucket_width))
+                .or_default() += 1;
+        }
+        self.bytes += entry.object_size.unwrap_or(0) as u64;
+        if let Some(s) = entry.status_code {
+            *self.statuses.entry(s.as_u16()).or_default() += 1;
+        }
+        if let Some(h) = entry.host {
+            self.hosts.observe(h);
+        }
+        if let Some(p) = entry.path() {
+            self.paths.observe(p.to_owned());
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            entries: self.entries,
+            first: self.first,
+            last: self.last,
+            bytes: self.bytes,
+            statuses: self.statuses.clone(),
+            top_hosts: self.hosts.top(self.top_n),
+            top_paths: self.paths.top(self.top_n),
+            requests_per_bucket: self.buckets.clone(),
+        }
+    }
+}
+
+impl<'a> Extend<&'a LogEntry> for Stats {
+    fn extend<T: IntoIterator<Item = &'a LogEntry>>(&mut self, iter: T) {
+        for e in iter {
+            self.observe(e);
+        }
+    }
+}
+
+impl Aggregate for Stats {
+    type Output = Summary;
+
+    fn observe(&mut self, entry: &LogEntry) {
+        Stats::observe(self, entry)
+    }
+
+    fn finish(self) -> Summary {
+        self.summary()
+    }
+}
@@ -0,0 +1,260 @@
+//! Aggregates kept up to date as entries arrive, for dashboards to query without rereading logs.
+//!
+//! An [`AggregateStore`] keeps [`Counters`] per path per time bucket (an hour, say). Feeding it
+//! each new entry updates one counter, and a query over a time range only adds up the buckets in
+//! it. The store can be saved and loaded, so together with a checkpoint of the log it summarizes
+//! (see the `checkpoint` module), a process can stop and pick up where it left off.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::AddAssign,
+    path::Path,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{slo::normalize_path, window::bucket_start, LogEntry};
+
+/// Counts for a set of entries.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct Counters {
+    pub requests: u64,
+    /// Entries with 4xx statuses.
+    pub client_errors: u64,
+    /// Entries with 5xx statuses.
+    pub server_errors: u64,
+    pub bytes: u64,
+}
+
+impl Counters {
+    pub fn observe(&mut self, entry: &LogEntry) {
+        self.requests += 1;
+        self.bytes += entry.object_size.unwrap_or(0) as u64;
+        match entry.status_code {
+            Some(s) if s.is_client_error() => self.client_errors += 1,
+            Some(s) if s.is_server_error() => self.server_errors += 1,
+            _ => (),
+        }
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.requests += rhs.requests;
+        self.client_errors += rhs.client_errors;
+        self.server_errors += rhs.server_errors;
+        self.bytes += rhs.bytes;
+    }
+}
+
+/// Per-path [`Counters`] in fixed-width time buckets, aligned to the Unix epoch.
+///
+/// Entries without a time are not counted, and entries without a path are counted under `-`.
+/// Queries cover whole buckets: those which start within the range asked for.
+///
+/// # Example
+/// ```
+/// use chrono::Duration;
+/// use common_log_format::{store::AggregateStore, LogEntry};
+/// let mut store = AggregateStore::new(Duration::hours(1)).with_normalized_paths(true);
+/// for line in [
+///     "10.0.0.1 - - [2024-05-01T13:05:00Z] \"GET /users/1 HTTP/1.1\" 200 100",
+///     "10.0.0.2 - - [2024-05-01T13:50:00Z] \"GET /users/2 HTTP/1.1\" 500 0",
+///     "10.0.0.1 - - [2024-05-01T14:10:00Z] \"GET /users/3 HTTP/1.1\" 200 100",
+/// ] {
+///     store.observe(&line.parse::<LogEntry>().unwrap());
+/// }
+///
+/// let start = "2024-05-01T13:00:00Z".parse().unwrap();
+/// let end = "2024-05-01T14:00:00Z".parse().unwrap();
+/// let by_path = store.query(start, end);
+/// assert_eq!(by_path["/users/*"].requests, 2);
+/// assert_eq!(by_path["/users/*"].server_errors, 1);
+///
+/// let series = store.series("/users/*", start, end + Duration::hours(1));
+/// assert_eq!(series.len(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateStore {
+    bucket_width: chrono::Duration,
+    normalize_paths: bool,
+    buckets: BTreeMap<DateTime<Utc>, HashMap<String, Counters>>,
+}
+
+impl AggregateStore {
+    pub fn new(bucket_width: chrono::Duration) -> Self {
+        assert!(
+            bucket_width.num_seconds() > 0,
+            "buckets must be at least a second"
+        );
+        AggregateStore {
+            bucket_width,
+            normalize_paths: false,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Group paths with [`normalize_path`], so that `/users/1` and `/users/2` are counted
+    /// together as `/users/*`.
+    pub fn with_normalized_paths(mut self, normalize_paths: bool) -> Self {
+        self.normalize_paths = normalize_paths;
+        self
+    }
+
+    pub fn bucket_width(&self) -> chrono::Duration {
+        self.bucket_width
+    }
+
+    pub fn observe(&mut self, entry: &LogEntry) {
+        let t = match entry.time {
+            Some(t) => t,
+            None => return,
+        };
+        let path = match entry.path() {
+            Some(p) if self.normalize_paths => normalize_path(p),
+            Some(p) => p.to_owned(),
+            None => "-".to_owned(),
+        };
+        self.buckets
+            .entry(bucket_start(t, self.bucket_width))
+            .or_default()
+            .entry(path)
+            .or_default()
+            .observe(entry);
+    }
+
+    /// The totals for each path over the buckets starting in `[start, end)`.
+    pub fn query(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> BTreeMap<String, Counters> {
+        let mut totals: BTreeMap<String, Counters> = BTreeMap::new();
+        for paths in self.buckets.range(start..end).map(|(_, p)| p) {
+            for (path, c) in paths {
+                *totals.entry(path.clone()).or_default() += *c;
+            }
+        }
+        totals
+    }
+
+    /// The counters for `path` in each bucket starting in `[start, end)` which saw it.
+    pub fn series(
+        &self,
+        path: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, Counters)> {
+        self.buckets
+            .range(start..end)
+            .filter_map(|(t, paths)| Some((*t, *paths.get(path)?)))
+            .collect()
+    }
+
+    /// The totals over all paths in each bucket starting in `[start, end)`.
+    pub fn totals(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, Counters)> {
+        self.buckets
+            .range(start..end)
+            .map(|(t, paths)| {
+                let mut total = Counters::default();
+                for c in paths.values() {
+                    total += *c;
+                }
+                (*t, total)
+            })
+            .collect()
+    }
+
+    /// Drop the buckets which end at or before `t`, to bound the store's size.
+    pub fn prune_before(&mut self, t: DateTime<Utc>) {
+        let keep = bucket_start(t, self.bucket_width);
+        self.buckets = self.buckets.split_off(&keep);
+    }
+
+    /// Write the store to `path`, replacing it atomically.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut f = BufWriter::new(File::create(&tmp)?);
+        writeln!(f, "bucket_width={}", self.bucket_width.num_seconds())?;
+        writeln!(f, "normalize_paths={}", self.normalize_paths)?;
+        for (t, paths) in &self.buckets {
+            let t = t.to_rfc3339_opts(SecondsFormat::Secs, true);
+            let mut paths: Vec<_> = paths.iter().collect();
+            paths.sort_unstable_by_key(|(p, _)| *p);
+            for (p, c) in paths {
+                writeln!(
+                    f,
+                    "count={} {} {} {} {} {}",
+                    t, c.requests, c.client_errors, c.server_errors, c.bytes, p
+                )?;
+            }
+        }
+        f.into_inner()?.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    /// Read a store written by [`AggregateStore::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid aggregate store: {}", what),
+            )
+        };
+        let num = |v: &str| v.parse::<u64>().map_err(|_| invalid("bad number"));
+
+        let (mut width, mut normalize_paths) = (None, false);
+        let mut buckets: BTreeMap<DateTime<Utc>, HashMap<String, Counters>> = BTreeMap::new();
+        for l in fs::read_to_string(path)?.lines() {
+            match l.split_once('=') {
+                Some(("bucket_width", v)) => width = Some(num(v)?),
+                Some(("normalize_paths", v)) => {
+                    normalize_paths = v.parse().map_err(|_| invalid("bad bool"))?
+                }
+                Some(("count", v)) => {
+                    // The path goes last, since it may contain spaces.
+                    let fields: Vec<&str> = v.splitn(6, ' ').collect();
+                    if fields.len() != 6 {
+                        return Err(invalid(l));
+                    }
+                    let t = DateTime::parse_from_rfc3339(fields[0])
+                        .map_err(|_| invalid("bad time"))?
+                        .with_timezone(&Utc);
+                    let c = Counters {
+                        requests: num(fields[1])?,
+                        client_errors: num(fields[2])?,
+                        server_errors: num(fields[3])?,
+                        bytes: num(fields[4])?,
+                    };
+                    buckets
+                        .entry(t)
+                        .or_default()
+                        .insert(fields[5].to_owned(), c);
+                }
+                _ => return Err(invalid(l)),
+            }
+        }
+
+        let width = width
+            .filter(|&w| w > 0 && w <= i64::MAX as u64 / 1000)
+            .ok_or_else(|| invalid("missing bucket width"))?;
+        Ok(AggregateStore {
+            bucket_width: chrono::Duration::seconds(width as i64),
+            normalize_paths,
+            buckets,
+        })
+    }
+}
+
+impl<'a> Extend<&'a LogEntry> for AggregateStore {
+    fn extend<T: IntoIterator<Item = &'a LogEntry>>(&mut self, iter: T) {
+        for e in iter {
+            self.observe(e);
+        }
+    }
+}
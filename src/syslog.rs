@@ -0,0 +1,263 @@
+//! Sending entries to a syslog collector.
+//!
+//! [`SyslogSink`] wraps each entry's Common Log Format line in an [RFC 5424] message and sends it
+//! over UDP, TCP, or TLS. Over TCP and TLS, messages are framed by octet counting, as [RFC 6587]
+//! and [RFC 5425] describe, so lines never need escaping.
+//!
+//! [RFC 5424]: https://www.rfc-editor.org/rfc/rfc5424
+//! [RFC 5425]: https://www.rfc-editor.org/rfc/rfc5425
+//! [RFC 6587]: https://www.rfc-editor.org/rfc/rfc6587
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufWriter, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+};
+
+use chrono::SecondsFormat;
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, StreamOwned};
+
+use crate::{sink::Sink, LogEntry};
+
+/// The syslog facility messages are sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Ntp = 12,
+    Audit = 13,
+    Alert = 14,
+    Clock = 15,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// The syslog severity of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Emergency = 0,
+    Alert = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Informational = 6,
+    Debug = 7,
+}
+
+impl Severity {
+    /// The severity [`SyslogSink`] gives `entry` by default: [`Severity::Error`] for a 5xx
+    /// status, [`Severity::Warning`] for 4xx, and [`Severity::Informational`] otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{syslog::Severity, LogEntry};
+    /// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 404 0".parse().unwrap();
+    /// assert_eq!(Severity::of(&entry), Severity::Warning);
+    /// ```
+    pub fn of(entry: &LogEntry) -> Severity {
+        match entry.status_code.map(|s| s.as_u16()) {
+            Some(500..) => Severity::Error,
+            Some(400..=499) => Severity::Warning,
+            _ => Severity::Informational,
+        }
+    }
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Stream(BufWriter<Box<dyn Write + Send>>),
+}
+
+/// A [`Sink`] which sends entries to a syslog collector as RFC 5424 messages.
+///
+/// Each message has the entry's time (or none, if it has none), the facility
+/// [`Facility::Local7`] unless set otherwise, a severity from [`Severity::of`] unless mapped
+/// otherwise, the app name `access-log`, the message ID `access`, and the entry's line as its
+/// text. The hostname is left out unless it's set with [`SyslogSink::with_hostname`], and the
+/// collector fills in the sender's.
+///
+/// UDP sends each message straight away, and drops it if the collector isn't listening. TCP and
+/// TLS buffer messages until [`Sink::flush`], and fail once the connection is lost.
+///
+/// # Example
+/// ```
+/// use std::net::UdpSocket;
+/// use common_log_format::{syslog::SyslogSink, sink::Sink, LogEntry};
+///
+/// let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut sink = SyslogSink::udp(collector.local_addr().unwrap()).unwrap().with_hostname("web-1");
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 503 0".parse().unwrap();
+/// sink.send(&entry).unwrap();
+///
+/// let mut buf = [0; 1024];
+/// let n = collector.recv(&mut buf).unwrap();
+/// assert_eq!(
+///     std::str::from_utf8(&buf[..n]).unwrap(),
+///     // local7.err is 23 * 8 + 3.
+///     "<187>1 2024-05-01T13:00:00.000000Z web-1 access-log - access - \
+///      10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 503 0",
+/// );
+/// ```
+pub struct SyslogSink {
+    transport: Transport,
+    facility: Facility,
+    severity: fn(&LogEntry) -> Severity,
+    hostname: String,
+    app_name: String,
+    msg: String,
+}
+
+impl SyslogSink {
+    /// Send messages to the collector at `addr` over UDP.
+    pub fn udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for collector"))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(Self::new(Transport::Udp(socket)))
+    }
+
+    /// Send messages to the collector at `addr` over TCP.
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self::new(Transport::Stream(BufWriter::new(Box::new(
+            stream,
+        )))))
+    }
+
+    /// Send messages to the collector at `addr` over TLS, checking that its certificate is for
+    /// `server_name` with `config`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::sync::Arc;
+    /// use common_log_format::syslog::SyslogSink;
+    /// use rustls::pki_types::{pem::PemObject, CertificateDer};
+    /// let mut roots = rustls::RootCertStore::empty();
+    /// for cert in CertificateDer::pem_file_iter("/etc/ssl/certs/ca-certificates.crt").unwrap() {
+    ///     roots.add(cert.unwrap()).unwrap();
+    /// }
+    /// let config = rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    /// let sink = SyslogSink::tls("logs.example.com:6514", "logs.example.com", Arc::new(config)).unwrap();
+    /// ```
+    pub fn tls(
+        addr: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<Self> {
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+        let stream = StreamOwned::new(connection, TcpStream::connect(addr)?);
+        Ok(Self::new(Transport::Stream(BufWriter::new(Box::new(
+            stream,
+        )))))
+    }
+
+    fn new(transport: Transport) -> Self {
+        SyslogSink {
+            transport,
+            facility: Facility::Local7,
+            severity: Severity::of,
+            hostname: "-".to_owned(),
+            app_name: "access-log".to_owned(),
+            msg: String::new(),
+        }
+    }
+
+    pub fn with_facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Give each message the severity `severity` returns for its entry.
+    pub fn with_severity(mut self, severity: fn(&LogEntry) -> Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Send messages as from `hostname`. It should be the machine's name or address.
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.hostname = header_field(hostname, 255);
+        self
+    }
+
+    /// Send messages as from the application `app_name`.
+    pub fn with_app_name(mut self, app_name: &str) -> Self {
+        self.app_name = header_field(app_name, 48);
+        self
+    }
+}
+
+/// `s` as a header field: printable ASCII without spaces, up to `max` characters, or `-`.
+fn header_field(s: &str, max: usize) -> String {
+    let s: String = s
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if s.is_empty() {
+        "-".to_owned()
+    } else {
+        s
+    }
+}
+
+impl Sink for SyslogSink {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let priority = self.facility as u8 * 8 + (self.severity)(entry) as u8;
+        let time = entry.time.map_or("-".to_owned(), |t| {
+            t.to_rfc3339_opts(SecondsFormat::Micros, true)
+        });
+        let msg = &mut self.msg;
+        msg.clear();
+        let _ = write!(
+            msg,
+            "<{}>1 {} {} {} - access - {}",
+            priority, time, self.hostname, self.app_name, entry
+        );
+
+        match &mut self.transport {
+            Transport::Udp(socket) => match socket.send(msg.as_bytes()) {
+                // An earlier message was refused, because the collector wasn't listening.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+                result => result.map(|_| ()),
+            },
+            Transport::Stream(stream) => write!(stream, "{} {}", msg.len(), msg),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.transport {
+            Transport::Udp(_) => Ok(()),
+            Transport::Stream(stream) => stream.flush(),
+        }
+    }
+}
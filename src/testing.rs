@@ -0,0 +1,144 @@
+//! Generating entries for property tests and fuzzing.
+//!
+//! With the `arbitrary` feature, [`LogEntry`] implements `arbitrary::Arbitrary`, for fuzz targets
+//! which want structured entries rather than raw bytes. With the `proptest` feature, this module
+//! provides strategies for entries and their fields.
+//!
+//! Either way, only entries which can be written out and parsed back are generated: `ident` and
+//! `authuser` are single words which don't start with `-`, `request_line` has no `"`, and times
+//! are within the years 0 to 9999. So every generated entry `e` satisfies
+//! `e.to_string().parse::<LogEntry>().unwrap() == e`.
+
+#[cfg(feature = "proptest")]
+pub use self::strategies::*;
+#[cfg(feature = "arbitrary")]
+use crate::LogEntry;
+
+/// The first second of the year 0 and the last of the year 9999, the range RFC 3339 can write.
+const MIN_TIMESTAMP: i64 = -62_167_219_200;
+const MAX_TIMESTAMP: i64 = 253_402_300_799;
+
+/// Make `s` a word which parses back as itself, or `None` if nothing is left of it.
+#[cfg(feature = "arbitrary")]
+fn to_word(s: String) -> Option<String> {
+    let word: String = s
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '\t'))
+        .skip_while(|c| *c == '-')
+        .collect();
+    Some(word).filter(|w| !w.is_empty())
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> ::arbitrary::Arbitrary<'a> for LogEntry {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Self> {
+        let ident: Option<String> = u.arbitrary()?;
+        let authuser: Option<String> = u.arbitrary()?;
+        let time = match u.arbitrary()? {
+            true => {
+                let secs = u.int_in_range(MIN_TIMESTAMP..=MAX_TIMESTAMP)?;
+                let nanos = u.int_in_range(0..=999_999_999)?;
+                chrono::DateTime::from_timestamp(secs, nanos)
+            }
+            false => None,
+        };
+        let request_line: Option<String> = u.arbitrary()?;
+        let status_code = match u.arbitrary()? {
+            true => http::StatusCode::from_u16(u.int_in_range(100..=999)?).ok(),
+            false => None,
+        };
+        Ok(LogEntry {
+            host: u.arbitrary()?,
+            ident: ident.and_then(to_word),
+            authuser: authuser.and_then(to_word),
+            time,
+            request_line: request_line.map(|r| r.replace('"', "")),
+            status_code,
+            object_size: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod strategies {
+    use std::net::IpAddr;
+
+    use chrono::{DateTime, Utc};
+    use http::StatusCode;
+    use proptest::{option, prelude::*};
+
+    use super::{MAX_TIMESTAMP, MIN_TIMESTAMP};
+    use crate::LogEntry;
+
+    /// Entries with any combination of fields present.
+    ///
+    /// # Example
+    /// ```
+    /// use common_log_format::{testing::log_entry, LogEntry};
+    /// use proptest::{prop_assert_eq, test_runner::TestRunner};
+    ///
+    /// TestRunner::default()
+    ///     .run(&log_entry(), |entry| {
+    ///         let parsed: LogEntry = entry.to_string().parse().unwrap();
+    ///         prop_assert_eq!(parsed, entry);
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn log_entry() -> impl Strategy<Value = LogEntry> {
+        (
+            option::of(any::<IpAddr>()),
+            option::of(word()),
+            option::of(word()),
+            option::of(time()),
+            option::of(request_line()),
+            option::of(status_code()),
+            option::of(any::<usize>()),
+        )
+            .prop_map(
+                |(host, ident, authuser, time, request_line, status_code, object_size)| LogEntry {
+                    host,
+                    ident,
+                    authuser,
+                    time,
+                    request_line,
+                    status_code,
+                    object_size,
+                },
+            )
+    }
+
+    /// Values for `ident` and `authuser`: non-empty, without spaces or tabs, and not starting
+    /// with `-`.
+    pub fn word() -> impl Strategy<Value = String> {
+        "[^ \t-][^ \t]*"
+    }
+
+    /// Times between the years 0 and 9999, to the nanosecond.
+    pub fn time() -> impl Strategy<Value = DateTime<Utc>> {
+        (MIN_TIMESTAMP..=MAX_TIMESTAMP, 0..1_000_000_000u32)
+            .prop_map(|(secs, nanos)| DateTime::from_timestamp(secs, nanos).unwrap())
+    }
+
+    /// Request lines, half of them well-formed `METHOD target HTTP/x.y` lines and the rest any
+    /// text without `"`.
+    pub fn request_line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            (
+                "GET|HEAD|POST|PUT|DELETE|OPTIONS|PATCH",
+                "/[a-zA-Z0-9/._~%-]{0,32}(\\?[a-zA-Z0-9=&%]{1,16})?",
+                "HTTP/1\\.[01]|HTTP/2\\.0",
+            )
+                .prop_map(|(method, target, protocol)| format!(
+                    "{} {} {}",
+                    method, target, protocol
+                )),
+            "[^\"]*",
+        ]
+    }
+
+    /// Any three-digit status code.
+    pub fn status_code() -> impl Strategy<Value = StatusCode> {
+        (100..1000u16).prop_map(|code| StatusCode::from_u16(code).unwrap())
+    }
+}
@@ -0,0 +1,141 @@
+//! Heavy hitters over recent traffic.
+
+use std::{hash::Hash, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::stats::ScoreHeap;
+
+/// Rescale stored scores once they grow past this, to keep them in `f64` range.
+const RESCALE_THRESHOLD: f64 = 1e100;
+
+/// Move the landmark forward before weighting an observation by more than `e` to this power,
+/// so that the weight can't overflow.
+const MAX_GROWTH_EXPONENT: f64 = 200.;
+
+/// The most frequent keys in a stream, with older occurrences counting for exponentially less.
+///
+/// Each occurrence is worth half as much after every `half_life`, so [`DecayedTopK::top`]
+/// reflects what is busy right now rather than over all time. At most `capacity` keys are
+/// tracked; when a new key arrives at a full table it replaces the lowest-scoring one and
+/// inherits its score, so scores of recently-admitted keys may be overestimates.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use common_log_format::topk::DecayedTopK;
+/// let t0: chrono::DateTime<chrono::Utc> = "2024-05-01T13:00:00Z".parse().unwrap();
+/// let mut top = DecayedTopK::new(10, Duration::from_secs(60));
+/// for _ in 0..4 {
+///     top.observe("10.0.0.1", t0);
+/// }
+/// top.observe("10.0.0.2", t0 + chrono::Duration::minutes(2));
+/// top.observe("10.0.0.2", t0 + chrono::Duration::minutes(2));
+///
+/// // Two minutes on, the earlier burst of 4 is worth 1.
+/// let now = t0 + chrono::Duration::minutes(2);
+/// let ranked = top.top(2, now);
+/// assert_eq!(ranked[0].0, "10.0.0.2");
+/// assert!((ranked[0].1 - 2.).abs() < 1e-9);
+/// assert!((ranked[1].1 - 1.).abs() < 1e-9);
+///
+/// // A day later, hundreds of half-lives on, the burst has decayed away.
+/// let later = now + chrono::Duration::days(1);
+/// top.observe("10.0.0.3", later);
+/// top.observe("10.0.0.3", later);
+/// let ranked = top.top(3, later);
+/// assert_eq!(ranked[0].0, "10.0.0.3");
+/// assert!((ranked[0].1 - 2.).abs() < 1e-9);
+/// assert!(ranked.iter().all(|(_, s)| s.is_finite()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DecayedTopK<K> {
+    capacity: usize,
+    /// Decay rate per second.
+    lambda: f64,
+    /// Scores are stored relative to this instant so that observing never touches other keys.
+    landmark: Option<DateTime<Utc>>,
+    scores: ScoreHeap<K, f64>,
+}
+
+impl<K: Hash + Eq + Clone> DecayedTopK<K> {
+    /// Track up to `capacity` keys, halving their scores every `half_life`. Times are
+    /// compared to the millisecond, so shorter half-lives are taken as one millisecond.
+    pub fn new(capacity: usize, half_life: Duration) -> Self {
+        DecayedTopK {
+            capacity: capacity.max(1),
+            lambda: std::f64::consts::LN_2 / half_life.max(Duration::from_millis(1)).as_secs_f64(),
+            landmark: None,
+            scores: ScoreHeap::new(capacity, |a, b| a.1.total_cmp(&b.1).is_lt()),
+        }
+    }
+
+    pub fn observe(&mut self, key: K, at: DateTime<Utc>) {
+        self.observe_weighted(key, 1., at)
+    }
+
+    /// Count `weight` occurrences of `key` at time `at`, e.g. weighting by bytes served.
+    pub fn observe_weighted(&mut self, key: K, weight: f64, at: DateTime<Utc>) {
+        let mut landmark = *self.landmark.get_or_insert(at);
+        if self.exponent(landmark, at) > MAX_GROWTH_EXPONENT {
+            self.rescale(at);
+            landmark = at;
+        }
+        let w = weight * self.growth(landmark, at);
+
+        if let Some(s) = self.scores.get(&key) {
+            self.scores.set(&key, s + w);
+        } else if self.scores.len() < self.capacity {
+            self.scores.insert(key, w);
+        } else {
+            let min = self.scores.min().map_or(0., |(_, s)| s);
+            self.scores.replace_min(key, min + w);
+        }
+
+        if w > RESCALE_THRESHOLD {
+            self.rescale(at);
+        }
+    }
+
+    /// The `n` highest-scoring keys with their scores as of `now`, highest first.
+    pub fn top(&self, n: usize, now: DateTime<Utc>) -> Vec<(K, f64)> {
+        let landmark = match self.landmark {
+            Some(l) => l,
+            None => return vec![],
+        };
+
+        let decay = (-self.exponent(landmark, now)).exp();
+        let mut v: Vec<_> = self
+            .scores
+            .iter()
+            .map(|(k, s)| (k.clone(), s * decay))
+            .collect();
+        v.sort_by(|a, b| b.1.total_cmp(&a.1));
+        v.truncate(n);
+        v
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    fn exponent(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+        let secs = (to - from).num_milliseconds() as f64 / 1000.;
+        self.lambda * secs
+    }
+
+    fn growth(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+        self.exponent(from, to).exp()
+    }
+
+    fn rescale(&mut self, to: DateTime<Utc>) {
+        // Decay directly rather than dividing by the growth, which may have overflowed.
+        let decay = (-self.exponent(self.landmark.unwrap(), to)).exp();
+        self.scores.map_scores(|s| s * decay);
+        self.landmark = Some(to);
+    }
+}
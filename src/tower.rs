@@ -0,0 +1,301 @@
+//! Access logging middleware for `tower` HTTP services.
+//!
+//! [`AccessLogLayer`] wraps a service taking [`http::Request`](http1::Request)s, such as an axum
+//! router or a hyper service, and writes a Combined Log Format line for each request it answers.
+//! The line is written once the response body has been sent (or dropped, if the client went
+//! away), so its size is the number of body bytes actually sent, not the `Content-Length` the
+//! service claimed:
+//!
+//! | field         | from                                                                 |
+//! |---------------|----------------------------------------------------------------------|
+//! | host          | a [`SocketAddr`] in the request's extensions, unless set otherwise   |
+//! | authuser      | the user of HTTP Basic authentication, unless set otherwise          |
+//! | time          | when the request was received                                        |
+//! | request line  | the method, path and query (or the whole URI, if it's absolute), and version |
+//! | status        | the response's, or `-` if the service failed                         |
+//! | size          | the bytes of response body sent                                      |
+//! | referer, user agent | the request's `Referer` and `User-Agent` headers               |
+//!
+//! How long the request took, from receiving it to the end of the response body, can be
+//! appended to each line with [`AccessLogLayer::with_duration`], as Apache's `%D` and nginx's
+//! `$request_time` do.
+
+use std::{
+    fmt,
+    future::Future,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use bytes::Buf;
+use chrono::Utc;
+use http1::{header, request::Parts, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    combined::CombinedLogEntry,
+    duration::DurationUnit,
+    request_log::{basic_auth_user, Record, Writer},
+    LogEntry,
+};
+
+struct Log {
+    writer: Writer,
+    duration: Option<DurationUnit>,
+    client: fn(&Parts) -> Option<IpAddr>,
+    user: fn(&Parts) -> Option<String>,
+}
+
+/// The client address of a request whose extensions have the [`SocketAddr`] it came from, as
+/// servers built directly on hyper usually insert.
+pub fn socket_addr(parts: &Parts) -> Option<IpAddr> {
+    parts
+        .extensions
+        .get::<SocketAddr>()
+        .map(|a| a.ip().to_canonical())
+}
+
+/// The user of a request with HTTP Basic authentication, from its `Authorization` header.
+pub fn basic_auth(parts: &Parts) -> Option<String> {
+    let authorization = parts.headers.get(header::AUTHORIZATION)?;
+    basic_auth_user(authorization.to_str().ok()?)
+}
+
+/// A [`Layer`] which logs the requests answered by the services it wraps, as described in the
+/// [module docs](self).
+///
+/// Lines are written to one writer, shared by every clone of the layer and the services it
+/// makes, each with a single `write_all` while holding its lock. Errors writing are ignored.
+///
+/// # Example
+/// ```
+/// use std::{convert::Infallible, net::SocketAddr, sync::{Arc, Mutex}};
+/// use common_log_format::tower::AccessLogLayer;
+/// use bytes::Bytes;
+/// use http_body_util::{BodyExt, Full};
+/// use tower::{ServiceBuilder, ServiceExt};
+///
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+/// impl std::io::Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let buffer = Buffer::default();
+/// let service = ServiceBuilder::new()
+///     .layer(AccessLogLayer::new(buffer.clone()))
+///     .service_fn(|_| async { Ok::<_, Infallible>(http1::Response::new(Full::new(Bytes::from("hello")))) });
+///
+/// let mut request = http1::Request::get("/index.html?lang=en")
+///     .header("User-Agent", "curl/8.5.0")
+///     .body(())
+///     .unwrap();
+/// request.extensions_mut().insert("10.0.0.1:51234".parse::<SocketAddr>().unwrap());
+/// let response = service.oneshot(request).await.unwrap();
+/// response.into_body().collect().await.unwrap();
+///
+/// let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+/// assert!(line.starts_with("10.0.0.1 - - ["));
+/// assert!(line.ends_with("] \"GET /index.html?lang=en HTTP/1.1\" 200 5 \"-\" \"curl/8.5.0\"\n"));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    log: Arc<Log>,
+}
+
+impl AccessLogLayer {
+    /// Write lines to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        AccessLogLayer {
+            log: Arc::new(Log {
+                writer: Arc::new(Mutex::new(Box::new(writer))),
+                duration: None,
+                client: socket_addr,
+                user: basic_auth,
+            }),
+        }
+    }
+
+    /// Append how long each request took to its line, in `unit`.
+    pub fn with_duration(mut self, unit: DurationUnit) -> Self {
+        self.log_mut().duration = Some(unit);
+        self
+    }
+
+    /// Take each request's client address from its parts with `client`, instead of
+    /// [`socket_addr`].
+    pub fn with_client(mut self, client: fn(&Parts) -> Option<IpAddr>) -> Self {
+        self.log_mut().client = client;
+        self
+    }
+
+    /// Take the user each request was authenticated as from its parts with `user`, instead of
+    /// [`basic_auth`].
+    pub fn with_user(mut self, user: fn(&Parts) -> Option<String>) -> Self {
+        self.log_mut().user = user;
+        self
+    }
+
+    /// The log's settings, which are only changed while building the layer, before it's cloned.
+    fn log_mut(&mut self) -> &mut Log {
+        Arc::get_mut(&mut self.log).expect("layer is configured before it's cloned")
+    }
+}
+
+impl fmt::Debug for AccessLogLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogLayer")
+            .field("duration", &self.log.duration)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> AccessLog<S> {
+        AccessLog {
+            inner,
+            log: self.log.clone(),
+        }
+    }
+}
+
+/// A service which logs the requests its inner service answers. See [`AccessLogLayer`].
+#[derive(Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    log: Arc<Log>,
+}
+
+impl<S, B, ResB> Service<Request<B>> for AccessLog<S>
+where
+    S: Service<Request<B>, Response = Response<ResB>>,
+    ResB: Body,
+{
+    type Response = Response<LoggedBody<ResB>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let (parts, body) = request.into_parts();
+        let record = record(&self.log, &parts);
+        ResponseFuture {
+            inner: self.inner.call(Request::from_parts(parts, body)),
+            record: Some(record),
+        }
+    }
+}
+
+/// A record of the request with `parts`, to be written to `log`.
+fn record(log: &Log, parts: &Parts) -> Record {
+    let target = match parts.uri.scheme() {
+        Some(_) => parts.uri.to_string(),
+        None => parts
+            .uri
+            .path_and_query()
+            .map_or("/".to_owned(), |p| p.to_string()),
+    };
+    let header = |name| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let entry = CombinedLogEntry {
+        entry: LogEntry {
+            host: (log.client)(parts),
+            ident: None,
+            authuser: (log.user)(parts),
+            time: Some(Utc::now()),
+            request_line: Some(format!("{} {} {:?}", parts.method, target, parts.version)),
+            status_code: None,
+            object_size: Some(0),
+        },
+        referer: header(header::REFERER),
+        user_agent: header(header::USER_AGENT),
+    };
+    Record::new(&log.writer, log.duration, entry)
+}
+
+pin_project! {
+    /// The future of an [`AccessLog`]'s response.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        record: Option<Record>,
+    }
+}
+
+impl<F, ResB, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<ResB>, E>>,
+{
+    type Output = Result<Response<LoggedBody<ResB>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let mut record = this.record.take();
+        if let Some(record) = &mut record {
+            record.entry.entry.status_code =
+                http::StatusCode::from_u16(response.status().as_u16()).ok();
+        }
+        Poll::Ready(Ok(response.map(|inner| LoggedBody { inner, record })))
+    }
+}
+
+pin_project! {
+    /// A response body which counts the bytes sent, and logs its request once it ends or is
+    /// dropped.
+    pub struct LoggedBody<B> {
+        #[pin]
+        inner: B,
+        record: Option<Record>,
+    }
+}
+
+impl<B: Body> Body for LoggedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match (&frame, this.record.as_mut()) {
+            (Some(Ok(frame)), Some(record)) => {
+                if let Some(data) = frame.data_ref() {
+                    record.add_size(data.remaining());
+                }
+            }
+            (None, _) => drop(this.record.take()),
+            _ => (),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,180 @@
+//! Writing access logs from [`tracing`] spans.
+//!
+//! Servers instrumented with `tracing` usually open a span per request, and record its method,
+//! URI, and eventually its status on it or on events inside it. [`ClfLayer`] is a
+//! `tracing-subscriber` [`Layer`] which recognizes those fields, under the names common
+//! instrumentation uses, and writes a Common or Combined Log Format line for each request span
+//! when it closes:
+//!
+//! | field            | recognized names                                                      |
+//! |------------------|-----------------------------------------------------------------------|
+//! | request line     | `request`, `request_line`                                             |
+//! | method           | `method`, `http.method`, `http.request.method`                        |
+//! | target           | `uri`, `url`, `target`, `path`, `http.target`, `http.uri`, `url.path` |
+//! | protocol version | `version`, `http.version`, `http.flavor`, `network.protocol.version`  |
+//! | status           | `status`, `status_code`, `http.status_code`, `http.response.status_code` |
+//! | response size    | `bytes`, `size`, `http.response.body.size`, `http.response_content_length` |
+//! | client address   | `client_addr`, `remote_addr`, `peer_addr`, `client.address`, `http.client_ip` |
+//! | ident            | `ident`                                                               |
+//! | user             | `user`, `authuser`, `enduser.id`                                      |
+//! | referer          | `referer`, `http.referer`, `http.request.header.referer`              |
+//! | user agent       | `user_agent`, `http.user_agent`, `user_agent.original`                |
+//! | time             | `time`, `timestamp`, in RFC 3339                                      |
+//!
+//! A span is a request span if it has a request line, method, or target field when it is
+//! created. Its time is when it was created, unless it has a time field. The request line is made
+//! of the method, target, and version if it isn't recorded whole. Statuses may be numbers or begin with one (as `http::StatusCode`'s
+//! `Display` does), and client addresses may have a port. This matches the spans and events of
+//! tower-http's `TraceLayer`, with the response size recorded by the service.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+use std::{fmt, io::Write};
+
+use chrono::Utc;
+use tracing_core::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{fmt::MakeWriter, layer::Context, registry::LookupSpan, Layer};
+
+use crate::request_fields::Fields;
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field.name(), value.to_owned());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field.name(), value.to_string());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field.name(), format!("{:?}", value));
+    }
+}
+
+/// A [`Layer`] which writes a log line for each request span, as described in the
+/// [module docs](self).
+///
+/// Lines are in the Common Log Format unless [`ClfLayer::with_combined`] is set. Each is written
+/// with one call to a writer from the [`MakeWriter`], such as `std::io::stdout` or a
+/// `tracing-appender` file; errors writing are ignored.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use common_log_format::tracing::ClfLayer;
+/// use tracing_subscriber::prelude::*;
+///
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+/// impl std::io::Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// let buffer = Buffer::default();
+/// let writer = buffer.clone();
+/// let subscriber = tracing_subscriber::registry()
+///     .with(ClfLayer::new(move || writer.clone()).with_combined(true));
+///
+/// tracing::subscriber::with_default(subscriber, || {
+///     let span = tracing::info_span!(
+///         "request",
+///         method = "GET",
+///         uri = "/index.html",
+///         client_addr = "10.0.0.1:51234",
+///         user_agent = "curl/8.5.0",
+///         status = tracing::field::Empty,
+///         bytes = tracing::field::Empty,
+///     );
+///     span.in_scope(|| tracing::info!(status = 200, "finished processing request"));
+///     span.record("bytes", 512);
+/// });
+///
+/// let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+/// assert!(line.starts_with("10.0.0.1 - - ["));
+/// assert!(line.ends_with("] \"GET /index.html HTTP/1.1\" 200 512 \"-\" \"curl/8.5.0\"\n"));
+/// ```
+pub struct ClfLayer<W> {
+    make_writer: W,
+    combined: bool,
+}
+
+impl<W> ClfLayer<W>
+where
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    /// Write lines to writers from `make_writer`.
+    pub fn new(make_writer: W) -> Self {
+        ClfLayer {
+            make_writer,
+            combined: false,
+        }
+    }
+
+    /// Write lines in the Combined Log Format, with the referer and user agent.
+    pub fn with_combined(mut self, combined: bool) -> Self {
+        self.combined = combined;
+        self
+    }
+}
+
+impl<S, W> Layer<S> for ClfLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut request = Fields::default();
+        attrs.record(&mut request);
+        if !request.is_request() {
+            return;
+        }
+        request.time.get_or_insert_with(Utc::now);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(request);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(request) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(request);
+            }
+        }
+    }
+
+    /// Record fields of events in a request span, or in a span inside one, on the request.
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        for span in scope {
+            if let Some(request) = span.extensions_mut().get_mut::<Fields>() {
+                event.record(request);
+                return;
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(request) = span.extensions_mut().remove::<Fields>() else {
+            return;
+        };
+        let entry = request.into_entry();
+        let line = match self.combined {
+            true => format!("{}\n", entry),
+            false => format!("{}\n", entry.entry),
+        };
+        let _ = self.make_writer.make_writer().write_all(line.as_bytes());
+    }
+}
@@ -0,0 +1,145 @@
+//! Parsing `User-Agent` strings, and telling bots from people.
+//!
+//! Formats which log the user agent (such as the combined format, via
+//! [`CanonicalEntry::user_agent`](crate::canonical::CanonicalEntry)) can have it parsed into a
+//! [`UserAgent`] of browser, operating system, and device. Parsing uses the [Woothee] rules, and
+//! an agent is flagged as a bot if Woothee knows it as a crawler or it contains a token common to
+//! automated clients (`bot`, `spider`, `curl/`, `python-requests`, ...), since many crawlers are
+//! missing from any list.
+//!
+//! [Woothee]: https://github.com/woothee/woothee
+
+use std::borrow::Cow;
+
+use woothee::{parser::Parser, woothee::VALUE_UNKNOWN};
+
+use crate::canonical::CanonicalEntry;
+
+/// Substrings of lower-cased user agents which mark automated clients.
+const BOT_TOKENS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "scrapy",
+    "headless",
+    "facebookexternalhit",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "libwww-perl",
+    "apache-httpclient",
+    "node-fetch",
+    "axios/",
+];
+
+/// The kind of device an agent runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Device {
+    /// A desktop or laptop computer.
+    Desktop,
+    Smartphone,
+    /// A feature phone.
+    MobilePhone,
+    /// A game console, TV, or other appliance.
+    Appliance,
+    /// A crawler or other automated client.
+    Bot,
+    Unknown,
+}
+
+/// What a `User-Agent` string says about the client.
+///
+/// # Example
+/// ```
+/// use common_log_format::useragent::{Device, UserAgent};
+/// let ua = UserAgent::parse(
+///     "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+/// );
+/// assert_eq!(ua.browser.as_deref(), Some("Chrome"));
+/// assert_eq!(ua.os.as_deref(), Some("Windows 10"));
+/// assert_eq!(ua.device, Device::Desktop);
+/// assert!(!ua.is_bot);
+///
+/// let ua = UserAgent::parse("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)");
+/// assert_eq!(ua.browser.as_deref(), Some("Googlebot"));
+/// assert!(ua.is_bot);
+///
+/// assert!(UserAgent::parse("python-requests/2.31.0").is_bot);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct UserAgent {
+    /// The browser or crawler, such as `Firefox` or `Googlebot`.
+    pub browser: Option<String>,
+    pub browser_version: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub device: Device,
+    pub is_bot: bool,
+}
+
+impl UserAgent {
+    pub fn parse(ua: &str) -> Self {
+        let known = |s: &str| (s != VALUE_UNKNOWN && !s.is_empty()).then(|| s.to_owned());
+        let lower = ua.to_ascii_lowercase();
+        let has_bot_token = BOT_TOKENS.iter().any(|t| lower.contains(t));
+
+        let parsed = Parser::new().parse(ua);
+        let (browser, browser_version, os, os_version, category) = match &parsed {
+            Some(r) => (
+                known(r.name),
+                known(r.version),
+                known(r.os),
+                known(&r.os_version),
+                r.category,
+            ),
+            None => (None, None, None, None, VALUE_UNKNOWN),
+        };
+        let is_bot = category == "crawler" || has_bot_token;
+        let device = match category {
+            _ if is_bot => Device::Bot,
+            "pc" => Device::Desktop,
+            "smartphone" => Device::Smartphone,
+            "mobilephone" => Device::MobilePhone,
+            "appliance" => Device::Appliance,
+            _ => Device::Unknown,
+        };
+
+        UserAgent {
+            browser,
+            browser_version,
+            os,
+            os_version,
+            device,
+            is_bot,
+        }
+    }
+}
+
+/// Whether `ua` is a bot, without parsing the rest of it when a token gives it away.
+pub fn is_bot(ua: &str) -> bool {
+    let lower: Cow<str> = if ua.bytes().any(|b| b.is_ascii_uppercase()) {
+        ua.to_ascii_lowercase().into()
+    } else {
+        ua.into()
+    };
+    BOT_TOKENS.iter().any(|t| lower.contains(t))
+        || Parser::new()
+            .parse(ua)
+            .is_some_and(|r| r.category == "crawler")
+}
+
+impl CanonicalEntry {
+    /// The parsed `user_agent`, if the entry has one.
+    pub fn parsed_user_agent(&self) -> Option<UserAgent> {
+        self.user_agent
+            .as_deref()
+            .filter(|ua| !ua.is_empty())
+            .map(UserAgent::parse)
+    }
+}
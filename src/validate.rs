@@ -0,0 +1,264 @@
+//! Checking a log for signs it can't be trusted as written.
+//!
+//! A log can parse cleanly and still be wrong: lines appended out of order by several writers,
+//! or after a clock jumped back; status codes no server sends; a body counted for a response
+//! which can't have one; timestamps in more than one format, from two configurations writing to
+//! the same file. None of these stop an analysis, but each can quietly skew one. A [`Validator`]
+//! looks for them, and sums them up in a [`Report`] which serializes for other tools to read.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::LogEntry;
+
+/// A problem with one line.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// The line didn't parse.
+    Unparseable { error: String },
+    /// The entry's time is before the previous entry's, by more than the tolerance.
+    NonMonotonicTime {
+        previous: DateTime<Utc>,
+        time: DateTime<Utc>,
+    },
+    /// The status code is outside 100 to 599.
+    ImpossibleStatus { status: u16 },
+    /// A size above zero for a response which has no body: 1xx, 204 No Content, or 304 Not
+    /// Modified.
+    SizeWithoutBody { status: u16, size: usize },
+    /// The timestamp is written in a different format than the first in the log. Formats are
+    /// shown with each run of digits as `9` and each run of letters as `a`.
+    MixedTimestampFormat { format: String, first: String },
+}
+
+/// An [`Issue`] and the line it was found on, counting from 1.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Problem {
+    pub line: u64,
+    #[serde(flatten)]
+    pub issue: Issue,
+}
+
+/// What a [`Validator`] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Report {
+    pub lines: u64,
+    /// Lines which parsed.
+    pub entries: u64,
+    pub unparseable: u64,
+    pub non_monotonic_time: u64,
+    pub impossible_status: u64,
+    pub size_without_body: u64,
+    pub mixed_timestamp_format: u64,
+    /// How many timestamps were written in each format, with formats shown as in
+    /// [`Issue::MixedTimestampFormat`].
+    pub timestamp_formats: BTreeMap<String, u64>,
+    /// The first problems found, in order.
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Whether no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.unparseable
+            + self.non_monotonic_time
+            + self.impossible_status
+            + self.size_without_body
+            + self.mixed_timestamp_format
+            == 0
+    }
+}
+
+/// Checks lines or entries in order for the [`Issue`]s a log can have.
+///
+/// Only the first 100 problems are kept in the report by default, though all are counted.
+///
+/// # Example
+/// ```
+/// use common_log_format::validate::{Issue, Validator};
+/// let log = "\
+/// 10.0.0.1 - - [2024-05-01T13:00:05Z] \"GET / HTTP/1.1\" 200 512
+/// 10.0.0.1 - - [2024-05-01T13:00:01Z] \"GET /a HTTP/1.1\" 304 512
+/// 10.0.0.2 - - [2024-05-01T13:00:06+00:00] \"GET /b HTTP/1.1\" 200 10
+/// not a log line
+/// ";
+/// let report = Validator::new().validate(log.as_bytes()).unwrap();
+/// assert_eq!((report.lines, report.entries, report.unparseable), (4, 3, 1));
+/// assert_eq!(report.non_monotonic_time, 1);
+/// assert_eq!(report.problems[1].issue, Issue::SizeWithoutBody { status: 304, size: 512 });
+/// assert_eq!(report.timestamp_formats["9-9-9a9:9:9+9:9"], 1);
+/// assert!(!report.is_clean());
+///
+/// let json = serde_json::to_value(&report).unwrap();
+/// assert_eq!(json["problems"][0]["kind"], "non_monotonic_time");
+/// assert_eq!(json["problems"][0]["line"], 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Validator {
+    tolerance: chrono::Duration,
+    max_problems: usize,
+    previous: Option<DateTime<Utc>>,
+    first_format: Option<String>,
+    report: Report,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator {
+            tolerance: chrono::Duration::zero(),
+            max_problems: 100,
+            previous: None,
+            first_format: None,
+            report: Report::default(),
+        }
+    }
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow entries to be up to `tolerance` earlier than the one before. Servers which log
+    /// requests as they complete, stamped with when they started, write slightly out of order.
+    pub fn with_tolerance(mut self, tolerance: chrono::Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Keep the first `max_problems` problems in the report.
+    pub fn with_max_problems(mut self, max_problems: usize) -> Self {
+        self.max_problems = max_problems;
+        self
+    }
+
+    /// Check the next line of the log, returning the issues with it.
+    pub fn check_line(&mut self, line: &str) -> Vec<Issue> {
+        self.report.lines += 1;
+        let mut issues = match line.parse::<LogEntry>() {
+            Ok(entry) => self.check_entry(&entry),
+            Err(e) => vec![Issue::Unparseable {
+                error: e.to_string(),
+            }],
+        };
+        if let Some(format) = timestamp_text(line).map(timestamp_format) {
+            *self
+                .report
+                .timestamp_formats
+                .entry(format.clone())
+                .or_default() += 1;
+            match &self.first_format {
+                None => self.first_format = Some(format),
+                Some(first) if *first != format => issues.push(Issue::MixedTimestampFormat {
+                    format,
+                    first: first.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        self.record(&issues);
+        issues
+    }
+
+    /// Check the next entry of the log, returning the issues with it. Entries carry no trace of
+    /// how their timestamps were written, so mixed formats are only found by
+    /// [`Validator::check_line`].
+    pub fn check(&mut self, entry: &LogEntry) -> Vec<Issue> {
+        self.report.lines += 1;
+        let issues = self.check_entry(entry);
+        self.record(&issues);
+        issues
+    }
+
+    fn check_entry(&mut self, entry: &LogEntry) -> Vec<Issue> {
+        self.report.entries += 1;
+        let mut issues = Vec::new();
+        if let Some(time) = entry.time {
+            if let Some(previous) = self.previous.filter(|&p| time < p - self.tolerance) {
+                issues.push(Issue::NonMonotonicTime { previous, time });
+            }
+            self.previous = Some(time);
+        }
+        if let Some(status) = entry.status_code.map(|s| s.as_u16()) {
+            if status > 599 {
+                issues.push(Issue::ImpossibleStatus { status });
+            }
+            match entry.object_size {
+                Some(size) if size > 0 && matches!(status, 100..=199 | 204 | 304) => {
+                    issues.push(Issue::SizeWithoutBody { status, size })
+                }
+                _ => (),
+            }
+        }
+        issues
+    }
+
+    fn record(&mut self, issues: &[Issue]) {
+        let line = self.report.lines;
+        for issue in issues {
+            let count = match issue {
+                Issue::Unparseable { .. } => &mut self.report.unparseable,
+                Issue::NonMonotonicTime { .. } => &mut self.report.non_monotonic_time,
+                Issue::ImpossibleStatus { .. } => &mut self.report.impossible_status,
+                Issue::SizeWithoutBody { .. } => &mut self.report.size_without_body,
+                Issue::MixedTimestampFormat { .. } => &mut self.report.mixed_timestamp_format,
+            };
+            *count += 1;
+            if self.report.problems.len() < self.max_problems {
+                self.report.problems.push(Problem {
+                    line,
+                    issue: issue.clone(),
+                });
+            }
+        }
+    }
+
+    /// Check every line of `reader`, and return the report. Invalid UTF-8 is replaced.
+    pub fn validate(mut self, mut reader: impl BufRead) -> io::Result<Report> {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(self.report);
+            }
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.check_line(&String::from_utf8_lossy(line));
+        }
+    }
+
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    pub fn into_report(self) -> Report {
+        self.report
+    }
+}
+
+/// The text between the first `[` of `line` and the next `]`.
+fn timestamp_text(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once('[')?;
+    rest.split_once(']').map(|(t, _)| t)
+}
+
+/// `timestamp` with each run of digits replaced by `9`, and each run of letters by `a`.
+fn timestamp_format(timestamp: &str) -> String {
+    let mut format = String::with_capacity(timestamp.len());
+    for c in timestamp.chars() {
+        let class = match c {
+            '0'..='9' => '9',
+            c if c.is_alphabetic() => 'a',
+            c => c,
+        };
+        if !(format.ends_with(class) && (class == '9' || class == 'a')) {
+            format.push(class);
+        }
+    }
+    format
+}
@@ -0,0 +1,80 @@
+//! Reporting data which was accepted but not kept exactly as written.
+//!
+//! Some inputs are coerced rather than rejected: invalid UTF-8 in string fields is replaced,
+//! out-of-range status codes deserialize to `None`, and text after the last field is ignored.
+//! Each of these raises a [`Warning`], which is dropped unless the code doing the parsing is
+//! wrapped in [`collect`].
+
+use std::cell::RefCell;
+
+/// A coercion made while parsing or deserializing an entry.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Warning {
+    /// The named field contained invalid UTF-8, which was replaced with `U+FFFD`.
+    InvalidUtf8 { field: String },
+    /// A deserialized status code was out of range and became `None`.
+    InvalidStatusCode(u16),
+    /// `len` bytes followed the last field and were ignored.
+    TrailingData { len: usize },
+}
+
+thread_local! {
+    static COLLECTOR: RefCell<Option<Vec<Warning>>> = const { RefCell::new(None) };
+}
+
+/// Record `w` if [`collect`] is active on this thread.
+pub(crate) fn emit(w: Warning) {
+    COLLECTOR.with(|c| {
+        if let Some(ws) = c.borrow_mut().as_mut() {
+            ws.push(w);
+        }
+    })
+}
+
+/// Run `f`, returning its result together with the warnings raised on this thread meanwhile.
+///
+/// Calls may be nested; inner calls take the warnings raised inside them.
+///
+/// # Example
+/// ```
+/// use common_log_format::{warnings::{self, Warning}, LogEntry};
+/// let json = r#"{"host":null,"ident":null,"authuser":null,"time":null,
+///     "request_line":null,"status_code":1000,"object_size":null}"#;
+/// let (entry, ws) = warnings::collect(|| serde_json::from_str::<LogEntry>(json));
+/// assert_eq!(entry.unwrap().status_code, None);
+/// assert_eq!(ws, vec![Warning::InvalidStatusCode(1000)]);
+/// ```
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<Warning>) {
+    let outer = COLLECTOR.with(|c| c.borrow_mut().replace(vec![]));
+    let t = f();
+    let ws = COLLECTOR.with(|c| std::mem::replace(&mut *c.borrow_mut(), outer));
+    (t, ws.unwrap_or_default())
+}
+
+/// Running totals of warnings over a stream of entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WarningCounts {
+    pub entries: u64,
+    pub entries_with_warnings: u64,
+    pub invalid_utf8: u64,
+    pub invalid_status_code: u64,
+    pub trailing_data: u64,
+}
+
+impl WarningCounts {
+    /// Account for one entry, which raised `warnings`.
+    pub fn record(&mut self, warnings: &[Warning]) {
+        self.entries += 1;
+        if !warnings.is_empty() {
+            self.entries_with_warnings += 1;
+        }
+
+        for w in warnings {
+            match w {
+                Warning::InvalidUtf8 { .. } => self.invalid_utf8 += 1,
+                Warning::InvalidStatusCode(_) => self.invalid_status_code += 1,
+                Warning::TrailingData { .. } => self.trailing_data += 1,
+            }
+        }
+    }
+}
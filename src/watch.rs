@@ -0,0 +1,288 @@
+//! Alerting on conditions over a live stream of entries.
+//!
+//! A [`Watch`] holds a set of [`Rule`]s, such as "5xx responses are over 5% of requests in the
+//! last minute" or "one address sent 100 requests in a second", and checks each entry against
+//! them as it's observed. When a rule's threshold is reached, the watch returns an [`Alert`] and
+//! passes it to its callbacks, which can log it, page someone, or send it down a channel.
+//!
+//! Rules are evaluated over a sliding window of log time, like [`crate::slo::BurnAlerter`], so
+//! replaying an old log fires the alerts it would have fired when it was written.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{filter::Filter, LogEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Threshold {
+    Count(u64),
+    Ratio(f64),
+}
+
+/// A condition over the entries in a window, which fires an [`Alert`] once reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    name: String,
+    filter: Filter,
+    threshold: Threshold,
+    window: chrono::Duration,
+    per_host: bool,
+    min_requests: u64,
+    cooldown: chrono::Duration,
+}
+
+impl Rule {
+    /// Fire when `threshold` entries matching `filter` are within `window`. [`Filter::True`]
+    /// counts every entry.
+    pub fn count(
+        name: impl Into<String>,
+        filter: Filter,
+        window: chrono::Duration,
+        threshold: u64,
+    ) -> Self {
+        Rule::new(
+            name.into(),
+            filter,
+            window,
+            Threshold::Count(threshold.max(1)),
+        )
+    }
+
+    /// Fire when the fraction of the entries within `window` which match `filter` reaches
+    /// `threshold`, such as `0.05` for 5%.
+    ///
+    /// By default, there have to be 10 entries in the window for the rule to fire, so the first
+    /// request of a quiet period failing isn't 100% of them.
+    pub fn ratio(
+        name: impl Into<String>,
+        filter: Filter,
+        window: chrono::Duration,
+        threshold: f64,
+    ) -> Self {
+        Rule::new(name.into(), filter, window, Threshold::Ratio(threshold)).with_min_requests(10)
+    }
+
+    fn new(name: String, filter: Filter, window: chrono::Duration, threshold: Threshold) -> Self {
+        Rule {
+            name,
+            filter,
+            threshold,
+            window,
+            per_host: false,
+            min_requests: 1,
+            cooldown: chrono::Duration::hours(1),
+        }
+    }
+
+    /// Evaluate the rule for each client address separately, rather than over all entries.
+    pub fn with_per_host(mut self, per_host: bool) -> Self {
+        self.per_host = per_host;
+        self
+    }
+
+    /// Don't fire unless the window holds at least `min_requests` entries, matching or not.
+    pub fn with_min_requests(mut self, min_requests: u64) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    /// Once fired, don't fire again (for the same host, if per host) until `cooldown` has passed.
+    /// An hour by default.
+    pub fn with_cooldown(mut self, cooldown: chrono::Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A [`Rule`] reaching its threshold.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Alert {
+    /// The [`Rule::name`] of the rule.
+    pub rule: String,
+    /// The client address, for rules evaluated per host.
+    pub host: Option<IpAddr>,
+    pub at: DateTime<Utc>,
+    /// The entries in the window ending at `at` which matched the rule's filter.
+    pub matching: u64,
+    /// All the entries in the window (from `host`, if set).
+    pub total: u64,
+}
+
+impl Alert {
+    /// The fraction of the entries in the window which matched.
+    pub fn ratio(&self) -> f64 {
+        self.matching as f64 / self.total.max(1) as f64
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Group {
+    /// Time and whether it matched, for each entry in the window.
+    recent: VecDeque<(DateTime<Utc>, bool)>,
+    matching: u64,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+struct Watched {
+    rule: Rule,
+    groups: HashMap<Option<IpAddr>, Group>,
+}
+
+impl Watched {
+    fn observe(&mut self, entry: &LogEntry, time: DateTime<Utc>) -> Option<Alert> {
+        let rule = &self.rule;
+        let host = match rule.per_host {
+            true => Some(entry.host?.to_canonical()),
+            false => None,
+        };
+        let matched = rule.filter.matches(entry);
+        let group = self.groups.entry(host).or_default();
+        group.recent.push_back((time, matched));
+        group.matching += matched as u64;
+        while let Some(&(t, m)) = group.recent.front() {
+            if t > time - rule.window {
+                break;
+            }
+            group.recent.pop_front();
+            group.matching -= m as u64;
+        }
+
+        let total = group.recent.len() as u64;
+        let reached = match rule.threshold {
+            Threshold::Count(n) => group.matching >= n,
+            Threshold::Ratio(r) => group.matching as f64 >= r * total as f64,
+        };
+        let cooled = group.last_alert.is_none_or(|t| time - t >= rule.cooldown);
+        if !matched || !reached || total < rule.min_requests || !cooled {
+            return None;
+        }
+
+        group.last_alert = Some(time);
+        Some(Alert {
+            rule: rule.name.clone(),
+            host,
+            at: time,
+            matching: group.matching,
+            total,
+        })
+    }
+}
+
+type Callback = Box<dyn FnMut(&Alert) + Send>;
+
+/// Checks entries against a set of [`Rule`]s, as described in the [module docs](self).
+///
+/// A rule only fires on an entry which matches its filter: a ratio of errors fires on an error,
+/// not on a success which happens to follow one. Entries without a timestamp are ignored, as are entries without
+/// a host by rules evaluated per host.
+///
+/// # Example
+/// ```
+/// use std::sync::mpsc;
+/// use common_log_format::{filter::Filter, watch::{Rule, Watch}, LogEntry};
+///
+/// let (alerts, received) = mpsc::channel();
+/// let mut watch = Watch::new()
+///     .with_rule(Rule::ratio("errors", Filter::status().ge(500), chrono::Duration::minutes(1), 0.05))
+///     .with_rule(
+///         Rule::count("flood", Filter::True, chrono::Duration::seconds(1), 100).with_per_host(true),
+///     )
+///     .on_alert(move |alert| alerts.send(alert.clone()).unwrap());
+///
+/// let line = |host: u8, ms: u32, status: u16| -> LogEntry {
+///     format!("10.0.0.{} - - [2024-05-01T13:00:{:02}.{:03}Z] \"GET / HTTP/1.1\" {} 10",
+///             host, ms / 1000, ms % 1000, status)
+///         .parse()
+///         .unwrap()
+/// };
+/// // One address sends 150 requests in the first second.
+/// for i in 0..150 {
+///     watch.observe(&line(1, i * 5, 200));
+/// }
+/// // Then half of another's fail.
+/// for i in 0..50 {
+///     watch.observe(&line(2, 10_000 + i * 100, if i % 2 == 0 { 503 } else { 200 }));
+/// }
+///
+/// let fired: Vec<_> = received.try_iter().map(|a| (a.rule, a.host)).collect();
+/// assert_eq!(fired, [
+///     ("flood".to_owned(), Some("10.0.0.1".parse().unwrap())),
+///     ("errors".to_owned(), None),
+/// ]);
+/// ```
+#[derive(Default)]
+pub struct Watch {
+    rules: Vec<Watched>,
+    callbacks: Vec<Callback>,
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(Watched {
+            rule,
+            groups: HashMap::new(),
+        });
+        self
+    }
+
+    /// Call `callback` with every alert, as it fires.
+    pub fn on_alert(mut self, callback: impl FnMut(&Alert) + Send + 'static) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Check `entry` against every rule, returning the alerts it fired, after passing them to the
+    /// callbacks.
+    pub fn observe(&mut self, entry: &LogEntry) -> Vec<Alert> {
+        let time = match entry.time {
+            Some(t) => t,
+            None => return Vec::new(),
+        };
+        let alerts: Vec<_> = self
+            .rules
+            .iter_mut()
+            .filter_map(|w| w.observe(entry, time))
+            .collect();
+        for alert in &alerts {
+            for callback in &mut self.callbacks {
+                callback(alert);
+            }
+        }
+        alerts
+    }
+
+    /// Forget hosts, and whole rules' windows, with no entries in the window ending at `now` and
+    /// no alert still cooling down, to bound memory.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        for w in &mut self.rules {
+            let (window, cooldown) = (w.rule.window, w.rule.cooldown);
+            w.groups.retain(|_, g| {
+                g.recent.back().is_some_and(|&(t, _)| t > now - window)
+                    || g.last_alert.is_some_and(|t| now - t < cooldown)
+            });
+        }
+    }
+}
+
+/// Observes each entry sent, for the callbacks to act on.
+#[cfg(feature = "io")]
+impl crate::sink::Sink for Watch {
+    type Error = std::convert::Infallible;
+
+    fn send(&mut self, entry: &LogEntry) -> Result<(), Self::Error> {
+        self.observe(entry);
+        Ok(())
+    }
+}
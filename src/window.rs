@@ -0,0 +1,143 @@
+//! Tumbling time windows over streams of entries.
+//!
+//! A [`TumblingWindows`] feeds entries into a fresh [`Aggregate`] for each fixed-width window
+//! and hands back the finished result once the window closes. Windows are aligned to the Unix
+//! epoch, so one-hour windows start on the hour.
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::LogEntry;
+
+/// A computation over a sequence of entries.
+pub trait Aggregate {
+    type Output;
+
+    fn observe(&mut self, entry: &LogEntry);
+    fn finish(self) -> Self::Output;
+}
+
+/// The result of aggregating the entries in `[start, end)`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Window<T> {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub output: T,
+}
+
+/// The start of the `width`-long bucket containing `t`, aligned to the Unix epoch.
+pub(crate) fn bucket_start(t: DateTime<Utc>, width: chrono::Duration) -> DateTime<Utc> {
+    let w = width.num_seconds().max(1);
+    let secs = t.timestamp();
+    Utc.timestamp_opt(secs - secs.rem_euclid(w), 0).unwrap()
+}
+
+/// Split a stream of entries into consecutive windows of a fixed width.
+///
+/// Windows normally close when an entry from a later window arrives. In streaming mode that can
+/// be arbitrarily late, so [`TumblingWindows::advance_to`] closes the current window as soon as a
+/// clock passes its end, and [`TumblingWindows::cut_at`] ends it at an exact instant.
+///
+/// Entries without a timestamp are counted in the current window, or dropped if there is none.
+/// Entries older than the current window are counted in it too, rather than reopening a window
+/// which was already emitted.
+///
+/// # Example
+/// ```
+/// use common_log_format::{window::{Aggregate, TumblingWindows}, LogEntry};
+///
+/// #[derive(Default)]
+/// struct Count(usize);
+/// impl Aggregate for Count {
+///     type Output = usize;
+///     fn observe(&mut self, _: &LogEntry) { self.0 += 1; }
+///     fn finish(self) -> usize { self.0 }
+/// }
+///
+/// let mut windows = TumblingWindows::new(chrono::Duration::hours(1), Count::default);
+/// let e: LogEntry = "10.0.0.1 - - [2024-05-01T13:05:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// assert!(windows.push(&e).is_none());
+///
+/// // Close the 13:00 window at 14:00 without waiting for the next entry.
+/// let w = windows.advance_to("2024-05-01T14:00:00Z".parse().unwrap()).unwrap();
+/// assert_eq!(w.start.to_rfc3339(), "2024-05-01T13:00:00+00:00");
+/// assert_eq!(w.output, 1);
+/// ```
+pub struct TumblingWindows<A, F> {
+    width: chrono::Duration,
+    new: F,
+    current: Option<(DateTime<Utc>, DateTime<Utc>, A)>,
+}
+
+impl<A, F> TumblingWindows<A, F>
+where
+    A: Aggregate,
+    F: FnMut() -> A,
+{
+    /// Windows are `width` long, and each starts with the aggregate returned by `new`.
+    pub fn new(width: chrono::Duration, new: F) -> Self {
+        TumblingWindows {
+            width,
+            new,
+            current: None,
+        }
+    }
+
+    /// Add `entry`, returning the previous window if `entry` is past its end.
+    pub fn push(&mut self, entry: &LogEntry) -> Option<Window<A::Output>> {
+        let closed = match entry.time {
+            Some(t) => self.advance_to(t),
+            None => None,
+        };
+
+        if let Some(t) = entry.time {
+            if self.current.is_none() {
+                let start = bucket_start(t, self.width);
+                self.current = Some((start, start + self.width, (self.new)()));
+            }
+        }
+
+        if let Some((_, _, agg)) = &mut self.current {
+            agg.observe(entry);
+        }
+
+        closed
+    }
+
+    /// Close the current window if it ends at or before `now`.
+    pub fn advance_to(&mut self, now: DateTime<Utc>) -> Option<Window<A::Output>> {
+        match self.current {
+            Some((_, end, _)) if end <= now => self.take(),
+            _ => None,
+        }
+    }
+
+    /// End the current window at exactly `at`, if it started before then.
+    ///
+    /// Later entries go into a window starting at `at`, which ends on the next regular boundary.
+    pub fn cut_at(&mut self, at: DateTime<Utc>) -> Option<Window<A::Output>> {
+        match self.current {
+            Some((start, _, _)) if start < at => (),
+            _ => return None,
+        }
+
+        let (start, end, agg) = self.current.take().unwrap();
+        if at < end {
+            self.current = Some((at, end, (self.new)()));
+        }
+
+        Some(Window {
+            start,
+            end: end.min(at),
+            output: agg.finish(),
+        })
+    }
+
+    /// Close the current window regardless of the time, e.g. at the end of the input.
+    pub fn take(&mut self) -> Option<Window<A::Output>> {
+        self.current.take().map(|(start, end, agg)| Window {
+            start,
+            end,
+            output: agg.finish(),
+        })
+    }
+}
@@ -0,0 +1,707 @@
+//! Writing log files, rotating them by size and age, or splitting them by time.
+//!
+//! [`ClfWriter`] appends entries to a live log file, and rotates it the way logrotate does:
+//! `access.log` becomes `access.log.1`, an existing `access.log.1` becomes `access.log.2`, and so
+//! on, with the oldest beyond the number kept deleted. Rotated files can be compressed, as
+//! `access.log.1.gz` for example. The set reads back in order with
+//! [`RotatedLogReader`](crate::rotated::RotatedLogReader).
+//!
+//! Several processes, such as a server's workers, can append to one file if each opens it with
+//! [`ClfWriter::with_shared`]. Each line is then written with a single `write` to a file opened
+//! with `O_APPEND`, which the kernel appends whole, so lines from different processes never
+//! interleave or tear.
+//!
+//! [`SplitWriter`] instead files each entry by its own timestamp, into one file per hour or day
+//! such as `access-2024-05-01-13.log.gz`, for re-partitioning archives written some other way.
+
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{FixedOffset, Utc};
+
+use crate::{
+    reader::{Compression, FileId},
+    sink::Sink,
+    LogEntry,
+};
+
+/// When a [`ClfWriter`] makes sure what it has written is on disk, with `fsync`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyncPolicy {
+    /// Never; the operating system writes the file out in its own time.
+    Never,
+    /// On [`Sink::flush`] and before rotating.
+    OnFlush,
+    /// After every given number of entries, as well as on flush and before rotating.
+    Every(u64),
+    /// After every entry. This is slow, but loses nothing if the machine crashes.
+    Always,
+}
+
+/// A [`Sink`] which appends entries to a log file, rotating it when it grows too large or too
+/// old.
+///
+/// Entries are written one per line, buffered until [`Sink::flush`] or as the [`SyncPolicy`]
+/// requires. Without a maximum size or rotation interval, the file is never rotated. Otherwise,
+/// 7 rotated files are kept, uncompressed, unless set otherwise, and what's written is synced on
+/// flush and before rotating.
+///
+/// # Example
+/// ```
+/// use common_log_format::{rotated::RotatedLogReader, sink::Sink, writer::ClfWriter, LogEntry};
+///
+/// let dir = std::env::temp_dir().join(format!("clf-writer-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let entry: LogEntry = "10.0.0.1 - - [2024-05-01T13:00:00Z] \"GET / HTTP/1.1\" 200 10".parse().unwrap();
+/// let line_len = entry.to_string().len() as u64 + 1;
+///
+/// let mut writer = ClfWriter::open(dir.join("access.log"))
+///     .unwrap()
+///     .with_max_size(2 * line_len)
+///     .with_keep(2);
+/// for _ in 0..7 {
+///     writer.send(&entry).unwrap();
+/// }
+/// writer.flush().unwrap();
+///
+/// // 2 entries in each of access.log.2 and access.log.1 (the two kept), and 1 in access.log.
+/// let reader = RotatedLogReader::new(dir.join("access.log*")).unwrap();
+/// assert_eq!(reader.paths().len(), 3);
+/// assert_eq!(reader.count(), 5);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct ClfWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// The size of the live file, including what's buffered.
+    size: u64,
+    /// When the live file's rotation interval began.
+    period: u64,
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    keep: usize,
+    compression: Compression,
+    sync: SyncPolicy,
+    unsynced: u64,
+    shared: bool,
+    lock: bool,
+    line: String,
+}
+
+impl ClfWriter {
+    /// Append to the file at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        Ok(ClfWriter {
+            size: metadata.len(),
+            period: secs(metadata.modified()?),
+            path,
+            file: BufWriter::new(file),
+            max_size: None,
+            interval: None,
+            keep: 7,
+            compression: Compression::None,
+            sync: SyncPolicy::OnFlush,
+            unsynced: 0,
+            shared: false,
+            lock: false,
+            line: String::new(),
+        })
+    }
+
+    /// Rotate the file before a write would make it larger than `max_size` bytes. A single entry
+    /// larger than that is still written, to a file of its own.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Rotate the file when a write comes after the end of the current `interval`. Intervals are
+    /// counted from the Unix epoch, so a day's interval rotates at midnight UTC, and an hour's on
+    /// the hour.
+    ///
+    /// The file's modification time decides which interval it began in when it's opened, so a log
+    /// left over from yesterday is rotated on the first write today.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        let interval = interval.max(Duration::from_secs(1));
+        self.period = start_of(self.period, interval);
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Keep `keep` rotated files, deleting older ones. With none kept, the file is truncated
+    /// instead of rotated.
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Compress rotated files as `compression`, adding its extension to their names. Rotating
+    /// fails with [`io::ErrorKind::Unsupported`] if the feature for `compression` isn't enabled.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_sync(mut self, sync: SyncPolicy) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Share the file with other processes appending to it.
+    ///
+    /// Each line is written straight away, with a single `write`, rather than buffered. Before
+    /// each one, the file is reopened if another process has rotated it, and its size is read
+    /// from the file, so that it's rotated by what every process has written.
+    ///
+    /// With `lock`, an exclusive advisory lock (`flock` on Unix) on the file is held around each
+    /// write and rotation, so that only one process rotates the file when it's due. Every
+    /// process must lock for this to work. Without it, only rotate shared files from outside,
+    /// as logrotate does.
+    pub fn with_shared(mut self, lock: bool) -> Self {
+        self.shared = true;
+        self.lock = lock;
+        self
+    }
+
+    /// The live file's path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entry`, such as a [`LogEntry`] or a
+    /// [`CombinedLogEntry`](crate::combined::CombinedLogEntry), as a line.
+    pub fn write(&mut self, entry: &impl Display) -> io::Result<()> {
+        use std::fmt::Write as _;
+        let mut line = std::mem::take(&mut self.line);
+        line.clear();
+        let _ = writeln!(line, "{}", entry);
+        let result = self.write_line(&line);
+        self.line = line;
+        result
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if !self.shared {
+            return self.append(line);
+        }
+        let locked = match self.lock {
+            true => Some(self.lock_live()?),
+            false => {
+                self.reopen_if_rotated()?;
+                None
+            }
+        };
+        let result = self.file.get_ref().metadata().and_then(|m| {
+            self.size = m.len();
+            self.append(line)
+        });
+        if let Some(locked) = locked {
+            let _ = locked.unlock();
+        }
+        result
+    }
+
+    /// Lock the live file, reopening it first if another process has rotated it. Returns a
+    /// handle to unlock it with, which stays valid if this process rotates it.
+    fn lock_live(&mut self) -> io::Result<File> {
+        loop {
+            self.reopen_if_rotated()?;
+            let locked = self.file.get_ref().try_clone()?;
+            locked.lock()?;
+            // It may have been rotated while waiting for the lock.
+            if !self.is_rotated()? {
+                return Ok(locked);
+            }
+            locked.unlock()?;
+        }
+    }
+
+    /// Whether the live file's path no longer leads to the file being written.
+    fn is_rotated(&self) -> io::Result<bool> {
+        let open = FileId::of(&self.file.get_ref().metadata()?);
+        match fs::metadata(&self.path) {
+            Ok(live) => Ok(FileId::of(&live) != open),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reopen_if_rotated(&mut self) -> io::Result<()> {
+        if !self.is_rotated()? {
+            return Ok(());
+        }
+        self.file.flush()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        if let Some(interval) = self.interval {
+            self.period = start_of(secs(SystemTime::now()), interval);
+        }
+        Ok(())
+    }
+
+    /// Append `line` to the live file, rotating it first if it's due.
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64;
+        let now = secs(SystemTime::now());
+        let too_large = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len > max);
+        let too_old = self
+            .interval
+            .is_some_and(|interval| start_of(now, interval) > self.period);
+        if too_large || too_old {
+            self.rotate()?;
+            if let Some(interval) = self.interval {
+                self.period = start_of(now, interval);
+            }
+        }
+
+        match self.shared {
+            true => self.file.get_ref().write_all(line.as_bytes())?,
+            false => self.file.write_all(line.as_bytes())?,
+        }
+        self.size += len;
+        self.unsynced += 1;
+        match self.sync {
+            SyncPolicy::Always => self.sync(),
+            SyncPolicy::Every(n) if self.unsynced >= n => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write out what's buffered, and sync it to disk.
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Rotate the live file now, whatever its size and age.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        match self.sync {
+            SyncPolicy::Never => self.file.flush()?,
+            _ => self.sync()?,
+        }
+        if self.keep == 0 {
+            self.file.get_ref().set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let extension = extension(self.compression);
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        let with_extension = |path: &Path, ext: &str| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(ext);
+            PathBuf::from(name)
+        };
+
+        // Shift each older file up by one, whether or not it was compressed, dropping the oldest.
+        for n in (1..=self.keep).rev() {
+            for ext in ["", ".gz", ".zst", ".bz2", ".xz"] {
+                let from = with_extension(&rotated(n), ext);
+                if !from.exists() {
+                    continue;
+                }
+                if n == self.keep {
+                    fs::remove_file(&from)?;
+                } else {
+                    fs::rename(&from, with_extension(&rotated(n + 1), ext))?;
+                }
+            }
+        }
+
+        let first = rotated(1);
+        fs::rename(&self.path, &first)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+
+        if self.compression != Compression::None {
+            let compressed = with_extension(&first, extension);
+            compress(&first, &compressed, self.compression)?;
+            fs::remove_file(&first)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for ClfWriter {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        self.write(entry)
+    }
+
+    /// Write out what's buffered, syncing it to disk unless the policy is [`SyncPolicy::Never`].
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sync {
+            SyncPolicy::Never => self.file.flush(),
+            _ => self.sync(),
+        }
+    }
+}
+
+/// How much time each file of a [`SplitWriter`] covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Window {
+    /// Files named like `access-2024-05-01-13.log`.
+    Hour,
+    /// Files named like `access-2024-05-01.log`.
+    Day,
+}
+
+impl Window {
+    fn format(self) -> &'static str {
+        match self {
+            Window::Hour => "%Y-%m-%d-%H",
+            Window::Day => "%Y-%m-%d",
+        }
+    }
+}
+
+/// A [`Sink`] which writes each entry to the file for the hour or day of its timestamp, in a
+/// directory.
+///
+/// Files are named `{prefix}-{window}.log`, with the compression's extension if any, and
+/// entries without a timestamp go to `{prefix}-undated.log`. Windows are taken in UTC unless a
+/// time zone is set. Files which already exist are appended to; compressed ones gain a new
+/// stream, which the readers in [`crate::reader`] read on from.
+///
+/// A few files are kept open at once (4 by default), closing the least recently written when
+/// another is needed, so an archive which is only roughly in order doesn't reopen files for
+/// every entry. Compressed files are only complete once they're closed, so call
+/// [`SplitWriter::finish`] at the end; dropping the writer closes them too, but loses any error.
+///
+/// # Example
+/// ```
+/// use common_log_format::{reader::Compression, sink::Sink, writer::{SplitWriter, Window}, LogEntry};
+///
+/// let dir = std::env::temp_dir().join(format!("clf-split-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let mut writer = SplitWriter::new(&dir, "access", Window::Hour);
+/// for time in ["2024-05-01T13:59:59Z", "2024-05-01T14:00:00Z", "2024-05-01T13:30:00+01:00"] {
+///     let line = format!("10.0.0.1 - - [{}] \"GET / HTTP/1.1\" 200 10", time);
+///     writer.send(&line.parse::<LogEntry>().unwrap()).unwrap();
+/// }
+/// writer.finish().unwrap();
+///
+/// let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+/// assert_eq!(read("access-2024-05-01-13.log").lines().count(), 1);
+/// assert_eq!(read("access-2024-05-01-14.log").lines().count(), 1);
+/// assert_eq!(read("access-2024-05-01-12.log").lines().count(), 1);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct SplitWriter {
+    dir: PathBuf,
+    prefix: String,
+    window: Window,
+    timezone: FixedOffset,
+    compression: Compression,
+    max_open: usize,
+    /// The open files by the name of their window, least recently written first.
+    open: Vec<(String, Output)>,
+    line: String,
+}
+
+impl SplitWriter {
+    /// Write files named `{prefix}-{window}.log` in `dir`, which must exist.
+    pub fn new(dir: impl Into<PathBuf>, prefix: impl Into<String>, window: Window) -> Self {
+        SplitWriter {
+            dir: dir.into(),
+            prefix: prefix.into(),
+            window,
+            timezone: FixedOffset::east_opt(0).expect("UTC is a valid offset"),
+            compression: Compression::None,
+            max_open: 4,
+            open: Vec::new(),
+            line: String::new(),
+        }
+    }
+
+    /// Divide time into hours or days in `timezone`, and name files by its local time.
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Compress files as they're written, adding the extension to their names. Writing fails
+    /// with [`io::ErrorKind::Unsupported`] if the feature for `compression` isn't enabled.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Keep up to `max_open` files open at once.
+    pub fn with_max_open(mut self, max_open: usize) -> Self {
+        self.max_open = max_open.max(1);
+        self
+    }
+
+    /// The path of the file for the window named `window`.
+    fn path(&self, window: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}-{}.log{}",
+            self.prefix,
+            window,
+            extension(self.compression)
+        ))
+    }
+
+    /// Append `entry`, such as a [`LogEntry`] or a
+    /// [`CombinedLogEntry`](crate::combined::CombinedLogEntry), as a line of the file for
+    /// `time`.
+    pub fn write(
+        &mut self,
+        time: Option<chrono::DateTime<Utc>>,
+        entry: &impl Display,
+    ) -> io::Result<()> {
+        use std::fmt::Write as _;
+        let window = match time {
+            Some(t) => t
+                .with_timezone(&self.timezone)
+                .format(self.window.format())
+                .to_string(),
+            None => "undated".to_owned(),
+        };
+
+        let i = match self.open.iter().position(|(w, _)| *w == window) {
+            Some(i) => i,
+            None => {
+                if self.open.len() >= self.max_open {
+                    let (_, oldest) = self.open.remove(0);
+                    oldest.finish()?;
+                }
+                let output = Output::open(&self.path(&window), self.compression)?;
+                self.open.push((window, output));
+                self.open.len() - 1
+            }
+        };
+        let last = self.open.len() - 1;
+        self.open[i..].rotate_left(1);
+
+        self.line.clear();
+        let _ = writeln!(self.line, "{}", entry);
+        self.open[last].1.write_all(self.line.as_bytes())
+    }
+
+    /// Close every file, completing compressed ones.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close_all()
+    }
+
+    fn close_all(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for (_, output) in self.open.drain(..) {
+            result = result.and(output.finish());
+        }
+        result
+    }
+}
+
+impl Sink for SplitWriter {
+    type Error = io::Error;
+
+    fn send(&mut self, entry: &LogEntry) -> io::Result<()> {
+        self.write(entry.time, entry)
+    }
+
+    /// Write out what's buffered for every open file. Compressed files stay open, and are only
+    /// readable up to the end of the last complete block until they're closed.
+    fn flush(&mut self) -> io::Result<()> {
+        self.open.iter_mut().try_for_each(|(_, o)| o.flush())
+    }
+}
+
+impl Drop for SplitWriter {
+    fn drop(&mut self) {
+        let _ = self.close_all();
+    }
+}
+
+/// A file being appended to, perhaps through an encoder.
+enum Output {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, BufWriter<File>>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::write::BzEncoder<BufWriter<File>>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    fn open(path: &Path, compression: Compression) -> io::Result<Self> {
+        let append = || -> io::Result<BufWriter<File>> {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Ok(BufWriter::new(file))
+        };
+        Ok(match compression {
+            Compression::None => Output::Plain(append()?),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Output::Gzip(flate2::write::GzEncoder::new(
+                append()?,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Output::Zstd(zstd::stream::write::Encoder::new(append()?, 0)?),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Output::Bzip2(bzip2::write::BzEncoder::new(
+                append()?,
+                bzip2::Compression::default(),
+            )),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Output::Xz(xz2::write::XzEncoder::new(append()?, 6)),
+            #[allow(unreachable_patterns)]
+            c => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "compressing {} as {:?} requires the matching feature",
+                        path.display(),
+                        c
+                    ),
+                ))
+            }
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(w) => w,
+            #[cfg(feature = "gzip")]
+            Output::Gzip(w) => w,
+            #[cfg(feature = "zstd")]
+            Output::Zstd(w) => w,
+            #[cfg(feature = "bzip2")]
+            Output::Bzip2(w) => w,
+            #[cfg(feature = "xz")]
+            Output::Xz(w) => w,
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.writer().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+
+    /// Finish the encoder, if any, and write out what's buffered.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut w) => w.flush(),
+            #[cfg(feature = "gzip")]
+            Output::Gzip(w) => w.finish()?.flush(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(w) => w.finish()?.flush(),
+            #[cfg(feature = "bzip2")]
+            Output::Bzip2(w) => w.finish()?.flush(),
+            #[cfg(feature = "xz")]
+            Output::Xz(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The start of the `interval` that `secs` falls in.
+fn start_of(secs: u64, interval: Duration) -> u64 {
+    let interval = interval.as_secs().max(1);
+    secs - secs % interval
+}
+
+fn extension(compression: Compression) -> &'static str {
+    match compression {
+        Compression::None => "",
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+        Compression::Bzip2 => ".bz2",
+        Compression::Xz => ".xz",
+    }
+}
+
+/// Write the file at `from` to `to`, compressed as `compression`.
+fn compress(from: &Path, to: &Path, compression: Compression) -> io::Result<()> {
+    let result = match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => encode(
+            from,
+            flate2::write::GzEncoder::new(create(to)?, flate2::Compression::default()),
+            flate2::write::GzEncoder::finish,
+        ),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => encode(
+            from,
+            zstd::stream::write::Encoder::new(create(to)?, 0)?,
+            zstd::stream::write::Encoder::finish,
+        ),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => encode(
+            from,
+            bzip2::write::BzEncoder::new(create(to)?, bzip2::Compression::default()),
+            bzip2::write::BzEncoder::finish,
+        ),
+        #[cfg(feature = "xz")]
+        Compression::Xz => encode(
+            from,
+            xz2::write::XzEncoder::new(create(to)?, 6),
+            xz2::write::XzEncoder::finish,
+        ),
+        #[allow(unreachable_patterns)]
+        c => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "compressing {} as {:?} requires the matching feature",
+                from.display(),
+                c
+            ),
+        )),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    result
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz"))]
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    File::create(path).map(BufWriter::new)
+}
+
+/// Copy the file at `from` into `encoder`, finish it with `finish`, and sync the output.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz"))]
+fn encode<E: Write>(
+    from: &Path,
+    mut encoder: E,
+    finish: impl FnOnce(E) -> io::Result<BufWriter<File>>,
+) -> io::Result<()> {
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    let output = finish(encoder)?;
+    output.into_inner().map_err(|e| e.into_error())?.sync_all()
+}